@@ -0,0 +1,490 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::fs::File;
+
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime};
+
+use crate::format::NumberFormat;
+use crate::logformat::RecordReader;
+use crate::source::MessageSource;
+use crate::vocabulary::Command;
+
+
+/// Statistics accumulated over the records of a single run.
+#[derive(Debug)]
+struct RunSummary {
+    name: String,
+    records: u32,
+    first: i64,
+    last: i64,
+    message_sec: f32,
+    command_sec: f32,
+    peak_command_sec: f32,
+    /// Shares of each command, weighted by the commands per second.
+    shares: [f32; Command::ALL.len()],
+    new_voters: u64,
+    returning_voters: u64,
+    /// Messages and commands of each source, for logs having them.
+    sources: [(u64, u64); MessageSource::ALL.len()],
+    /// Messages at each stage of the vote pipeline, for logs having them:
+    /// received, candidates, accepted, counted and weighted.
+    funnel: [f64; 5],
+    /// Chatter messages, exclamation marks, messages in capitals and
+    /// emotes, for logs having them.
+    hype: [u64; 4],
+    /// Subscriptions, resubscriptions, gifts, raids, raiders, announcements
+    /// and raid messages, for logs having them.
+    notices: [u64; 7],
+    /// Moves executed by the game and moves matching the last command sent
+    /// by the bot, for logs having them.
+    moves: [u64; 2],
+    /// Sessions and connections of the bot writing the records, for logs
+    /// having them, to tell the restarts and the reconnections apart.
+    sessions: Vec<(String, u32)>,
+}
+
+impl RunSummary {
+
+    fn new(name: &str, time: i64) -> Self {
+        Self {
+            name: name.to_string(),
+            records: 0,
+            first: time,
+            last: time,
+            message_sec: 0.0,
+            command_sec: 0.0,
+            peak_command_sec: 0.0,
+            shares: [0.0; Command::ALL.len()],
+            new_voters: 0,
+            returning_voters: 0,
+            sources: Default::default(),
+            funnel: [0.0; 5],
+            hype: [0; 4],
+            notices: [0; 7],
+            moves: [0; 2],
+            sessions: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, time: i64, values: &[f32], sources: &[(u64, u64)], funnel: &[f64], hype: &[u64], notices: &[u64]) {
+        self.records += 1;
+        self.first = self.first.min(time);
+        self.last = self.last.max(time);
+        self.message_sec += values[1];
+        self.command_sec += values[2];
+        self.peak_command_sec = self.peak_command_sec.max(values[2]);
+        for (share, value) in self.shares.iter_mut().zip(&values[3..3 + Command::BASE_COUNT]) {
+            *share += value * values[2];
+        }
+        self.new_voters += values[14] as u64;
+        self.returning_voters += values[15] as u64;
+        for (total, (messages, commands)) in self.sources.iter_mut().zip(sources) {
+            total.0 += messages;
+            total.1 += commands;
+        }
+        for (total, count) in self.funnel.iter_mut().zip(funnel) {
+            *total += count;
+        }
+        for (total, count) in self.hype.iter_mut().zip(hype) {
+            *total += count;
+        }
+        for (total, count) in self.notices.iter_mut().zip(notices) {
+            *total += count;
+        }
+    }
+
+    /// Add the shares of the extended buttons, logged after the other
+    /// columns.
+    fn push_extended(&mut self, command_sec: f32, shares: &[f32]) {
+        for (share, value) in self.shares[Command::BASE_COUNT..].iter_mut().zip(shares) {
+            *share += value * command_sec;
+        }
+    }
+
+    fn push_moves(&mut self, moves: &[u64]) {
+        for (total, count) in self.moves.iter_mut().zip(moves) {
+            *total += count;
+        }
+    }
+
+    fn push_session(&mut self, id: &str, connection: u32) {
+        if !self.sessions.iter().any(|(other_id, other_connection)| other_id == id && *other_connection == connection) {
+            self.sessions.push((id.to_string(), connection));
+        }
+    }
+
+    fn merge(&mut self, other: &Self) {
+        self.records += other.records;
+        self.first = self.first.min(other.first);
+        self.last = self.last.max(other.last);
+        self.message_sec += other.message_sec;
+        self.command_sec += other.command_sec;
+        self.peak_command_sec = self.peak_command_sec.max(other.peak_command_sec);
+        for (share, other_share) in self.shares.iter_mut().zip(other.shares) {
+            *share += other_share;
+        }
+        self.new_voters += other.new_voters;
+        self.returning_voters += other.returning_voters;
+        for (total, (messages, commands)) in self.sources.iter_mut().zip(other.sources) {
+            total.0 += messages;
+            total.1 += commands;
+        }
+        for (total, count) in self.funnel.iter_mut().zip(other.funnel) {
+            *total += count;
+        }
+        for (total, count) in self.hype.iter_mut().zip(other.hype) {
+            *total += count;
+        }
+        for (total, count) in self.notices.iter_mut().zip(other.notices) {
+            *total += count;
+        }
+        for (total, count) in self.moves.iter_mut().zip(other.moves) {
+            *total += count;
+        }
+        for (id, connection) in &other.sessions {
+            self.push_session(id, *connection);
+        }
+    }
+
+    /// Command ratio of each source, none for sources without messages.
+    fn source_ratios(&self) -> impl Iterator<Item = (MessageSource, u64, Option<f32>)> + '_ {
+        MessageSource::ALL.into_iter().zip(self.sources).map(|(source, (messages, commands))| {
+            (source, messages, (messages > 0).then(|| commands as f32 / messages as f32))
+        })
+    }
+
+    /// The metrics compared between two summaries, with their decimals.
+    fn metrics(&self) -> Vec<(String, f32, usize)> {
+        let records = self.records.max(1) as f32;
+        let mut metrics = vec![
+            ("records".to_string(), self.records as f32, 0),
+            ("msg/s".to_string(), self.message_sec / records, 2),
+            ("cmd/s".to_string(), self.command_sec / records, 2),
+            ("peak cmd/s".to_string(), self.peak_command_sec, 2),
+            ("cmd/msg".to_string(), if self.message_sec > 0.0 { self.command_sec / self.message_sec } else { 0.0 }, 2),
+            ("new voters".to_string(), self.new_voters as f32, 0),
+            ("returning voters".to_string(), self.returning_voters as f32, 0),
+        ];
+        for (command, share) in Command::ALL.iter().zip(self.shares) {
+            let share = if self.command_sec > 0.0 { share / self.command_sec * 100.0 } else { 0.0 };
+            metrics.push((format!("{} %", command.name()), share, 1));
+        }
+        for (source, _, ratio) in self.source_ratios() {
+            metrics.push((format!("cmd/msg {}", source.name()), ratio.unwrap_or(0.0), 2));
+        }
+        metrics
+    }
+
+    fn write(&self, out: &mut impl Write, nf: &NumberFormat) -> io::Result<()> {
+
+        let records = self.records.max(1) as f32;
+        writeln!(out, "run '{}'", self.name)?;
+        writeln!(out, "  period:     {} to {}", format_time(self.first), format_time(self.last))?;
+        writeln!(out, "  records:    {}", nf.int(self.records as i64))?;
+        writeln!(out, "  msg/s:      {}", nf.float(self.message_sec / records, 2))?;
+        writeln!(out, "  cmd/s:      {} (peak {})", nf.float(self.command_sec / records, 2), nf.float(self.peak_command_sec, 2))?;
+        if self.message_sec > 0.0 {
+            writeln!(out, "  cmd/msg:    {}", nf.float(self.command_sec / self.message_sec, 2))?;
+        }
+        writeln!(out, "  voters:     {} new, {} returning", nf.int(self.new_voters as i64), nf.int(self.returning_voters as i64))?;
+
+        if self.command_sec > 0.0 {
+            let mut shares = Command::ALL.iter().zip(self.shares).collect::<Vec<_>>();
+            shares.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+            let shares = shares.iter()
+                .filter(|(_, share)| *share > 0.0)
+                .take(5)
+                .map(|(command, share)| format!("{} {}%", command.name(), nf.float(share / self.command_sec * 100.0, 0)))
+                .collect::<Vec<_>>();
+            writeln!(out, "  commands:   {}", shares.join(", "))?;
+        }
+
+        let sources = self.source_ratios()
+            .filter_map(|(source, messages, ratio)| Some(format!("{} {} cmd/msg ({} msg)",
+                source.name(), nf.float(ratio?, 2), nf.int(messages as i64))))
+            .collect::<Vec<_>>();
+        if !sources.is_empty() {
+            writeln!(out, "  sources:    {}", sources.join(", "))?;
+        }
+
+        // Each stage is given as a share of the received messages.
+        let [received, candidates, accepted, counted, weighted] = self.funnel;
+        if received > 0.0 {
+            let percent = |count: f64| nf.float((count / received * 100.0) as f32, 1);
+            writeln!(out, "  funnel:     {} received, {}% candidates, {}% accepted, {}% counted, {} weighted",
+                nf.int(received as i64), percent(candidates), percent(accepted), percent(counted), nf.float(weighted as f32, 0))?;
+        }
+
+        let [messages, exclamations, caps, emotes] = self.hype;
+        if messages > 0 {
+            let per_message = |count: u64| count as f32 / messages as f32;
+            writeln!(out, "  hype:       {} !/msg, {}% caps, {} emotes/msg ({} chatter msg)",
+                nf.float(per_message(exclamations), 2), nf.float(per_message(caps) * 100.0, 1), nf.float(per_message(emotes), 2), nf.int(messages as i64))?;
+        }
+
+        let [subs, resubs, gifts, raids, raiders, announcements, raid_messages] = self.notices;
+        if self.notices.iter().any(|&count| count > 0) {
+            writeln!(out, "  notices:    {} subs, {} resubs, {} gifts, {} raids of {} viewers ({} raid msg), {} announcements",
+                nf.int(subs as i64), nf.int(resubs as i64), nf.int(gifts as i64), nf.int(raids as i64),
+                nf.int(raiders as i64), nf.int(raid_messages as i64), nf.int(announcements as i64))?;
+        }
+
+        let [moves, matched_moves] = self.moves;
+        if moves > 0 {
+            writeln!(out, "  moves:      {} executed, {}% as sent by the bot",
+                nf.int(moves as i64), nf.float(matched_moves as f32 / moves as f32 * 100.0, 1))?;
+        }
+
+        if !self.sessions.is_empty() {
+            let mut ids = self.sessions.iter().map(|(id, _)| id).collect::<Vec<_>>();
+            ids.sort();
+            ids.dedup();
+            writeln!(out, "  sessions:   {} ({} connections)", nf.int(ids.len() as i64), nf.int(self.sessions.len() as i64))?;
+        }
+
+        Ok(())
+
+    }
+
+}
+
+
+fn format_time(time: i64) -> String {
+    DateTime::from_timestamp(time, 0)
+        .map(|time| time.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_default()
+}
+
+
+/// Parse a local time like `2024-01-01 12:00`, or a date for its midnight,
+/// as a timestamp.
+pub fn parse_time(text: &str) -> Result<i64, String> {
+
+    let time = NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M")
+        .or_else(|_| NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%M"))
+        .or_else(|_| NaiveDate::parse_from_str(text, "%Y-%m-%d").map(|date| date.and_hms_opt(0, 0, 0).unwrap()))
+        .map_err(|_| format!("invalid time '{text}', expected 'YYYY-MM-DD' or 'YYYY-MM-DD HH:MM'"))?;
+
+    time.and_local_timezone(Local).earliest()
+        .map(|time| time.timestamp())
+        .ok_or_else(|| format!("time '{text}' doesn't exist in the local time zone"))
+
+}
+
+
+/// The records of a log to analyze: all of them, or only those of a run or
+/// of a time range.
+#[derive(Debug, Clone)]
+pub struct Selection {
+    pub path: PathBuf,
+    pub run: Option<String>,
+    /// Start and end timestamps, the end is excluded.
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+}
+
+impl Selection {
+
+    /// Read the summary of each run of the selected records, returning the
+    /// number of invalid lines.
+    fn read(&self) -> io::Result<(Vec<RunSummary>, u32)> {
+        self.read_from(BufReader::new(File::open(&self.path)?))
+    }
+
+    /// Read the summary of each run of the selected records of the given
+    /// log, instead of the file of the selection.
+    fn read_from(&self, log: impl BufRead) -> io::Result<(Vec<RunSummary>, u32)> {
+
+        let mut summaries = Vec::<RunSummary>::new();
+        let mut invalid = 0;
+        let mut reader = RecordReader::default();
+
+        // The shares of all the buttons come first, then the voters.
+        let voters_start = 3 + Command::ALL.len();
+
+        for line in log.lines() {
+
+            // Logs in any format and version are read, headers are skipped.
+            let Some(columns) = reader.parse(&line?) else { continue };
+            let columns = columns.iter().map(String::as_str).collect::<Vec<_>>();
+
+            // Logs written before runs existed have no run column.
+            let name = columns.get(voters_start + 2).copied().unwrap_or("-");
+            if self.run.as_deref().is_some_and(|run| run != name) {
+                continue;
+            }
+
+            // The rates, the shares of the base buttons and the voters.
+            let values = columns.iter().take(3 + Command::BASE_COUNT)
+                .chain(columns.get(voters_start..voters_start + 2).unwrap_or_default())
+                .map(|value| value.parse::<f32>())
+                .collect::<Result<Vec<_>, _>>();
+            let (Ok(time), Ok(values)) = (columns[0].parse::<i64>(), values) else {
+                invalid += 1;
+                continue;
+            };
+            if values.len() < 16 {
+                invalid += 1;
+                continue;
+            }
+
+            if self.from.is_some_and(|from| time < from) || self.to.is_some_and(|to| time >= to) {
+                continue;
+            }
+
+            // Records of a run are not always contiguous, when runs are resumed.
+            let summary = match summaries.iter().position(|summary| summary.name == name) {
+                Some(index) => &mut summaries[index],
+                None => {
+                    summaries.push(RunSummary::new(name, time));
+                    summaries.last_mut().unwrap()
+                }
+            };
+
+            // Older logs don't have the extended buttons columns.
+            let extended = columns.get(3 + Command::BASE_COUNT..voters_start)
+                .and_then(|columns| columns.iter().map(|value| value.parse().ok()).collect::<Option<Vec<_>>>())
+                .unwrap_or_default();
+            summary.push_extended(values[2], &extended);
+
+            // Older logs don't have the source columns.
+            let sources_start = voters_start + 5;
+            let sources = columns.get(sources_start..sources_start + 2 * MessageSource::ALL.len())
+                .and_then(|columns| columns.chunks(2)
+                    .map(|pair| Some((pair[0].parse().ok()?, pair[1].parse().ok()?)))
+                    .collect::<Option<Vec<_>>>())
+                .unwrap_or_default();
+
+            // Older logs don't have the funnel columns.
+            let funnel_start = sources_start + 2 * MessageSource::ALL.len();
+            let funnel = columns.get(funnel_start..funnel_start + 5)
+                .and_then(|columns| columns.iter().map(|value| value.parse().ok()).collect::<Option<Vec<_>>>())
+                .unwrap_or_default();
+
+            // Older logs don't have the hype columns.
+            let hype_start = funnel_start + 5;
+            let hype = columns.get(hype_start..hype_start + 4)
+                .and_then(|columns| columns.iter().map(|value| value.parse().ok()).collect::<Option<Vec<_>>>())
+                .unwrap_or_default();
+
+            // Older logs don't have the notice columns.
+            let notices_start = hype_start + 4;
+            let notices = columns.get(notices_start..notices_start + 7)
+                .and_then(|columns| columns.iter().map(|value| value.parse().ok()).collect::<Option<Vec<_>>>())
+                .unwrap_or_default();
+
+            summary.push(time, &values, &sources, &funnel, &hype, &notices);
+
+            // Older logs don't have the move columns.
+            let moves_start = notices_start + 7;
+            let moves = columns.get(moves_start..moves_start + 2)
+                .and_then(|columns| columns.iter().map(|value| value.parse().ok()).collect::<Option<Vec<_>>>())
+                .unwrap_or_default();
+            summary.push_moves(&moves);
+
+            // Older logs and imported records don't have a session.
+            let session_start = moves_start + 2;
+            if let (Some(&id), Some(Ok(connection))) = (columns.get(session_start), columns.get(session_start + 1).map(|value| value.parse())) {
+                if id != "-" {
+                    summary.push_session(id, connection);
+                }
+            }
+
+        }
+
+        Ok((summaries, invalid))
+
+    }
+
+    /// Describe the selection in the legend of a comparison.
+    fn describe(&self) -> String {
+        let mut text = format!("{}", self.path.display());
+        if let Some(run) = &self.run {
+            text.push_str(&format!(", run '{run}'"));
+        }
+        if let Some(from) = self.from {
+            text.push_str(&format!(", from {}", format_time(from)));
+        }
+        if let Some(to) = self.to {
+            text.push_str(&format!(", to {}", format_time(to)));
+        }
+        text
+    }
+
+}
+
+
+/// Write the summary of each selected run.
+pub fn report(selection: &Selection, out: &mut impl Write, nf: &NumberFormat) -> io::Result<()> {
+    write_report(selection.read()?, out, nf)
+}
+
+/// Write the summary of each selected run of records already read, like
+/// the records of several log files.
+pub fn report_records(selection: &Selection, records: &[u8], out: &mut impl Write, nf: &NumberFormat) -> io::Result<()> {
+    write_report(selection.read_from(records)?, out, nf)
+}
+
+fn write_report((summaries, invalid): (Vec<RunSummary>, u32), out: &mut impl Write, nf: &NumberFormat) -> io::Result<()> {
+
+    if invalid > 0 {
+        writeln!(out, "{} invalid lines skipped", nf.int(invalid as i64))?;
+    }
+    if summaries.is_empty() {
+        writeln!(out, "no records")?;
+    }
+    for summary in &summaries {
+        summary.write(out, nf)?;
+    }
+
+    Ok(())
+
+}
+
+/// Entry point of the `analyze` subcommand, summarizing each selected run,
+/// or comparing two selections side by side.
+pub fn main(selection: &Selection, compare: Option<&Selection>, nf: &NumberFormat) -> io::Result<()> {
+
+    let Some(compare) = compare else {
+        return report(selection, &mut io::stdout().lock(), nf);
+    };
+
+    let (summaries, invalid) = selection.read()?;
+    if invalid > 0 {
+        println!("{} invalid lines skipped", nf.int(invalid as i64));
+    }
+
+    let (compare_summaries, compare_invalid) = compare.read()?;
+    if compare_invalid > 0 {
+        println!("{} invalid lines skipped in the comparison", nf.int(compare_invalid as i64));
+    }
+
+    // Each side is summarized as a whole, whatever its runs.
+    let merge = |summaries: &[RunSummary]| {
+        let mut merged = RunSummary::new("*", summaries.first().map(|summary| summary.first).unwrap_or_default());
+        for summary in summaries {
+            merged.merge(summary);
+        }
+        merged
+    };
+
+    let a = merge(&summaries);
+    let b = merge(&compare_summaries);
+
+    println!("A: {}", selection.describe());
+    println!("B: {}", compare.describe());
+    println!();
+    println!("{:18}{:>14}{:>14}{:>10}", "", "A", "B", "change");
+
+    for ((name, a_value, decimals), (_, b_value, _)) in a.metrics().into_iter().zip(b.metrics()) {
+        let change = if a_value != 0.0 {
+            format!("{}{}%", if b_value >= a_value { "+" } else { "" }, nf.float((b_value - a_value) / a_value * 100.0, 0))
+        } else {
+            "-".to_string()
+        };
+        println!("{name:18}{:>14}{:>14}{change:>10}", nf.float(a_value, decimals), nf.float(b_value, decimals));
+    }
+
+    Ok(())
+
+}
@@ -0,0 +1,100 @@
+use std::time::Instant;
+use std::path::Path;
+use std::io;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::{json, Value};
+
+use crate::irc::IrcReplyRef;
+use crate::logfile::LogFile;
+use crate::vocabulary::Command;
+
+
+/// Archive of every chat message of a channel, one JSON object per line,
+/// for analyzing the chat later with pandas or jq. The records can be
+/// imported, replayed and backtested as JSON logs. Like the log, the
+/// messages are kept in memory while the file fails.
+#[derive(Debug)]
+pub struct ChatArchive {
+    file: LogFile,
+}
+
+impl ChatArchive {
+
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Ok(Self { file: LogFile::open(path, Vec::new())? })
+    }
+
+    /// Append a chat message with the command it matched, if any.
+    pub fn write(&mut self, time: DateTime<Utc>, reply: &IrcReplyRef, command: Option<Command>) {
+        let mut line = record(time, reply, command).to_string();
+        line.push('\n');
+        self.file.write_record(Instant::now(), line.into_bytes());
+    }
+
+    /// Write the messages kept in memory, at the end of the session.
+    pub fn write_pending(&mut self) {
+        self.file.write_pending(Instant::now());
+    }
+
+}
+
+
+/// The record of a chat message, like `{"timestamp":
+/// "2024-03-12T12:00:00.123Z", "channel": "chan", "username": "foo",
+/// "user_id": "1", "badges": ["subscriber/12"], "text": "a", "command":
+/// "a", "first_msg": false}`.
+fn record(time: DateTime<Utc>, reply: &IrcReplyRef, command: Option<Command>) -> Value {
+
+    let badges = reply.tag("badges").unwrap_or("")
+        .split(',')
+        .filter(|badge| !badge.is_empty())
+        .collect::<Vec<_>>();
+
+    json!({
+        "timestamp": time.to_rfc3339_opts(SecondsFormat::Millis, true),
+        "channel": reply.target().and_then(|target| target.strip_prefix('#')),
+        "username": reply.sender().and_then(|sender| sender.nickname),
+        "user_id": reply.tag("user-id"),
+        "badges": badges,
+        "text": reply.text(),
+        "command": command.map(Command::name),
+        "first_msg": reply.tag("first-msg") == Some("1"),
+    })
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use crate::import::{self, ImportFormat, TextZone};
+
+    use super::*;
+
+    #[test]
+    fn records() {
+
+        let time = DateTime::from_timestamp_millis(1_700_000_000_123).unwrap();
+        let reply = IrcReplyRef::parse("@badges=subscriber/12,premium/1;first-msg=1;user-id=42 :foo!foo@foo.tmi.twitch.tv PRIVMSG #chan :a").unwrap();
+        let value = record(time, &reply, Some(Command::A));
+
+        assert_eq!(value, json!({
+            "timestamp": "2023-11-14T22:13:20.123Z",
+            "channel": "chan",
+            "username": "foo",
+            "user_id": "42",
+            "badges": ["subscriber/12", "premium/1"],
+            "text": "a",
+            "command": "a",
+            "first_msg": true,
+        }));
+
+        // The archive is read back as a JSON log.
+        let events = import::read_events(ImportFormat::Json, format!("{value}\n").as_bytes(), None, TextZone::Utc).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!((events[0].time, events[0].user.as_str(), events[0].text.as_str(), events[0].first_msg), (time, "42", "a", true));
+
+    }
+
+}
@@ -0,0 +1,106 @@
+use std::time::{Duration, Instant};
+
+use crate::config::parse_seconds;
+
+
+/// Thresholds on the command rate and ratio that must be sustained
+/// for some duration.
+#[derive(Debug, Clone)]
+pub struct Band {
+    pub cmd_sec: f32,
+    pub ratio: f32,
+    pub duration: Duration,
+}
+
+impl Band {
+
+    /// Parse a band from a `cmd/s,ratio,seconds` string.
+    pub fn parse(text: &str) -> Result<Self, String> {
+
+        let mut parts = text.split(',').map(str::trim);
+
+        let (Some(cmd_sec), Some(ratio), Some(secs), None) = (parts.next(), parts.next(), parts.next(), parts.next()) else {
+            return Err(format!("expected 'cmd/s,ratio,seconds', got '{text}'"));
+        };
+
+        Ok(Self {
+            cmd_sec: cmd_sec.parse().map_err(|e| format!("invalid cmd/s: {e}"))?,
+            ratio: ratio.parse().map_err(|e| format!("invalid ratio: {e}"))?,
+            duration: parse_seconds(secs).map_err(|e| format!("invalid seconds: {e}"))?,
+        })
+
+    }
+
+}
+
+
+/// Automatically enable the bot mode when the chat is actively playing
+/// and disable it when the run clearly ends. Two distinct bands are used
+/// in order to have some hysteresis between the two states.
+#[derive(Debug, Clone)]
+pub struct AutoBot {
+    /// Rates must be above this band to enable the bot.
+    enable: Band,
+    /// Rates must be below this band to disable the bot.
+    disable: Band,
+    /// Time since the rates are crossing the band of the current state.
+    since: Option<Instant>,
+}
+
+impl AutoBot {
+
+    pub fn new(enable: Band, disable: Band) -> Self {
+        Self {
+            enable,
+            disable,
+            since: None,
+        }
+    }
+
+    /// Update with the current rates and the current bot state, returning
+    /// the new state if it needs to be switched.
+    pub fn update(&mut self, bot: bool, cmd_sec: f32, ratio: f32) -> Option<bool> {
+
+        let (crossing, duration) = if bot {
+            (cmd_sec < self.disable.cmd_sec || ratio < self.disable.ratio, self.disable.duration)
+        } else {
+            (cmd_sec >= self.enable.cmd_sec && ratio >= self.enable.ratio, self.enable.duration)
+        };
+
+        if !crossing {
+            self.since = None;
+            return None;
+        }
+
+        let since = *self.since.get_or_insert_with(Instant::now);
+        if since.elapsed() >= duration {
+            self.since = None;
+            Some(!bot)
+        } else {
+            None
+        }
+
+    }
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn band() {
+
+        let band = Band::parse("2, 0.6, 1.5").unwrap();
+        assert_eq!((band.cmd_sec, band.ratio, band.duration), (2.0, 0.6, Duration::from_millis(1500)));
+
+        assert!(Band::parse("2,0.6,-5").unwrap_err().starts_with("invalid seconds"));
+        assert!(Band::parse("2,0.6,inf").unwrap_err().starts_with("invalid seconds"));
+        assert!(Band::parse("2,0.6,NaN").unwrap_err().starts_with("invalid seconds"));
+        assert!(Band::parse("2,0.6").is_err());
+
+    }
+
+}
@@ -0,0 +1,243 @@
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use rand::rngs::StdRng;
+
+use crate::config::Settings;
+use crate::curve::IntervalCurve;
+use crate::import::{self, ChatEvent, ImportFormat, TextZone};
+use crate::logformat::RecordFormat;
+use crate::policy::CommandPolicy;
+use crate::ratelimit::RateTier;
+use crate::rng;
+use crate::seal;
+use crate::shadow::{ShadowConfig, Strategy};
+use crate::stats::Stats;
+use crate::trigger::TriggerConfig;
+use crate::vocabulary::{Command, Vocabulary};
+
+
+/// Number of commands listed in the summary of each strategy.
+const TOP_COMMANDS: usize = 3;
+
+
+/// Results of a strategy over a recorded chat.
+#[derive(Debug, Clone)]
+pub struct StrategyReport {
+    pub name: String,
+    pub decisions: u32,
+    /// Decisions whose command was still the most used one once a full TPP
+    /// window had passed, as evaluated by the shadow strategies.
+    pub hits: u32,
+    /// Number of decisions of each command.
+    pub commands: [u32; Command::ALL.len()],
+}
+
+impl StrategyReport {
+
+    pub fn hit_rate(&self) -> f32 {
+        if self.decisions == 0 { 0.0 } else { self.hits as f32 / self.decisions as f32 }
+    }
+
+    /// The commands decided the most, with their number of decisions.
+    pub fn top_commands(&self) -> Vec<(Command, u32)> {
+        let mut commands = Command::ALL.iter()
+            .map(|&command| (command, self.commands[command.index()]))
+            .filter(|&(_, count)| count > 0)
+            .collect::<Vec<_>>();
+        commands.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+        commands.truncate(TOP_COMMANDS);
+        commands
+    }
+
+}
+
+
+/// Entry point of the `backtest` subcommand: the recorded chat is run
+/// through the sampling engine at full speed, the live strategy and the
+/// shadow strategies of the settings deciding on it. The TSV log of the
+/// stats is written to the output, if any, and the results of each strategy
+/// are printed.
+pub fn main(format: ImportFormat, input: &Path, output: Option<&Path>, date: Option<NaiveDate>, settings: &Settings) -> io::Result<()> {
+
+    let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidData, e);
+
+    // Sealed logs are read transparently.
+    let events = import::read_events(format, seal::open(input, settings.get("seal_passphrase"))?, date, TextZone::Utc)?;
+
+    let vocabulary = Vocabulary::from_settings(settings).map_err(invalid)?;
+    let policy = CommandPolicy::from_settings(settings).map_err(invalid)?;
+    let trigger = TriggerConfig::from_settings(settings).map_err(invalid)?;
+
+    // The live strategy only uses the thresholds, not the send condition.
+    let interval_curve = match settings.get("interval_curve_path") {
+        Some(path) => IntervalCurve::load(Path::new(path))?,
+        None => IntervalCurve::default(),
+    };
+    let mut strategies = vec![Strategy {
+        min_ratio: trigger.min_ratio,
+        min_cmd_sec: trigger.min_cmd_sec,
+        ..Strategy::live(interval_curve)
+    }];
+    if let Some(shadow) = settings.parse_with("shadow_path", |path| ShadowConfig::load(Path::new(path))).map_err(invalid)? {
+        strategies.extend(shadow.strategies);
+    }
+
+    let verified = settings.parse::<bool>("verified").map_err(invalid)?.unwrap_or(false);
+    let min_interval = if verified { RateTier::Verified } else { RateTier::User }.min_interval();
+    // Backtests are reproducible unless another seed is given.
+    let seed = settings.parse::<u64>("seed").map_err(invalid)?.unwrap_or(0);
+
+    let record_format = RecordFormat::from_settings(settings).map_err(invalid)?;
+    let mut output = output.map(|path| record_format.open(path)).transpose()?;
+
+    let stats = Stats::new().with_command_policy(policy, rng::derive(seed, "ties#backtest"));
+    let mut rng = rng::derive(seed, "backtest");
+    let reports = backtest(&events, &vocabulary, stats, &strategies, min_interval, &mut rng, |stats, time| {
+        match &mut output {
+            Some(output) => stats.write_log(output, time, "backtest", record_format),
+            None => Ok(()),
+        }
+    })?;
+
+    if let Some(mut output) = output {
+        output.flush()?;
+    }
+
+    match (events.first(), events.last()) {
+        (Some(first), Some(last)) => {
+            let minutes = (last.time - first.time).num_seconds() as f32 / 60.0;
+            println!("backtested {} messages over {minutes:.1} minutes", events.len());
+        }
+        _ => println!("no message to backtest"),
+    }
+
+    for report in &reports {
+        let top = report.top_commands().iter()
+            .map(|(command, count)| format!("{}:{count}", command.name()))
+            .collect::<Vec<_>>()
+            .join(",");
+        println!("{}\t{} decisions\t{} hits\t{:.1}%\t{top}",
+            report.name,
+            report.decisions,
+            report.hits,
+            report.hit_rate() * 100.0);
+    }
+
+    Ok(())
+
+}
+
+
+/// Run the strategies over the recorded chat events, with the given stats
+/// and minimum interval between two decisions of a strategy. The given
+/// function is called with the stats every time a log record is due.
+pub fn backtest<F>(
+    events: &[ChatEvent],
+    vocabulary: &Vocabulary,
+    stats: Stats,
+    strategies: &[Strategy],
+    min_interval: Duration,
+    rng: &mut StdRng,
+    mut on_record: F,
+) -> io::Result<Vec<StrategyReport>>
+where
+    F: FnMut(&mut Stats, DateTime<Utc>) -> io::Result<()>,
+{
+
+    let mut reports = strategies.iter()
+        .map(|strategy| StrategyReport {
+            name: strategy.name.clone(),
+            decisions: 0,
+            hits: 0,
+            commands: [0; Command::ALL.len()],
+        })
+        .collect::<Vec<_>>();
+
+    // Time of the next decision of each strategy.
+    let mut next_times = vec![None::<DateTime<Utc>>; strategies.len()];
+    // Decisions waiting to be evaluated: time, strategy index and command.
+    let mut pending = VecDeque::<(DateTime<Utc>, usize, Command)>::new();
+
+    import::simulate_samples(events, vocabulary, stats, |stats, time, log_due| {
+
+        if log_due {
+            on_record(stats, time)?;
+        }
+
+        for (index, strategy) in strategies.iter().enumerate() {
+            if next_times[index].is_some_and(|next_time| time < next_time) {
+                continue;
+            }
+            if let Some((command, interval)) = strategy.decide(stats, rng) {
+                pending.push_back((time, index, command));
+                next_times[index] = Some(time + chrono::Duration::from_std(interval.max(min_interval)).unwrap());
+            }
+        }
+
+        let tpp_duration = chrono::Duration::from_std(stats.tpp_duration()).unwrap();
+        while let Some(&(decision_time, index, command)) = pending.front() {
+            if time - decision_time < tpp_duration {
+                break;
+            }
+            pending.pop_front();
+            let report = &mut reports[index];
+            report.decisions += 1;
+            report.hits += (stats.most_used() == command) as u32;
+            report.commands[command.index()] += 1;
+        }
+
+        Ok(())
+
+    })?;
+
+    Ok(reports)
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use rand::SeedableRng;
+
+    use super::*;
+
+    #[test]
+    fn strategies() {
+
+        let start = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        // A steady chat of 5 messages per second, mostly voting a, for 2
+        // minutes.
+        let events = (0..600)
+            .map(|i| ChatEvent {
+                time: start + chrono::Duration::milliseconds(i * 200),
+                user: format!("user{}", i % 20),
+                text: if i % 5 == 0 { "b" } else { "a" }.to_string(),
+                first_msg: false,
+            })
+            .collect::<Vec<_>>();
+
+        let live = Strategy::live(IntervalCurve::default());
+        let strict = Strategy { name: "strict".to_string(), min_cmd_sec: 10.0, ..live.clone() };
+
+        let mut records = 0;
+        let mut rng = StdRng::seed_from_u64(0);
+        let reports = backtest(&events, &Vocabulary::default(), Stats::new(), &[live, strict], Duration::from_secs(2), &mut rng, |_, _| {
+            records += 1;
+            Ok(())
+        }).unwrap();
+
+        assert!(records > 100);
+        assert!(reports[0].decisions > 10);
+        assert_eq!(reports[0].hits, reports[0].decisions);
+        assert_eq!(reports[0].top_commands(), vec![(Command::A, reports[0].decisions)]);
+        assert_eq!(reports[1].decisions, 0);
+        assert_eq!(reports[1].hit_rate(), 0.0);
+
+    }
+
+}
@@ -0,0 +1,206 @@
+use std::io::{self, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::Instant;
+use std::fs::File;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::logfile::{FlushPolicy, LogFile, Rotation};
+use crate::sample::Sample;
+
+
+/// Header of a binary log, followed by its format version.
+const MAGIC: &[u8; 4] = b"TPPB";
+const VERSION: u8 = 1;
+
+
+/// Text format of a converted binary log.
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum BinaryLogFormat {
+    /// One tab-separated line per sample.
+    Tsv,
+    /// One JSON object per sample and per line.
+    Json,
+}
+
+
+/// Raw counts of a single sample, as stored in the binary log.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BinaryRecord {
+    /// End time of the sample, in milliseconds.
+    pub timestamp_ms: i64,
+    pub duration_ms: u32,
+    pub messages: u16,
+    pub commands: u16,
+    /// Count of each command, in the order of [`Command::ALL`](crate::vocabulary::Command::ALL).
+    pub counts: Vec<u16>,
+    pub bits: u32,
+    pub redemptions: u16,
+}
+
+impl BinaryRecord {
+
+    /// The record of a single sample, whose counts fit in the record, they
+    /// are capped otherwise.
+    pub fn new(time: DateTime<Utc>, duration_ms: u32, sample: &Sample) -> Self {
+        let cap = |count: u32| u16::try_from(count).unwrap_or(u16::MAX);
+        Self {
+            timestamp_ms: time.timestamp_millis(),
+            duration_ms,
+            messages: cap(sample.message_count),
+            commands: cap(sample.tpp_command_count),
+            counts: sample.counts.iter().map(|(_, count)| cap(count)).collect(),
+            bits: sample.bits,
+            redemptions: cap(sample.redemptions),
+        }
+    }
+
+}
+
+
+/// A binary log of every sample of a channel, much cheaper to write than a
+/// text record, for the full resolution capture of the chat. The file is a
+/// header followed by the postcard encoding of each record.
+#[derive(Debug)]
+pub struct BinaryLog {
+    file: LogFile,
+}
+
+impl BinaryLog {
+
+    /// Open the binary log in append mode, writing its header if new.
+    pub fn open(path: &Path, rotation: Option<Rotation>, flush: FlushPolicy) -> io::Result<Self> {
+
+        let mut header = MAGIC.to_vec();
+        header.push(VERSION);
+
+        Ok(Self { file: LogFile::open(path, header)?.with_rotation(rotation).with_flush(flush) })
+
+    }
+
+    /// Append the record of a closed sample.
+    pub fn write(&mut self, now: Instant, record: &BinaryRecord) {
+        // Encoding to a vector can't fail, all types are supported.
+        self.file.write_record(now, postcard::to_stdvec(record).unwrap());
+    }
+
+    /// Write the records that couldn't be written yet.
+    pub fn write_pending(&mut self, now: Instant) {
+        self.file.write_pending(now);
+    }
+
+}
+
+
+/// Read all the records of a binary log.
+pub fn read(mut input: impl Read) -> io::Result<Vec<BinaryRecord>> {
+
+    let mut data = Vec::new();
+    input.read_to_end(&mut data)?;
+
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+
+    let mut rest = match data.split_first_chunk::<4>() {
+        Some((magic, [version, rest @ ..])) if magic == MAGIC => {
+            if *version != VERSION {
+                return Err(invalid(format!("unsupported binary log version {version}")));
+            }
+            rest
+        }
+        _ => return Err(invalid("not a binary log".to_string())),
+    };
+
+    let mut records = Vec::new();
+    while !rest.is_empty() {
+        let (record, next) = postcard::take_from_bytes::<BinaryRecord>(rest)
+            .map_err(|e| invalid(format!("record {}: {e}", records.len() + 1)))?;
+        records.push(record);
+        rest = next;
+    }
+
+    Ok(records)
+
+}
+
+/// Write the records in the given text format.
+pub fn convert(records: &[BinaryRecord], format: BinaryLogFormat, out: &mut impl Write) -> io::Result<()> {
+
+    for record in records {
+        match format {
+            BinaryLogFormat::Tsv => {
+                write!(out, "{}\t{}\t{}\t{}", record.timestamp_ms, record.duration_ms, record.messages, record.commands)?;
+                for count in &record.counts {
+                    write!(out, "\t{count}")?;
+                }
+                writeln!(out, "\t{}\t{}", record.bits, record.redemptions)?;
+            }
+            BinaryLogFormat::Json => {
+                serde_json::to_writer(&mut *out, record)?;
+                writeln!(out)?;
+            }
+        }
+    }
+
+    Ok(())
+
+}
+
+
+/// Entry point of the `binlog` subcommand, converting a binary log to a text
+/// format, on the standard output if no output is given.
+pub fn main(input: &Path, output: Option<&Path>, format: BinaryLogFormat) -> io::Result<()> {
+
+    let records = read(File::open(input)?)?;
+
+    match output {
+        Some(output) => {
+            let mut output = BufWriter::new(File::create(output)?);
+            convert(&records, format, &mut output)?;
+            output.flush()
+        }
+        None => convert(&records, format, &mut io::stdout().lock()),
+    }
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use crate::vocabulary::Command;
+
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+
+        let mut sample = Sample::default();
+        sample.push_message(Some(Command::Up));
+        sample.push_message(Some(Command::A));
+        sample.push_message(None);
+        sample.bits = 100;
+
+        let time = DateTime::from_timestamp_millis(1_700_000_000_100).unwrap();
+        let record = BinaryRecord::new(time, 100, &sample);
+
+        let mut data = MAGIC.to_vec();
+        data.push(VERSION);
+        data.extend(postcard::to_stdvec(&record).unwrap());
+        data.extend(postcard::to_stdvec(&record).unwrap());
+        // Far smaller than the text record of the same sample.
+        assert!(data.len() < 2 * 40);
+
+        let records = read(&data[..]).unwrap();
+        assert_eq!(records, [record.clone(), record]);
+
+        let mut text = Vec::new();
+        convert(&records[..1], BinaryLogFormat::Tsv, &mut text).unwrap();
+        assert_eq!(String::from_utf8(text).unwrap(), "1700000000100\t100\t3\t2\t1\t0\t0\t0\t1\t0\t0\t0\t0\t0\t0\t0\t0\t0\t0\t100\t0\n");
+
+        assert!(read(&b"TPPB\x09"[..]).is_err());
+        assert!(read(&b"time\tmsg"[..]).is_err());
+
+    }
+
+}
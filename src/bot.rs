@@ -0,0 +1,1541 @@
+use std::borrow::Cow;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use std::io;
+use std::thread;
+
+use chrono::{DateTime, Local, SecondsFormat, TimeDelta, Utc};
+use rand::Rng;
+use serde_json::json;
+use tracing::{debug, error, info, info_span, warn};
+
+use crate::binlog::BinaryRecord;
+use crate::campaign::Campaign;
+use crate::channel::Channel;
+use crate::clock::ServerClock;
+use crate::config::{ChatSource, Config};
+use crate::console::{Console, ConsoleLine};
+use crate::control::{ControlCommand, Setting};
+use crate::curve::{CurveFile, IntervalCurve};
+use crate::democracy::Democracy;
+use crate::emergency;
+use crate::error::{TppError, TppResult};
+use crate::export::Exporter;
+use crate::funnel;
+use crate::http::HttpServer;
+use crate::hype;
+use crate::influx::Influx;
+use crate::irc::{IrcClient, IrcReplyCommand, IrcReplyRef, IrcWriter};
+use crate::journal::{Journal, JournalKind};
+use crate::latency::Latency;
+use crate::logformat::LogSession;
+use crate::lock::SendLock;
+use crate::overlay::{OverlayFile, OverlayServer};
+use crate::pause::Pauses;
+use crate::print_prompt;
+use crate::ratelimit::RateLimiter;
+use crate::reconnect::Reconnect;
+use crate::resolution::AdaptiveResolution;
+use crate::rng;
+use crate::rules::RuleAction;
+use crate::sample::Sample;
+use crate::script::{Script, ScriptInput};
+use crate::snapshot::SnapshotFile;
+use crate::source::MessageSource;
+use crate::stats::{GLOBAL_SAMPLE_DURATION, SAMPLE_DURATION};
+use crate::status::{ChannelStatus, StatusEmitter, StatusFormat};
+use crate::touch;
+use crate::tppbot::{Announcement, InputMode};
+use crate::transport::IrcTransport;
+use crate::trigger::TriggerMetrics;
+use crate::upload::Uploader;
+use crate::usernotice::UserNoticeKind;
+use crate::variation::Variation;
+use crate::vocabulary::{Command, Vocabulary};
+use crate::warmstart;
+use crate::watchdog::Watchdog;
+use crate::whisper::Whisperer;
+
+
+/// Set when a termination signal is received, the session then ends after
+/// logging its last samples.
+pub static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+
+/// How a connection ended without error.
+enum RunEnd {
+    /// The server requested a reconnection.
+    Reconnect,
+    /// A termination signal was received.
+    Shutdown,
+    /// The operator requested to reload the configuration, the line is
+    /// answered once reloaded.
+    Reload(ConsoleLine),
+}
+
+
+/// Interfaces of the bot to the operator and to the monitoring, kept
+/// across connections.
+struct Interfaces {
+    console: Console,
+    http: Option<HttpServer>,
+    overlay: Option<OverlayServer>,
+    influx: Option<Influx>,
+    whisperer: Option<Whisperer>,
+    /// Exporter of the aggregates to an HTTP endpoint.
+    exporter: Option<Exporter>,
+    /// Uploader of the logs and daily summaries.
+    uploader: Option<Uploader>,
+    /// File of the current state, for static overlays.
+    snapshot_file: Option<SnapshotFile>,
+    /// File of the pending commands, for an OBS text source.
+    overlay_file: Option<OverlayFile>,
+    /// Session of the log records, each run is a new connection.
+    log_session: LogSession,
+}
+
+
+/// Run a bot session with the given configuration, reconnecting until a
+/// termination signal is received. An error is returned if the
+/// authentication fails or if the reconnections are exhausted. The
+/// configuration is loaded again with the given function when the operator
+/// requests it.
+pub fn session(mut config: Config, reload: impl Fn() -> Result<Config, Vec<String>>) -> TppResult<()> {
+
+    let _session = info_span!("session", user = %config.user).entered();
+
+    let session_start = Utc::now();
+    let console = Console::spawn();
+    if let Some(address) = &config.control_socket {
+        console.listen(address)
+            .map_err(|e| TppError::Config(format!("failed to open the control socket: {e}")))?;
+    }
+    let http = config.http.as_ref().map(HttpServer::spawn).transpose().map_err(TppError::Config)?;
+    let overlay = config.overlay.clone().map(OverlayServer::spawn).transpose().map_err(TppError::Config)?;
+    let influx = config.influx.clone().map(Influx::open).transpose()
+        .map_err(|e| TppError::Config(format!("failed to open the influx metrics: {e}")))?;
+    // Identifies the records of this session in the logs.
+    let log_session = LogSession::new(session_start);
+    info!("log session {}", log_session.id);
+    let whisperer = config.whisper.clone().map(|whisper| Whisperer::spawn(whisper, &config.user, &config.token));
+    let exporter = config.export.clone().map(Exporter::spawn);
+    let uploader = config.upload.clone().map(|upload| Uploader::spawn(upload, config.number_format, session_start));
+    let snapshot_file = config.snapshot.clone().map(SnapshotFile::new);
+    let overlay_file = config.overlay_file.clone().map(OverlayFile::new);
+    let mut interfaces = Interfaces { console, http, overlay, influx, whisperer, exporter, uploader, snapshot_file, overlay_file, log_session };
+    let mut campaign = Campaign::open(config.run_path.clone())
+        .map_err(|e| TppError::Config(format!("failed to open the run file: {e}")))?;
+    let mut pauses = Pauses::open(config.pause_path.clone())
+        .map_err(|e| TppError::Config(format!("failed to open the pause file: {e}")))?;
+    let mut journal = config.journal_path.clone().map(Journal::open).transpose()
+        .map_err(|e| TppError::Config(format!("failed to open the journal: {e}")))?;
+
+    if let Some(run) = campaign.current() {
+        info!("continue run '{}'", run.name);
+    }
+
+    // Setting this seed reproduces the random choices of the session.
+    info!("random seed {}", config.seed);
+    if let Some(journal) = journal.as_mut() {
+        journal.record(session_start, JournalKind::Session, "*", &format!("seed={}", config.seed));
+    }
+
+    let watchdog = config.watchdog_timeout.map(Watchdog::spawn);
+
+    let mut reconnect = Reconnect::new(config.reconnect.clone(), rng::derive(config.seed, "reconnect"));
+
+    // Channels are kept across connections, so that their samples survive
+    // a reconnection.
+    let mut channels = config.channels.iter()
+        .map(|name| Channel::open(&config, name))
+        .collect::<io::Result<Vec<_>>>()
+        .map_err(|e| TppError::Config(format!("failed to open the channel files: {e}")))?;
+
+    // A restarted bot decides on the rates of its last log record, if recent.
+    if config.warm_start {
+        for channel in &mut channels {
+            match warmstart::read_last_record(channel.log_file.path(), Utc::now()) {
+                Ok(Some((age, sample))) => {
+                    info!(channel = %channel.name, "warm start from the log record of {}s ago", age.num_seconds());
+                    channel.stats.warm_start(&sample);
+                }
+                Ok(None) => info!(channel = %channel.name, "no recent log record, cold start"),
+                Err(e) => warn!(channel = %channel.name, "failed to read the log for a warm start: {e}"),
+            }
+        }
+    }
+
+    loop {
+
+        let connection_start = Instant::now();
+        let connection = info_span!("connection", attempt = reconnect.attempts() + 1);
+        interfaces.log_session.connection += 1;
+        match connection.in_scope(|| run(&config, &mut interfaces, watchdog.as_ref(), &mut channels, &mut campaign, &mut pauses, &mut journal)) {
+            // Reconnections requested by the server are immediate.
+            Ok(RunEnd::Reconnect) => continue,
+            Ok(RunEnd::Shutdown) => break,
+            Ok(RunEnd::Reload(line)) => {
+                line.answer(reload_config(&mut config, &reload, &mut channels));
+                continue;
+            }
+            // A replay is not restarted once its log has been read.
+            Err(e) if matches!(config.source, ChatSource::Replay(_)) => {
+                info!("replay finished: {e}");
+                break;
+            }
+            // Retrying would fail the same way.
+            Err(e) if e.is_fatal() => return Err(e),
+            // The reconnection delay lets the limit expire.
+            Err(TppError::RateLimited(text)) => warn!("rate limited by the server: {text}"),
+            Err(e) => warn!("connection lost: {e}"),
+        }
+
+        match reconnect.next_delay(connection_start.elapsed()) {
+            Some(delay) => {
+                info!("reconnect in {:.1}s, attempt {} ({} reconnections in total)",
+                    delay.as_secs_f32(), reconnect.attempts(), reconnect.total());
+                // Sleep by steps to stay responsive to termination signals.
+                let reconnect_time = Instant::now() + delay;
+                while Instant::now() < reconnect_time && !SHUTDOWN.load(Ordering::Relaxed) {
+                    thread::sleep(Duration::from_millis(100));
+                }
+                if SHUTDOWN.load(Ordering::Relaxed) {
+                    break;
+                }
+            }
+            None => {
+                return Err(TppError::GaveUp(reconnect.attempts()));
+            }
+        }
+
+    }
+
+    // Log the samples of the interrupted interval, so that nothing is lost.
+    let now = Utc::now();
+    let duration = now - session_start;
+    for channel in &mut channels {
+        channel.stats.flush();
+        if let Some(sample) = channel.stats.last_sample() {
+            campaign.push_sample(&channel.name, sample);
+        }
+        let run_name = campaign.current().map(|run| run.name.as_str()).unwrap_or("-");
+        let values = channel.stats.log_record(now, run_name, &interfaces.log_session);
+        let mut record = Vec::new();
+        config.record_format.write_record(&mut record, &values)?;
+        channel.log_file.write_record(Instant::now(), record);
+        if let Some(database) = &mut channel.database {
+            database.insert_record(&values);
+            database.end_session(now, channel.message_count);
+        }
+        if let Some(influx) = &mut interfaces.influx {
+            influx.push(&channel.name, &values);
+        }
+        channel.log_file.write_pending(Instant::now());
+        if let Some(binary_log) = &mut channel.binary_log {
+            binary_log.write_pending(Instant::now());
+        }
+        if let Some(chat_sample_file) = &mut channel.chat_sample_file {
+            chat_sample_file.write_pending(Instant::now());
+        }
+        if let Some(archive) = &mut channel.archive {
+            archive.write_pending();
+        }
+        if channel.log_file.pending() > 0 {
+            error!(channel = %channel.name, "{} records could not be written to the log", channel.log_file.pending());
+        }
+        info!(channel = %channel.name, "session ended after {}h{:02}m, {} messages sent",
+            duration.num_hours(), duration.num_minutes() % 60, channel.message_count);
+    }
+
+
+    Ok(())
+
+}
+
+
+/// State of the bot shared by its connections.
+struct Shared<'a> {
+    config: &'a Config,
+    interfaces: &'a mut Interfaces,
+    channels: &'a mut [Channel],
+    campaign: &'a mut Campaign,
+    pauses: &'a mut Pauses,
+    journal: &'a mut Option<Journal>,
+}
+
+
+/// State of a connection, started again on each reconnection.
+struct ConnectionState {
+    /// True when the server has sent a welcome command.
+    welcome: bool,
+    /// Number of malformed lines skipped, as last reported.
+    decode_errors: u64,
+    /// Canonical clock for all timestamps, synchronized on server time.
+    clock: ServerClock,
+    /// Start time of the active sample.
+    active_sample_time: Instant,
+    /// Duration of the samples, coarsened under load if configured.
+    resolution: Option<AdaptiveResolution>,
+    sample_duration: Duration,
+    /// Choose the variant of each message to avoid the duplicate detection.
+    variation: Variation,
+    /// The rate limit applies to the account, so across all channels.
+    next_send_time: Instant,
+    limiter: RateLimiter,
+    /// Status lines for the scripts, in place of the prompt.
+    status_emitter: StatusEmitter,
+    /// Current interval curve, reloaded when its file is modified.
+    interval_curve: IntervalCurve,
+    interval_curve_file: Option<CurveFile>,
+    /// Estimated delay of the commands, compensated in the TPP window.
+    latency: Latency,
+}
+
+impl ConnectionState {
+
+    /// Start the state of a new connection, the samples of the channels
+    /// are reset to the default resolution.
+    fn new(config: &Config, channels: &mut [Channel]) -> Self {
+
+        // Each connection starts at the default resolution.
+        let sample_duration = SAMPLE_DURATION;
+        for channel in channels.iter_mut() {
+            channel.stats.set_sample_duration(sample_duration);
+        }
+
+        // The messages sent before a restart or a reconnection still count for
+        // the duplicate rule and the rate limit.
+        let clock = ServerClock::default();
+        let mut variation = Variation::new(config.variation.clone(), &config.vocabulary);
+        let mut limiter = RateLimiter::new(config.verified);
+        let (now, utc_now) = (Instant::now(), clock.now());
+        for channel in channels.iter() {
+            for (time, message) in channel.sent_history.messages() {
+                let age = (utc_now - time).to_std().unwrap_or_default();
+                if let Some(sent_time) = now.checked_sub(age) {
+                    variation.record(&channel.name, message.to_string(), sent_time);
+                    limiter.record(sent_time, &channel.name);
+                }
+            }
+        }
+
+        Self {
+            welcome: false,
+            decode_errors: 0,
+            clock,
+            active_sample_time: Instant::now(),
+            resolution: config.resolution.clone().map(AdaptiveResolution::new),
+            sample_duration,
+            variation,
+            next_send_time: Instant::now(),
+            limiter,
+            status_emitter: StatusEmitter::new(config.status.clone()),
+            interval_curve: config.interval_curve.clone(),
+            interval_curve_file: config.interval_curve_path.clone().map(CurveFile::new),
+            latency: Latency::new(config.stream_delay, config.ping_interval),
+        }
+
+    }
+
+}
+
+
+/// Connect and run the bot until the connection is lost, the server requests
+/// a reconnection or a termination signal is received.
+fn run(config: &Config, interfaces: &mut Interfaces, watchdog: Option<&Watchdog>, channels: &mut [Channel], campaign: &mut Campaign, pauses: &mut Pauses, journal: &mut Option<Journal>) -> TppResult<RunEnd> {
+
+    info!("connect");
+    let mut irc = match &config.source {
+        ChatSource::Chaos(chaos) => IrcClient::connect_chaos(chaos.clone(), config.seed, &config.user, &config.channels)?,
+        ChatSource::Replay(replay) => IrcClient::connect_replay(replay, &config.user, &config.channels[0])?,
+        ChatSource::Twitch => if let Some(url) = &config.websocket_url {
+            IrcClient::connect_websocket(&config.addr, url, config.tls.then_some(config.host.as_str()))?
+        } else if config.tls {
+            IrcClient::connect_tls(&config.addr, &config.host)?
+        } else {
+            IrcClient::connect(&config.addr)?
+        },
+    };
+    if let Some(capture_path) = &config.capture_path {
+        irc = irc.with_capture(capture_path)?;
+    }
+
+    info!("auth");
+    irc.send_auth(&config.user, &config.token)?;
+    // Tags are required to know if a chatter is new to the channel, the
+    // other capabilities give the Twitch-specific commands and membership.
+    irc.send_cap_req(&["twitch.tv/tags", "twitch.tv/commands", "twitch.tv/membership"])?;
+    // Requested separately because it may be rejected by the server.
+    irc.send_cap_req(&["server-time"])?;
+
+    let mut state = ConnectionState::new(config, channels);
+    let mut shared = Shared { config, interfaces, channels, campaign, pauses, journal };
+
+    // Record the stages of each iteration for the watchdog diagnostics.
+    let stage = |name| if let Some(watchdog) = watchdog { watchdog.stage(name) };
+
+    loop {
+
+        if let Some(watchdog) = watchdog {
+            // The connection may be in a bad state after a stall, it is then
+            // reopened if configured.
+            if watchdog.take_stall() && config.watchdog_restart {
+                return Err(TppError::Stalled);
+            }
+            watchdog.iteration();
+        }
+
+        if SHUTDOWN.load(Ordering::Relaxed) {
+            info!("shutting down");
+            // The connection is closed anyway, ignore a failed goodbye.
+            let _ = irc.send_fmt(format_args!("QUIT :shutting down"));
+            return Ok(RunEnd::Shutdown);
+        }
+
+        // If the active sample is long enough, flush it and count it in the
+        // global sample.
+        if state.active_sample_time.elapsed() > state.sample_duration {
+            stage("flush");
+            flush(&mut shared, &mut state)?;
+        }
+
+        stage("decide");
+        decide(&mut shared, &mut state, &mut irc)?;
+
+        stage("console");
+        if let Some(end) = poll_console(&mut shared, &mut state, &mut irc)? {
+            return Ok(end);
+        }
+
+        // Regularly ping the server to measure the latency, which also keeps
+        // the connection alive.
+        if state.welcome && state.latency.should_ping() {
+            irc.send_fmt(format_args!("PING :tpp-bot"))?;
+            state.latency.ping_sent();
+        }
+
+        // Bytes delayed by a congested socket are written first, then the
+        // chat messages delayed by the rate limit once allowed.
+        irc.flush()?;
+        irc.flush_queue(&mut state.limiter)?;
+
+        // The following section receive replies and process them.
+        stage("recv");
+        irc.recv()?;
+        // A connection dropped without notice is only noticed by the
+        // missing pongs.
+        if irc.idle() > config.receive_timeout {
+            return Err(TppError::Timeout(irc.idle()));
+        }
+        stage("replies");
+        let (decoder, writer) = irc.split();
+        while let Some(reply) = decoder.decode() {
+            if let Some(end) = handle_reply(&mut shared, &mut state, writer, reply)? {
+                return Ok(end);
+            }
+        }
+
+        if decoder.errors() > state.decode_errors {
+            state.decode_errors = decoder.errors();
+            warn!("skipped {} malformed lines", state.decode_errors);
+        }
+
+        stage("sleep");
+        thread::sleep(Duration::from_millis(10));
+
+    }
+
+}
+
+
+/// Flush the active sample of each channel: log it, update the automatic
+/// bot mode and evaluate the rules, then write the state files.
+fn flush(shared: &mut Shared, state: &mut ConnectionState) -> TppResult<()> {
+
+    let config = shared.config;
+
+    state.active_sample_time = Instant::now();
+
+    for channel in shared.channels.iter_mut() {
+
+        // File logging, every sample is kept in the binary log.
+        let log = channel.stats.flush();
+        if let (Some(binary_log), Some(sample)) = (&mut channel.binary_log, channel.stats.last_sample()) {
+            let duration_ms = state.sample_duration.as_millis() as u32;
+            binary_log.write(Instant::now(), &BinaryRecord::new(state.clock.now(), duration_ms, sample));
+        }
+        if let Some(sample) = channel.stats.last_sample() {
+            shared.campaign.push_sample(&channel.name, sample);
+        }
+        if !log {
+            continue;
+        }
+
+        let run_name = shared.campaign.current().map(|run| run.name.as_str()).unwrap_or("-");
+        let values = channel.stats.log_record(state.clock.now(), run_name, &shared.interfaces.log_session);
+        let mut record = Vec::new();
+        config.record_format.write_record(&mut record, &values)?;
+        channel.log_file.write_record(Instant::now(), record);
+        if let Some(database) = &mut channel.database {
+            database.insert_record(&values);
+        }
+        if let Some(influx) = &mut shared.interfaces.influx {
+            influx.push(&channel.name, &values);
+        }
+
+        channel.stats.compensate(state.latency.total());
+
+        if let Some(exporter) = &mut shared.interfaces.exporter {
+            let global = channel.stats.global();
+            let now = state.clock.now();
+            exporter.push(&channel.name, Instant::now(), &[
+                ("time", now.to_rfc3339_opts(SecondsFormat::Secs, true)),
+                ("timestamp", now.timestamp().to_string()),
+                ("channel", channel.name.clone()),
+                ("run", run_name.to_string()),
+                ("msg_sec", global.message_sec(GLOBAL_SAMPLE_DURATION).to_string()),
+                ("cmd_sec", global.tpp_command_sec(GLOBAL_SAMPLE_DURATION).to_string()),
+                ("ratio", global.tpp_command_ratio().to_string()),
+                ("most_used", config.vocabulary.output(channel.stats.most_used()).to_string()),
+                ("sent", channel.message_count.to_string()),
+                ("bits", global.bits.to_string()),
+                ("redemptions", global.redemptions.to_string()),
+            ]);
+        }
+
+        if let Some(auto_bot) = &mut channel.auto_bot {
+
+            let cmd_sec = channel.stats.global().tpp_command_sec(GLOBAL_SAMPLE_DURATION);
+            let ratio = channel.stats.global().tpp_command_ratio();
+
+            if let Some(new_bot) = auto_bot.update(channel.bot, cmd_sec, ratio) {
+                info!(channel = %channel.name, "auto bot {}", if new_bot { "enabled" } else { "disabled" });
+                if let Some(journal) = shared.journal.as_mut() {
+                    journal.record(state.clock.now(), JournalKind::Auto, &channel.name,
+                        &format!("bot={} cmd_sec={cmd_sec:.1} ratio={ratio:.2}", if new_bot { "on" } else { "off" }));
+                }
+                channel.bot = new_bot;
+            }
+
+        }
+
+        apply_rules(config, shared.interfaces.whisperer.as_ref(), shared.pauses, shared.journal, channel, state.clock.now(), state.latency.total());
+
+    }
+
+    if let Some(uploader) = &mut shared.interfaces.uploader {
+        uploader.update(state.clock.now(), shared.channels.iter().map(|channel| (channel.name.as_str(), channel.log_file.path())));
+    }
+
+    if let Some(snapshot_file) = shared.interfaces.snapshot_file.as_mut().filter(|file| file.is_due(Instant::now())) {
+        let snapshot = json!({
+            "time": state.clock.now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            "run": shared.campaign.current().map(|run| run.name.as_str()),
+            "latency": state.latency.total().as_secs_f32(),
+            "channels": shared.channels.iter().map(|channel| {
+                let global = channel.stats.global();
+                json!({
+                    "name": channel.name,
+                    "msg_sec": global.message_sec(GLOBAL_SAMPLE_DURATION),
+                    "cmd_sec": global.tpp_command_sec(GLOBAL_SAMPLE_DURATION),
+                    "ratio": global.tpp_command_ratio(),
+                    "most_used": config.vocabulary.output(channel.stats.most_used()),
+                    "bot": channel.bot,
+                    "paused": shared.pauses.remaining(&channel.name, state.clock.now()).is_some(),
+                    "moderator": channel.moderator,
+                    "sent": channel.message_count,
+                })
+            }).collect::<Vec<_>>(),
+        });
+        if let Err(e) = snapshot_file.write(Instant::now(), &snapshot) {
+            warn!("failed to write snapshot: {e}");
+        }
+    }
+
+    if let Some(resolution) = &mut state.resolution {
+        let message_sec = shared.channels.iter()
+            .map(|channel| channel.stats.global().message_sec(GLOBAL_SAMPLE_DURATION))
+            .sum::<f32>();
+        if let Some(new_duration) = resolution.update(Instant::now(), message_sec) {
+            info!("sample duration changed to {}ms at {message_sec:.0} msg/s", new_duration.as_millis());
+            state.sample_duration = new_duration;
+            for channel in shared.channels.iter_mut() {
+                channel.stats.set_sample_duration(state.sample_duration);
+            }
+        }
+    }
+
+    if let Some(curve_file) = &mut state.interval_curve_file {
+        match curve_file.reload() {
+            Ok(Some(curve)) => {
+                info!("reloaded interval curve");
+                state.interval_curve = curve;
+            }
+            Ok(None) => {}
+            Err(e) => {
+                warn!("failed to reload interval curve {:?}: {e}", curve_file.path());
+            }
+        }
+    }
+
+    Ok(())
+
+}
+
+
+/// Evaluate the rules of a channel on its global sample, and apply the
+/// actions of the rules triggered.
+fn apply_rules(config: &Config, whisperer: Option<&Whisperer>, pauses: &mut Pauses, journal: &mut Option<Journal>, channel: &mut Channel, now: DateTime<Utc>, latency: Duration) {
+
+    let global = channel.stats.global();
+    let paused = pauses.remaining(&channel.name, now).is_some();
+    let (bot, message_count) = (channel.bot, channel.message_count);
+    let metric = |name: &str| match name {
+        "msg/s" => Some(global.message_sec(GLOBAL_SAMPLE_DURATION)),
+        "cmd/s" => Some(global.tpp_command_sec(GLOBAL_SAMPLE_DURATION)),
+        "cmd/msg" => Some(global.tpp_command_ratio()),
+        "latency" => Some(latency.as_secs_f32()),
+        "bot" => Some(bot as u8 as f32),
+        "paused" => Some(paused as u8 as f32),
+        "sent" => Some(message_count as f32),
+        "bits" => Some(global.bits as f32),
+        "redemptions" => Some(global.redemptions as f32),
+        _ => None,
+    };
+
+    for (rule, action) in channel.rules.update(Instant::now(), &metric) {
+        match action {
+            RuleAction::Marker(text) => {
+                info!(channel = %channel.name, "marker: {text}");
+            }
+            RuleAction::Notify(text) => {
+                warn!(channel = %channel.name, rule = %rule.source, "\x07alert: {text}");
+            }
+            RuleAction::Pause(end) => {
+                let end = end.resolve(now);
+                pauses.pause(Some(&channel.name), end);
+                info!(channel = %channel.name, "paused until {} by rule", end.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S"));
+            }
+            RuleAction::Whisper { user, text } => {
+                send_whisper(whisperer, config, user, text);
+            }
+        }
+        if let Some(journal) = journal.as_mut() {
+            journal.record(now, JournalKind::Rule, &channel.name, &format!("action={} rule={}", action.name(), rule.source));
+        }
+    }
+
+}
+
+
+/// Decide on the command to send in each channel, send it if any, and
+/// show the state of the channels to the operator.
+fn decide<T: IrcTransport>(shared: &mut Shared, state: &mut ConnectionState, irc: &mut IrcWriter<T>) -> TppResult<()> {
+
+    let config = shared.config;
+
+    // Numbers of the prompt follow the configured locale.
+    let nf = &config.number_format;
+    let mut prompts = Vec::new();
+    let mut statuses = Vec::new();
+
+    for channel in shared.channels.iter_mut() {
+
+        let stats = &channel.stats;
+
+        // Moderators and verified bots have higher rate limits, accounted
+        // across channels.
+        let tier = state.limiter.tier(channel.moderator);
+        // Messages sent faster than the slow mode are dropped.
+        let min_interval = tier.min_interval().max(channel.room_state.min_interval(channel.moderator));
+
+        // In the following section, we take the most used command and send
+        // it if enough time has passed.
+        if let Some(touches) = &mut channel.touches {
+            touches.expire(Instant::now(), stats.tpp_duration());
+        }
+
+        let most_used = stats.most_used();
+        let tpp_command = touch::output(&config.vocabulary, channel.touches.as_ref(), most_used).unwrap_or_default();
+
+        // Compute the average number of command per second
+        let tpp_command_sec = stats.tpp().tpp_command_sec(stats.tpp_duration());
+
+        // Compute the ratio of commands/messages.
+        let tpp_command_ratio = stats.tpp().tpp_command_ratio();
+
+        let trigger_metrics = TriggerMetrics {
+            cmd_sec: tpp_command_sec,
+            ratio: tpp_command_ratio,
+            consensus: stats.tpp().share(most_used),
+            min_ratio: channel.min_ratio,
+            min_cmd_sec: channel.min_cmd_sec,
+        };
+
+        // The real message interval is derived from the average interval
+        // through the interval curve or formula, unless fixed by the owner.
+        let interval = channel.interval
+            .unwrap_or_else(|| Duration::from_secs_f32(config.trigger.interval(&trigger_metrics, &state.interval_curve)))
+            .max(min_interval);
+
+        let remaining_time = if stats.is_full() {
+            channel.next_message_time.max(state.next_send_time).saturating_duration_since(Instant::now())
+        } else {
+            interval
+        };
+
+        // The schedule pauses the channel during the quiet hours and once
+        // the daily limit is reached, sending resumes with the pause end.
+        if channel.bot && shared.pauses.remaining(&channel.name, state.clock.now()).is_none() {
+            if let Some((end, reason)) = config.schedule.pause(state.clock.now(), channel.daily_sent.get(state.clock.now())) {
+                info!(channel = %channel.name, "paused until {} for the {reason}", end.with_timezone(&Local).format("%H:%M"));
+                shared.pauses.pause(Some(&channel.name), end);
+            }
+        }
+
+        // Pauses are distinct from the bot mode, they only suspend sending.
+        let pause = shared.pauses.remaining(&channel.name, state.clock.now());
+
+        let remaining_sec = remaining_time.as_secs_f32();
+        let pause_sec = pause.map(|pause| pause.num_seconds()).unwrap_or(0);
+        let pause_text = format!("paused {:02}:{:02}:{:02}", pause_sec / 3600, pause_sec / 60 % 60, pause_sec % 60);
+        let total_text = format!("{:>3}", nf.int(channel.message_count as i64));
+        // Channels are only named when several are joined.
+        let name_text = if config.channels.len() > 1 { format!("#{} ", channel.name) } else { String::new() };
+        let mut status = ChannelStatus {
+            channel: channel.name.clone(),
+            democracy: channel.democracy.is_some(),
+            bot: channel.bot,
+            command: tpp_command.to_string(),
+            next: remaining_time,
+            paused: pause.map(|pause| pause.to_std().unwrap_or_default()),
+            cmd_sec: tpp_command_sec,
+            ratio: tpp_command_ratio,
+            margin: 0,
+            sent: channel.message_count,
+            votes: stats.tpp().counts.iter()
+                .map(|(command, count)| (config.vocabulary.output(command).to_string(), count))
+                .collect(),
+        };
+
+        let now = Instant::now();
+        let allowed = state.limiter.allows(now, &channel.name, channel.moderator)
+            && !irc.is_queued(&channel.name)
+            && channel.room_state.blocking(channel.moderator).is_none()
+            && !emergency::is_stopped()
+            && !config.schedule.is_session_over(channel.message_count);
+
+        // The command to send now, if any.
+        let mut send_command = None;
+        // Index of the message in the current burst, if any.
+        let mut burst_index = None;
+
+        if let Some(democracy) = &mut channel.democracy {
+
+            // In democracy mode, the bot votes once per window, near its end
+            // when its vote can tip a close tally.
+            let window_sec = democracy.remaining(now).unwrap_or_default().as_secs_f32();
+            let (leader, margin) = democracy.leader(most_used)
+                .map(|vote| (config.vocabulary.output(vote.command), vote.margin))
+                .unwrap_or(("-", 0));
+
+            if pause.is_some() {
+                prompts.push(format!("{name_text}vote {leader:16} [{pause_text}, margin {margin:02}, {total_text} total]"));
+            } else {
+                prompts.push(format!("{name_text}vote {leader:16} [window {:>4}s, margin {margin:02}, {total_text} total]", nf.float(window_sec, 1)));
+            }
+            status.command = leader.to_string();
+            status.next = Duration::from_secs_f32(window_sec);
+            status.margin = margin;
+
+            if channel.bot && pause.is_none() && allowed && now >= channel.next_message_time && now >= state.next_send_time {
+                if let Some(vote) = democracy.vote(now, most_used) {
+                    send_command = Some(vote.command);
+                }
+            }
+
+        } else {
+
+            let rates_text = format!("{:>4} cmd/s, {} cmd/msg", nf.float(tpp_command_sec, 1), nf.float(tpp_command_ratio, 2));
+            // The aliases that voted for the command, to tune the vocabulary.
+            let aliases_text = stats.tpp().aliases(most_used, &config.vocabulary).iter()
+                .take(3)
+                .map(|(alias, count)| format!("{alias} {count}"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let aliases_text = if aliases_text.is_empty() { aliases_text } else { format!(" ({aliases_text})") };
+            if pause.is_some() {
+                prompts.push(format!("{name_text}send {tpp_command:16} [{pause_text}, {rates_text}, {total_text} total]{aliases_text}"));
+            } else {
+                prompts.push(format!("{name_text}send {tpp_command:16} [in {:>4}s, {rates_text}, {total_text} total]{aliases_text}", nf.float(remaining_sec, 1)));
+            }
+
+            // During high-consensus moments, moderators may send a burst
+            // of the consensus command, its messages bypass the interval.
+            let consensus = trigger_metrics.consensus;
+            let can_send = channel.bot && pause.is_none() && allowed;
+            match &mut channel.burst {
+                Some(burst) if can_send && channel.moderator => {
+                    burst_index = burst.due(now, consensus);
+                }
+                Some(burst) => burst.cancel(),
+                None => {}
+            }
+
+            // Many condition are required to send a message, to avoid being caught as a bot.
+            if burst_index.is_some() {
+                send_command = Some(most_used);
+            } else if can_send && remaining_time.is_zero() {
+                // A decision script replaces the thresholds and may choose
+                // another command than the most used one.
+                send_command = match &mut channel.script {
+                    Some(script) => script.decide(ScriptInput {
+                        channel: &channel.name,
+                        sample: stats.tpp(),
+                        most_used,
+                        cmd_sec: tpp_command_sec,
+                        ratio: tpp_command_ratio,
+                    }, &config.vocabulary),
+                    None => config.trigger.is_met(&trigger_metrics).then_some(most_used),
+                };
+                if let Some(burst) = channel.burst.as_mut().filter(|_| channel.moderator && send_command == Some(most_used)) {
+                    if burst.start(now, consensus, state.limiter.remaining(now, tier)) {
+                        info!(channel = %channel.name, "burst of {} '{tpp_command}', consensus {consensus:.2}", burst.size());
+                        burst_index = Some(0);
+                    }
+                }
+            }
+
+        }
+
+        // The most used command isn't a choice of the chat with too few
+        // votes, a script may still choose any command.
+        if send_command == Some(most_used) && !stats.is_eligible(most_used) {
+            send_command = None;
+        }
+
+        // Some sends of the anarchy mode are skipped at random, waiting
+        // for another interval, so that the cadence isn't regular.
+        if let Some(skipped) = send_command.filter(|_| burst_index.is_none() && channel.democracy.is_none()) {
+            if config.jitter.skips(&mut channel.jitter_rng) {
+                let command = config.vocabulary.output(skipped);
+                debug!(channel = %channel.name, "skip '{command}' at random");
+                if let Some(journal) = shared.journal.as_mut() {
+                    journal.record(state.clock.now(), JournalKind::Skip, &channel.name, &format!("command={command} mode=anarchy reason=jitter"));
+                }
+                channel.next_message_time = now + config.jitter.interval(interval, &mut channel.jitter_rng).max(min_interval);
+                send_command = None;
+            }
+        }
+
+        // A command forced by the owner is sent whatever the bot mode
+        // and the thresholds, only the pauses and the rate limit apply.
+        if send_command.is_none() && pause.is_none() && allowed && now >= state.next_send_time {
+            send_command = channel.forced.take();
+        }
+
+        // The blocked commands are never sent, even when forced.
+        if let Some(blocked) = send_command.filter(|&command| config.command_policy.is_blocked(command)) {
+            debug!(channel = %channel.name, "blocked command '{}'", config.vocabulary.output(blocked));
+            send_command = None;
+        }
+
+        statuses.push(status);
+
+        // A touch is only sent with the taps of its cluster, and a button
+        // with the combo or the modifier most of its voters typed, if
+        // enabled.
+        let send_command = send_command.and_then(|send_command| {
+            let output = touch::output(&config.vocabulary, channel.touches.as_ref(), send_command)?;
+            let combo = config.send_combos.then(|| stats.tpp().dominant_combo(send_command, &config.vocabulary)).flatten();
+            let modifier = config.send_modifiers.then(|| stats.tpp().dominant_modifier(send_command, &config.vocabulary)).flatten();
+            Some((send_command, match (combo, modifier) {
+                (Some(combo), _) => Cow::Owned(combo.to_string()),
+                (None, Some(modifier)) => Cow::Owned(format!("{output}{modifier}")),
+                (None, None) => output,
+            }))
+        });
+
+        if let Some((send_command, output)) = send_command {
+
+            let command = output.as_ref();
+            let mode = if channel.democracy.is_some() { "democracy" } else { "anarchy" };
+            let burst_text = match (burst_index, &channel.burst) {
+                (Some(index), Some(burst)) => format!(" burst={}/{}", index + 1, burst.size()),
+                _ => String::new(),
+            };
+            match state.variation.vary(&channel.name, command, now) {
+                Some(message) if config.dry_run => {
+                    // Everything the message would have been sent with, to
+                    // review the decisions of the bot.
+                    let votes = votes_text(stats.tpp(), &config.vocabulary);
+                    info!(channel = %channel.name, command, mode, votes, "dry run, not sent 'PRIVMSG #{} :{message}'", channel.name);
+                    if let Some(journal) = shared.journal.as_mut() {
+                        journal.record(state.clock.now(), JournalKind::Skip, &channel.name,
+                            &format!("message={message} command={command} mode={mode}{burst_text} cmd_sec={tpp_command_sec:.1} ratio={tpp_command_ratio:.2} interval={:.1} votes={votes} reason=dry_run", interval.as_secs_f32()));
+                    }
+                    state.variation.record(&channel.name, message, now);
+                }
+                Some(message) => {
+                    irc.send_privmsg(&mut state.limiter, &channel.name, channel.moderator, &message)?;
+                    info!(channel = %channel.name, command, mode, "sent '{message}'");
+                    if let Some(journal) = shared.journal.as_mut() {
+                        journal.record(state.clock.now(), JournalKind::Send, &channel.name,
+                            &format!("message={message} command={command} mode={mode}{burst_text} cmd_sec={tpp_command_sec:.1} ratio={tpp_command_ratio:.2} interval={:.1}", interval.as_secs_f32()));
+                    }
+                    channel.sent_history.record(state.clock.now(), message.clone());
+                    if let Some(database) = &mut channel.database {
+                        database.insert_message(state.clock.now(), &message, command, mode, tpp_command_sec, tpp_command_ratio);
+                    }
+                    state.variation.record(&channel.name, message, now);
+                    channel.message_count += 1;
+                    shared.campaign.push_sent(&channel.name);
+                    channel.daily_sent.push(state.clock.now());
+                    if config.schedule.is_session_over(channel.message_count) {
+                        info!(channel = %channel.name, "session limit reached, not sending until restarted");
+                    }
+                    channel.last_sent = Some(send_command);
+                    if let Some(shadows) = &mut channel.shadows {
+                        shadows.record_live(now, send_command);
+                    }
+                }
+                None => {
+                    info!(channel = %channel.name, "skip duplicate '{command}'");
+                    if let Some(journal) = shared.journal.as_mut() {
+                        journal.record(state.clock.now(), JournalKind::Skip, &channel.name,
+                            &format!("command={command} mode={mode}{burst_text} reason=duplicate"));
+                    }
+                }
+            }
+
+            // Votes are timed by the window, only the rate limit applies.
+            channel.next_message_time = if channel.democracy.is_some() {
+                now + min_interval
+            } else {
+                now + config.jitter.interval(interval, &mut channel.jitter_rng).max(min_interval)
+            };
+            state.next_send_time = now + tier.min_interval();
+
+        }
+
+        // Shadow strategies decide on the same stats, but never send.
+        if let Some(shadows) = &mut channel.shadows {
+            shadows.update(Instant::now(), state.clock.now(), &channel.stats, min_interval)?;
+        }
+
+    }
+
+    if emergency::is_stopped() {
+        prompts.insert(0, "EMERGENCY STOP, type 'arm' to resume".to_string());
+    }
+    if config.status.format == StatusFormat::Prompt {
+        print_prompt(format_args!("{}", prompts.join(" | ")));
+    }
+    state.status_emitter.emit(Instant::now(), state.clock.now(), &statuses);
+    if let Some(http) = &shared.interfaces.http {
+        http.update(Instant::now(), state.clock.now(), state.welcome, &statuses);
+    }
+    if let Some(overlay) = &mut shared.interfaces.overlay {
+        overlay.push(Instant::now(), state.clock.now(), &statuses);
+    }
+    if let Some(overlay_file) = &mut shared.interfaces.overlay_file {
+        if let Err(e) = overlay_file.write(Instant::now(), &statuses) {
+            warn!("failed to write the overlay file: {e}");
+        }
+    }
+
+    Ok(())
+
+}
+
+
+/// Process the commands typed in the console, returning how the connection
+/// ends if requested.
+fn poll_console<T: IrcTransport>(shared: &mut Shared, state: &mut ConnectionState, irc: &mut IrcWriter<T>) -> TppResult<Option<RunEnd>> {
+
+    let config = shared.config;
+
+    while let Some(line) = shared.interfaces.console.poll() {
+
+        let command = ControlCommand::parse(&line.text);
+        if let (Ok(_), Some(journal)) = (&command, shared.journal.as_mut()) {
+            journal.record(state.clock.now(), JournalKind::Operator, "*", line.text.trim());
+        }
+
+        let reply = match command {
+            Ok(ControlCommand::RunStart(name)) => {
+                let prev = shared.campaign.start(name, state.clock.now())?;
+                let started = format!("run '{}' started", shared.campaign.current().unwrap().name);
+                match prev {
+                    Some(prev) => format!("{}, {started}", prev.summary(state.clock.now())),
+                    None => started,
+                }
+            }
+            Ok(ControlCommand::RunEnd) => {
+                match shared.campaign.end()? {
+                    Some(prev) => prev.summary(state.clock.now()),
+                    None => "no run to end".to_string(),
+                }
+            }
+            Ok(ControlCommand::Stop) => {
+                emergency_stop("the console", irc, shared.channels);
+                "stopped, type 'arm' to resume".to_string()
+            }
+            Ok(ControlCommand::Arm) => {
+                if emergency::arm() {
+                    warn!("re-armed after the emergency stop, sending resumes");
+                    "re-armed".to_string()
+                } else {
+                    "not stopped".to_string()
+                }
+            }
+            // The configuration is swapped between two connections, the
+            // line is answered once reloaded.
+            Ok(ControlCommand::ReloadConfig) => {
+                let _ = irc.send_fmt(format_args!("QUIT :reloading"));
+                return Ok(Some(RunEnd::Reload(line)));
+            }
+            Ok(command) => apply_control(command, config, shared.channels, shared.pauses, state.clock.now())?,
+            Err(e) => format!("error: {e}"),
+        };
+
+        line.answer(reply);
+
+    }
+
+    Ok(None)
+
+}
+
+
+/// Process a reply of the server, returning how the connection ends if
+/// requested.
+fn handle_reply<T: IrcTransport>(shared: &mut Shared, state: &mut ConnectionState, writer: &mut IrcWriter<T>, reply: IrcReplyRef) -> TppResult<Option<RunEnd>> {
+
+    let config = shared.config;
+
+    if let Some(server_time) = reply.server_time() {
+        state.clock.observe(server_time);
+    }
+
+    match reply.command {
+        IrcReplyCommand::Welcome if !state.welcome => {
+            let targets = config.channels.iter().map(|name| format!("#{name}")).collect::<Vec<_>>();
+            info!("join {}", targets.join(" "));
+            writer.send_fmt(format_args!("JOIN {}", targets.join(",")))?;
+            state.welcome = true;
+        }
+        IrcReplyCommand::Ping => {
+            let text = reply.text().unwrap_or_default();
+            debug!("pong '{text}'");
+            writer.send_fmt(format_args!("PONG :{text}"))?;
+        }
+        IrcReplyCommand::Pong => {
+            state.latency.pong_received();
+        }
+        IrcReplyCommand::Notice => {
+            let text = reply.text().unwrap_or_default();
+            // Authentication failures are only reported by a notice
+            // before the welcome, retrying would fail the same way.
+            if !state.welcome && (text.contains("authentication failed") || text.contains("Improperly formatted auth")) {
+                return Err(TppError::Auth(text.to_string()));
+            }
+            // The messages are dropped for a while, and sending more
+            // would extend the limit.
+            if reply.tag("msg-id") == Some("msg_ratelimit") {
+                return Err(TppError::RateLimited(text.to_string()));
+            }
+            info!("notice: {text}");
+        }
+        IrcReplyCommand::Reconnect => {
+            info!("server requested a reconnection");
+            return Ok(Some(RunEnd::Reconnect));
+        }
+        IrcReplyCommand::CapAck => {
+            info!("capabilities acknowledged: {}", reply.capabilities().collect::<Vec<_>>().join(" "));
+        }
+        IrcReplyCommand::CapNak => {
+            warn!("capabilities rejected: {}", reply.capabilities().collect::<Vec<_>>().join(" "));
+        }
+        IrcReplyCommand::UserState => {
+            let moderator = reply.tag("mod") == Some("1")
+                || reply.tag("badges").is_some_and(|badges| badges.split(',').any(|badge| badge.starts_with("broadcaster/")));
+            let channel = reply.target()
+                .and_then(|target| target.strip_prefix('#'))
+                .and_then(|name| shared.channels.iter_mut().find(|channel| channel.name == name));
+            if let Some(channel) = channel {
+                if channel.moderator != moderator {
+                    info!(channel = %channel.name, "{}", if moderator { "moderator" } else { "not moderator" });
+                    channel.moderator = moderator;
+                }
+            }
+        }
+        IrcReplyCommand::RoomState => {
+            let channel = reply.target()
+                .and_then(|target| target.strip_prefix('#'))
+                .and_then(|name| shared.channels.iter_mut().find(|channel| channel.name == name));
+            if let Some(channel) = channel {
+                if channel.room_state.update(&reply) {
+                    let blocking_text = match channel.room_state.blocking(channel.moderator) {
+                        Some(mode) => format!(", not sending in {mode} mode"),
+                        None => String::new(),
+                    };
+                    info!(channel = %channel.name, "chat modes: {}{blocking_text}", channel.room_state.describe());
+                }
+            }
+        }
+        IrcReplyCommand::ClearChat => {
+            let channel = reply.target()
+                .and_then(|target| target.strip_prefix('#'))
+                .and_then(|name| shared.channels.iter_mut().find(|channel| channel.name == name));
+            // Only the moderation of the bot itself matters.
+            let (Some(channel), Some(user)) = (channel, reply.text()) else { return Ok(None) };
+            if !user.eq_ignore_ascii_case(&config.user) {
+                return Ok(None);
+            }
+            let action = match reply.tag("ban-duration").and_then(|duration| duration.parse::<i64>().ok()) {
+                Some(duration) => {
+                    // Messages sent during the timeout are dropped, the
+                    // pause survives a restart.
+                    let end = state.clock.now() + TimeDelta::seconds(duration);
+                    shared.pauses.pause(Some(&channel.name), end);
+                    warn!(channel = %channel.name, "\x07alert: timed out for {duration}s, paused until {}", end.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S"));
+                    format!("action=timeout duration={duration}")
+                }
+                None => {
+                    channel.bot = false;
+                    channel.auto_bot = None;
+                    warn!(channel = %channel.name, "\x07alert: banned, bot mode disabled");
+                    "action=ban".to_string()
+                }
+            };
+            if let Some(journal) = shared.journal.as_mut() {
+                journal.record(state.clock.now(), JournalKind::Moderation, &channel.name, &action);
+            }
+        }
+        IrcReplyCommand::ClearMsg => {
+            if reply.tag("login").is_some_and(|login| login.eq_ignore_ascii_case(&config.user)) {
+                let channel = reply.target().and_then(|target| target.strip_prefix('#')).unwrap_or_default();
+                info!(channel, "message deleted by a moderator: {}", reply.text().unwrap_or_default());
+            }
+        }
+        // Membership events are frequent and not used for now.
+        IrcReplyCommand::Join | IrcReplyCommand::Part => {}
+        IrcReplyCommand::UserNotice => {
+            let channel = reply.target()
+                .and_then(|target| target.strip_prefix('#'))
+                .and_then(|name| shared.channels.iter_mut().find(|channel| channel.name == name));
+            let Some(channel) = channel else { return Ok(None) };
+            let kind = UserNoticeKind::parse(reply.tag("msg-id").unwrap_or_default());
+            let viewers = reply.tag("msg-param-viewerCount").and_then(|viewers| viewers.parse().ok()).unwrap_or(0);
+            channel.stats.push_notice(kind, viewers);
+            if kind == UserNoticeKind::Raid {
+                let raider = reply.tag("msg-param-displayName").or(reply.tag("login")).unwrap_or_default();
+                info!(channel = %channel.name, "raid from {raider} with {viewers} viewers");
+                if !config.raid_window.is_zero() {
+                    channel.raid_end = Some(Instant::now() + config.raid_window);
+                }
+            }
+        }
+        IrcReplyCommand::PrivMsg if state.welcome => handle_chat(shared, state, writer, reply)?,
+        IrcReplyCommand::Whisper => {
+            let text = reply.text().unwrap_or_default();
+            let nickname = reply.sender().and_then(|sender| sender.nickname);
+            let user_id = reply.tag("user-id");
+            if config.emergency.as_ref().is_some_and(|emergency| emergency.matches(text, nickname, user_id)) {
+                let nickname = nickname.unwrap_or_default();
+                emergency_stop(nickname, writer, shared.channels);
+                if let Some(journal) = shared.journal.as_mut() {
+                    journal.record(state.clock.now(), JournalKind::Operator, "*", &format!("action=stop by={nickname} via=whisper"));
+                }
+            } else if config.owners.as_ref().is_some_and(|owners| owners.contains(nickname, user_id)) {
+                // The owner controls the bot like from the console,
+                // and is answered by whisper.
+                let nickname = nickname.unwrap_or_default();
+                let reply_text = owner_command(shared, state.clock.now(), nickname, text, None)?;
+                info!("whisper from {nickname} '{}': {reply_text}", text.trim());
+                send_whisper(shared.interfaces.whisperer.as_ref(), config, nickname, &reply_text);
+            } else {
+                debug!("ignored whisper from {}", nickname.unwrap_or_default());
+            }
+        }
+        _ => {
+            debug!("received {:?}", reply);
+        }
+    }
+
+    Ok(None)
+
+}
+
+
+/// Process a message of the chat, counted in the stats of its channel
+/// unless it's a command of an owner or an announcement of the stream.
+fn handle_chat<T: IrcTransport>(shared: &mut Shared, state: &mut ConnectionState, writer: &mut IrcWriter<T>, reply: IrcReplyRef) -> TppResult<()> {
+
+    let config = shared.config;
+
+    let Some(text) = reply.text() else { return Ok(()) };
+
+    // The emergency stop is checked first, it applies to all
+    // the channels.
+    let nickname = reply.sender().and_then(|sender| sender.nickname);
+    if config.emergency.as_ref().is_some_and(|emergency| emergency.matches(text, nickname, reply.tag("user-id"))) {
+        let nickname = nickname.unwrap_or_default();
+        emergency_stop(nickname, writer, shared.channels);
+        if let Some(journal) = shared.journal.as_mut() {
+            let channel = reply.target().and_then(|target| target.strip_prefix('#')).unwrap_or("*");
+            journal.record(state.clock.now(), JournalKind::Operator, channel, &format!("action=stop by={nickname} via=chat"));
+        }
+        return Ok(());
+    }
+
+    // Commands of the owner in the chat apply to its channel by
+    // default, and are answered in the chat.
+    let owner = config.owners.as_ref().is_some_and(|owners| owners.contains(nickname, reply.tag("user-id")));
+    if let Some(line) = text.strip_prefix("!tppbot ").filter(|_| owner) {
+        let Some(name) = reply.target().and_then(|target| target.strip_prefix('#')) else { return Ok(()) };
+        let nickname = nickname.unwrap_or_default();
+        let reply_text = owner_command(shared, state.clock.now(), nickname, line, Some(name))?;
+        info!(channel = name, "command from {nickname} '{}': {reply_text}", line.trim());
+        if config.dry_run || emergency::is_stopped() {
+            info!(channel = name, "not answered to {nickname}");
+        } else if let Some(channel) = shared.channels.iter().find(|channel| channel.name == name) {
+            writer.send_privmsg(&mut state.limiter, name, channel.moderator, &format!("@{nickname} {reply_text}"))?;
+        }
+        return Ok(());
+    }
+
+    let Some(channel) = reply.target()
+        .and_then(|target| target.strip_prefix('#'))
+        .and_then(|name| shared.channels.iter_mut().find(|channel| channel.name == name)) else {
+        return Ok(());
+    };
+
+    if let Some(archive) = &mut channel.archive {
+        archive.write(state.clock.now(), &reply, config.vocabulary.parse(text));
+    }
+
+    // The stream's bot is not a chatter, its announcements
+    // switch the voting strategy with the mode of the stream,
+    // and give the actual votes and moves of the game.
+    if config.stream_bot.as_deref().is_some_and(|bot| nickname.is_some_and(|nickname| nickname.eq_ignore_ascii_case(bot))) {
+        match Announcement::parse(text, &config.vocabulary) {
+            Some(Announcement::Mode(mode)) => {
+                let democracy = mode == InputMode::Democracy;
+                if let Some(model) = config.democracy.filter(|_| democracy != channel.democracy.is_some()) {
+                    info!(channel = %channel.name, "stream switched to {}", mode.name());
+                    channel.democracy = democracy.then(|| Democracy::new(model));
+                    if let Some(journal) = shared.journal.as_mut() {
+                        journal.record(state.clock.now(), JournalKind::Auto, &channel.name, &format!("mode={}", mode.name()));
+                    }
+                }
+            }
+            Some(Announcement::Tally(tally)) => {
+                if let Some(democracy) = &mut channel.democracy {
+                    democracy.push_tally(&tally, Instant::now());
+                }
+            }
+            Some(Announcement::Move(command)) => {
+                let matched = channel.last_sent.take() == Some(command);
+                debug!(channel = %channel.name, matched, "stream executed {}", command.name());
+                channel.stats.push_move(matched);
+            }
+            // Every channel of the stream may announce the
+            // same run.
+            Some(Announcement::RunStart(name)) if shared.campaign.current().is_none_or(|run| run.name != name) => {
+                if let Some(journal) = shared.journal.as_mut() {
+                    journal.record(state.clock.now(), JournalKind::Auto, &channel.name, &format!("run={name}"));
+                }
+                match shared.campaign.start(name, state.clock.now())? {
+                    Some(prev) => info!(channel = %channel.name, "{}, run '{}' started", prev.summary(state.clock.now()), shared.campaign.current().unwrap().name),
+                    None => info!(channel = %channel.name, "run '{}' started", shared.campaign.current().unwrap().name),
+                }
+            }
+            Some(Announcement::RunEnd) => {
+                if let Some(prev) = shared.campaign.end()? {
+                    if let Some(journal) = shared.journal.as_mut() {
+                        journal.record(state.clock.now(), JournalKind::Auto, &channel.name, "run=-");
+                    }
+                    info!(channel = %channel.name, "{}", prev.summary(state.clock.now()));
+                }
+            }
+            // The run already started.
+            Some(Announcement::RunStart(_)) | None => {}
+        }
+        return Ok(());
+    }
+
+    // The own messages of the bot, echoed back or sent from
+    // another connection, would vote for what it just sent.
+    if nickname.is_some_and(|nickname| nickname.eq_ignore_ascii_case(&config.user)) {
+        return Ok(());
+    }
+
+    // The messages of the other bots would skew the command
+    // ratio, they are not counted at all.
+    if config.ignore_users.contains(nickname, reply.tag("user-id")) {
+        return Ok(());
+    }
+
+    // Prefer the stable user id over the nickname.
+    let user = reply.tag("user-id")
+        .or_else(|| reply.sender().and_then(|sender| sender.nickname));
+
+    let chatter = match user {
+        Some(user) => {
+            let first_msg = reply.tag("first-msg") == Some("1");
+            let seen = channel.seen_users.as_mut().is_some_and(|seen_users| seen_users.insert(user));
+            Some((user, seen || first_msg))
+        }
+        None => None,
+    };
+
+    // The chatter of raiders is counted apart until the end
+    // of the raid window, it would lower the command ratio.
+    // Taps on the touchscreen are counted as touches, their
+    // coordinates are clustered when sending.
+    let tap = channel.touches.as_ref().and_then(|touches| touches.config().parse(text));
+    let command = config.vocabulary.parse(text).or(tap.map(|_| Command::Touch));
+    let weight = config.vote_weights.as_ref().map_or(1, |weights| weights.weight(&reply));
+    let raid_message = command.is_none() && channel.raid_end.is_some_and(|end| Instant::now() < end);
+    let is_tpp_command = if raid_message {
+        channel.stats.push_raid_message();
+        false
+    } else {
+        channel.stats.push_message(command, chatter, weight)
+    };
+    // Each button of a combo is a vote, the first being the
+    // vote of the message.
+    if let Some(combo) = config.vocabulary.parse_combo(text).filter(|_| is_tpp_command) {
+        let commands = combo.iter().skip(1).map(|&(command, _)| command).collect::<Vec<_>>();
+        channel.stats.push_combo(&commands, weight);
+    }
+    if let (Some(tap), Some(touches), true) = (tap, &mut channel.touches, is_tpp_command) {
+        touches.push(Instant::now(), tap);
+    } else if is_tpp_command {
+        channel.stats.push_alias(text);
+    } else {
+        if funnel::is_candidate(text) {
+            channel.stats.push_candidate();
+        }
+        let emotes = reply.tag("emotes").map(hype::count_emotes).unwrap_or(0);
+        channel.stats.push_hype(text, emotes);
+    }
+    channel.stats.push_source(MessageSource::classify(&reply), is_tpp_command);
+
+    // Cheers carry their bits, and channel points rewards
+    // redeemed with a message carry their reward id.
+    let bits = reply.tag("bits").and_then(|bits| bits.parse().ok()).unwrap_or(0);
+    let redemption = reply.tag("custom-reward-id").is_some()
+        || reply.tag("msg-id") == Some("highlighted-message");
+    if bits > 0 || redemption {
+        channel.stats.push_support(bits, redemption);
+    }
+
+    if let Some(democracy) = &mut channel.democracy {
+        democracy.push_message(command, weight, Instant::now());
+    }
+
+    if !is_tpp_command {
+        if let Some(file) = &mut channel.chat_sample_file {
+            if channel.rng.gen::<f32>() < config.chat_sample_rate {
+                let nickname = reply.sender().and_then(|sender| sender.nickname).unwrap_or("");
+                file.write_record(Instant::now(), format!("{}\t{nickname}\t{}\n", 
+                    state.clock.now().timestamp(), 
+                    text.replace('\t', " ")).into_bytes());
+            }
+        }
+    }
+
+    Ok(())
+
+}
+
+
+/// Apply a command of an owner, given by whisper or in the chat of the
+/// given channel, its default channel. Returns the answer to the owner.
+fn owner_command(shared: &mut Shared, now: DateTime<Utc>, nickname: &str, line: &str, channel: Option<&str>) -> TppResult<String> {
+
+    let command = match ControlCommand::parse(line) {
+        Ok(command) => command,
+        Err(e) => return Ok(e),
+    };
+
+    if let Some(journal) = shared.journal.as_mut() {
+        let via = if channel.is_some() { "chat" } else { "whisper" };
+        journal.record(now, JournalKind::Operator, channel.unwrap_or("*"), &format!("by={nickname} via={via} command={}", line.trim()));
+    }
+
+    let command = match channel {
+        Some(name) => command.with_default_channel(name),
+        None => command,
+    };
+    apply_control(command, shared.config, shared.channels, shared.pauses, now)
+
+}
+
+
+/// Trigger the emergency stop: the queued chat messages are dropped and
+/// the pending log records are written, nothing is sent until re-armed.
+fn emergency_stop<T: IrcTransport>(by: &str, writer: &mut IrcWriter<T>, channels: &mut [Channel]) {
+
+    if !emergency::stop() {
+        return;
+    }
+
+    let dropped = writer.clear_queue();
+    for channel in channels.iter_mut() {
+        channel.log_file.write_pending(Instant::now());
+    }
+
+    error!("\x07alert: emergency stop by {by}, {dropped} queued messages dropped, type 'arm' to resume");
+
+}
+
+/// Whisper to a user with the Helix API, as Twitch doesn't run the `/w`
+/// command sent over IRC. Whispers have their own limits, so they don't
+/// count in the rate limit of the chat. Nothing is sent in a dry run, when
+/// stopped or without the whisper configuration.
+fn send_whisper(whisperer: Option<&Whisperer>, config: &Config, user: &str, text: &str) {
+    match whisperer {
+        _ if emergency::is_stopped() => info!("emergency stop, not whispered to {user}: {text}"),
+        _ if config.dry_run => info!("dry run, not whispered to {user}: {text}"),
+        Some(whisperer) => whisperer.send(user, text),
+        None => warn!("whisper_client_id isn't set, not whispered to {user}: {text}"),
+    }
+}
+
+/// Apply a control command that may be given remotely by an owner, and
+/// return the reply. The runs and the emergency stop are only controlled
+/// from the console.
+fn apply_control(command: ControlCommand, config: &Config, channels: &mut [Channel], pauses: &mut Pauses, now: DateTime<Utc>) -> TppResult<String> {
+
+    // Commands apply to the given channel, or to all channels if none.
+    let selected = |target: &Option<String>, channel: &Channel| target.as_ref().is_none_or(|name| *name == channel.name);
+    let target_text = |target: &Option<String>| target.as_ref().map(|name| format!("#{name}")).unwrap_or_else(|| "all channels".to_string());
+
+    if let Some(name) = match &command {
+        ControlCommand::Pause(_, Some(name)) | ControlCommand::Resume(Some(name)) | ControlCommand::Send(_, Some(name)) |
+        ControlCommand::Set(_, Some(name)) | ControlCommand::Stats(Some(name)) => Some(name),
+        _ => None,
+    } {
+        if !channels.iter().any(|channel| channel.name == *name) {
+            return Ok(format!("unknown channel #{name}"));
+        }
+    }
+
+    Ok(match command {
+        ControlCommand::Pause(end, target) => {
+            let end = end.resolve(now);
+            pauses.pause(target.as_deref(), end);
+            format!("paused {} until {}", target_text(&target), end.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S"))
+        }
+        ControlCommand::Resume(target) => {
+            if pauses.resume(target.as_deref()) {
+                format!("resumed {}", target_text(&target))
+            } else {
+                "not paused".to_string()
+            }
+        }
+        ControlCommand::Send(name, target) => {
+            let Some(command) = config.vocabulary.parse(&name) else {
+                return Ok(format!("unknown command '{name}'"));
+            };
+            for channel in channels.iter_mut().filter(|channel| selected(&target, channel)) {
+                channel.forced = Some(command);
+            }
+            format!("sending '{}' in {}", config.vocabulary.output(command), target_text(&target))
+        }
+        ControlCommand::Set(setting, target) => {
+            for channel in channels.iter_mut().filter(|channel| selected(&target, channel)) {
+                match setting {
+                    Setting::MinRatio(ratio) => channel.min_ratio = ratio,
+                    Setting::MinCmdSec(cmd_sec) => channel.min_cmd_sec = cmd_sec,
+                    Setting::Interval(interval) => channel.interval = interval,
+                    Setting::Bot(bot) => {
+                        // Another instance may be sending as the account
+                        // since the channel was opened.
+                        if bot && channel.send_lock.is_none() && matches!(config.source, ChatSource::Twitch) && !config.dry_run {
+                            match SendLock::acquire(&config.lock_dir, &config.user, &channel.name)? {
+                                Ok(lock) => channel.send_lock = Some(lock),
+                                Err(_) => return Ok(format!("another instance is sending as {} in #{}", config.user, channel.name)),
+                            }
+                        }
+                        channel.bot = bot;
+                        channel.auto_bot = None;
+                    }
+                }
+            }
+            let setting_text = match setting {
+                Setting::MinRatio(ratio) => format!("min_ratio={ratio}"),
+                Setting::MinCmdSec(cmd_sec) => format!("min_cmd_sec={cmd_sec}"),
+                Setting::Interval(Some(interval)) => format!("interval={}s", interval.as_secs_f32()),
+                Setting::Interval(None) => "interval=auto".to_string(),
+                Setting::Bot(bot) => format!("bot={bot}"),
+            };
+            format!("{setting_text} in {}", target_text(&target))
+        }
+        ControlCommand::Stats(target) => {
+            channels.iter().filter(|channel| selected(&target, channel)).map(|channel| {
+                let global = channel.stats.global();
+                let paused_text = if pauses.remaining(&channel.name, now).is_some() { ", paused" } else { "" };
+                format!("#{}: bot {}, {:.1} cmd/s, {:.2} cmd/msg, most used '{}', {} sent{paused_text}",
+                    channel.name,
+                    if channel.bot { "on" } else { "off" },
+                    global.tpp_command_sec(GLOBAL_SAMPLE_DURATION),
+                    global.tpp_command_ratio(),
+                    config.vocabulary.output(channel.stats.most_used()),
+                    channel.message_count)
+            }).collect::<Vec<_>>().join(" | ")
+        }
+        ControlCommand::RunStart(_) | ControlCommand::RunEnd | ControlCommand::Stop | ControlCommand::Arm |
+        ControlCommand::ReloadConfig => {
+            "only available in the console".to_string()
+        }
+    })
+
+}
+
+/// Load the configuration again and swap it, the connection being reopened
+/// with the new one. The state of the channels is kept, so the settings of
+/// their files and the channels joined need a restart.
+fn reload_config(config: &mut Config, reload: impl Fn() -> Result<Config, Vec<String>>, channels: &mut [Channel]) -> String {
+
+    let mut new_config = match reload() {
+        Ok(new_config) => new_config,
+        Err(errors) => return format!("error: invalid configuration, kept the current one: {}", errors.join(", ")),
+    };
+
+    if new_config.channels != config.channels {
+        return "error: the channels changed, restart to join them".to_string();
+    }
+
+    // The random choices stay reproducible from the seed of the session.
+    new_config.seed = config.seed;
+
+    // The bot mode is left as is, it is changed with `set bot=...`.
+    for channel in channels.iter_mut() {
+        channel.rules = new_config.rules.clone();
+        channel.script = new_config.script.as_ref().map(Script::new);
+        channel.stats.set_command_policy(new_config.command_policy.clone());
+    }
+
+    *config = new_config;
+    info!("configuration reloaded");
+    "configuration reloaded".to_string()
+
+}
+
+/// The votes of a sample for each command, the most voted first, like
+/// `a:12,n:8,b:3`.
+fn votes_text(sample: &Sample, vocabulary: &Vocabulary) -> String {
+    let mut votes = sample.counts.iter()
+        .filter(|&(_, count)| count > 0)
+        .collect::<Vec<_>>();
+    votes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.index().cmp(&b.0.index())));
+    votes.iter()
+        .map(|&(command, count)| format!("{}:{count}", vocabulary.output(command)))
+        .collect::<Vec<_>>()
+        .join(",")
+}
@@ -0,0 +1,156 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::config::{parse_seconds, Settings};
+
+
+/// Configuration of the input bursts.
+#[derive(Debug, Clone)]
+pub struct BurstConfig {
+    /// Number of messages of each burst.
+    pub size: usize,
+    /// Duration over which the messages of a burst are spread.
+    pub span: Duration,
+    /// Minimum share of the most used command among the TPP commands.
+    pub consensus: f32,
+    /// Minimum delay between the end of a burst and the start of the next.
+    pub cooldown: Duration,
+}
+
+impl BurstConfig {
+
+    /// Read the burst configuration from the `burst_*` settings, none if no
+    /// burst size is configured.
+    pub fn from_settings(settings: &Settings) -> Result<Option<Self>, String> {
+
+        let Some(size) = settings.parse::<usize>("burst_size")? else { return Ok(None) };
+        if size < 2 {
+            return Err(format!("invalid {}: expected at least 2 messages", settings.origin("burst_size")));
+        }
+
+        let config = Self {
+            size,
+            span: settings.parse_with("burst_span", parse_seconds)?.unwrap_or(Duration::from_secs(2)),
+            consensus: settings.parse("burst_consensus")?.unwrap_or(0.8),
+            cooldown: settings.parse_with("burst_cooldown", parse_seconds)?.unwrap_or(Duration::from_secs(30)),
+        };
+
+        if !(0.0..=1.0).contains(&config.consensus) {
+            return Err(format!("invalid {}: expected a ratio between 0 and 1", settings.origin("burst_consensus")));
+        }
+
+        Ok(Some(config))
+
+    }
+
+}
+
+
+/// Bursts of a channel, sending several messages of the consensus command
+/// within a short time when the chat strongly agrees. Only possible with the
+/// higher rate limit of moderators.
+#[derive(Debug)]
+pub struct Burst {
+    config: BurstConfig,
+    /// Due times of the messages of the current burst not yet sent.
+    pending: VecDeque<Instant>,
+    /// No burst starts before this time.
+    next_start: Instant,
+}
+
+impl Burst {
+
+    pub fn new(config: BurstConfig) -> Self {
+        Self {
+            config,
+            pending: VecDeque::new(),
+            next_start: Instant::now(),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.config.size
+    }
+
+    /// Start a burst if the consensus is high enough and the remaining budget
+    /// covers all its messages. The first message is due immediately and is
+    /// not returned by [`Self::due`].
+    pub fn start(&mut self, now: Instant, consensus: f32, budget: usize) -> bool {
+
+        if !self.pending.is_empty() || now < self.next_start {
+            return false;
+        }
+        if consensus < self.config.consensus || budget < self.config.size {
+            return false;
+        }
+
+        let step = self.config.span / (self.config.size as u32 - 1);
+        self.pending = (1..self.config.size as u32).map(|index| now + step * index).collect();
+        self.next_start = now + self.config.span + self.config.cooldown;
+        true
+
+    }
+
+    /// Return the index of the burst message due at the given time, if any,
+    /// starting at 1 after the first message. The burst is cancelled when the
+    /// consensus drops.
+    pub fn due(&mut self, now: Instant, consensus: f32) -> Option<usize> {
+
+        if consensus < self.config.consensus {
+            self.cancel();
+            return None;
+        }
+
+        let time = *self.pending.front()?;
+        if now < time {
+            return None;
+        }
+
+        self.pending.pop_front();
+        Some(self.config.size - 1 - self.pending.len())
+
+    }
+
+    /// Cancel the pending messages of the current burst, the cooldown still
+    /// applies.
+    pub fn cancel(&mut self) {
+        self.pending.clear();
+    }
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn burst() {
+
+        let mut burst = Burst::new(BurstConfig {
+            size: 3,
+            span: Duration::from_secs(2),
+            consensus: 0.8,
+            cooldown: Duration::from_secs(30),
+        });
+
+        let start = Instant::now();
+        assert!(!burst.start(start, 0.5, 100));
+        assert!(!burst.start(start, 0.9, 2));
+        assert!(burst.start(start, 0.9, 100));
+
+        assert_eq!(burst.due(start, 0.9), None);
+        assert_eq!(burst.due(start + Duration::from_secs(1), 0.9), Some(1));
+        assert_eq!(burst.due(start + Duration::from_secs(2), 0.9), Some(2));
+        assert_eq!(burst.due(start + Duration::from_secs(3), 0.9), None);
+
+        // The cooldown starts at the end of the burst.
+        assert!(!burst.start(start + Duration::from_secs(31), 0.9, 100));
+        assert!(burst.start(start + Duration::from_secs(32), 0.9, 100));
+        assert_eq!(burst.due(start + Duration::from_secs(33), 0.5), None);
+        assert_eq!(burst.due(start + Duration::from_secs(34), 0.9), None);
+
+    }
+
+}
@@ -0,0 +1,254 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::fs;
+use std::io;
+
+use chrono::{DateTime, Utc};
+use tracing::{info, warn};
+
+use crate::sample::Sample;
+
+
+/// Totals of a channel during a run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RunTotals {
+    /// Messages of the chat.
+    pub messages: u64,
+    /// TPP commands of the chat.
+    pub commands: u64,
+    /// Messages sent by the bot.
+    pub sent: u64,
+}
+
+/// A named run, under which all stats are grouped.
+#[derive(Debug, Clone)]
+pub struct Run {
+    pub name: String,
+    pub start: DateTime<Utc>,
+    /// Totals of each channel since the start of the run.
+    pub channels: BTreeMap<String, RunTotals>,
+}
+
+impl Run {
+
+    /// Summary of the run ended at the given time, with the totals of each
+    /// channel.
+    pub fn summary(&self, now: DateTime<Utc>) -> String {
+
+        let duration = now - self.start;
+        let mut summary = format!("run '{}' ended after {}h{:02}m",
+            self.name,
+            duration.num_hours(),
+            duration.num_minutes() % 60);
+
+        for (channel, totals) in &self.channels {
+            let ratio = if totals.messages == 0 { 0.0 } else { totals.commands as f64 / totals.messages as f64 };
+            summary.push_str(&format!(", #{channel}: {} messages, {} commands ({:.1}%), {} sent",
+                totals.messages, totals.commands, ratio * 100.0, totals.sent));
+        }
+
+        summary
+
+    }
+
+}
+
+/// Track the current run of a campaign, persisting it to a file so that
+/// a restarted bot continues the same run. The first line of the file is
+/// the start timestamp and the name of the run, the next ones are the
+/// channels with their messages, commands and sent messages.
+#[derive(Debug)]
+pub struct Campaign {
+    path: Option<PathBuf>,
+    current: Option<Run>,
+    /// True while the file fails, so that the error is only reported once.
+    failing: bool,
+}
+
+impl Campaign {
+
+    /// Open the campaign state from the given file, if existing.
+    pub fn open(path: Option<PathBuf>) -> io::Result<Self> {
+
+        let mut current = None;
+
+        if let Some(path) = &path {
+            match fs::read_to_string(path) {
+                Ok(content) => {
+                    let mut lines = content.lines();
+                    if let Some((timestamp, name)) = lines.next().and_then(|line| line.split_once('\t')) {
+                        let start = timestamp.parse::<i64>().ok()
+                            .and_then(|timestamp| DateTime::from_timestamp(timestamp, 0));
+                        if let Some(start) = start {
+                            let channels = lines.filter_map(parse_totals).collect();
+                            current = Some(Run { name: name.to_string(), start, channels });
+                        }
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(Self { path, current, failing: false })
+
+    }
+
+    pub fn current(&self) -> Option<&Run> {
+        self.current.as_ref()
+    }
+
+    /// Start a new run at the given time, returning the previous one if any.
+    pub fn start(&mut self, name: String, start: DateTime<Utc>) -> io::Result<Option<Run>> {
+        let run = Run { name, start, channels: BTreeMap::new() };
+        if let Some(path) = &self.path {
+            fs::write(path, content(&run))?;
+        }
+        Ok(self.current.replace(run))
+    }
+
+    /// End the current run, returning it if any.
+    pub fn end(&mut self) -> io::Result<Option<Run>> {
+        if let Some(path) = &self.path {
+            match fs::remove_file(path) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(self.current.take())
+    }
+
+    /// Count a sample of the chat of a channel in the current run.
+    pub fn push_sample(&mut self, channel: &str, sample: &Sample) {
+        self.update(channel, |totals| {
+            totals.messages += sample.message_count as u64;
+            totals.commands += sample.tpp_command_count as u64;
+        });
+    }
+
+    /// Count a message sent in a channel in the current run.
+    pub fn push_sent(&mut self, channel: &str) {
+        self.update(channel, |totals| totals.sent += 1);
+    }
+
+    /// Update the totals of a channel, if a run is started, and save them.
+    /// The totals stay in memory if the file fails and are saved again with
+    /// the next update.
+    fn update(&mut self, channel: &str, f: impl FnOnce(&mut RunTotals)) {
+
+        let Some(run) = &mut self.current else { return };
+        f(run.channels.entry(channel.to_string()).or_default());
+
+        let Some(path) = &self.path else { return };
+        match fs::write(path, content(run)) {
+            Ok(()) => {
+                if self.failing {
+                    info!("{} written again", path.display());
+                    self.failing = false;
+                }
+            }
+            Err(e) => {
+                if !self.failing {
+                    warn!("\x07alert: failed to write {}: {e}, keeping the run in memory", path.display());
+                    self.failing = true;
+                }
+            }
+        }
+
+    }
+
+}
+
+
+/// Content of the run file.
+fn content(run: &Run) -> String {
+    let mut content = format!("{}\t{}\n", run.start.timestamp(), run.name);
+    for (channel, totals) in &run.channels {
+        content.push_str(&format!("{channel}\t{}\t{}\t{}\n", totals.messages, totals.commands, totals.sent));
+    }
+    content
+}
+
+/// Parse a line of the totals of a channel.
+fn parse_totals(line: &str) -> Option<(String, RunTotals)> {
+    let mut parts = line.split('\t');
+    let channel = parts.next()?.to_string();
+    let totals = RunTotals {
+        messages: parts.next()?.parse().ok()?,
+        commands: parts.next()?.parse().ok()?,
+        sent: parts.next()?.parse().ok()?,
+    };
+    Some((channel, totals))
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::vocabulary::Command;
+
+    #[test]
+    fn boundaries() {
+
+        let path = std::env::temp_dir().join(format!("tpp-bot-run-{}.tsv", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let start = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+
+        let mut sample = Sample::default();
+        sample.push_message(Some(Command::Up));
+        sample.push_message(None);
+
+        // Nothing is counted outside of a run.
+        let mut campaign = Campaign::open(Some(path.clone())).unwrap();
+        campaign.push_sample("chan", &sample);
+        assert!(campaign.current().is_none());
+        assert!(!path.exists());
+
+        assert!(campaign.start("one".to_string(), start).unwrap().is_none());
+        campaign.push_sample("chan", &sample);
+        campaign.push_sample("chan", &sample);
+        campaign.push_sent("chan");
+
+        // A restarted bot continues the run with its totals.
+        let mut campaign = Campaign::open(Some(path.clone())).unwrap();
+        campaign.push_sample("other", &sample);
+        let run = campaign.current().unwrap();
+        assert_eq!((run.name.as_str(), run.start), ("one", start));
+        assert_eq!(run.channels["chan"], RunTotals { messages: 4, commands: 2, sent: 1 });
+        assert_eq!(run.channels["other"], RunTotals { messages: 2, commands: 1, sent: 0 });
+
+        // The next run starts from zero.
+        let prev = campaign.start("two".to_string(), start + chrono::Duration::hours(2)).unwrap().unwrap();
+        assert_eq!(prev.name, "one");
+        assert_eq!(prev.channels.len(), 2);
+        assert!(campaign.current().unwrap().channels.is_empty());
+        campaign.push_sent("chan");
+
+        let prev = campaign.end().unwrap().unwrap();
+        assert_eq!(prev.name, "two");
+        assert_eq!(prev.channels["chan"], RunTotals { messages: 0, commands: 0, sent: 1 });
+        assert!(campaign.current().is_none());
+        assert!(!path.exists());
+        assert!(campaign.end().unwrap().is_none());
+
+    }
+
+    #[test]
+    fn summary() {
+
+        let start = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let mut run = Run { name: "Red".to_string(), start, channels: BTreeMap::new() };
+        let now = start + chrono::Duration::minutes(125);
+        assert_eq!(run.summary(now), "run 'Red' ended after 2h05m");
+
+        run.channels.insert("b".to_string(), RunTotals { messages: 0, commands: 0, sent: 0 });
+        run.channels.insert("a".to_string(), RunTotals { messages: 200, commands: 150, sent: 12 });
+        assert_eq!(run.summary(now), "run 'Red' ended after 2h05m, \
+            #a: 200 messages, 150 commands (75.0%), 12 sent, \
+            #b: 0 messages, 0 commands (0.0%), 0 sent");
+
+    }
+
+}
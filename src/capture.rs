@@ -0,0 +1,154 @@
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::fs::File;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use tracing::warn;
+
+use crate::transport::IrcTransport;
+
+
+/// Marker of the lines received from the server.
+pub const RECEIVED: char = '<';
+/// Marker of the lines sent to the server.
+pub const SENT: char = '>';
+
+
+/// A transport recording every raw line received and sent to a capture
+/// file, like `2024-03-12T12:00:00.123Z\t<\t@tags :user PRIVMSG #chan :a`,
+/// for debugging the parser. The received lines are kept even if they can't
+/// be decoded, and the captures can be imported like raw IRC logs. The
+/// token of the authentication is never written.
+pub struct CaptureStream<T> {
+    inner: T,
+    /// The capture file, none once it failed so that the connection isn't
+    /// broken by the capture.
+    file: Option<File>,
+    /// Partial lines received and sent.
+    received: Vec<u8>,
+    sent: Vec<u8>,
+}
+
+impl<T> CaptureStream<T> {
+
+    /// Wrap a transport, appending its lines to the given file.
+    pub fn open(inner: T, path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            inner,
+            file: Some(File::options().append(true).create(true).open(path)?),
+            received: Vec::new(),
+            sent: Vec::new(),
+        })
+    }
+
+}
+
+impl<T: IrcTransport> IrcTransport for CaptureStream<T> {
+    fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()> {
+        self.inner.set_nonblocking(nonblocking)
+    }
+}
+
+impl<T: Read> Read for CaptureStream<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let size = self.inner.read(buf)?;
+        capture(&mut self.file, &mut self.received, &buf[..size], RECEIVED);
+        Ok(size)
+    }
+}
+
+impl<T: Write> Write for CaptureStream<T> {
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Only the bytes accepted by the transport were sent.
+        let size = self.inner.write(buf)?;
+        capture(&mut self.file, &mut self.sent, &buf[..size], SENT);
+        Ok(size)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+}
+
+
+/// Parse a line of a capture, returning its time, its direction marker and
+/// the raw line, none if it isn't a captured line.
+pub fn parse_line(line: &str) -> Option<(DateTime<Utc>, char, &str)> {
+    let (time, rest) = line.split_once('\t')?;
+    let time = DateTime::parse_from_rfc3339(time).ok()?.with_timezone(&Utc);
+    let (marker, line) = rest.split_once('\t')?;
+    match marker {
+        "<" => Some((time, RECEIVED, line)),
+        ">" => Some((time, SENT, line)),
+        _ => None,
+    }
+}
+
+
+/// Append the data to the partial line, and write the lines completed.
+fn capture(file: &mut Option<File>, partial: &mut Vec<u8>, data: &[u8], marker: char) {
+
+    let Some(out) = file else { return };
+    partial.extend_from_slice(data);
+
+    let mut record = String::new();
+    let time = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+    let mut start = 0;
+    while let Some(pos) = partial[start..].iter().position(|&b| b == b'\n') {
+        let line = String::from_utf8_lossy(&partial[start..start + pos]);
+        let line = line.trim_end_matches('\r');
+        let line = match line.strip_prefix("PASS ") {
+            Some(_) => "PASS ***",
+            None => line,
+        };
+        record.push_str(&format!("{time}\t{marker}\t{line}\n"));
+        start += pos + 1;
+    }
+    partial.drain(..start);
+
+    if let Err(e) = out.write_all(record.as_bytes()) {
+        warn!("raw capture stopped: {e}");
+        *file = None;
+    }
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use crate::transport::MockTransport;
+
+    use super::*;
+
+    #[test]
+    fn capture() {
+
+        let path = std::env::temp_dir().join(format!("tpp-bot-capture-{}.txt", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut stream = CaptureStream::open(MockTransport::default(), &path).unwrap();
+        stream.inner.input = b"@user-id=1 :foo!foo@foo.tmi.twitch.tv PRIVMSG #chan :a\r\n:tmi.twitch.tv PI".to_vec();
+        let mut buf = [0; 256];
+        assert_eq!(stream.read(&mut buf).unwrap(), 73);
+        stream.write_all(b"PASS oauth:secret\r\nPRIVMSG #chan :b\r\nPRIV").unwrap();
+        stream.inner.input = b"NG :tmi.twitch.tv\r\n".to_vec();
+        assert_eq!(stream.read(&mut buf).unwrap(), 19);
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines = content.lines().map(|line| parse_line(line).unwrap()).map(|(_, marker, line)| (marker, line)).collect::<Vec<_>>();
+        assert_eq!(lines, [
+            (RECEIVED, "@user-id=1 :foo!foo@foo.tmi.twitch.tv PRIVMSG #chan :a"),
+            (SENT, "PASS ***"),
+            (SENT, "PRIVMSG #chan :b"),
+            (RECEIVED, ":tmi.twitch.tv PING :tmi.twitch.tv"),
+        ]);
+        assert!(!content.contains("secret"));
+        assert_eq!(parse_line("@user-id=1 :foo PRIVMSG #chan :a"), None);
+
+    }
+
+}
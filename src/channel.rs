@@ -0,0 +1,196 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use std::io;
+
+use chrono::Utc;
+use rand::rngs::StdRng;
+use tracing::warn;
+
+use crate::archive::ChatArchive;
+use crate::autobot::AutoBot;
+use crate::binlog::BinaryLog;
+use crate::burst::Burst;
+use crate::config::{ChatSource, Config};
+use crate::database::Database;
+use crate::democracy::Democracy;
+use crate::history::SentHistory;
+use crate::lock::SendLock;
+use crate::logfile::LogFile;
+use crate::rng;
+use crate::roomstate::RoomState;
+use crate::rules::Rules;
+use crate::schedule::DailyCount;
+use crate::script::Script;
+use crate::seen::SeenUsers;
+use crate::shadow::{ShadowConfig, Shadows};
+use crate::stats::Stats;
+use crate::touch::TouchTaps;
+use crate::tppbot::InputMode;
+use crate::vocabulary::Command;
+
+
+/// State of a joined channel, its samples, files and sending decisions are
+/// independent of the other channels.
+pub struct Channel {
+    pub name: String,
+    pub log_file: LogFile,
+    /// Binary log of every sample, if enabled.
+    pub binary_log: Option<BinaryLog>,
+    pub seen_users: Option<SeenUsers>,
+    /// Last messages sent in the channel, across restarts.
+    pub sent_history: SentHistory,
+    /// Optional file where a random sample of non-command messages are logged.
+    pub chat_sample_file: Option<LogFile>,
+    pub archive: Option<ChatArchive>,
+    pub database: Option<Database>,
+    /// Samples and windows of the chat.
+    pub stats: Stats,
+    pub next_message_time: Instant,
+    /// Number of messages sent since the beginning.
+    pub message_count: u32,
+    /// Number of messages sent today.
+    pub daily_sent: DailyCount,
+    /// Last command sent, compared to the move announced by the stream.
+    pub last_sent: Option<Command>,
+    /// Taps of the touchscreen commands, if enabled.
+    pub touches: Option<TouchTaps>,
+    /// Voting windows of the democracy mode, if the stream is in democracy.
+    pub democracy: Option<Democracy>,
+    /// Strategies run in shadow of the live one.
+    pub shadows: Option<Shadows>,
+    /// Alert rules, with their state.
+    pub rules: Rules,
+    /// Script deciding the commands to send, if any.
+    pub script: Option<Script>,
+    /// Current bot mode, may be switched automatically.
+    pub bot: bool,
+    pub auto_bot: Option<AutoBot>,
+    /// True if the bot is moderator or broadcaster of the channel, as
+    /// reported by the server.
+    pub moderator: bool,
+    /// Chat modes of the channel, as reported by the server.
+    pub room_state: RoomState,
+    /// End of the window after a raid, during which the chatter messages
+    /// are not counted in the samples.
+    pub raid_end: Option<Instant>,
+    /// Minimum command ratio and commands per second to send, may be
+    /// changed by the owner.
+    pub min_ratio: f32,
+    pub min_cmd_sec: f32,
+    /// Fixed interval between two messages set by the owner, instead of the
+    /// interval curve.
+    pub interval: Option<Duration>,
+    /// Command forced by the owner, sent as soon as possible.
+    pub forced: Option<Command>,
+    pub burst: Option<Burst>,
+    /// Lock of the account in the channel, held while this instance may
+    /// send in the channel.
+    pub send_lock: Option<SendLock>,
+    /// Generator of the random choices in the channel.
+    pub rng: StdRng,
+    /// Generator of the jitter of the sending cadence.
+    pub jitter_rng: StdRng,
+}
+
+impl Channel {
+
+    /// Open the state of a channel. When several channels are joined, the
+    /// files of each channel are suffixed with its name.
+    pub fn open(config: &Config, name: &str) -> io::Result<Self> {
+
+        let multi = config.channels.len() > 1;
+        let path = |path: &Path| if multi { channel_path(path, name) } else { path.to_path_buf() };
+
+        let chat_sample_file = match &config.chat_sample_path {
+            Some(chat_sample_path) => Some(LogFile::open(&path(chat_sample_path), Vec::new())?),
+            None => None,
+        };
+
+        let shadows = match &config.shadow {
+            Some(shadow) => Some(Shadows::new(&ShadowConfig {
+                log_path: path(&shadow.log_path),
+                strategies: shadow.strategies.clone(),
+            }, rng::derive(config.seed, &format!("shadow#{name}")))?),
+            None => None,
+        };
+
+        // Another instance sending as the same account would double the
+        // message rate, this one then only observes the channel.
+        let mut bot = config.bot;
+        let mut auto_bot = config.auto_bot.clone();
+        let mut send_lock = None;
+        if matches!(config.source, ChatSource::Twitch) && !config.dry_run && (bot || auto_bot.is_some()) {
+            match SendLock::acquire(&config.lock_dir, &config.user, name)? {
+                Ok(lock) => send_lock = Some(lock),
+                Err(pid) => {
+                    let pid_text = pid.map(|pid| format!(" (pid {pid})")).unwrap_or_default();
+                    warn!(channel = name, "another instance{pid_text} is sending as {}, observing only", config.user);
+                    bot = false;
+                    auto_bot = None;
+                }
+            }
+        }
+
+        let header = config.record_format.header().unwrap_or_default().into_bytes();
+        let log_file = LogFile::open(&path(&config.log_path), header)?
+            .with_rotation(config.log_rotation.clone())
+            .with_flush(config.log_flush.clone());
+
+        // The messages sent earlier in the day, before a restart, still
+        // count for the daily limit.
+        let sent_history = SentHistory::open(config.history_path.as_deref().map(path), config.history_size)?;
+        let daily_sent = DailyCount::from_times(sent_history.messages().map(|(time, _)| time), Utc::now());
+
+        Ok(Self {
+            name: name.to_string(),
+            log_file,
+            binary_log: config.binary_log_path.as_deref().map(|binary_log_path| BinaryLog::open(&path(binary_log_path), config.log_rotation.clone(), config.log_flush.clone())).transpose()?,
+            seen_users: config.seen_path.as_deref().map(|seen_path| SeenUsers::open(&path(seen_path))).transpose()?,
+            sent_history,
+            chat_sample_file,
+            database: config.sqlite_path.as_deref().map(|sqlite_path| Database::open(&path(sqlite_path), name, &config.user, Utc::now())).transpose()?,
+            archive: config.archive_path.as_deref().map(|archive_path| ChatArchive::open(&path(archive_path))).transpose()?,
+            stats: Stats::new()
+                .with_vote_dedup(config.vote_dedup)
+                .with_command_policy(config.command_policy.clone(), rng::derive(config.seed, &format!("ties#{name}"))),
+            next_message_time: Instant::now(),
+            message_count: 0,
+            daily_sent,
+            last_sent: None,
+            touches: config.touch.clone().map(TouchTaps::new),
+            democracy: config.democracy.filter(|_| config.start_mode == InputMode::Democracy).map(Democracy::new),
+            shadows,
+            rules: config.rules.clone(),
+            script: config.script.as_ref().map(Script::new),
+            bot,
+            auto_bot,
+            moderator: false,
+            room_state: RoomState::default(),
+            raid_end: None,
+            min_ratio: config.trigger.min_ratio,
+            min_cmd_sec: config.trigger.min_cmd_sec,
+            interval: None,
+            forced: None,
+            burst: config.burst.clone().map(Burst::new),
+            send_lock,
+            rng: rng::derive(config.seed, &format!("channel#{name}")),
+            jitter_rng: rng::derive(config.seed, &format!("jitter#{name}")),
+        })
+
+    }
+
+}
+
+
+/// Derive the path of a file of the given channel, like `log.chan.tsv` from
+/// `log.tsv`.
+pub fn channel_path(path: &Path, channel: &str) -> PathBuf {
+    let mut file_name = path.file_stem().unwrap_or_default().to_os_string();
+    file_name.push(".");
+    file_name.push(channel);
+    if let Some(extension) = path.extension() {
+        file_name.push(".");
+        file_name.push(extension);
+    }
+    path.with_file_name(file_name)
+}
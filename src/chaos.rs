@@ -0,0 +1,303 @@
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+use std::fmt;
+
+use rand::distributions::WeightedIndex;
+use rand::prelude::*;
+use chrono::Utc;
+
+use crate::config::{parse_seconds, Settings};
+use crate::rng;
+use crate::transport::IrcTransport;
+
+
+/// Some non-command messages randomly sent by synthetic chatters.
+const CHATTER_TEXTS: &[&str] = &[
+    "lol", "gg", "PogChamp", "KEKW", "LUL", "what is happening",
+    "hello chat", "!help", "nooo", "we are so back",
+];
+
+
+/// Configuration of the synthetic chat generator.
+#[derive(Debug, Clone)]
+pub struct ChaosConfig {
+    /// Average number of messages per second.
+    pub rate: f32,
+    /// Ratio of messages that are TPP commands.
+    pub ratio: f32,
+    /// Commands sent by the synthetic chatters, with their weights.
+    pub mix: Vec<(String, f32)>,
+    /// Optional burst pattern.
+    pub burst: Option<Burst>,
+    /// Number of distinct synthetic chatters.
+    pub users: u32,
+}
+
+/// A burst pattern, multiplying the message rate for a while at regular
+/// interval, like when the chat is raided or hyped.
+#[derive(Debug, Clone)]
+pub struct Burst {
+    pub interval: Duration,
+    pub duration: Duration,
+    pub factor: f32,
+}
+
+impl ChaosConfig {
+
+    /// Read the chaos configuration from the `chaos_*` settings.
+    pub fn from_settings(settings: &Settings) -> Result<Self, String> {
+        Ok(Self {
+            rate: settings.parse("chaos_rate")?.unwrap_or(10.0),
+            ratio: settings.parse("chaos_ratio")?.unwrap_or(0.8),
+            mix: Self::parse_mix(settings.get("chaos_mix").unwrap_or("n:4,s:3,w:3,e:3,a:3,b:1,start:0.1"))
+                .map_err(|e| format!("invalid {}: {e}", settings.origin("chaos_mix")))?,
+            burst: settings.parse_with("chaos_burst", Burst::parse)?,
+            users: settings.parse("chaos_users")?.unwrap_or(200),
+        })
+    }
+
+    /// Parse a command mix from a `command:weight,...` string.
+    pub fn parse_mix(text: &str) -> Result<Vec<(String, f32)>, String> {
+
+        let mut mix = Vec::new();
+
+        for part in text.split(',') {
+            let (command, weight) = part.trim().split_once(':')
+                .ok_or_else(|| format!("expected 'command:weight', got '{part}'"))?;
+            let weight = weight.parse::<f32>().map_err(|e| format!("invalid weight of '{command}': {e}"))?;
+            if !weight.is_finite() || weight < 0.0 {
+                return Err(format!("weight of '{command}' must be a positive number, got {weight}"));
+            }
+            mix.push((command.to_string(), weight));
+        }
+
+        if !mix.iter().any(|&(_, weight)| weight > 0.0) {
+            return Err("at least one command must have a positive weight".to_string());
+        }
+
+        Ok(mix)
+
+    }
+
+}
+
+impl Burst {
+
+    /// Parse a burst pattern from an `interval,duration,factor` string,
+    /// with interval and duration in seconds.
+    pub fn parse(text: &str) -> Result<Self, String> {
+
+        let mut parts = text.split(',').map(str::trim);
+
+        let (Some(interval), Some(duration), Some(factor), None) = (parts.next(), parts.next(), parts.next(), parts.next()) else {
+            return Err(format!("expected 'interval,duration,factor', got '{text}'"));
+        };
+
+        Ok(Self {
+            interval: parse_seconds(interval).map_err(|e| format!("invalid interval: {e}"))?,
+            duration: parse_seconds(duration).map_err(|e| format!("invalid duration: {e}"))?,
+            factor: factor.parse().map_err(|e| format!("invalid factor: {e}"))?,
+        })
+
+    }
+
+}
+
+
+/// A fake IRC stream generating synthetic chat messages, used to run the
+/// whole pipeline locally without connecting to Twitch.
+pub struct ChaosStream {
+    config: ChaosConfig,
+    mix_index: WeightedIndex<f32>,
+    /// Channels where messages are sent, at random.
+    channels: Vec<String>,
+    /// Data waiting to be read.
+    pending: Vec<u8>,
+    /// Data written but not yet processed.
+    written: Vec<u8>,
+    /// True for each user that already sent a message.
+    seen_users: Vec<bool>,
+    start: Instant,
+    last: Instant,
+    /// Number of messages to generate, accumulated over time.
+    debt: f32,
+    rng: StdRng,
+}
+
+impl ChaosStream {
+
+    /// Create the stream, its messages are drawn from the given seed.
+    pub fn new(config: ChaosConfig, seed: u64, user: &str, channels: &[String]) -> Result<Self, String> {
+
+        let mix_index = WeightedIndex::new(config.mix.iter().map(|&(_, weight)| weight))
+            .map_err(|e| format!("invalid chaos mix: {e}"))?;
+        let seen_users = vec![false; config.users as usize];
+
+        let mut stream = Self {
+            config,
+            mix_index,
+            channels: channels.to_vec(),
+            pending: Vec::new(),
+            written: Vec::new(),
+            seen_users,
+            start: Instant::now(),
+            last: Instant::now(),
+            debt: 0.0,
+            rng: rng::derive(seed, "chaos"),
+        };
+
+        push_line(&mut stream.pending, format_args!(":tmi.twitch.tv 001 {user} :Welcome, GLHF!"));
+        Ok(stream)
+
+    }
+
+    /// Generate all messages due since the last call.
+    fn generate(&mut self) {
+
+        let now = Instant::now();
+        let elapsed = now - self.last;
+        self.last = now;
+
+        let mut rate = self.config.rate;
+        if let Some(burst) = &self.config.burst {
+            let phase = (now - self.start).as_secs_f32() % burst.interval.as_secs_f32();
+            if phase < burst.duration.as_secs_f32() {
+                rate *= burst.factor;
+            }
+        }
+
+        // Limit the debt to avoid flooding after a long pause.
+        self.debt = (self.debt + rate * elapsed.as_secs_f32()).min(1000.0);
+
+        while self.debt >= 1.0 {
+
+            self.debt -= 1.0;
+
+            let text = if self.rng.gen::<f32>() < self.config.ratio {
+                self.config.mix[self.mix_index.sample(&mut self.rng)].0.clone()
+            } else {
+                CHATTER_TEXTS.choose(&mut self.rng).unwrap().to_string()
+            };
+
+            let user_id = self.rng.gen_range(0..self.config.users.max(1)) as usize;
+            let first_msg = self.seen_users.get_mut(user_id)
+                .map(|seen| !std::mem::replace(seen, true))
+                .unwrap_or(false);
+
+            // Some rare messages are cheers.
+            let bits = if self.rng.gen::<f32>() < 0.01 { "bits=100;" } else { "" };
+            // Most messages are sent from the official clients.
+            let nonce = if self.rng.gen::<f32>() < 0.7 { "client-nonce=0;" } else { "" };
+
+            push_line(&mut self.pending, format_args!("@{bits}{nonce}first-msg={};tmi-sent-ts={};user-id={user_id} :chatter{user_id}!chatter{user_id}@chatter{user_id}.tmi.twitch.tv PRIVMSG #{} :{text}",
+                first_msg as u8,
+                Utc::now().timestamp_millis(),
+                self.channels.choose(&mut self.rng).unwrap()));
+
+        }
+
+    }
+
+}
+
+/// Push an IRC line to be read.
+pub fn push_line(pending: &mut Vec<u8>, fmt: fmt::Arguments) {
+    pending.write_fmt(fmt).unwrap();
+    pending.extend_from_slice(b"\r\n");
+}
+
+/// Process the complete lines written to a fake server, pings, capability
+/// requests and joins are answered and other commands are discarded. The bot
+/// is moderator of the joined channels, to exercise the moderator features.
+pub fn answer_written(written: &mut Vec<u8>, pending: &mut Vec<u8>) {
+    while let Some(pos) = written.windows(2).position(|w| w == b"\r\n") {
+        let line = String::from_utf8_lossy(&written[..pos]).into_owned();
+        written.drain(..pos + 2);
+        if let Some(text) = line.strip_prefix("PING :") {
+            push_line(pending, format_args!(":tmi.twitch.tv PONG tmi.twitch.tv :{text}"));
+        } else if let Some(capabilities) = line.strip_prefix("CAP REQ :") {
+            push_line(pending, format_args!(":tmi.twitch.tv CAP * ACK :{capabilities}"));
+        } else if let Some(targets) = line.strip_prefix("JOIN ") {
+            for target in targets.split(',') {
+                push_line(pending, format_args!("@badges=moderator/1;mod=1;user-type=mod :tmi.twitch.tv USERSTATE {target}"));
+            }
+        }
+    }
+}
+
+
+impl IrcTransport for ChaosStream {
+    fn set_nonblocking(&mut self, _nonblocking: bool) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Read for ChaosStream {
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+
+        self.generate();
+
+        if self.pending.is_empty() {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+
+        let len = buf.len().min(self.pending.len());
+        buf[..len].copy_from_slice(&self.pending[..len]);
+        self.pending.drain(..len);
+        Ok(len)
+
+    }
+
+}
+
+impl Write for ChaosStream {
+
+    /// Written commands are discarded, except pings and capability requests
+    /// that are answered.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+
+        self.written.extend_from_slice(buf);
+        answer_written(&mut self.written, &mut self.pending);
+        Ok(buf.len())
+
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn burst() {
+
+        let burst = Burst::parse("60, 5.5, 3").unwrap();
+        assert_eq!((burst.interval, burst.duration, burst.factor), (Duration::from_secs(60), Duration::from_millis(5500), 3.0));
+
+        assert!(Burst::parse("-60,5,3").unwrap_err().starts_with("invalid interval"));
+        assert!(Burst::parse("60,inf,3").unwrap_err().starts_with("invalid duration"));
+        assert!(Burst::parse("60,NaN,3").unwrap_err().starts_with("invalid duration"));
+
+    }
+
+    #[test]
+    fn mix() {
+
+        assert_eq!(ChaosConfig::parse_mix("n:4, start:0.5,b:0").unwrap(), [("n".to_string(), 4.0), ("start".to_string(), 0.5), ("b".to_string(), 0.0)]);
+
+        assert_eq!(ChaosConfig::parse_mix("n:4,s:-1").unwrap_err(), "weight of 's' must be a positive number, got -1");
+        assert_eq!(ChaosConfig::parse_mix("n:NaN").unwrap_err(), "weight of 'n' must be a positive number, got NaN");
+        assert_eq!(ChaosConfig::parse_mix("n:4,s:inf").unwrap_err(), "weight of 's' must be a positive number, got inf");
+        assert_eq!(ChaosConfig::parse_mix("n:0,s:0").unwrap_err(), "at least one command must have a positive weight");
+        assert!(ChaosConfig::parse_mix("n").unwrap_err().starts_with("expected 'command:weight'"));
+
+    }
+
+}
@@ -0,0 +1,194 @@
+use std::path::PathBuf;
+
+use chrono::NaiveDate;
+use clap::{Args, Parser, Subcommand};
+
+use tpp_bot::analyze;
+use tpp_bot::binlog::BinaryLogFormat;
+use tpp_bot::import::{ImportFormat, TextZone};
+
+
+/// Analyze the chat of Twitch Plays channels, and optionally play along.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<CliCommand>,
+    #[command(flatten)]
+    pub settings: SettingsArgs,
+}
+
+/// Flags overriding the settings of the configuration file and of the
+/// environment.
+#[derive(Debug, Args)]
+pub struct SettingsArgs {
+    /// Configuration file, config.toml by default if it exists.
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+    /// Channels to join, separated by commas.
+    #[arg(long, global = true)]
+    pub channel: Option<String>,
+    /// Path of the TSV log of the chat statistics.
+    #[arg(long, global = true, value_name = "PATH")]
+    pub log_path: Option<PathBuf>,
+    /// Decide as the bot would, but never send any message.
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+    /// Format of the periodic status: prompt, kv or json.
+    #[arg(long, global = true, value_name = "FORMAT")]
+    pub status_format: Option<String>,
+    /// Override any setting, like `--set chaos_rate=20`.
+    #[arg(long = "set", global = true, value_name = "KEY=VALUE")]
+    pub overrides: Vec<String>,
+}
+
+impl SettingsArgs {
+
+    /// All the settings given on the command line, as key and value.
+    pub fn overrides(&self) -> Result<Vec<(String, String)>, String> {
+
+        let mut overrides = Vec::new();
+
+        for item in &self.overrides {
+            let (key, value) = item.split_once('=')
+                .ok_or_else(|| format!("invalid --set '{item}', expected 'key=value'"))?;
+            overrides.push((key.trim().replace('-', "_"), value.to_string()));
+        }
+
+        if let Some(channel) = &self.channel {
+            overrides.push(("channel".to_string(), channel.clone()));
+        }
+        if let Some(log_path) = &self.log_path {
+            overrides.push(("log_path".to_string(), log_path.display().to_string()));
+        }
+        if let Some(status_format) = &self.status_format {
+            overrides.push(("status_format".to_string(), status_format.clone()));
+        }
+        if self.dry_run {
+            overrides.push(("dry_run".to_string(), "true".to_string()));
+        }
+
+        Ok(overrides)
+
+    }
+
+}
+
+#[derive(Debug, Subcommand)]
+pub enum CliCommand {
+    /// Connect to the Twitch chat, the default.
+    Run,
+    /// Run on a synthetic chat generated locally, configured by the
+    /// `chaos_*` settings.
+    Chaos,
+    /// Run on a recorded chat log, replayed in real time.
+    Replay {
+        format: ImportFormat,
+        input: PathBuf,
+        /// Date of the messages, for text logs that only contain the time.
+        #[arg(long)]
+        date: Option<NaiveDate>,
+        /// Speed factor of the replay.
+        #[arg(long, default_value_t = 1.0)]
+        speed: f32,
+    },
+    /// Summarize a TSV log of the chat statistics per run, or compare it
+    /// with another log or time range.
+    Analyze {
+        /// The log to analyze, the configured log path by default.
+        log: Option<PathBuf>,
+        /// Only analyze the given run.
+        #[arg(long)]
+        run: Option<String>,
+        /// Only analyze from the given local time, like `2024-01-01 12:00`.
+        #[arg(long, value_parser = analyze::parse_time)]
+        from: Option<i64>,
+        /// Only analyze until the given local time.
+        #[arg(long, value_parser = analyze::parse_time)]
+        to: Option<i64>,
+        /// Compare with another log, the same one by default when a
+        /// comparison run or time range is given.
+        #[arg(long, value_name = "LOG")]
+        compare: Option<PathBuf>,
+        /// Run of the comparison.
+        #[arg(long)]
+        compare_run: Option<String>,
+        /// Start of the comparison time range.
+        #[arg(long, value_parser = analyze::parse_time)]
+        compare_from: Option<i64>,
+        /// End of the comparison time range.
+        #[arg(long, value_parser = analyze::parse_time)]
+        compare_to: Option<i64>,
+    },
+    /// Validate the configuration and exit.
+    ValidateConfig {
+        /// Validate for the chaos mode, where no server is needed.
+        #[arg(long)]
+        chaos: bool,
+    },
+    /// Run the live and shadow strategies over a recorded chat log at full
+    /// speed, unlike the replay, and summarize their decisions.
+    Backtest {
+        format: ImportFormat,
+        input: PathBuf,
+        /// Path of the TSV log of the stats, none by default.
+        #[arg(long)]
+        output: Option<PathBuf>,
+        /// Date of the messages, for text logs that only contain the time.
+        #[arg(long)]
+        date: Option<NaiveDate>,
+    },
+    /// Import a third-party chat log into a TSV log.
+    Import {
+        format: ImportFormat,
+        input: PathBuf,
+        output: PathBuf,
+        /// Date of the messages, for text logs that only contain the time.
+        #[arg(long)]
+        date: Option<NaiveDate>,
+        /// Time zone of the times of text logs.
+        #[arg(long, value_enum, default_value = "utc")]
+        timezone: TextZone,
+    },
+    /// Convert a binary log of the samples to a text format.
+    Binlog {
+        input: PathBuf,
+        /// Path of the text log, the standard output by default.
+        output: Option<PathBuf>,
+        #[arg(long, value_enum, default_value = "tsv")]
+        format: BinaryLogFormat,
+    },
+    /// Verify a decision journal and export its compliance report.
+    Journal {
+        input: PathBuf,
+        /// Path of the report, the standard output by default.
+        report: Option<PathBuf>,
+    },
+    /// Seal a chat log before sharing it, with an integrity footer and
+    /// optional compression and encryption. Sealed logs can be replayed
+    /// and imported directly.
+    Seal {
+        input: PathBuf,
+        output: PathBuf,
+        /// Compress the log with zstd.
+        #[arg(long)]
+        compress: bool,
+        /// Encrypt the log with the `seal_passphrase` setting.
+        #[arg(long)]
+        encrypt: bool,
+    },
+    /// Verify a sealed log, and write its plain content if an output is
+    /// given.
+    Unseal {
+        input: PathBuf,
+        output: Option<PathBuf>,
+    },
+    /// Run the golden replays through the pipeline and compare the stats and
+    /// decisions with their expectations.
+    SelfTest {
+        /// Print the output of the given golden replay instead, to update
+        /// its expectations.
+        #[arg(long, value_name = "NAME")]
+        print: Option<String>,
+    },
+}
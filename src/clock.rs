@@ -0,0 +1,29 @@
+use chrono::{DateTime, Duration, Utc};
+
+
+/// A clock synchronized on the server time given by the tags of received
+/// messages, so that timestamps line up even when the local clock is
+/// skewed. The local time is used until a server time is known.
+#[derive(Debug, Default)]
+pub struct ServerClock {
+    offset: Option<Duration>,
+}
+
+impl ServerClock {
+
+    /// Observe a server timestamp for an event that just happened.
+    pub fn observe(&mut self, server_time: DateTime<Utc>) {
+        let offset = server_time - Utc::now();
+        self.offset = Some(match self.offset {
+            // Smooth the offset to absorb the network jitter.
+            Some(prev) => prev + (offset - prev) / 8,
+            None => offset,
+        });
+    }
+
+    /// Return the current time, corrected with the server time if known.
+    pub fn now(&self) -> DateTime<Utc> {
+        Utc::now() + self.offset.unwrap_or_else(Duration::zero)
+    }
+
+}
@@ -0,0 +1,630 @@
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use std::fmt::Display;
+use std::{env, fs};
+
+use crate::autobot::{AutoBot, Band};
+use crate::burst::BurstConfig;
+use crate::chaos::ChaosConfig;
+use crate::control::Owners;
+use crate::curve::IntervalCurve;
+use crate::democracy::DemocracyModel;
+use crate::emergency::EmergencyConfig;
+use crate::export::ExportConfig;
+use crate::format::NumberFormat;
+use crate::http::HttpConfig;
+use crate::ignore::IgnoreList;
+use crate::influx::InfluxConfig;
+use crate::jitter::JitterConfig;
+use crate::logfile::{FlushPolicy, Rotation};
+use crate::logformat::RecordFormat;
+use crate::logging::LogConfig;
+use crate::overlay::{OverlayConfig, OverlayFileConfig};
+use crate::policy::CommandPolicy;
+use crate::reconnect::ReconnectPolicy;
+use crate::replay::ReplayConfig;
+use crate::resolution::ResolutionConfig;
+use crate::rules::Rules;
+use crate::schedule::Schedule;
+use crate::script::ScriptSource;
+use crate::shadow::ShadowConfig;
+use crate::snapshot::SnapshotConfig;
+use crate::socket::ControlAddress;
+use crate::stats::VoteDedup;
+use crate::status::StatusConfig;
+use crate::touch::TouchConfig;
+use crate::tppbot::InputMode;
+use crate::trigger::TriggerConfig;
+use crate::upload::UploadConfig;
+use crate::variation::VariationConfig;
+use crate::vocabulary::Vocabulary;
+use crate::weights::VoteWeights;
+use crate::whisper::WhisperConfig;
+
+
+/// Default path of the configuration file, only read if it exists.
+const DEFAULT_CONFIG_PATH: &str = "config.toml";
+
+/// Default time after which the main loop is considered stalled.
+const DEFAULT_WATCHDOG_TIMEOUT: Duration = Duration::from_secs(30);
+/// Default interval between two pings sent to the server.
+const DEFAULT_PING_INTERVAL: Duration = Duration::from_secs(10);
+/// Default time without any data after which the connection is dead.
+const DEFAULT_RECEIVE_TIMEOUT: Duration = Duration::from_secs(45);
+/// Default time after a raid during which the chatter is not counted.
+const DEFAULT_RAID_WINDOW: Duration = Duration::from_secs(60);
+
+
+/// Where a setting comes from.
+#[derive(Debug, Clone)]
+enum Source {
+    File(PathBuf),
+    Env,
+    Cli,
+}
+
+/// The raw settings, layered from the lowest to the highest priority: the
+/// configuration file, the `TPP_*` environment variables and the command
+/// line flags. Keys are snake case, like `log_path`, which is `TPP_LOG_PATH`
+/// in the environment and `--set log_path=...` on the command line.
+/// Tables of the configuration file are flattened, so `rate` in a `[chaos]`
+/// table is the `chaos_rate` key.
+#[derive(Debug, Default)]
+pub struct Settings {
+    values: HashMap<String, (String, Source)>,
+}
+
+impl Settings {
+
+    /// Load the settings from the given configuration file, or `TPP_CONFIG`,
+    /// the environment and the given command line overrides.
+    pub fn load(config_path: Option<&Path>, overrides: Vec<(String, String)>) -> Result<Self, String> {
+
+        let mut settings = Self::default();
+
+        let config_path = config_path.map(Path::to_path_buf)
+            .or_else(|| env::var("TPP_CONFIG").ok().map(PathBuf::from));
+
+        match config_path {
+            Some(path) => settings.load_file(&path)?,
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => settings.load_file(Path::new(DEFAULT_CONFIG_PATH))?,
+            None => {}
+        }
+
+        for (name, value) in env::vars() {
+            if let Some(key) = name.strip_prefix("TPP_") {
+                if key != "CONFIG" {
+                    settings.values.insert(key.to_lowercase(), (value, Source::Env));
+                }
+            }
+        }
+
+        for (key, value) in overrides {
+            settings.values.insert(key, (value, Source::Cli));
+        }
+
+        Ok(settings)
+
+    }
+
+    fn load_file(&mut self, path: &Path) -> Result<(), String> {
+
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("failed to read config file {path:?}: {e}"))?;
+        let table = toml::from_str::<toml::Table>(&content)
+            .map_err(|e| format!("invalid config file {path:?}: {e}"))?;
+
+        self.load_table(path, "", table)
+
+    }
+
+    fn load_table(&mut self, path: &Path, prefix: &str, table: toml::Table) -> Result<(), String> {
+        for (key, value) in table {
+            let key = format!("{prefix}{key}");
+            let value = match value {
+                toml::Value::String(value) => value,
+                toml::Value::Integer(value) => value.to_string(),
+                toml::Value::Float(value) => value.to_string(),
+                toml::Value::Boolean(value) => value.to_string(),
+                toml::Value::Table(table) => {
+                    self.load_table(path, &format!("{key}_"), table)?;
+                    continue;
+                }
+                _ => return Err(format!("unsupported value for '{key}' in config file {path:?}")),
+            };
+            self.values.insert(key, (value, Source::File(path.to_path_buf())));
+        }
+        Ok(())
+    }
+
+    /// Get the raw value of a setting.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(|(value, _)| value.as_str())
+    }
+
+    /// Describe where a setting comes from, or where it can be set.
+    pub fn origin(&self, key: &str) -> String {
+        match self.values.get(key).map(|(_, source)| source) {
+            Some(Source::File(path)) => format!("'{key}' in {path:?}"),
+            Some(Source::Env) => format!("TPP_{}", key.to_uppercase()),
+            Some(Source::Cli) => format!("'{key}' on the command line"),
+            None => format!("'{key}' (config file, TPP_{} or --set {key}=...)", key.to_uppercase()),
+        }
+    }
+
+    /// Get a setting that must be present.
+    pub fn require(&self, key: &str) -> Result<&str, String> {
+        self.get(key).ok_or_else(|| format!("missing {}", self.origin(key)))
+    }
+
+    /// Parse a setting with the given function, if present.
+    pub fn parse_with<T, E: Display>(&self, key: &str, parse: impl FnOnce(&str) -> Result<T, E>) -> Result<Option<T>, String> {
+        match self.get(key) {
+            Some(value) => parse(value).map(Some).map_err(|e| format!("invalid {}: {e}", self.origin(key))),
+            None => Ok(None),
+        }
+    }
+
+    /// Parse a setting, if present.
+    pub fn parse<T: FromStr>(&self, key: &str) -> Result<Option<T>, String>
+    where
+        T::Err: Display,
+    {
+        self.parse_with(key, str::parse)
+    }
+
+}
+
+
+/// Parse a comma-separated list of channels, the leading '#' is optional.
+fn parse_channels(text: &str) -> Result<Vec<String>, String> {
+
+    let mut channels = Vec::<String>::new();
+
+    for channel in text.split(',').map(|channel| channel.trim().trim_start_matches('#').to_lowercase()) {
+        if channel.is_empty() {
+            return Err("empty channel name".to_string());
+        }
+        if channels.contains(&channel) {
+            return Err(format!("channel '{channel}' given twice"));
+        }
+        channels.push(channel);
+    }
+
+    Ok(channels)
+
+}
+
+
+/// Parse a duration in seconds, like `1.5`.
+pub fn parse_seconds(text: &str) -> Result<Duration, String> {
+    text.parse::<f32>().map_err(|e| e.to_string())
+        .and_then(|secs| Duration::try_from_secs_f32(secs).map_err(|e| e.to_string()))
+}
+
+
+/// Where the chat comes from.
+#[derive(Debug, Clone)]
+pub enum ChatSource {
+    /// The Twitch chat server.
+    Twitch,
+    /// A synthetic chat generated locally.
+    Chaos(ChaosConfig),
+    /// A recorded chat log, replayed in real time.
+    Replay(ReplayConfig),
+}
+
+/// The source of the chat to run on, before validation.
+#[derive(Debug, Clone)]
+pub enum RunMode {
+    Twitch,
+    Chaos,
+    Replay(ReplayConfig),
+}
+
+
+/// The configuration of the bot, validated before connecting.
+#[derive(Debug)]
+pub struct Config {
+    pub addr: SocketAddr,
+    /// Host name of the server, used to verify its TLS certificate.
+    pub host: String,
+    pub tls: bool,
+    /// WebSocket URL when connecting through WebSocket.
+    pub websocket_url: Option<String>,
+    pub user: String,
+    pub token: String,
+    /// Channels to join, without the leading '#'.
+    pub channels: Vec<String>,
+    pub log_path: PathBuf,
+    pub record_format: RecordFormat,
+    /// Rotation of the text and binary logs.
+    pub log_rotation: Option<Rotation>,
+    pub log_flush: FlushPolicy,
+    /// Binary log of every sample, for the full resolution capture.
+    pub binary_log_path: Option<PathBuf>,
+    /// Capture of every raw IRC line received and sent.
+    pub capture_path: Option<PathBuf>,
+    pub bot: bool,
+    /// Decide as the bot would, but never send any message.
+    pub dry_run: bool,
+    /// The account is a verified bot, with the highest rate limit.
+    pub verified: bool,
+    /// File of the users already seen in the channels, the new chatters
+    /// are those missing from it or with the `first-msg` tag. Without it,
+    /// only the tag tells them.
+    pub seen_path: Option<PathBuf>,
+    pub interval_curve: IntervalCurve,
+    pub interval_curve_path: Option<PathBuf>,
+    /// Conditions to send and interval formula, replacing the thresholds
+    /// and the interval curve.
+    pub trigger: TriggerConfig,
+    /// Commands never sent, and the weights of the votes.
+    pub command_policy: CommandPolicy,
+    /// How the messages are varied to avoid the duplicate detection.
+    pub variation: VariationConfig,
+    /// Random variations of the sending cadence.
+    pub jitter: JitterConfig,
+    /// Quiet hours and limits of the messages sent.
+    pub schedule: Schedule,
+    pub auto_bot: Option<AutoBot>,
+    pub chat_sample_path: Option<PathBuf>,
+    pub chat_sample_rate: f32,
+    /// Archive of every chat message, in JSON Lines.
+    pub archive_path: Option<PathBuf>,
+    /// SQLite database of the log records and of the messages sent.
+    pub sqlite_path: Option<PathBuf>,
+    /// Delay between the chat typing a command and the emulator acting.
+    pub stream_delay: Duration,
+    /// Fill the windows from the last log record at startup, if recent.
+    pub warm_start: bool,
+    /// Time after a raid during which the chatter messages are not counted
+    /// in the command ratio, zero to always count them.
+    pub raid_window: Duration,
+    pub run_path: Option<PathBuf>,
+    pub pause_path: Option<PathBuf>,
+    pub journal_path: Option<PathBuf>,
+    /// File of the last messages sent, restored on startup.
+    pub history_path: Option<PathBuf>,
+    /// Number of messages kept in the history.
+    pub history_size: usize,
+    /// Directory of the locks preventing two instances from sending as the
+    /// same account in a channel.
+    pub lock_dir: PathBuf,
+    /// Source of the chat, the server address is only used for Twitch.
+    pub source: ChatSource,
+    /// Model of the democracy voting window, enabling democracy piloting.
+    pub democracy: Option<DemocracyModel>,
+    /// Input mode of the stream at startup.
+    pub start_mode: InputMode,
+    /// Nickname of the stream's bot, whose announcements switch the mode.
+    pub stream_bot: Option<String>,
+    /// Alert rules evaluated on the metrics.
+    pub rules: Rules,
+    /// Script deciding the commands to send, in place of the thresholds.
+    pub script: Option<ScriptSource>,
+    /// How votes of a single user are deduplicated.
+    pub vote_dedup: VoteDedup,
+    /// Weights of the votes by badge, and of the known bots.
+    pub vote_weights: Option<VoteWeights>,
+    /// Users whose messages are not counted.
+    pub ignore_users: IgnoreList,
+    /// Commands of the channel.
+    pub vocabulary: Vocabulary,
+    /// Send the hold or repeat form of a command when most of its voters
+    /// typed it.
+    pub send_modifiers: bool,
+    /// Send the combo starting with a command when most of its voters
+    /// typed it.
+    pub send_combos: bool,
+    /// Touchscreen commands, counted as touches.
+    pub touch: Option<TouchConfig>,
+    /// Strategies run in shadow of the live one.
+    pub shadow: Option<ShadowConfig>,
+    /// Format of the numbers shown in the prompt.
+    pub number_format: NumberFormat,
+    /// Level and format of the diagnostics.
+    pub log: LogConfig,
+    /// Format of the periodic status.
+    pub status: StatusConfig,
+    /// Seed of all the random choices, drawn at startup if not configured
+    /// and recorded so that a session can be reproduced.
+    pub seed: u64,
+    /// Time after which the main loop is considered stalled, none to
+    /// disable the watchdog.
+    pub watchdog_timeout: Option<Duration>,
+    /// Reconnect when the main loop resumes after a stall.
+    pub watchdog_restart: bool,
+    /// Interval between two pings sent to the server, which also keep the
+    /// connection alive.
+    pub ping_interval: Duration,
+    /// Time without any data received after which the connection is
+    /// considered dead and reopened.
+    pub receive_timeout: Duration,
+    pub reconnect: ReconnectPolicy,
+    /// Exporter of the aggregates to an HTTP endpoint.
+    pub export: Option<ExportConfig>,
+    /// Metrics in InfluxDB line protocol, to a file or a server.
+    pub influx: Option<InfluxConfig>,
+    /// Input bursts where the bot is moderator.
+    pub burst: Option<BurstConfig>,
+    /// Adaptive duration of the samples under load.
+    pub resolution: Option<ResolutionConfig>,
+    /// JSON file of the current state, for static overlays.
+    pub snapshot: Option<SnapshotConfig>,
+    /// Accounts allowed to control the bot remotely.
+    pub owners: Option<Owners>,
+    /// Phrase of the emergency stop from the chat.
+    pub emergency: Option<EmergencyConfig>,
+    /// Scheduled upload of the logs and daily summaries.
+    pub upload: Option<UploadConfig>,
+    /// Whispers of the bot, to the owners and from the rules.
+    pub whisper: Option<WhisperConfig>,
+    /// HTTP server of the status and health, for the monitoring.
+    pub http: Option<HttpConfig>,
+    /// WebSocket server of the live stats, for the overlays.
+    pub overlay: Option<OverlayConfig>,
+    /// File of the pending commands, for an OBS text source.
+    pub overlay_file: Option<OverlayFileConfig>,
+    /// Socket accepting the commands of the console.
+    pub control_socket: Option<ControlAddress>,
+}
+
+impl Config {
+
+    /// Build and validate the configuration from the settings, returning
+    /// all the errors found. The server address and token are only needed
+    /// when running on the Twitch chat.
+    pub fn load(settings: &Settings, mode: RunMode) -> Result<Self, Vec<String>> {
+
+        let mut errors = Vec::new();
+
+        // Keep the value of a result, or record its error.
+        fn check<T>(errors: &mut Vec<String>, result: Result<T, String>) -> Option<T> {
+            result.map_err(|e| errors.push(e)).ok()
+        }
+
+        let (addr_raw, token) = match mode {
+            RunMode::Twitch => (check(&mut errors, settings.require("addr").map(str::to_string)),
+                                check(&mut errors, settings.require("token").map(str::to_string))),
+            RunMode::Chaos | RunMode::Replay(_) => (Some("localhost:6667".to_string()), Some(String::new())),
+        };
+
+        let user = check(&mut errors, settings.require("user").map(str::to_string));
+        let channels = check(&mut errors, settings.require("channel").and_then(|channel| parse_channels(channel)
+            .map_err(|e| format!("invalid {}: {e}", settings.origin("channel")))));
+        let log_path = check(&mut errors, settings.require("log_path").map(PathBuf::from));
+        let record_format = check(&mut errors, RecordFormat::from_settings(settings));
+        let log_rotation = check(&mut errors, Rotation::from_settings(settings)).flatten();
+        let log_flush = check(&mut errors, FlushPolicy::from_settings(settings));
+        let dry_run = check(&mut errors, settings.parse::<bool>("dry_run")).flatten().unwrap_or(false);
+        // A dry run is only useful with the bot deciding.
+        let bot = check(&mut errors, settings.parse::<bool>("bot")).flatten().unwrap_or(false) || dry_run;
+        let verified = check(&mut errors, settings.parse::<bool>("verified")).flatten().unwrap_or(false);
+        let tls = check(&mut errors, settings.parse::<bool>("tls")).flatten().unwrap_or(false);
+        let stream_delay = check(&mut errors, settings.parse_with("stream_delay", parse_seconds))
+            .flatten()
+            .unwrap_or_default();
+        let warm_start = check(&mut errors, settings.parse::<bool>("warm_start")).flatten().unwrap_or(false);
+        let raid_window = check(&mut errors, settings.parse_with("raid_window", parse_seconds))
+            .flatten()
+            .unwrap_or(DEFAULT_RAID_WINDOW);
+        let path = |key: &str| settings.get(key).map(PathBuf::from);
+        let chat_sample_rate = check(&mut errors, settings.parse_with("chat_sample_rate", |text| match text.parse::<f32>() {
+            Ok(rate) if (0.0..=1.0).contains(&rate) => Ok(rate),
+            _ => Err("expected a rate between 0 and 1"),
+        })).flatten().unwrap_or(0.01);
+
+        // The curve is validated at startup, but hot-reloaded later.
+        let interval_curve_path = path("interval_curve_path");
+        let interval_curve = match &interval_curve_path {
+            Some(curve_path) => check(&mut errors, IntervalCurve::load(curve_path)
+                .map_err(|e| format!("invalid {} curve: {e}", settings.origin("interval_curve_path")))),
+            None => Some(IntervalCurve::default()),
+        };
+
+        let trigger = check(&mut errors, TriggerConfig::from_settings(settings));
+        let command_policy = check(&mut errors, CommandPolicy::from_settings(settings));
+        let variation = check(&mut errors, VariationConfig::from_settings(settings));
+        let jitter = check(&mut errors, JitterConfig::from_settings(settings));
+        let schedule = check(&mut errors, Schedule::from_settings(settings));
+        // The history holds a day of messages by default, the daily count
+        // is restored from it.
+        let history_size = check(&mut errors, settings.parse::<usize>("history_size")).flatten()
+            .unwrap_or_else(|| schedule.as_ref().and_then(Schedule::daily_limit).map_or(50, |limit| (limit as usize).max(50)));
+        let auto_bot_on = check(&mut errors, settings.parse_with("auto_bot_on", Band::parse)).flatten();
+        let auto_bot_off = check(&mut errors, settings.parse_with("auto_bot_off", Band::parse)).flatten();
+        // Auto bot mode requires both bands to be configured.
+        let auto_bot = match (auto_bot_on, auto_bot_off) {
+            (Some(on), Some(off)) => Some(AutoBot::new(on, off)),
+            (None, None) => None,
+            _ => {
+                if (settings.get("auto_bot_on").is_some()) != (settings.get("auto_bot_off").is_some()) {
+                    errors.push(format!("both {} and {} are required for auto bot mode",
+                        settings.origin("auto_bot_on"), settings.origin("auto_bot_off")));
+                }
+                None
+            }
+        };
+
+        let rules = check(&mut errors, settings.parse_with("rules_path", |path| Rules::load(Path::new(path))))
+            .flatten()
+            .unwrap_or_default();
+        let touch = check(&mut errors, TouchConfig::from_settings(settings)).flatten();
+        let script = check(&mut errors, settings.parse_with("script_path", |path| ScriptSource::load(Path::new(path)))).flatten();
+        let vocabulary = check(&mut errors, Vocabulary::from_settings(settings)).unwrap_or_default();
+        let send_modifiers = check(&mut errors, settings.parse::<bool>("send_modifiers")).flatten().unwrap_or(false);
+        let send_combos = check(&mut errors, settings.parse::<bool>("send_combos")).flatten().unwrap_or(false);
+        let shadow = check(&mut errors, settings.parse_with("shadow_path", |path| ShadowConfig::load(Path::new(path))))
+            .flatten();
+        let vote_dedup = check(&mut errors, VoteDedup::from_settings(settings)).unwrap_or_default();
+        let vote_weights = check(&mut errors, VoteWeights::from_settings(settings)).flatten();
+        let democracy = check(&mut errors, settings.parse_with("democracy", DemocracyModel::parse)).flatten();
+        // A configured democracy model is piloted from the start, unless the
+        // stream is known to start in anarchy.
+        let start_mode = check(&mut errors, settings.parse_with("start_mode", |s| InputMode::parse(s).ok_or("expected anarchy or democracy")))
+            .flatten()
+            .unwrap_or(if democracy.is_some() { InputMode::Democracy } else { InputMode::Anarchy });
+        // Without a democracy model, the modes announced by the stream's bot
+        // are ignored, but its moves and runs are still followed.
+        let stream_bot = settings.get("stream_bot").map(str::to_lowercase);
+        if democracy.is_none() && start_mode == InputMode::Democracy {
+            errors.push(format!("{} is required to vote in democracy", settings.origin("democracy")));
+        }
+        let watchdog_timeout = check(&mut errors, settings.parse_with("watchdog_timeout", parse_seconds))
+            .flatten()
+            .unwrap_or(DEFAULT_WATCHDOG_TIMEOUT);
+        let watchdog_restart = check(&mut errors, settings.parse::<bool>("watchdog_restart")).flatten().unwrap_or(false);
+        let ping_interval = check(&mut errors, settings.parse_with("ping_interval", parse_seconds))
+            .flatten()
+            .unwrap_or(DEFAULT_PING_INTERVAL);
+        let receive_timeout = check(&mut errors, settings.parse_with("receive_timeout", parse_seconds))
+            .flatten()
+            .unwrap_or(DEFAULT_RECEIVE_TIMEOUT);
+        // The pongs are the only data received on a quiet channel.
+        if receive_timeout <= ping_interval {
+            errors.push(format!("invalid {}: expected more than {}", settings.origin("receive_timeout"), settings.origin("ping_interval")));
+        }
+        let reconnect = check(&mut errors, ReconnectPolicy::from_settings(settings));
+        let export = check(&mut errors, ExportConfig::from_settings(settings)).flatten();
+        let influx = check(&mut errors, InfluxConfig::from_settings(settings)).flatten();
+        let burst = check(&mut errors, BurstConfig::from_settings(settings)).flatten();
+        let resolution = check(&mut errors, ResolutionConfig::from_settings(settings)).flatten();
+        let snapshot = check(&mut errors, SnapshotConfig::from_settings(settings)).flatten();
+        let log = check(&mut errors, LogConfig::from_settings(settings));
+        let status = check(&mut errors, StatusConfig::from_settings(settings));
+        let owners = check(&mut errors, settings.parse_with("owner", Owners::parse)).flatten();
+        let emergency = check(&mut errors, EmergencyConfig::from_settings(settings)).flatten();
+        let upload = check(&mut errors, UploadConfig::from_settings(settings)).flatten();
+        let whisper = check(&mut errors, WhisperConfig::from_settings(settings)).flatten();
+        let http = check(&mut errors, HttpConfig::from_settings(settings)).flatten();
+        let overlay = check(&mut errors, OverlayConfig::from_settings(settings)).flatten();
+        let overlay_file = check(&mut errors, OverlayFileConfig::from_settings(settings)).flatten();
+        let control_socket = check(&mut errors, ControlAddress::from_settings(settings)).flatten();
+        let seed = check(&mut errors, settings.parse::<u64>("seed")).flatten().unwrap_or_else(rand::random);
+        let number_format = check(&mut errors, settings.parse_with("locale", NumberFormat::parse))
+            .flatten()
+            .unwrap_or_default();
+        let source = match mode {
+            RunMode::Twitch => Some(ChatSource::Twitch),
+            RunMode::Chaos => check(&mut errors, ChaosConfig::from_settings(settings)).map(ChatSource::Chaos),
+            RunMode::Replay(replay) => Some(ChatSource::Replay(replay)),
+        };
+
+        // A WebSocket URL can be given instead of the raw address, in which
+        // case the address is derived from the URL.
+        let mut server = None;
+        if let Some(addr_raw) = addr_raw {
+
+            let websocket_url = (addr_raw.starts_with("ws://") || addr_raw.starts_with("wss://")).then(|| addr_raw.clone());
+            let tls = tls || addr_raw.starts_with("wss://");
+            let addr_raw = match websocket_url.as_deref().and_then(|url| url.split_once("://")) {
+                Some((scheme, rest)) => {
+                    let host_port = rest.split('/').next().unwrap();
+                    if host_port.contains(':') {
+                        host_port.to_string()
+                    } else {
+                        format!("{host_port}:{}", if scheme == "wss" { 443 } else { 80 })
+                    }
+                }
+                None => addr_raw,
+            };
+
+            let host = addr_raw.rsplit_once(':').map(|(host, _)| host).unwrap_or(&addr_raw).to_string();
+            match addr_raw.to_socket_addrs().map(|mut addrs| addrs.next()) {
+                Ok(Some(addr)) => server = Some((addr, host, tls, websocket_url)),
+                Ok(None) => errors.push(format!("invalid {}: '{addr_raw}' resolves to no address", settings.origin("addr"))),
+                Err(e) => errors.push(format!("invalid {}: '{addr_raw}' {e}, expected 'host:port' or a WebSocket URL", settings.origin("addr"))),
+            }
+
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        let (addr, host, tls, websocket_url) = server.unwrap();
+
+        Ok(Self {
+            addr,
+            host,
+            tls,
+            websocket_url,
+            user: user.unwrap(),
+            token: token.unwrap(),
+            channels: channels.unwrap(),
+            log_path: log_path.unwrap(),
+            record_format: record_format.unwrap(),
+            log_rotation,
+            log_flush: log_flush.unwrap(),
+            binary_log_path: path("binary_log_path"),
+            capture_path: path("capture_path"),
+            bot,
+            dry_run,
+            verified,
+            seen_path: path("seen_path"),
+            interval_curve: interval_curve.unwrap(),
+            interval_curve_path,
+            trigger: trigger.unwrap(),
+            command_policy: command_policy.unwrap(),
+            variation: variation.unwrap(),
+            jitter: jitter.unwrap(),
+            schedule: schedule.unwrap(),
+            auto_bot,
+            chat_sample_path: path("chat_sample_path"),
+            chat_sample_rate,
+            archive_path: path("archive_path"),
+            sqlite_path: path("sqlite_path"),
+            stream_delay,
+            warm_start,
+            raid_window,
+            run_path: path("run_path"),
+            pause_path: path("pause_path"),
+            journal_path: path("journal_path"),
+            history_path: path("history_path"),
+            history_size,
+            lock_dir: path("lock_dir").unwrap_or_else(env::temp_dir),
+            source: source.unwrap(),
+            democracy,
+            start_mode,
+            stream_bot,
+            rules,
+            script,
+            vote_dedup,
+            vote_weights,
+            ignore_users: IgnoreList::from_settings(settings),
+            vocabulary,
+            send_modifiers,
+            send_combos,
+            touch,
+            shadow,
+            number_format,
+            log: log.unwrap(),
+            status: status.unwrap(),
+            seed,
+            watchdog_timeout: (!watchdog_timeout.is_zero()).then_some(watchdog_timeout),
+            watchdog_restart,
+            ping_interval,
+            receive_timeout,
+            reconnect: reconnect.unwrap(),
+            export,
+            influx,
+            burst,
+            resolution,
+            snapshot,
+            owners,
+            emergency,
+            upload,
+            whisper,
+            http,
+            overlay,
+            overlay_file,
+            control_socket,
+        })
+
+    }
+
+}
@@ -0,0 +1,75 @@
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::io::{self, BufRead};
+use std::thread;
+
+use tracing::info;
+
+use crate::socket::{self, ControlAddress};
+
+
+/// A command line of the operator, typed in the console or received on the
+/// control socket.
+#[derive(Debug)]
+pub struct ConsoleLine {
+    pub text: String,
+    /// Where to answer the line, none to log the answer.
+    pub reply: Option<Sender<String>>,
+}
+
+impl ConsoleLine {
+
+    /// Answer the line on its connection, or in the logs if typed in the
+    /// console.
+    pub fn answer(&self, text: String) {
+        match &self.reply {
+            // The connection may have been closed meanwhile.
+            Some(reply) => { let _ = reply.send(text); }
+            None => info!("{text}"),
+        }
+    }
+
+}
+
+
+/// The interactive console, reading operator commands from the standard
+/// input in a background thread.
+pub struct Console {
+    tx: Sender<ConsoleLine>,
+    rx: Receiver<ConsoleLine>,
+}
+
+impl Console {
+
+    pub fn spawn() -> Self {
+
+        let (tx, rx) = mpsc::channel();
+        let stdin_tx = tx.clone();
+
+        thread::Builder::new()
+            .name("console".to_string())
+            .spawn(move || {
+                for line in io::stdin().lock().lines() {
+                    let Ok(text) = line else { break };
+                    if stdin_tx.send(ConsoleLine { text, reply: None }).is_err() {
+                        break;
+                    }
+                }
+            })
+            .unwrap();
+
+        Self { tx, rx }
+
+    }
+
+    /// Also read the commands from the control socket at the given address.
+    pub fn listen(&self, address: &ControlAddress) -> io::Result<()> {
+        socket::listen(address, self.tx.clone())
+    }
+
+    /// Poll the next line typed in the console or received on the control
+    /// socket, if any.
+    pub fn poll(&self) -> Option<ConsoleLine> {
+        self.rx.try_recv().ok()
+    }
+
+}
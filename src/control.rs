@@ -0,0 +1,222 @@
+use std::time::Duration;
+
+use crate::config::parse_seconds;
+use crate::pause::PauseEnd;
+
+
+/// A command given by the operator to control the bot at runtime.
+#[derive(Debug)]
+pub enum ControlCommand {
+    /// Start a new named run, ending the current one.
+    RunStart(String),
+    /// End the current run.
+    RunEnd,
+    /// Pause sending in the given channel, or in all channels if none.
+    Pause(PauseEnd, Option<String>),
+    /// Resume sending in the given channel, or in all channels if none.
+    Resume(Option<String>),
+    /// Send the given command as soon as the rate limit allows it, in the
+    /// given channel or in all channels, whatever the bot mode.
+    Send(String, Option<String>),
+    /// Change a setting of the given channel, or of all channels if none.
+    Set(Setting, Option<String>),
+    /// Summarize the stats of the given channel, or of all channels.
+    Stats(Option<String>),
+    /// Emergency stop, halting all sending until re-armed.
+    Stop,
+    /// Re-arm the bot after an emergency stop.
+    Arm,
+    /// Reload the configuration, keeping the samples of the channels.
+    ReloadConfig,
+}
+
+impl ControlCommand {
+
+    /// Apply the command to the given channel if no channel is given, for
+    /// commands given in the chat of a channel.
+    pub fn with_default_channel(mut self, name: &str) -> Self {
+        if let Self::Pause(_, channel) | Self::Resume(channel) | Self::Send(_, channel) |
+            Self::Set(_, channel) | Self::Stats(channel) = &mut self {
+            channel.get_or_insert_with(|| name.to_string());
+        }
+        self
+    }
+
+    /// Parse a control command from a line of text.
+    pub fn parse(line: &str) -> Result<Self, String> {
+
+        let mut parts = line.split_whitespace();
+
+        match (parts.next(), parts.next()) {
+            (Some("run"), Some("start")) => {
+                let name = parts.collect::<Vec<_>>().join(" ");
+                if name.is_empty() {
+                    Err("missing run name".to_string())
+                } else {
+                    Ok(Self::RunStart(name))
+                }
+            }
+            (Some("run"), Some("end")) => Ok(Self::RunEnd),
+            (Some("pause"), Some(_)) => {
+                let mut args = line.split_whitespace().skip(1).collect::<Vec<_>>();
+                let channel = take_channel(&mut args);
+                Ok(Self::Pause(PauseEnd::parse(&args.join(" "))?, channel))
+            }
+            (Some("resume"), _) => {
+                let mut args = line.split_whitespace().skip(1).collect::<Vec<_>>();
+                let channel = take_channel(&mut args);
+                if let Some(arg) = args.first() {
+                    return Err(format!("unexpected argument '{arg}'"));
+                }
+                Ok(Self::Resume(channel))
+            }
+            (Some("send"), Some(_)) => {
+                let mut args = line.split_whitespace().skip(1).collect::<Vec<_>>();
+                let channel = take_channel(&mut args);
+                match args[..] {
+                    [command] => Ok(Self::Send(command.to_string(), channel)),
+                    _ => Err("expected a single command to send".to_string()),
+                }
+            }
+            // A setting can also be changed without `set`, like `interval 6`.
+            (Some(first), Some(_)) if first == "set" || Setting::KEYS.contains(&first) => {
+                let skip = if first == "set" { 1 } else { 0 };
+                let mut args = line.split_whitespace().skip(skip).collect::<Vec<_>>();
+                let channel = take_channel(&mut args);
+                // Both `set key=value` and `set key value` are accepted.
+                let (key, value) = match args[..] {
+                    [setting] => setting.split_once('=').ok_or_else(|| format!("expected key=value, got '{setting}'"))?,
+                    [key, value] => (key, value),
+                    _ => return Err("expected key=value".to_string()),
+                };
+                Ok(Self::Set(Setting::parse(key, value)?, channel))
+            }
+            (Some("stats" | "status"), _) => {
+                let mut args = line.split_whitespace().skip(1).collect::<Vec<_>>();
+                let channel = take_channel(&mut args);
+                if let Some(arg) = args.first() {
+                    return Err(format!("unexpected argument '{arg}'"));
+                }
+                Ok(Self::Stats(channel))
+            }
+            (Some("stop"), None) => Ok(Self::Stop),
+            (Some("arm"), None) => Ok(Self::Arm),
+            (Some("reload-config"), None) => Ok(Self::ReloadConfig),
+            _ => Err(format!("unknown command '{}'", line.trim())),
+        }
+
+    }
+
+}
+
+
+/// A setting of a channel that can be changed at runtime.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Setting {
+    /// Minimum command ratio to send.
+    MinRatio(f32),
+    /// Minimum commands per second to send.
+    MinCmdSec(f32),
+    /// Fixed interval between two messages, none to follow the interval
+    /// curve.
+    Interval(Option<Duration>),
+    /// Bot mode, disabling the automatic switching.
+    Bot(bool),
+}
+
+impl Setting {
+
+    pub const KEYS: [&'static str; 4] = ["min_ratio", "min_cmd_sec", "interval", "bot"];
+
+    pub fn parse(key: &str, value: &str) -> Result<Self, String> {
+
+        let invalid = || format!("invalid value '{value}' for {key}");
+
+        match key {
+            "min_ratio" => value.parse().ok()
+                .filter(|ratio| (0.0..=1.0).contains(ratio))
+                .map(Self::MinRatio)
+                .ok_or_else(invalid),
+            "min_cmd_sec" => value.parse().ok()
+                .filter(|&cmd_sec: &f32| cmd_sec >= 0.0)
+                .map(Self::MinCmdSec)
+                .ok_or_else(invalid),
+            "interval" if value == "auto" => Ok(Self::Interval(None)),
+            "interval" => parse_seconds(value)
+                .map(|interval| Self::Interval(Some(interval)))
+                .map_err(|_| invalid()),
+            "bot" => value.parse().map(Self::Bot).map_err(|_| invalid()),
+            _ => Err(format!("unknown setting '{key}', expected {}", Self::KEYS.join(", "))),
+        }
+
+    }
+
+}
+
+
+/// Accounts allowed to control the bot remotely, given by nickname or by
+/// user id.
+#[derive(Debug, Clone)]
+pub struct Owners(Vec<String>);
+
+impl Owners {
+
+    /// Parse a comma-separated list of nicknames or user ids.
+    pub fn parse(text: &str) -> Result<Self, &'static str> {
+        let owners = text.split(',')
+            .map(|owner| owner.trim().to_lowercase())
+            .filter(|owner| !owner.is_empty())
+            .collect::<Vec<_>>();
+        if owners.is_empty() {
+            Err("expected nicknames or user ids")
+        } else {
+            Ok(Self(owners))
+        }
+    }
+
+    /// Return true if the sender of a message, given by its nickname and
+    /// user id, is an owner.
+    pub fn contains(&self, nickname: Option<&str>, user_id: Option<&str>) -> bool {
+        self.0.iter().any(|owner| nickname.is_some_and(|nickname| owner.eq_ignore_ascii_case(nickname)) || Some(owner.as_str()) == user_id)
+    }
+
+}
+
+
+/// Take the trailing `#channel` argument, if any.
+fn take_channel(args: &mut Vec<&str>) -> Option<String> {
+    let channel = args.last()?.strip_prefix('#')?.to_string();
+    args.pop();
+    Some(channel)
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn remote_commands() {
+
+        assert!(matches!(ControlCommand::parse("send a #chan"), Ok(ControlCommand::Send(command, Some(channel))) if command == "a" && channel == "chan"));
+        assert!(ControlCommand::parse("send a b").is_err());
+        assert!(matches!(ControlCommand::parse("set min_ratio=0.5"), Ok(ControlCommand::Set(Setting::MinRatio(0.5), None))));
+        assert!(matches!(ControlCommand::parse("set min_cmd_sec 3 #chan"), Ok(ControlCommand::Set(Setting::MinCmdSec(3.0), Some(_)))));
+        assert!(ControlCommand::parse("set min_ratio=2").is_err());
+        assert!(matches!(ControlCommand::parse("interval 6").map(|command| command.with_default_channel("chan")),
+            Ok(ControlCommand::Set(Setting::Interval(Some(interval)), Some(channel))) if interval.as_secs() == 6 && channel == "chan"));
+        assert!(matches!(ControlCommand::parse("interval auto"), Ok(ControlCommand::Set(Setting::Interval(None), None))));
+        assert!(matches!(ControlCommand::parse("stats"), Ok(ControlCommand::Stats(None))));
+        assert!(matches!(ControlCommand::parse("status #chan"), Ok(ControlCommand::Stats(Some(_)))));
+        assert!(matches!(ControlCommand::parse("set bot=false"), Ok(ControlCommand::Set(Setting::Bot(false), None))));
+        assert!(matches!(ControlCommand::parse("reload-config"), Ok(ControlCommand::ReloadConfig)));
+
+        let owners = Owners::parse("Owner, 1234").unwrap();
+        assert!(owners.contains(Some("owner"), None));
+        assert!(owners.contains(Some("someone"), Some("1234")));
+        assert!(!owners.contains(Some("someone"), Some("42")));
+
+    }
+
+}
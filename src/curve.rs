@@ -0,0 +1,201 @@
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use std::fs;
+use std::io;
+
+
+/// A piecewise linear curve giving the interval (in seconds) between two
+/// messages sent by the bot, from the average number of commands per
+/// second in the chat. Points are given as `cmd/s interval` pairs, and
+/// the curve is flat before the first and after the last point.
+#[derive(Debug, Clone)]
+pub struct IntervalCurve {
+    points: Vec<(f32, f32)>,
+}
+
+impl Default for IntervalCurve {
+
+    /// The default curve is `8.0 - cmd/s`.
+    fn default() -> Self {
+        Self {
+            points: vec![(0.0, 8.0), (8.0, 0.0)],
+        }
+    }
+
+}
+
+impl IntervalCurve {
+
+    /// Parse a curve from its text representation, one `cmd/s interval`
+    /// point per line, empty lines and lines starting with '#' are ignored.
+    /// The points must be sorted by strictly increasing cmd/s.
+    pub fn parse(text: &str) -> Result<Self, String> {
+
+        let mut points: Vec<(f32, f32)> = Vec::new();
+
+        for (index, line) in text.lines().enumerate() {
+
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let line_num = index + 1;
+            let mut parts = line.split_whitespace();
+
+            let (Some(cmd_sec), Some(interval), None) = (parts.next(), parts.next(), parts.next()) else {
+                return Err(format!("line {line_num}: expected 'cmd/s interval'"));
+            };
+
+            let cmd_sec = cmd_sec.parse::<f32>()
+                .map_err(|e| format!("line {line_num}: invalid cmd/s: {e}"))?;
+            let interval = interval.parse::<f32>()
+                .map_err(|e| format!("line {line_num}: invalid interval: {e}"))?;
+
+            if !cmd_sec.is_finite() || cmd_sec < 0.0 {
+                return Err(format!("line {line_num}: cmd/s must be positive"));
+            } else if !interval.is_finite() || interval < 0.0 {
+                return Err(format!("line {line_num}: interval must be positive"));
+            }
+
+            if let Some(&(prev_cmd_sec, _)) = points.last() {
+                if cmd_sec <= prev_cmd_sec {
+                    return Err(format!("line {line_num}: cmd/s must be strictly increasing"));
+                }
+            }
+
+            points.push((cmd_sec, interval));
+
+        }
+
+        if points.is_empty() {
+            return Err("curve has no point".to_string());
+        }
+
+        Ok(Self { points })
+
+    }
+
+    /// Load and parse a curve from the given file.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Compute the interval in seconds for the given cmd/s.
+    pub fn interval(&self, cmd_sec: f32) -> f32 {
+
+        let (first_cmd_sec, first_interval) = self.points[0];
+        if cmd_sec <= first_cmd_sec {
+            return first_interval;
+        }
+
+        for window in self.points.windows(2) {
+            let (x0, y0) = window[0];
+            let (x1, y1) = window[1];
+            if cmd_sec <= x1 {
+                return y0 + (y1 - y0) * (cmd_sec - x0) / (x1 - x0);
+            }
+        }
+
+        self.points.last().unwrap().1
+
+    }
+
+}
+
+
+/// Watch a curve file in order to reload it when it is modified.
+#[derive(Debug)]
+pub struct CurveFile {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl CurveFile {
+
+    pub fn new(path: PathBuf) -> Self {
+        let modified = Self::modified_time(&path);
+        Self { path, modified }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn modified_time(path: &Path) -> Option<SystemTime> {
+        fs::metadata(path).and_then(|m| m.modified()).ok()
+    }
+
+    /// Reload the curve if the file has been modified since the last
+    /// call, returning the new curve if so.
+    pub fn reload(&mut self) -> io::Result<Option<IntervalCurve>> {
+
+        let modified = Self::modified_time(&self.path);
+        if modified == self.modified {
+            return Ok(None);
+        }
+
+        self.modified = modified;
+        IntervalCurve::load(&self.path).map(Some)
+
+    }
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn parse() {
+
+        let curve = IntervalCurve::parse("# cmd/s interval\n\n 0 10 \n2.5\t5\n10 1\n").unwrap();
+        assert_eq!(curve.points, [(0.0, 10.0), (2.5, 5.0), (10.0, 1.0)]);
+
+        let error = |text| IntervalCurve::parse(text).unwrap_err();
+        assert_eq!(error(""), "curve has no point");
+        assert_eq!(error("# only comments\n\n"), "curve has no point");
+        assert_eq!(error("1"), "line 1: expected 'cmd/s interval'");
+        assert_eq!(error("1 2 3"), "line 1: expected 'cmd/s interval'");
+        assert!(error("fast 2").starts_with("line 1: invalid cmd/s"));
+        assert!(error("1 slow").starts_with("line 1: invalid interval"));
+        assert_eq!(error("-1 2"), "line 1: cmd/s must be positive");
+        assert_eq!(error("inf 2"), "line 1: cmd/s must be positive");
+        assert_eq!(error("0 8\n1 -2"), "line 2: interval must be positive");
+        assert_eq!(error("0 8\n1 NaN"), "line 2: interval must be positive");
+        assert_eq!(error("0 8\n2 4\n# back\n1 6"), "line 4: cmd/s must be strictly increasing");
+        assert_eq!(error("0 8\n0 4"), "line 2: cmd/s must be strictly increasing");
+
+    }
+
+    #[test]
+    fn interval() {
+
+        let curve = IntervalCurve::parse("1 10\n3 6\n5 6\n7 2").unwrap();
+
+        // Flat before the first point and after the last one.
+        assert_eq!(curve.interval(0.0), 10.0);
+        assert_eq!(curve.interval(1.0), 10.0);
+        assert_eq!(curve.interval(7.0), 2.0);
+        assert_eq!(curve.interval(100.0), 2.0);
+
+        // Linear between the points.
+        assert_eq!(curve.interval(2.0), 8.0);
+        assert_eq!(curve.interval(3.0), 6.0);
+        assert_eq!(curve.interval(4.0), 6.0);
+        assert_eq!(curve.interval(6.5), 3.0);
+
+        let single = IntervalCurve::parse("4 3").unwrap();
+        assert_eq!(single.interval(0.0), 3.0);
+        assert_eq!(single.interval(9.0), 3.0);
+
+        let default = IntervalCurve::default();
+        assert_eq!(default.interval(2.0), 6.0);
+        assert_eq!(default.interval(20.0), 0.0);
+
+    }
+
+}
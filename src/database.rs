@@ -0,0 +1,185 @@
+use std::collections::HashSet;
+use std::path::Path;
+use std::io;
+
+use chrono::{DateTime, Utc};
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection};
+use tracing::warn;
+
+use crate::logformat::{log_columns, RecordValue, LOG_VERSION};
+
+
+/// A SQLite database of the log records, the messages sent and the sessions
+/// of a channel, given by the `sqlite_path` setting, for querying the
+/// history in SQL. The records have the columns of the log. A failing
+/// database is reported once and never stops the bot.
+#[derive(Debug)]
+pub struct Database {
+    connection: Connection,
+    /// Row id of the session of the bot.
+    session: i64,
+    /// True while the database fails, so that the error is only reported
+    /// once.
+    failing: bool,
+}
+
+impl Database {
+
+    /// Open the database, creating its tables if needed, and start a
+    /// session. Failing to open it at startup is an error, as the path is
+    /// likely wrong.
+    pub fn open(path: &Path, channel: &str, user: &str, now: DateTime<Utc>) -> io::Result<Self> {
+        Self::open_connection(Connection::open(path).map_err(io::Error::other)?, channel, user, now)
+            .map_err(io::Error::other)
+    }
+
+    fn open_connection(connection: Connection, channel: &str, user: &str, now: DateTime<Utc>) -> rusqlite::Result<Self> {
+
+        let columns = log_columns().iter()
+            .map(|name| column(name))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        connection.execute_batch(&format!("
+            CREATE TABLE IF NOT EXISTS sessions (
+                id INTEGER PRIMARY KEY,
+                channel TEXT NOT NULL,
+                user TEXT NOT NULL,
+                version TEXT NOT NULL,
+                start_time INTEGER NOT NULL,
+                end_time INTEGER,
+                messages INTEGER
+            );
+            CREATE TABLE IF NOT EXISTS records (
+                session INTEGER NOT NULL REFERENCES sessions(id),
+                {columns}
+            );
+            CREATE INDEX IF NOT EXISTS records_time ON records(time);
+            CREATE TABLE IF NOT EXISTS messages (
+                session INTEGER NOT NULL REFERENCES sessions(id),
+                time INTEGER NOT NULL,
+                message TEXT NOT NULL,
+                command TEXT NOT NULL,
+                mode TEXT NOT NULL,
+                cmd_sec REAL NOT NULL,
+                ratio REAL NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS messages_time ON messages(time);
+            PRAGMA user_version = {LOG_VERSION};
+        "))?;
+
+        // The columns added to the log since the database was created.
+        let existing = connection.prepare("SELECT name FROM pragma_table_info('records')")?
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<HashSet<_>>>()?;
+        for name in log_columns().iter().filter(|name| !existing.contains(*name)) {
+            connection.execute_batch(&format!("ALTER TABLE records ADD COLUMN {}", column(name)))?;
+        }
+
+        connection.execute("INSERT INTO sessions (channel, user, version, start_time) VALUES (?1, ?2, ?3, ?4)",
+            params![channel, user, env!("CARGO_PKG_VERSION"), now.timestamp()])?;
+        let session = connection.last_insert_rowid();
+
+        Ok(Self { connection, session, failing: false })
+
+    }
+
+    /// Insert a log record, with the values of the columns of the log.
+    pub fn insert_record(&mut self, values: &[RecordValue]) {
+
+        let placeholders = vec!["?"; values.len() + 1].join(", ");
+        let names = log_columns()[..values.len()].join(", ");
+        let params = std::iter::once(Value::Integer(self.session))
+            .chain(values.iter().map(|value| match *value {
+                RecordValue::Int(value) => Value::Integer(value),
+                // The shortest decimal of the value, as written in the log.
+                RecordValue::Float(value) => Value::Real(value.to_string().parse().unwrap_or(f64::NAN)),
+                RecordValue::Text(text) => Value::Text(text.to_string()),
+            }));
+
+        let result = self.connection
+            .prepare_cached(&format!("INSERT INTO records (session, {names}) VALUES ({placeholders})"))
+            .and_then(|mut statement| statement.execute(params_from_iter(params)));
+        self.check(result);
+
+    }
+
+    /// Insert a message sent by the bot, with the command it sends.
+    pub fn insert_message(&mut self, time: DateTime<Utc>, message: &str, command: &str, mode: &str, cmd_sec: f32, ratio: f32) {
+        let result = self.connection.execute(
+            "INSERT INTO messages (session, time, message, command, mode, cmd_sec, ratio) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![self.session, time.timestamp(), message, command, mode, cmd_sec, ratio]);
+        self.check(result);
+    }
+
+    /// End the session with the number of messages sent.
+    pub fn end_session(&mut self, now: DateTime<Utc>, messages: u32) {
+        let result = self.connection.execute("UPDATE sessions SET end_time = ?1, messages = ?2 WHERE id = ?3",
+            params![now.timestamp(), messages, self.session]);
+        self.check(result);
+    }
+
+    fn check(&mut self, result: rusqlite::Result<usize>) {
+        match result {
+            Ok(_) => self.failing = false,
+            Err(e) => {
+                if !self.failing {
+                    warn!("\x07alert: failed to write the database: {e}");
+                    self.failing = true;
+                }
+            }
+        }
+    }
+
+}
+
+
+/// The definition of a column of the records, the text columns are the
+/// only ones without a number.
+fn column(name: &str) -> String {
+    match name {
+        "run" | "session_id" => format!("{name} TEXT"),
+        _ => format!("{name} NUMERIC"),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use crate::logformat::LogSession;
+    use crate::stats::Stats;
+
+    use super::*;
+
+    #[test]
+    fn tables() {
+
+        let now = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let mut database = Database::open_connection(Connection::open_in_memory().unwrap(), "chan", "me", now).unwrap();
+
+        let mut stats = Stats::new();
+        let session = LogSession::new(now);
+        let record = stats.log_record(now, "run1", &session);
+        database.insert_record(&record);
+        database.insert_message(now, "a", "a", "anarchy", 2.5, 0.8);
+        database.end_session(now + chrono::Duration::hours(1), 1);
+        assert!(!database.failing);
+
+        let query = |sql: &str| database.connection.query_row(sql, [], |row| row.get::<_, String>(0)).unwrap();
+        assert_eq!(query("SELECT run || ',' || time || ',' || share_up FROM records"), "run1,1700000000,0");
+        assert_eq!(query("SELECT message || ',' || mode || ',' || cmd_sec FROM messages"), "a,anarchy,2.5");
+        assert_eq!(query("SELECT channel || ',' || (end_time - start_time) || ',' || messages FROM sessions"), "chan,3600,1");
+        assert_eq!(query("SELECT session_id FROM records"), session.id);
+
+        // The columns added to the log are added to an older database.
+        let connection = Connection::open_in_memory().unwrap();
+        connection.execute_batch("CREATE TABLE records (session INTEGER NOT NULL, time NUMERIC, run TEXT)").unwrap();
+        let mut database = Database::open_connection(connection, "chan", "me", now).unwrap();
+        database.insert_record(&stats.log_record(now, "run2", &session));
+        assert!(!database.failing);
+
+    }
+
+}
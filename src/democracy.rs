@@ -0,0 +1,180 @@
+use std::time::{Duration, Instant};
+
+use crate::config::parse_seconds;
+use crate::sample::Sample;
+use crate::vocabulary::Command;
+
+
+/// Model of the stream's democracy voting window: votes are collected
+/// for a fixed duration from the first vote, then the winner executes.
+#[derive(Debug, Clone, Copy)]
+pub struct DemocracyModel {
+    /// Duration of a voting window.
+    pub window: Duration,
+    /// How long before the end of the window the bot votes.
+    pub lead: Duration,
+}
+
+impl DemocracyModel {
+
+    /// Parse the model from a `window,lead` string, in seconds.
+    pub fn parse(text: &str) -> Result<Self, String> {
+
+        let (window, lead) = text.split_once(',')
+            .ok_or_else(|| format!("expected 'window,lead', got '{text}'"))?;
+
+        let window = parse_seconds(window.trim()).map_err(|e| format!("invalid window: {e}"))?;
+        let lead = parse_seconds(lead.trim()).map_err(|e| format!("invalid lead: {e}"))?;
+
+        if window.is_zero() {
+            return Err("window must be positive".to_string());
+        }
+        if lead.is_zero() || lead >= window {
+            return Err(format!("lead must be positive and shorter than the window, got {}s", lead.as_secs_f32()));
+        }
+
+        Ok(Self { window, lead })
+
+    }
+
+}
+
+
+/// A vote the bot should cast in the current window.
+#[derive(Debug, Clone, Copy)]
+pub struct DemocracyVote {
+    pub command: Command,
+    /// Difference of votes between the leader and the second command.
+    pub margin: u32,
+}
+
+/// Track the democracy voting windows from the chat, and choose when and
+/// what to vote so that the bot's single vote has the most weight.
+#[derive(Debug)]
+pub struct Democracy {
+    model: DemocracyModel,
+    /// Opening time of the current window, if any.
+    open: Option<Instant>,
+    /// Votes counted in the current window.
+    tally: Sample,
+    /// True when the bot already voted in the current window.
+    voted: bool,
+}
+
+impl Democracy {
+
+    pub fn new(model: DemocracyModel) -> Self {
+        Self {
+            model,
+            open: None,
+            tally: Sample::default(),
+            voted: false,
+        }
+    }
+
+    /// Close the current window if its duration has elapsed.
+    fn update(&mut self, now: Instant) {
+        if let Some(open) = self.open {
+            if now.saturating_duration_since(open) >= self.model.window {
+                self.open = None;
+                self.tally = Sample::default();
+                self.voted = false;
+            }
+        }
+    }
+
+    /// Count a chat message received at the given time with its command and
+    /// the weight of its vote, the first vote after a window has closed
+    /// opens a new one.
+    pub fn push_message(&mut self, command: Option<Command>, weight: u16, now: Instant) {
+
+        self.update(now);
+
+        let Some(command) = command.filter(|command| command.is_button()) else {
+            return;
+        };
+
+        self.open.get_or_insert(now);
+        self.tally.push_weighted(Some(command), weight);
+
+    }
+
+    /// Replace the votes of the window with the tally announced by the
+    /// stream, the votes seen in the chat may have been missed or filtered.
+    pub fn push_tally(&mut self, tally: &[(Command, u16)], now: Instant) {
+        self.update(now);
+        self.open.get_or_insert(now);
+        self.tally = Sample::from_counts(&tally.iter().map(|&(command, count)| (command, count as u32)).collect::<Vec<_>>());
+    }
+
+    /// Remaining time of the current window, if open.
+    pub fn remaining(&self, now: Instant) -> Option<Duration> {
+        let open = self.open?;
+        Some(self.model.window.saturating_sub(now.saturating_duration_since(open)))
+    }
+
+    /// The current leader of the window and its margin over the second
+    /// command, the preferred command breaks a tie if it is a leader.
+    pub fn leader(&self, preferred: Command) -> Option<DemocracyVote> {
+
+        self.open?;
+
+        let mut buttons = self.tally.buttons();
+        buttons.sort_by_key(|&(count, _)| std::cmp::Reverse(count));
+
+        let (first, second) = (buttons[0].0, buttons[1].0);
+        let command = buttons.iter()
+            .take_while(|&&(count, _)| count == first)
+            .find(|&&(_, command)| command == preferred)
+            .unwrap_or(&buttons[0]).1;
+
+        Some(DemocracyVote { command, margin: first - second })
+
+    }
+
+    /// Return the vote to cast now, if the end of the window is near, the
+    /// bot hasn't voted yet and the tally is close enough for a single vote
+    /// to tip it. The vote is considered cast once returned.
+    pub fn vote(&mut self, now: Instant, preferred: Command) -> Option<DemocracyVote> {
+
+        self.update(now);
+
+        if self.voted || self.remaining(now)? > self.model.lead {
+            return None;
+        }
+
+        let vote = self.leader(preferred)?;
+        if vote.margin > 1 {
+            return None;
+        }
+
+        self.voted = true;
+        Some(vote)
+
+    }
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn parse() {
+
+        let model = DemocracyModel::parse("30, 5").unwrap();
+        assert_eq!((model.window, model.lead), (Duration::from_secs(30), Duration::from_secs(5)));
+
+        assert!(DemocracyModel::parse("inf,5").unwrap_err().starts_with("invalid window"));
+        assert!(DemocracyModel::parse("NaN,5").unwrap_err().starts_with("invalid window"));
+        assert!(DemocracyModel::parse("-30,5").unwrap_err().starts_with("invalid window"));
+        assert!(DemocracyModel::parse("30,-5").unwrap_err().starts_with("invalid lead"));
+        assert!(DemocracyModel::parse("0,0").is_err());
+        assert!(DemocracyModel::parse("30,30").is_err());
+        assert!(DemocracyModel::parse("30").is_err());
+
+    }
+
+}
@@ -0,0 +1,85 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::config::Settings;
+use crate::control::Owners;
+
+
+/// Set when the emergency stop is triggered, all sending is halted until
+/// the operator re-arms the bot, whatever the bot mode and pauses.
+static STOPPED: AtomicBool = AtomicBool::new(false);
+
+
+/// Configuration of the emergency stop from the chat.
+#[derive(Debug, Clone)]
+pub struct EmergencyConfig {
+    /// Phrase triggering the stop, case-insensitive.
+    pub phrase: String,
+    /// Accounts allowed to trigger the stop.
+    pub users: Owners,
+}
+
+impl EmergencyConfig {
+
+    /// Read the configuration from the `stop_phrase` and `stop_users`
+    /// settings, none if no phrase is configured.
+    pub fn from_settings(settings: &Settings) -> Result<Option<Self>, String> {
+
+        let Some(phrase) = settings.get("stop_phrase") else { return Ok(None) };
+
+        let users = Owners::parse(settings.require("stop_users")?)
+            .map_err(|e| format!("invalid {}: {e}", settings.origin("stop_users")))?;
+
+        if phrase.trim().is_empty() {
+            return Err(format!("invalid {}: expected a phrase", settings.origin("stop_phrase")));
+        }
+
+        Ok(Some(Self { phrase: phrase.trim().to_string(), users }))
+
+    }
+
+    /// Return true if the message is the stop phrase sent by an allowed
+    /// user, given by nickname or user id.
+    pub fn matches(&self, text: &str, nickname: Option<&str>, user_id: Option<&str>) -> bool {
+        text.trim().eq_ignore_ascii_case(&self.phrase) && self.users.contains(nickname, user_id)
+    }
+
+}
+
+
+/// Halt all sending, returning false if already stopped.
+pub fn stop() -> bool {
+    !STOPPED.swap(true, Ordering::Relaxed)
+}
+
+/// Re-arm the bot after a stop, returning false if not stopped.
+pub fn arm() -> bool {
+    STOPPED.swap(false, Ordering::Relaxed)
+}
+
+/// Return true if the emergency stop is triggered.
+pub fn is_stopped() -> bool {
+    STOPPED.load(Ordering::Relaxed)
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn allowed_users() {
+
+        let config = EmergencyConfig {
+            phrase: "bot stop now".to_string(),
+            users: Owners::parse("owner,1234").unwrap(),
+        };
+
+        assert!(config.matches(" BOT STOP NOW ", Some("Owner"), None));
+        assert!(config.matches("bot stop now", Some("someone"), Some("1234")));
+        assert!(!config.matches("bot stop now", Some("someone"), Some("42")));
+        assert!(!config.matches("bot stop", Some("owner"), None));
+
+    }
+
+}
@@ -0,0 +1,47 @@
+use std::time::Duration;
+use std::io;
+
+use thiserror::Error;
+
+
+/// Errors of a session on the chat, telling apart the failures that a
+/// reconnection may solve from those that it can't.
+#[derive(Debug, Error)]
+pub enum TppError {
+    /// Network or file error, usually transient.
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// The server sent data that can't be decoded.
+    #[error("invalid data from the server: {0}")]
+    Parse(String),
+    /// The server rejected the authentication, the token must be fixed.
+    #[error("authentication failed: {0}")]
+    Auth(String),
+    /// The server dropped messages because too many were sent.
+    #[error("rate limited: {0}")]
+    RateLimited(String),
+    /// A file of the configuration can't be used.
+    #[error("{0}")]
+    Config(String),
+    /// Nothing was received from the server for too long, the connection
+    /// was silently dropped.
+    #[error("nothing received for {}s", .0.as_secs())]
+    Timeout(Duration),
+    /// The main loop was stalled and is restarted.
+    #[error("main loop stalled, restarting")]
+    Stalled,
+    /// The reconnection policy gave up.
+    #[error("giving up after {0} attempts")]
+    GaveUp(u32),
+}
+
+impl TppError {
+
+    /// Return true if retrying would fail the same way.
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, Self::Auth(_) | Self::Config(_) | Self::GaveUp(_))
+    }
+
+}
+
+pub type TppResult<T> = Result<T, TppError>;
@@ -0,0 +1,111 @@
+use std::sync::mpsc::{self, Sender};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use std::thread;
+
+use tracing::warn;
+
+use crate::config::{parse_seconds, Settings};
+
+
+/// Default body template, a CSV line.
+const DEFAULT_TEMPLATE: &str = "{{time}},{{channel}},{{msg_sec}},{{cmd_sec}},{{ratio}},{{most_used}},{{sent}}";
+
+
+/// Configuration of the HTTP exporter.
+#[derive(Debug, Clone)]
+pub struct ExportConfig {
+    pub url: String,
+    /// Template of the request body, where `{{name}}` placeholders are
+    /// replaced with the values of the aggregates.
+    pub template: String,
+    pub content_type: String,
+    /// Minimum interval between two exports of a channel.
+    pub interval: Duration,
+}
+
+impl ExportConfig {
+
+    /// Read the exporter configuration from the `export_*` settings, none if
+    /// no URL is configured.
+    pub fn from_settings(settings: &Settings) -> Result<Option<Self>, String> {
+
+        let Some(url) = settings.get("export_url") else { return Ok(None) };
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            return Err(format!("invalid {}: expected an HTTP URL", settings.origin("export_url")));
+        }
+
+        Ok(Some(Self {
+            url: url.to_string(),
+            template: settings.get("export_template").unwrap_or(DEFAULT_TEMPLATE).to_string(),
+            content_type: settings.get("export_content_type").unwrap_or("text/csv").to_string(),
+            interval: settings.parse_with("export_interval", parse_seconds)?.unwrap_or(Duration::from_secs(60)),
+        }))
+
+    }
+
+}
+
+
+/// Periodically push the latest aggregates to an HTTP endpoint with a POST
+/// request, to feed live dashboards like spreadsheets. Requests are sent by
+/// a background thread, so that a slow endpoint doesn't block the bot.
+#[derive(Debug)]
+pub struct Exporter {
+    config: ExportConfig,
+    tx: Sender<String>,
+    /// Time of the next export of each channel.
+    next_times: HashMap<String, Instant>,
+}
+
+impl Exporter {
+
+    pub fn spawn(config: ExportConfig) -> Self {
+
+        let (tx, rx) = mpsc::channel::<String>();
+        let url = config.url.clone();
+        let content_type = config.content_type.clone();
+
+        thread::Builder::new()
+            .name("export".to_string())
+            .spawn(move || {
+                let agent = ureq::AgentBuilder::new()
+                    .timeout(Duration::from_secs(10))
+                    .build();
+                for body in rx {
+                    if let Err(e) = agent.post(&url).set("Content-Type", &content_type).send_string(&body) {
+                        warn!("export failed: {e}");
+                    }
+                }
+            })
+            .unwrap();
+
+        Self {
+            config,
+            tx,
+            next_times: HashMap::new(),
+        }
+
+    }
+
+    /// Export the aggregates of a channel if its export interval has passed,
+    /// the values are given by name.
+    pub fn push(&mut self, channel: &str, now: Instant, values: &[(&str, String)]) {
+
+        let next_time = self.next_times.entry(channel.to_string()).or_insert(now);
+        if now < *next_time {
+            return;
+        }
+        *next_time = now + self.config.interval;
+
+        let mut body = self.config.template.clone();
+        for (name, value) in values {
+            body = body.replace(&format!("{{{{{name}}}}}"), value);
+        }
+
+        // The thread only stops with the process.
+        let _ = self.tx.send(body);
+
+    }
+
+}
@@ -0,0 +1,365 @@
+use std::fmt;
+
+
+/// A small numeric expression over named metrics, where booleans are
+/// represented by 1 and 0. Metric names may contain slashes, like `cmd/s`,
+/// so the division operator must be surrounded by spaces.
+#[derive(Debug, Clone)]
+pub enum Expr {
+    Number(f32),
+    Metric(String),
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(Box<Expr>, BinaryOp, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Or,
+    And,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+impl Expr {
+
+    /// Parse an expression, like `cmd/s > 20 and cmd/msg >= 0.6`.
+    pub fn parse(text: &str) -> Result<Self, String> {
+
+        let tokens = tokenize(text)?;
+        let mut parser = Parser { tokens: &tokens, pos: 0 };
+        let expr = parser.parse_or()?;
+
+        match parser.tokens.get(parser.pos) {
+            Some(token) => Err(format!("unexpected '{token}' in '{text}'")),
+            None => Ok(expr),
+        }
+
+    }
+
+    /// Check that all metrics of this expression are known.
+    pub fn check(&self, known: &impl Fn(&str) -> bool) -> Result<(), String> {
+        match self {
+            Self::Number(_) => Ok(()),
+            Self::Metric(name) if known(name) => Ok(()),
+            Self::Metric(name) => Err(format!("unknown metric '{name}'")),
+            Self::Neg(expr) | Self::Not(expr) => expr.check(known),
+            Self::Binary(lhs, _, rhs) => {
+                lhs.check(known)?;
+                rhs.check(known)
+            }
+        }
+    }
+
+    /// Evaluate the expression with the given metrics, unknown metrics
+    /// are evaluated to 0.
+    pub fn eval(&self, metric: &impl Fn(&str) -> Option<f32>) -> f32 {
+
+        let bool_value = |value: bool| if value { 1.0 } else { 0.0 };
+
+        match self {
+            Self::Number(value) => *value,
+            Self::Metric(name) => metric(name).unwrap_or(0.0),
+            Self::Neg(expr) => -expr.eval(metric),
+            Self::Not(expr) => bool_value(expr.eval(metric) == 0.0),
+            Self::Binary(lhs, op, rhs) => {
+                let lhs = lhs.eval(metric);
+                // Short-circuit the logical operators.
+                match op {
+                    BinaryOp::Or if lhs != 0.0 => return 1.0,
+                    BinaryOp::And if lhs == 0.0 => return 0.0,
+                    _ => {}
+                }
+                let rhs = rhs.eval(metric);
+                match op {
+                    BinaryOp::Or | BinaryOp::And => bool_value(rhs != 0.0),
+                    BinaryOp::Lt => bool_value(lhs < rhs),
+                    BinaryOp::Le => bool_value(lhs <= rhs),
+                    BinaryOp::Gt => bool_value(lhs > rhs),
+                    BinaryOp::Ge => bool_value(lhs >= rhs),
+                    BinaryOp::Eq => bool_value(lhs == rhs),
+                    BinaryOp::Ne => bool_value(lhs != rhs),
+                    BinaryOp::Add => lhs + rhs,
+                    BinaryOp::Sub => lhs - rhs,
+                    BinaryOp::Mul => lhs * rhs,
+                    BinaryOp::Div => if rhs == 0.0 { 0.0 } else { lhs / rhs },
+                }
+            }
+        }
+
+    }
+
+    /// Evaluate the expression as a condition.
+    pub fn test(&self, metric: &impl Fn(&str) -> Option<f32>) -> bool {
+        self.eval(metric) != 0.0
+    }
+
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f32),
+    Ident(String),
+    Op(&'static str),
+    Open,
+    Close,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Number(value) => write!(f, "{value}"),
+            Token::Ident(name) => f.write_str(name),
+            Token::Op(op) => f.write_str(op),
+            Token::Open => f.write_str("("),
+            Token::Close => f.write_str(")"),
+        }
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+
+    const OPS: &[&str] = &["<=", ">=", "==", "!=", "&&", "||", "<", ">", "!", "+", "-", "*", "/"];
+
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+
+    while let Some(c) = rest.chars().next() {
+
+        let len = if c.is_ascii_digit() || c == '.' {
+            let len = rest.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(rest.len());
+            let value = rest[..len].parse().map_err(|e| format!("invalid number '{}': {e}", &rest[..len]))?;
+            tokens.push(Token::Number(value));
+            len
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let len = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_' && c != '/').unwrap_or(rest.len());
+            tokens.push(match &rest[..len] {
+                "and" => Token::Op("&&"),
+                "or" => Token::Op("||"),
+                "not" => Token::Op("!"),
+                "true" => Token::Number(1.0),
+                "false" => Token::Number(0.0),
+                name => Token::Ident(name.to_string()),
+            });
+            len
+        } else if c == '(' {
+            tokens.push(Token::Open);
+            1
+        } else if c == ')' {
+            tokens.push(Token::Close);
+            1
+        } else if let Some(op) = OPS.iter().find(|op| rest.starts_with(*op)) {
+            tokens.push(Token::Op(op));
+            op.len()
+        } else {
+            return Err(format!("unexpected character '{c}' in '{text}'"));
+        };
+
+        rest = rest[len..].trim_start();
+
+    }
+
+    Ok(tokens)
+
+}
+
+
+/// A recursive descent parser, from the lowest to the highest precedence.
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+}
+
+impl Parser<'_> {
+
+    /// Consume the next token if it is one of the given operators.
+    fn eat_op(&mut self, ops: &[(&str, BinaryOp)]) -> Option<BinaryOp> {
+        let Some(Token::Op(op)) = self.tokens.get(self.pos) else { return None };
+        let (_, op) = ops.iter().find(|(name, _)| name == op)?;
+        self.pos += 1;
+        Some(*op)
+    }
+
+    /// Parse a left-associative sequence of binary operators.
+    fn parse_binary(&mut self, ops: &[(&str, BinaryOp)], next: fn(&mut Self) -> Result<Expr, String>) -> Result<Expr, String> {
+        let mut lhs = next(self)?;
+        while let Some(op) = self.eat_op(ops) {
+            lhs = Expr::Binary(Box::new(lhs), op, Box::new(next(self)?));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, String> {
+        self.parse_binary(&[("||", BinaryOp::Or)], Self::parse_and)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, String> {
+        self.parse_binary(&[("&&", BinaryOp::And)], Self::parse_not)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, String> {
+        if self.tokens.get(self.pos) == Some(&Token::Op("!")) {
+            self.pos += 1;
+            Ok(Expr::Not(Box::new(self.parse_not()?)))
+        } else {
+            self.parse_cmp()
+        }
+    }
+
+    fn parse_cmp(&mut self) -> Result<Expr, String> {
+        self.parse_binary(&[
+            ("<=", BinaryOp::Le), (">=", BinaryOp::Ge),
+            ("<", BinaryOp::Lt), (">", BinaryOp::Gt),
+            ("==", BinaryOp::Eq), ("!=", BinaryOp::Ne),
+        ], Self::parse_sum)
+    }
+
+    fn parse_sum(&mut self) -> Result<Expr, String> {
+        self.parse_binary(&[("+", BinaryOp::Add), ("-", BinaryOp::Sub)], Self::parse_product)
+    }
+
+    fn parse_product(&mut self) -> Result<Expr, String> {
+        self.parse_binary(&[("*", BinaryOp::Mul), ("/", BinaryOp::Div)], Self::parse_unary)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, String> {
+        if self.tokens.get(self.pos) == Some(&Token::Op("-")) {
+            self.pos += 1;
+            Ok(Expr::Neg(Box::new(self.parse_unary()?)))
+        } else {
+            self.parse_primary()
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, String> {
+
+        let token = self.tokens.get(self.pos).ok_or("unexpected end of expression")?;
+        self.pos += 1;
+
+        match token {
+            Token::Number(value) => Ok(Expr::Number(*value)),
+            Token::Ident(name) => Ok(Expr::Metric(name.clone())),
+            Token::Open => {
+                let expr = self.parse_or()?;
+                match self.tokens.get(self.pos) {
+                    Some(Token::Close) => {
+                        self.pos += 1;
+                        Ok(expr)
+                    }
+                    _ => Err("missing closing parenthesis".to_string()),
+                }
+            }
+            token => Err(format!("unexpected '{token}'")),
+        }
+
+    }
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use std::cell::Cell;
+
+    use super::*;
+
+    fn eval(text: &str) -> f32 {
+        let metric = |name: &str| match name {
+            "cmd/s" => Some(10.0),
+            "cmd/msg" => Some(0.5),
+            _ => None,
+        };
+        Expr::parse(text).unwrap().eval(&metric)
+    }
+
+    #[test]
+    fn precedence() {
+
+        assert_eq!(eval("1 + 2 * 3"), 7.0);
+        assert_eq!(eval("(1 + 2) * 3"), 9.0);
+        assert_eq!(eval("10 - 4 - 3"), 3.0);
+        assert_eq!(eval("8 / 2 / 2"), 2.0);
+        assert_eq!(eval("-2 * 3 + 1"), -5.0);
+        assert_eq!(eval("--2"), 2.0);
+        assert_eq!(eval("1 + 1 == 2"), 1.0);
+        assert_eq!(eval("1 < 2 == 1"), 1.0);
+        assert_eq!(eval("not 1 == 2"), 1.0);
+        assert_eq!(eval("!0 && 0"), 0.0);
+        assert_eq!(eval("1 or 1 and 0"), 1.0);
+        assert_eq!(eval("(1 or 1) and 0"), 0.0);
+        assert_eq!(eval("true and not false"), 1.0);
+
+    }
+
+    #[test]
+    fn operators() {
+
+        assert_eq!(eval("cmd/s / 4"), 2.5);
+        assert_eq!(eval("cmd/s > 5 and cmd/msg >= 0.5"), 1.0);
+        assert_eq!(eval("cmd/s < 10 || cmd/msg <= 0.4"), 0.0);
+        assert_eq!(eval("cmd/s != 10"), 0.0);
+        assert_eq!(eval("1.5 * 2 - 0.5"), 2.5);
+        // Dividing by zero gives zero.
+        assert_eq!(eval("cmd/s / 0"), 0.0);
+        // The logical operators give 1 or 0.
+        assert_eq!(eval("2 and 3"), 1.0);
+        assert_eq!(eval("0 or 3"), 1.0);
+
+    }
+
+    #[test]
+    fn short_circuit() {
+
+        let calls = Cell::new(0);
+        let metric = |_: &str| {
+            calls.set(calls.get() + 1);
+            Some(1.0)
+        };
+
+        assert!(!Expr::parse("0 and a").unwrap().test(&metric));
+        assert!(Expr::parse("1 or a").unwrap().test(&metric));
+        assert_eq!(calls.get(), 0);
+        assert!(Expr::parse("1 and a").unwrap().test(&metric));
+        assert_eq!(calls.get(), 1);
+
+    }
+
+    #[test]
+    fn unknown_metrics() {
+
+        let known = |name: &str| name == "cmd/s";
+        assert!(Expr::parse("cmd/s > 2").unwrap().check(&known).is_ok());
+        assert_eq!(Expr::parse("cmd/s > 2 and -(viewers)").unwrap().check(&known).unwrap_err(), "unknown metric 'viewers'");
+        assert_eq!(Expr::parse("not msg/s").unwrap().check(&known).unwrap_err(), "unknown metric 'msg/s'");
+
+        // Unknown metrics are evaluated to zero.
+        assert_eq!(eval("viewers + 1"), 1.0);
+
+    }
+
+    #[test]
+    fn errors() {
+
+        let error = |text| Expr::parse(text).unwrap_err();
+        assert_eq!(error(""), "unexpected end of expression");
+        assert_eq!(error("1 +"), "unexpected end of expression");
+        assert_eq!(error("(1 + 2"), "missing closing parenthesis");
+        assert_eq!(error("1 2"), "unexpected '2' in '1 2'");
+        assert_eq!(error("1 + )"), "unexpected ')'");
+        assert_eq!(error("* 2"), "unexpected '*'");
+        assert_eq!(error("1 $ 2"), "unexpected character '$' in '1 $ 2'");
+        assert!(error("1.2.3").starts_with("invalid number '1.2.3'"));
+
+    }
+
+}
@@ -0,0 +1,109 @@
+/// Formatting of the numbers shown to the operator, in the prompt and in the
+/// reports, following the conventions of a locale. The logs don't use it
+/// and stay machine-readable.
+#[derive(Debug, Clone, Copy)]
+pub struct NumberFormat {
+    decimal: char,
+    /// Separator of the thousands groups, none to not group digits.
+    group: Option<char>,
+}
+
+impl Default for NumberFormat {
+    fn default() -> Self {
+        Self { decimal: '.', group: None }
+    }
+}
+
+impl NumberFormat {
+
+    /// Parse a locale like `en`, `fr_FR` or `de-CH`, only the language is
+    /// used. The `plain` locale is the default, without digit grouping.
+    pub fn parse(locale: &str) -> Result<Self, String> {
+
+        let language = locale.split(['_', '-', '.']).next().unwrap_or_default().to_lowercase();
+
+        let (decimal, group) = match language.as_str() {
+            "plain" | "c" | "posix" => return Ok(Self::default()),
+            "en" | "ja" | "ko" | "zh" => ('.', ','),
+            // Narrow no-break space, as recommended for French.
+            "fr" => (',', '\u{202F}'),
+            "de" | "es" | "it" | "nl" | "pt" | "da" | "id" | "tr" => (',', '.'),
+            "pl" | "ru" | "uk" | "cs" | "sv" | "fi" | "nb" | "no" => (',', '\u{A0}'),
+            _ => return Err(format!("unsupported locale '{locale}'")),
+        };
+
+        Ok(Self { decimal, group: Some(group) })
+
+    }
+
+    /// Format an integer.
+    pub fn int(&self, value: i64) -> String {
+        let digits = value.unsigned_abs().to_string();
+        let sign = if value < 0 { "-" } else { "" };
+        format!("{sign}{}", self.group_digits(&digits))
+    }
+
+    /// Format a decimal number with the given number of decimals.
+    pub fn float(&self, value: f32, decimals: usize) -> String {
+
+        let text = format!("{:.*}", decimals, value.abs());
+        let (int_part, frac_part) = text.split_once('.').unwrap_or((&text, ""));
+        // Avoid printing a negative zero.
+        let sign = if value < 0.0 && text.bytes().any(|b| matches!(b, b'1'..=b'9')) { "-" } else { "" };
+
+        let mut result = format!("{sign}{}", self.group_digits(int_part));
+        if !frac_part.is_empty() {
+            result.push(self.decimal);
+            result.push_str(frac_part);
+        }
+        result
+
+    }
+
+    fn group_digits(&self, digits: &str) -> String {
+
+        let Some(group) = self.group else { return digits.to_string() };
+
+        let mut result = String::new();
+        for (index, c) in digits.chars().enumerate() {
+            if index > 0 && (digits.len() - index).is_multiple_of(3) {
+                result.push(group);
+            }
+            result.push(c);
+        }
+        result
+
+    }
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn plain() {
+        let nf = NumberFormat::default();
+        assert_eq!(nf.int(1234567), "1234567");
+        assert_eq!(nf.float(1234.567, 2), "1234.57");
+    }
+
+    #[test]
+    fn grouped() {
+
+        let en = NumberFormat::parse("en_US").unwrap();
+        assert_eq!(en.int(1234567), "1,234,567");
+        assert_eq!(en.int(-123), "-123");
+        assert_eq!(en.float(1234.5, 1), "1,234.5");
+
+        let de = NumberFormat::parse("de-DE").unwrap();
+        assert_eq!(de.float(-1234.5, 2), "-1.234,50");
+        assert_eq!(de.float(-0.001, 1), "0,0");
+
+        assert!(NumberFormat::parse("xx").is_err());
+
+    }
+
+}
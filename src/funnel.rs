@@ -0,0 +1,77 @@
+use crate::policy::CommandWeights;
+use crate::vocabulary::Command;
+
+
+/// Maximum length of a message that may be a mistyped command.
+const MAX_CANDIDATE_LEN: usize = 16;
+
+
+/// Number of chat messages reaching each stage of the vote pipeline, to
+/// measure how many are rejected at each stage.
+#[derive(Debug, Clone, Default)]
+pub struct Funnel {
+    /// All chat messages.
+    pub received: u32,
+    /// Messages that look like a command, accepted or not.
+    pub candidates: u32,
+    /// Messages parsed as a command of the vocabulary.
+    pub accepted: u32,
+    /// Commands counted as votes, once deduplicated.
+    pub counted: u32,
+    /// Sum of the weights of the votes in the choice of the most used
+    /// command.
+    pub weighted: f32,
+}
+
+impl Funnel {
+
+    /// Count a message, with its command if any and true if its vote is
+    /// counted. Messages that are not accepted must be counted as
+    /// candidates separately.
+    pub fn push(&mut self, command: Option<Command>, counted: bool, weights: &CommandWeights) {
+        self.received += 1;
+        if let Some(command) = command {
+            self.candidates += 1;
+            self.accepted += 1;
+            if counted {
+                self.counted += 1;
+                self.weighted += weights[command];
+            }
+        }
+    }
+
+}
+
+
+/// Return true if a message that isn't a command of the vocabulary looks
+/// like one, being a single short word.
+pub fn is_candidate(text: &str) -> bool {
+    let text = text.trim();
+    !text.is_empty() && text.len() <= MAX_CANDIDATE_LEN && !text.contains(char::is_whitespace)
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn stages() {
+
+        let weights = CommandWeights::default();
+        let mut funnel = Funnel::default();
+        funnel.push(Some(Command::Democracy), true, &weights);
+        funnel.push(Some(Command::A), false, &weights);
+        funnel.push(None, false, &weights);
+
+        assert_eq!((funnel.received, funnel.candidates, funnel.accepted, funnel.counted), (3, 2, 2, 1));
+        assert_eq!(funnel.weighted, 2.0);
+
+        assert!(is_candidate(" upp "));
+        assert!(!is_candidate("go up please"));
+        assert!(!is_candidate(""));
+
+    }
+
+}
@@ -0,0 +1,132 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::fs;
+use std::io;
+
+use chrono::{DateTime, Utc};
+use tracing::{info, warn};
+
+
+/// A rolling history of the last messages sent by the bot in a channel,
+/// persisted to a file so that the duplicate checks survive a restart.
+#[derive(Debug)]
+pub struct SentHistory {
+    path: Option<PathBuf>,
+    /// Maximum number of messages kept.
+    size: usize,
+    messages: VecDeque<(DateTime<Utc>, String)>,
+    /// True while the file fails, so that the error is only reported once.
+    failing: bool,
+}
+
+impl SentHistory {
+
+    /// Open the history from the given file, if existing. Each line of the
+    /// file is the timestamp in milliseconds and the message.
+    pub fn open(path: Option<PathBuf>, size: usize) -> io::Result<Self> {
+
+        let mut history = Self {
+            path,
+            size,
+            messages: VecDeque::new(),
+            failing: false,
+        };
+
+        if let Some(path) = &history.path {
+            match fs::read_to_string(path) {
+                Ok(content) => {
+                    for line in content.lines() {
+                        let Some((timestamp, message)) = line.split_once('\t') else { continue };
+                        let Some(time) = timestamp.parse::<i64>().ok()
+                            .and_then(DateTime::from_timestamp_millis) else { continue };
+                        history.push(time, message.to_string());
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(history)
+
+    }
+
+    /// The messages of the history, oldest first.
+    pub fn messages(&self) -> impl Iterator<Item = (DateTime<Utc>, &str)> {
+        self.messages.iter().map(|(time, message)| (*time, message.as_str()))
+    }
+
+    /// Record a message sent at the given time, forgetting the oldest one
+    /// if the history is full. If the file fails, the history is kept in
+    /// memory and saved again with the next message.
+    pub fn record(&mut self, time: DateTime<Utc>, message: String) {
+        self.push(time, message);
+        let Some(path) = &self.path else { return };
+        match self.save(path) {
+            Ok(()) => {
+                if self.failing {
+                    info!("{} written again", path.display());
+                    self.failing = false;
+                }
+            }
+            Err(e) => {
+                if !self.failing {
+                    warn!("\x07alert: failed to write {}: {e}, keeping the history in memory", path.display());
+                    self.failing = true;
+                }
+            }
+        }
+    }
+
+    fn push(&mut self, time: DateTime<Utc>, message: String) {
+        self.messages.push_back((time, message));
+        while self.messages.len() > self.size {
+            self.messages.pop_front();
+        }
+    }
+
+    /// Rewrite the file through a temporary file, so that a crash never
+    /// leaves a truncated history.
+    fn save(&self, path: &Path) -> io::Result<()> {
+
+        let mut content = String::new();
+        for (time, message) in &self.messages {
+            content.push_str(&format!("{}\t{message}\n", time.timestamp_millis()));
+        }
+
+        let mut tmp_path = path.as_os_str().to_os_string();
+        tmp_path.push(".tmp");
+        fs::write(&tmp_path, content)?;
+        fs::rename(tmp_path, path)
+
+    }
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn rolling() {
+
+        let path = std::env::temp_dir().join(format!("tpp-bot-history-{}.tsv", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let start = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+
+        let mut history = SentHistory::open(Some(path.clone()), 2).unwrap();
+        for (index, message) in ["a", "b", "c"].into_iter().enumerate() {
+            history.record(start + chrono::Duration::seconds(index as i64), message.to_string());
+        }
+
+        let history = SentHistory::open(Some(path.clone()), 2).unwrap();
+        let messages = history.messages().collect::<Vec<_>>();
+        assert_eq!(messages, [(start + chrono::Duration::seconds(1), "b"), (start + chrono::Duration::seconds(2), "c")]);
+
+        fs::remove_file(&path).unwrap();
+
+    }
+
+}
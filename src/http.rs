@@ -0,0 +1,149 @@
+use std::sync::{Arc, Mutex};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use std::thread;
+
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+use tiny_http::{Header, Response, Server};
+use tracing::warn;
+
+use crate::config::Settings;
+use crate::status::ChannelStatus;
+
+
+/// Maximum age of the last status for the bot to be healthy, the main loop
+/// updates it at each iteration.
+const HEALTH_TIMEOUT: Duration = Duration::from_secs(10);
+
+
+/// Configuration of the HTTP status server.
+#[derive(Debug, Clone)]
+pub struct HttpConfig {
+    pub addr: SocketAddr,
+}
+
+impl HttpConfig {
+
+    /// Read the configuration from the `http_addr` setting, none if not set.
+    pub fn from_settings(settings: &Settings) -> Result<Option<Self>, String> {
+        Ok(settings.parse::<SocketAddr>("http_addr")?.map(|addr| Self { addr }))
+    }
+
+}
+
+
+/// Last status given by the main loop.
+#[derive(Debug, Default)]
+struct LiveStatus {
+    time: Option<DateTime<Utc>>,
+    updated: Option<Instant>,
+    connected: bool,
+    channels: Vec<ChannelStatus>,
+}
+
+impl LiveStatus {
+
+    fn is_healthy(&self, now: Instant) -> bool {
+        self.updated.is_some_and(|updated| now.duration_since(updated) < HEALTH_TIMEOUT)
+    }
+
+    fn to_json(&self, now: Instant) -> Value {
+        let time = self.time.unwrap_or_default();
+        json!({
+            "healthy": self.is_healthy(now),
+            "connected": self.connected,
+            "channels": self.channels.iter().map(|status| {
+                let mut value = status.to_json(time);
+                value["votes"] = status.votes_json();
+                value
+            }).collect::<Vec<_>>(),
+        })
+    }
+
+}
+
+
+/// A small HTTP server for the monitoring, serving the live status of the
+/// channels as JSON on `/status`, and the health of the main loop on
+/// `/healthz`. Requests are served by a background thread from the last
+/// status given by the main loop, kept across connections.
+#[derive(Debug)]
+pub struct HttpServer {
+    status: Arc<Mutex<LiveStatus>>,
+}
+
+impl HttpServer {
+
+    pub fn spawn(config: &HttpConfig) -> Result<Self, String> {
+
+        let server = Server::http(config.addr).map_err(|e| format!("failed to listen on {}: {e}", config.addr))?;
+        let status = Arc::new(Mutex::new(LiveStatus::default()));
+        let thread_status = Arc::clone(&status);
+
+        thread::Builder::new()
+            .name("http".to_string())
+            .spawn(move || {
+                for request in server.incoming_requests() {
+                    let (code, content_type, body) = {
+                        let status = thread_status.lock().unwrap();
+                        respond(request.url(), &status, Instant::now())
+                    };
+                    let header = Header::from_bytes("Content-Type", content_type).unwrap();
+                    let response = Response::from_string(body).with_status_code(code).with_header(header);
+                    if let Err(e) = request.respond(response) {
+                        warn!("failed to answer an HTTP request: {e}");
+                    }
+                }
+            })
+            .unwrap();
+
+        Ok(Self { status })
+
+    }
+
+    /// Give the live status of the channels, at each iteration of the main
+    /// loop.
+    pub fn update(&self, now: Instant, time: DateTime<Utc>, connected: bool, channels: &[ChannelStatus]) {
+        let mut status = self.status.lock().unwrap();
+        status.time = Some(time);
+        status.updated = Some(now);
+        status.connected = connected;
+        status.channels = channels.to_vec();
+    }
+
+}
+
+
+/// Status code, content type and body of the response to a request.
+fn respond(url: &str, status: &LiveStatus, now: Instant) -> (u16, &'static str, String) {
+    match url.split('?').next().unwrap_or_default() {
+        "/status" => (200, "application/json", status.to_json(now).to_string()),
+        "/healthz" if status.is_healthy(now) => (200, "text/plain", "ok\n".to_string()),
+        "/healthz" => (503, "text/plain", "stalled\n".to_string()),
+        _ => (404, "text/plain", "not found\n".to_string()),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn health() {
+
+        let now = Instant::now();
+        let mut status = LiveStatus::default();
+        assert_eq!(respond("/healthz", &status, now).0, 503);
+
+        status.updated = Some(now);
+        assert_eq!(respond("/healthz", &status, now + Duration::from_secs(1)).0, 200);
+        assert_eq!(respond("/healthz", &status, now + HEALTH_TIMEOUT).0, 503);
+        assert_eq!(respond("/status?pretty", &status, now).0, 200);
+        assert_eq!(respond("/metrics", &status, now).0, 404);
+
+    }
+
+}
@@ -0,0 +1,69 @@
+/// Minimum number of letters of a message to count it as written in
+/// capitals, short words are often capitalized anyway.
+const MIN_CAPS_LETTERS: usize = 4;
+
+
+/// A cheap proxy of the excitement of the chat, from the shape of its
+/// chatter messages, which often rises before a surge of inputs.
+#[derive(Debug, Clone, Default)]
+pub struct Hype {
+    /// Chatter messages, that are not commands.
+    pub messages: u32,
+    /// Exclamation marks in the messages.
+    pub exclamations: u32,
+    /// Messages written in capitals.
+    pub caps: u32,
+    /// Emotes in the messages, as tagged by the server.
+    pub emotes: u32,
+}
+
+impl Hype {
+
+    /// Count a chatter message with its number of emotes.
+    pub fn push(&mut self, text: &str, emotes: u32) {
+
+        self.messages += 1;
+        self.exclamations += text.chars().filter(|&c| c == '!').count() as u32;
+        self.emotes += emotes;
+
+        let letters = text.chars().filter(|c| c.is_alphabetic()).count();
+        if letters >= MIN_CAPS_LETTERS && !text.chars().any(char::is_lowercase) {
+            self.caps += 1;
+        }
+
+    }
+
+}
+
+
+/// Number of emotes in a message from its `emotes` tag, like
+/// `25:0-4,12-16/1902:6-10`, each range being an emote.
+pub fn count_emotes(tag: &str) -> u32 {
+    tag.split('/')
+        .filter_map(|emote| emote.split_once(':'))
+        .map(|(_, ranges)| ranges.split(',').count() as u32)
+        .sum()
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn proxy() {
+
+        let mut hype = Hype::default();
+        hype.push("LETS GO!!", 0);
+        hype.push("UP", 0);
+        hype.push("nice Kappa Kappa", 2);
+
+        assert_eq!((hype.messages, hype.exclamations, hype.caps, hype.emotes), (3, 2, 1, 2));
+
+        assert_eq!(count_emotes("25:0-4,12-16/1902:6-10"), 3);
+        assert_eq!(count_emotes(""), 0);
+
+    }
+
+}
@@ -0,0 +1,66 @@
+use std::collections::HashSet;
+
+use crate::config::Settings;
+
+
+/// Common bots of the Twitch channels, ignored unless configured otherwise.
+const DEFAULT_BOTS: [&str; 6] = ["nightbot", "streamelements", "streamlabs", "moobot", "fossabot", "wizebot"];
+
+
+/// Users whose messages are not counted at all, like the bots of the
+/// channel, given by nickname or by user id.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IgnoreList(HashSet<String>);
+
+impl Default for IgnoreList {
+    fn default() -> Self {
+        Self(DEFAULT_BOTS.iter().map(|bot| bot.to_string()).collect())
+    }
+}
+
+impl IgnoreList {
+
+    /// Read the list from the `ignore_users` setting, a comma-separated
+    /// list replacing the default bots, empty to count every user.
+    pub fn from_settings(settings: &Settings) -> Self {
+        settings.get("ignore_users").map(Self::parse).unwrap_or_default()
+    }
+
+    pub fn parse(text: &str) -> Self {
+        Self(text.split(',')
+            .map(|user| user.trim().to_lowercase())
+            .filter(|user| !user.is_empty())
+            .collect())
+    }
+
+    /// Return true if the sender of a message, given by its nickname and
+    /// user id, is ignored.
+    pub fn contains(&self, nickname: Option<&str>, user_id: Option<&str>) -> bool {
+        nickname.is_some_and(|nickname| self.0.contains(&nickname.to_lowercase()))
+            || user_id.is_some_and(|user_id| self.0.contains(user_id))
+    }
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn ignored_users() {
+
+        let default = IgnoreList::default();
+        assert!(default.contains(Some("Nightbot"), Some("19264788")));
+        assert!(!default.contains(Some("viewer"), None));
+
+        let list = IgnoreList::parse("tppbot, 123456");
+        assert!(list.contains(Some("TPPBot"), None));
+        assert!(list.contains(Some("renamed"), Some("123456")));
+        assert!(!list.contains(Some("nightbot"), None));
+        assert!(!IgnoreList::parse("").contains(Some("nightbot"), None));
+
+    }
+
+}
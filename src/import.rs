@@ -0,0 +1,397 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::Path;
+
+use chrono::{DateTime, Duration, Local, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use serde_json::Value;
+
+use crate::capture;
+use crate::config::Settings;
+use crate::funnel;
+use crate::irc::{IrcReplyCommand, IrcReplyRef};
+use crate::logformat::RecordFormat;
+use crate::policy::CommandPolicy;
+use crate::rng;
+use crate::stats::{Stats, VoteDedup, SAMPLE_DURATION};
+use crate::seal;
+use crate::vocabulary::Vocabulary;
+
+
+/// Format of third-party chat logs.
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum ImportFormat {
+    /// Raw IRC lines with tags, as logged by justlog, or a raw capture of
+    /// the bot.
+    Irc,
+    /// Text logs with `[time] user: message` lines, as logged by
+    /// justlog and Chatterino.
+    Text,
+    /// JSON logs from the justlog API, either a single object with a
+    /// "messages" array, or one message object per line, like the chat
+    /// archive of the bot.
+    Json,
+}
+
+/// Time zone of the times of text logs, which have no offset.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum TextZone {
+    /// UTC, as logged by justlog.
+    #[default]
+    Utc,
+    /// The local time zone of the system, as logged by Chatterino.
+    Local,
+}
+
+/// A chat message read from a third-party log.
+#[derive(Debug)]
+pub struct ChatEvent {
+    pub time: DateTime<Utc>,
+    pub user: String,
+    pub text: String,
+    pub first_msg: bool,
+}
+
+
+/// Entry point of the `import` subcommand, the date is required for text
+/// logs that only contain the time, which are in the given time zone.
+pub fn main(format: ImportFormat, input: &Path, output: &Path, date: Option<NaiveDate>, zone: TextZone, settings: &Settings) -> io::Result<()> {
+
+    let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidData, e);
+
+    // Sealed logs are read transparently.
+    let events = read_events(format, seal::open(input, settings.get("seal_passphrase"))?, date, zone)?;
+
+    // The vocabulary, the format and the counting of the votes are shared
+    // with the bot configuration.
+    let vocabulary = Vocabulary::from_settings(settings).map_err(invalid)?;
+    let record_format = RecordFormat::from_settings(settings).map_err(invalid)?;
+    let policy = CommandPolicy::from_settings(settings).map_err(invalid)?;
+    let vote_dedup = VoteDedup::from_settings(settings).map_err(invalid)?;
+    // Imports are reproducible unless another seed is given.
+    let seed = settings.parse::<u64>("seed").map_err(invalid)?.unwrap_or(0);
+    let stats = Stats::new()
+        .with_vote_dedup(vote_dedup)
+        .with_command_policy(policy, rng::derive(seed, "ties#import"));
+
+    let mut output = record_format.open(output)?;
+    let records = import(&events, &vocabulary, stats, record_format, &mut output)?;
+    output.flush()?;
+
+    println!("imported {} messages into {records} records", events.len());
+    Ok(())
+
+}
+
+
+/// Read all chat events from a log in the given format. Events are
+/// expected to be in chronological order, the times of text logs are in
+/// the given time zone, the other formats give their offset.
+pub fn read_events(format: ImportFormat, input: impl Read, date: Option<NaiveDate>, zone: TextZone) -> io::Result<Vec<ChatEvent>> {
+
+    let mut events = Vec::new();
+
+    match format {
+        ImportFormat::Irc => {
+            for line in BufReader::new(input).lines() {
+                if let Some(event) = parse_irc_line(&line?) {
+                    events.push(event);
+                }
+            }
+        }
+        ImportFormat::Text => {
+            for line in BufReader::new(input).lines() {
+                if let Some(event) = parse_text_line(&line?, date, zone) {
+                    events.push(event);
+                }
+            }
+        }
+        ImportFormat::Json => {
+
+            let mut content = String::new();
+            BufReader::new(input).read_to_string(&mut content)?;
+
+            match serde_json::from_str::<Value>(&content) {
+                Ok(Value::Object(object)) if object.contains_key("messages") => {
+                    if let Some(Value::Array(messages)) = object.get("messages") {
+                        events.extend(messages.iter().filter_map(parse_json_message));
+                    }
+                }
+                _ => {
+                    for line in content.lines() {
+                        if let Ok(message) = serde_json::from_str::<Value>(line) {
+                            events.extend(parse_json_message(&message));
+                        }
+                    }
+                }
+            }
+
+        }
+    }
+
+    Ok(events)
+
+}
+
+
+/// Run all events through the sampling engine and write the resulting log
+/// records in the given format, returning the number of records written.
+pub fn import(events: &[ChatEvent], vocabulary: &Vocabulary, stats: Stats, format: RecordFormat, output: &mut impl Write) -> io::Result<usize> {
+    let mut records = 0;
+    simulate(events, vocabulary, stats, |stats, time| {
+        stats.write_log(output, time, "-", format)?;
+        records += 1;
+        Ok(())
+    })?;
+    Ok(records)
+}
+
+/// Run all events through the given stats, at their original time, the
+/// given function is called with the stats every time a log record is due.
+pub fn simulate<F>(events: &[ChatEvent], vocabulary: &Vocabulary, stats: Stats, mut on_record: F) -> io::Result<()>
+where
+    F: FnMut(&mut Stats, DateTime<Utc>) -> io::Result<()>,
+{
+    simulate_samples(events, vocabulary, stats, |stats, time, log_due| {
+        if log_due { on_record(stats, time) } else { Ok(()) }
+    })
+}
+
+/// Run all events through the given stats, at their original time, the
+/// given function is called with the stats at the end of every sample, with
+/// true if a log record is due.
+pub fn simulate_samples<F>(events: &[ChatEvent], vocabulary: &Vocabulary, mut stats: Stats, mut on_sample: F) -> io::Result<()>
+where
+    F: FnMut(&mut Stats, DateTime<Utc>, bool) -> io::Result<()>,
+{
+
+    let sample_duration = Duration::from_std(SAMPLE_DURATION).unwrap();
+
+    // End time of the active sample.
+    let mut sample_end = None;
+
+    for event in events {
+
+        let sample_end = sample_end.get_or_insert(event.time + sample_duration);
+        while event.time >= *sample_end {
+            let log_due = stats.flush();
+            on_sample(&mut stats, *sample_end, log_due)?;
+            *sample_end += sample_duration;
+        }
+
+        // The chatters are new by their tag only, like in a bot without
+        // seen users file.
+        let command = vocabulary.parse(&event.text);
+        stats.push_message(command, Some((&event.user, event.first_msg)), 1);
+        if let Some(combo) = vocabulary.parse_combo(&event.text) {
+            let commands = combo.iter().skip(1).map(|&(command, _)| command).collect::<Vec<_>>();
+            stats.push_combo(&commands, 1);
+        }
+        if command.is_none() {
+            if funnel::is_candidate(&event.text) {
+                stats.push_candidate();
+            }
+            // Emotes are not kept in the imported events.
+            stats.push_hype(&event.text, 0);
+        }
+
+    }
+
+    Ok(())
+
+}
+
+
+fn parse_irc_line(line: &str) -> Option<ChatEvent> {
+
+    // The lines of a capture are received at their capture time, the lines
+    // sent by the bot aren't chat messages.
+    let (capture_time, line) = match capture::parse_line(line) {
+        Some((time, capture::RECEIVED, line)) => (Some(time), line),
+        Some(_) => return None,
+        None => (None, line),
+    };
+
+    let reply = IrcReplyRef::parse(line)?;
+    if !matches!(reply.command, IrcReplyCommand::PrivMsg) {
+        return None;
+    }
+
+    let user = reply.tag("user-id")
+        .or_else(|| reply.sender().and_then(|sender| sender.nickname))?;
+
+    Some(ChatEvent {
+        time: reply.server_time().or(capture_time)?,
+        user: user.to_string(),
+        text: reply.text()?.to_string(),
+        first_msg: reply.tag("first-msg") == Some("1"),
+    })
+
+}
+
+/// Parse a `[2021-01-01 12:00:00] #channel user: message` line, the date and
+/// the channel being optional.
+fn parse_text_line(line: &str, date: Option<NaiveDate>, zone: TextZone) -> Option<ChatEvent> {
+
+    let (time, rest) = line.strip_prefix('[')?.split_once("] ")?;
+
+    let time = match NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M:%S") {
+        Ok(time) => time,
+        Err(_) => date?.and_time(NaiveTime::parse_from_str(time, "%H:%M:%S").ok()?),
+    };
+
+    let rest = match rest.strip_prefix('#') {
+        Some(rest) => rest.split_once(' ')?.1,
+        None => rest,
+    };
+
+    let (user, text) = rest.split_once(": ")?;
+
+    let time = match zone {
+        TextZone::Utc => time.and_utc(),
+        // The skipped times of a DST change don't exist.
+        TextZone::Local => time.and_local_timezone(Local).earliest()?.with_timezone(&Utc),
+    };
+
+    Some(ChatEvent {
+        time,
+        user: user.to_string(),
+        text: text.to_string(),
+        first_msg: false,
+    })
+
+}
+
+fn parse_json_message(message: &Value) -> Option<ChatEvent> {
+
+    // Only keep chat messages when the type is known.
+    if let Some(kind) = message.get("type").and_then(Value::as_u64) {
+        if kind != 1 {
+            return None;
+        }
+    }
+
+    let tags = message.get("tags");
+    let tag = |key: &str| tags.and_then(|tags| tags.get(key)).and_then(Value::as_str);
+
+    let time = DateTime::parse_from_rfc3339(message.get("timestamp")?.as_str()?).ok()?;
+    // The chat archive of the bot has the tags as fields.
+    let field = |key: &str| message.get(key).and_then(Value::as_str);
+    let user = tag("user-id").or_else(|| field("user_id")).or_else(|| field("username"))?;
+
+    Some(ChatEvent {
+        time: time.with_timezone(&Utc),
+        user: user.to_string(),
+        text: message.get("text")?.as_str()?.to_string(),
+        first_msg: tag("first-msg") == Some("1") || message.get("first_msg").and_then(Value::as_bool) == Some(true),
+    })
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn events(format: ImportFormat, log: &str, date: Option<NaiveDate>, zone: TextZone) -> Vec<(i64, String, String, bool)> {
+        read_events(format, log.as_bytes(), date, zone).unwrap()
+            .into_iter()
+            .map(|event| (event.time.timestamp(), event.user, event.text, event.first_msg))
+            .collect()
+    }
+
+    fn event(timestamp: i64, user: &str, text: &str, first_msg: bool) -> (i64, String, String, bool) {
+        (timestamp, user.to_string(), text.to_string(), first_msg)
+    }
+
+    #[test]
+    fn text_lines() {
+
+        let date = NaiveDate::from_ymd_opt(2023, 11, 14);
+        let log = "\
+            [2023-11-14 22:13:20] #chan foo: up\n\
+            [22:13:21] bar: a: b\n\
+            22:13:23 foo: no brackets\n\
+            [25:00:00] foo: invalid time\n\
+            [2023-11-14 22:13:24] foo without colon\n\
+            [2023-11-14 22:13:25] #chan\n";
+
+        assert_eq!(events(ImportFormat::Text, log, date, TextZone::Utc), [
+            event(1_700_000_000, "foo", "up", false),
+            event(1_700_000_001, "bar", "a: b", false),
+        ]);
+
+        // Without date, only the lines with a date are read.
+        assert_eq!(events(ImportFormat::Text, log, None, TextZone::Utc), [event(1_700_000_000, "foo", "up", false)]);
+
+        let local = NaiveDate::from_ymd_opt(2023, 11, 14).unwrap().and_hms_opt(22, 13, 20).unwrap()
+            .and_local_timezone(Local).earliest().unwrap();
+        assert_eq!(events(ImportFormat::Text, log, date, TextZone::Local)[0].0, local.timestamp());
+
+    }
+
+    #[test]
+    fn irc_lines() {
+
+        let log = "\
+            @first-msg=1;tmi-sent-ts=1700000000000;user-id=42 :foo!foo@foo.tmi.twitch.tv PRIVMSG #chan :up\n\
+            @tmi-sent-ts=1700000001000 :bar!bar@bar.tmi.twitch.tv PRIVMSG #chan :a\n\
+            @tmi-sent-ts=1700000002000 :tmi.twitch.tv USERNOTICE #chan :raid\n\
+            :foo!foo@foo.tmi.twitch.tv PRIVMSG #chan :no time\n\
+            not an irc line\n\
+            2023-11-14T22:13:23Z\t<\t:baz!baz@baz.tmi.twitch.tv PRIVMSG #chan :b\n\
+            2023-11-14T22:13:24Z\t>\tPRIVMSG #chan :sent by the bot\n";
+
+        assert_eq!(events(ImportFormat::Irc, log, None, TextZone::Utc), [
+            event(1_700_000_000, "42", "up", true),
+            event(1_700_000_001, "bar", "a", false),
+            event(1_700_000_003, "baz", "b", false),
+        ]);
+
+    }
+
+    #[test]
+    fn json_messages() {
+
+        let log = r#"{"messages": [
+            {"type": 1, "timestamp": "2023-11-14T22:13:20Z", "text": "up", "tags": {"user-id": "42", "first-msg": "1"}},
+            {"type": 2, "timestamp": "2023-11-14T22:13:21Z", "text": "joined", "username": "foo"},
+            {"timestamp": "2023-11-14T23:13:22+01:00", "text": "a", "username": "bar"},
+            {"timestamp": "yesterday", "text": "b", "username": "bar"}
+        ]}"#;
+
+        assert_eq!(events(ImportFormat::Json, log, None, TextZone::Utc), [
+            event(1_700_000_000, "42", "up", true),
+            event(1_700_000_002, "bar", "a", false),
+        ]);
+
+        // One message per line, the malformed lines and the lines of other
+        // formats are skipped.
+        let log = "\
+            {\"timestamp\": \"2023-11-14T22:13:20Z\", \"text\": \"up\", \"user_id\": \"42\", \"first_msg\": true}\n\
+            [2023-11-14 22:13:21] foo: a\n\
+            {\"timestamp\": \"2023-11-14T22:13:22Z\", \"text\": \n\
+            {\"timestamp\": \"2023-11-14T22:13:23Z\", \"text\": \"b\", \"username\": \"bar\"}\n";
+
+        assert_eq!(events(ImportFormat::Json, log, None, TextZone::Utc), [
+            event(1_700_000_000, "42", "up", true),
+            event(1_700_000_003, "bar", "b", false),
+        ]);
+
+    }
+
+    #[test]
+    fn mixed_formats() {
+
+        // Each format only reads its own lines.
+        let log = "\
+            [2023-11-14 22:13:20] foo: up\n\
+            @tmi-sent-ts=1700000001000 :bar!bar@bar.tmi.twitch.tv PRIVMSG #chan :a\n\
+            {\"timestamp\": \"2023-11-14T22:13:22Z\", \"text\": \"b\", \"username\": \"baz\"}\n";
+
+        assert_eq!(events(ImportFormat::Text, log, None, TextZone::Utc), [event(1_700_000_000, "foo", "up", false)]);
+        assert_eq!(events(ImportFormat::Irc, log, None, TextZone::Utc), [event(1_700_000_001, "bar", "a", false)]);
+        assert_eq!(events(ImportFormat::Json, log, None, TextZone::Utc), [event(1_700_000_002, "baz", "b", false)]);
+
+    }
+
+}
@@ -0,0 +1,206 @@
+use std::sync::mpsc::{self, Sender};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+use std::fs::File;
+use std::thread;
+
+use tracing::warn;
+
+use crate::config::Settings;
+use crate::logformat::{log_columns, RecordValue};
+
+
+/// Where the metrics are written.
+#[derive(Debug, Clone, PartialEq)]
+pub enum InfluxDestination {
+    /// A file of line protocol, for Telegraf or a later import.
+    File(PathBuf),
+    /// The write endpoint of an InfluxDB server, like
+    /// `http://localhost:8086/api/v2/write?org=tpp&bucket=chat`.
+    Http {
+        url: String,
+        /// Token of the `Authorization` header, if any.
+        token: Option<String>,
+    },
+}
+
+/// Configuration of the InfluxDB metrics.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InfluxConfig {
+    pub destination: InfluxDestination,
+    pub measurement: String,
+}
+
+impl InfluxConfig {
+
+    /// Read the configuration from the `influx_dest` setting, an HTTP URL
+    /// or a file path, and the `influx_token` and `influx_measurement` ones,
+    /// none if no destination is set.
+    pub fn from_settings(settings: &Settings) -> Result<Option<Self>, String> {
+
+        let Some(dest) = settings.get("influx_dest") else { return Ok(None) };
+
+        let destination = if dest.starts_with("http://") || dest.starts_with("https://") {
+            InfluxDestination::Http {
+                url: dest.to_string(),
+                token: settings.get("influx_token").map(str::to_string),
+            }
+        } else {
+            InfluxDestination::File(PathBuf::from(dest))
+        };
+
+        let measurement = settings.get("influx_measurement").unwrap_or("tpp");
+        if measurement.is_empty() {
+            return Err(format!("invalid {}: expected a name", settings.origin("influx_measurement")));
+        }
+
+        Ok(Some(Self { destination, measurement: measurement.to_string() }))
+
+    }
+
+}
+
+
+/// Write the log records as InfluxDB line protocol, to graph the chat
+/// alongside other metrics of the stream. The channel and the text values
+/// are tags, the numbers are fields. Requests are sent by a background
+/// thread, so that a slow server doesn't block the bot.
+#[derive(Debug)]
+pub struct Influx {
+    measurement: String,
+    sink: InfluxSink,
+    /// True while the file fails, so that the error is only reported once.
+    failing: bool,
+}
+
+#[derive(Debug)]
+enum InfluxSink {
+    File(File),
+    Http(Sender<String>),
+}
+
+impl Influx {
+
+    pub fn open(config: InfluxConfig) -> io::Result<Self> {
+
+        let sink = match config.destination {
+            InfluxDestination::File(path) => InfluxSink::File(File::options().append(true).create(true).open(path)?),
+            InfluxDestination::Http { url, token } => {
+
+                let (tx, rx) = mpsc::channel::<String>();
+
+                thread::Builder::new()
+                    .name("influx".to_string())
+                    .spawn(move || {
+                        let agent = ureq::AgentBuilder::new()
+                            .timeout(Duration::from_secs(10))
+                            .build();
+                        while let Ok(line) = rx.recv() {
+                            // The lines queued while sending are sent in a
+                            // single batch.
+                            let body = std::iter::once(line).chain(rx.try_iter()).collect::<String>();
+                            let mut request = agent.post(&url).set("Content-Type", "text/plain; charset=utf-8");
+                            if let Some(token) = &token {
+                                request = request.set("Authorization", &format!("Token {token}"));
+                            }
+                            if let Err(e) = request.send_string(&body) {
+                                warn!("influx write failed: {e}");
+                            }
+                        }
+                    })?;
+
+                InfluxSink::Http(tx)
+
+            }
+        };
+
+        Ok(Self { measurement: config.measurement, sink, failing: false })
+
+    }
+
+    /// Write a log record of a channel, with the values of the columns of
+    /// the log.
+    pub fn push(&mut self, channel: &str, values: &[RecordValue]) {
+        let line = line(&self.measurement, channel, values);
+        match &mut self.sink {
+            InfluxSink::File(file) => match file.write_all(line.as_bytes()) {
+                Ok(()) => self.failing = false,
+                Err(e) => {
+                    if !self.failing {
+                        warn!("failed to write the influx metrics: {e}");
+                        self.failing = true;
+                    }
+                }
+            },
+            InfluxSink::Http(tx) => {
+                // The thread only stops with the process.
+                let _ = tx.send(line);
+            }
+        }
+    }
+
+}
+
+
+/// The line of a log record, like `tpp,channel=chan,run=- msg_sec=3,
+/// cmd_sec=2.4,new_voters=24i 1700000000000000000`, the time column being
+/// the timestamp in nanoseconds.
+fn line(measurement: &str, channel: &str, values: &[RecordValue]) -> String {
+
+    let mut tags = format!("{},channel={}", escape(measurement, ", "), escape(channel, ", ="));
+    let mut fields = Vec::new();
+    let mut time = None;
+
+    for (name, value) in log_columns().iter().zip(values) {
+        match *value {
+            RecordValue::Int(value) if name == "time" => time = Some(value),
+            RecordValue::Int(value) => fields.push(format!("{name}={value}i")),
+            // The line protocol has no infinite numbers.
+            RecordValue::Float(value) if !value.is_finite() => {}
+            RecordValue::Float(value) => fields.push(format!("{name}={value}")),
+            RecordValue::Text(text) => tags.push_str(&format!(",{name}={}", escape(text, ", ="))),
+        }
+    }
+
+    match time {
+        Some(time) => format!("{tags} {} {}\n", fields.join(","), time * 1_000_000_000),
+        None => format!("{tags} {}\n", fields.join(",")),
+    }
+
+}
+
+/// Escape the given characters of a name or a tag with a backslash.
+fn escape(text: &str, chars: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if chars.contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::vocabulary::Command;
+
+    #[test]
+    fn lines() {
+
+        let values = [RecordValue::Int(1_700_000_000), RecordValue::Float(3.0), RecordValue::Float(f32::NAN), RecordValue::Float(0.375)];
+        assert_eq!(line("tpp", "chan", &values), "tpp,channel=chan msg_sec=3,share_up=0.375 1700000000000000000\n");
+
+        let mut values = vec![RecordValue::Int(1_700_000_000); 3 + Command::ALL.len() + 2];
+        values.push(RecordValue::Text("run one,=2"));
+        let line = line("tpp chat", "chan", &values);
+        assert!(line.starts_with("tpp\\ chat,channel=chan,run=run\\ one\\,\\=2 msg_sec=1700000000i,"), "{line}");
+        assert!(line.ends_with(" 1700000000000000000\n"));
+
+    }
+
+}
@@ -1,74 +1,343 @@
-use std::net::{TcpStream, SocketAddr};
-use std::io::{self, Read, Write};
-use std::time::Duration;
-use std::ops::Range;
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::cell::OnceCell;
+use std::io::{self, Write};
+use std::ops::{Deref, DerefMut, Range};
+use std::path::Path;
+use std::time::{Duration, Instant};
 use std::fmt;
 
+use chrono::{DateTime, Utc};
 
-pub struct IrcClient {
-    stream: TcpStream,
+use crate::capture::CaptureStream;
+use crate::chaos::{ChaosConfig, ChaosStream};
+use crate::error::{TppError, TppResult};
+use crate::ratelimit::RateLimiter;
+use crate::replay::{ReplayConfig, ReplayStream};
+use crate::transport::{self, IrcTransport, WsStream};
+
+
+/// Maximum length of a line, with its tags.
+const MAX_LINE_LEN: usize = 64 * 1024;
+/// Maximum number of bytes waiting to be written, the connection is
+/// considered dead beyond.
+const MAX_SEND_LEN: usize = 64 * 1024;
+
+
+/// An IRC client over any transport, by default a boxed one chosen when
+/// connecting. Commands are sent through its [`IrcWriter`].
+pub struct IrcClient<T = Box<dyn IrcTransport>> {
+    writer: IrcWriter<T>,
+    decoder: IrcDecoder,
+    /// Time of the last data received.
+    last_recv: Instant,
+}
+
+/// The sending half of an [`IrcClient`]. Commands are sent immediately,
+/// while chat messages are queued until the rate limit allows them, so
+/// that they never delay a PONG.
+pub struct IrcWriter<T> {
+    transport: T,
+    /// Bytes not yet accepted by the transport, sent before anything else.
+    output: Vec<u8>,
+    queue: VecDeque<QueuedMessage>,
+}
+
+/// A chat message waiting for the rate limit.
+#[derive(Debug)]
+struct QueuedMessage {
+    channel: String,
+    /// True if the account is moderator of the channel.
+    moderator: bool,
+    text: String,
+}
+
+/// The receiving half of an [`IrcClient`], buffering raw data until whole
+/// lines are received, which are decoded in place.
+#[derive(Debug, Default)]
+pub struct IrcDecoder {
     data: Vec<u8>,
+    /// Start of the data that is not yet decoded, the data before it is
+    /// still borrowed by the last decoded reply and is drained on next push.
+    start: usize,
+    errors: u64,
 }
 
 impl IrcClient {
 
-    pub fn connect(addr: &SocketAddr) -> io::Result<Self> {
-        let stream = TcpStream::connect_timeout(addr, Duration::from_secs(2))?;
-        stream.set_nonblocking(true)?;
+    pub fn connect(addr: &SocketAddr) -> TppResult<Self> {
+        let stream = transport::connect_tcp(addr)?;
+        Self::new(Box::new(stream))
+    }
+
+    /// Connect to the server using TLS, the server name is used to verify 
+    /// the server certificate.
+    pub fn connect_tls(addr: &SocketAddr, server_name: &str) -> TppResult<Self> {
+        let stream = transport::connect_tls(transport::connect_tcp(addr)?, server_name)?;
+        Self::new(Box::new(stream))
+    }
+
+    /// Connect to the server through a WebSocket at the given URL, using
+    /// TLS if a server name is given to verify the server certificate.
+    pub fn connect_websocket(addr: &SocketAddr, url: &str, server_name: Option<&str>) -> TppResult<Self> {
+
+        let stream = transport::connect_tcp(addr)?;
+        let stream: Box<dyn IrcTransport> = match server_name {
+            Some(server_name) => Box::new(transport::connect_tls(stream, server_name)?),
+            None => Box::new(stream),
+        };
+
+        Self::new(Box::new(WsStream::connect(url, stream)?))
+
+    }
+
+    /// Create a client that doesn't connect to any server, but receives a
+    /// synthetic chat generated locally instead.
+    pub fn connect_chaos(config: ChaosConfig, seed: u64, user: &str, channels: &[String]) -> TppResult<Self> {
+        Self::new(Box::new(ChaosStream::new(config, seed, user, channels).map_err(TppError::Config)?))
+    }
+
+    /// Create a client that doesn't connect to any server, but receives the
+    /// messages of a recorded chat log instead.
+    pub fn connect_replay(config: &ReplayConfig, user: &str, channel: &str) -> TppResult<Self> {
+        Self::new(Box::new(ReplayStream::open(config, user, channel)?))
+    }
+
+    /// Record every raw line received and sent from now on to the given
+    /// capture file.
+    pub fn with_capture(self, path: &Path) -> TppResult<Self> {
+        let transport: Box<dyn IrcTransport> = Box::new(CaptureStream::open(self.writer.transport, path)?);
         Ok(Self {
-            stream,
-            data: Vec::new(),
+            writer: IrcWriter { transport, output: self.writer.output, queue: self.writer.queue },
+            decoder: self.decoder,
+            last_recv: self.last_recv,
+        })
+    }
+
+}
+
+impl<T: IrcTransport> IrcClient<T> {
+
+    /// Create a client over an already connected transport, the transport
+    /// is switched to non-blocking mode.
+    pub fn new(mut transport: T) -> TppResult<Self> {
+        transport.set_nonblocking(true)?;
+        Ok(Self {
+            writer: IrcWriter { transport, output: Vec::new(), queue: VecDeque::new() },
+            decoder: IrcDecoder::default(),
+            last_recv: Instant::now(),
         })
     }
 
     /// Receive raw data from the socket. To read the replies, 
-    /// use [`read_reply`].
-    pub fn recv(&mut self) -> io::Result<()> {
+    /// use [`Self::decode_reply`].
+    pub fn recv(&mut self) -> TppResult<()> {
 
         let mut buf = [0; 64];
 
         loop {
-            match self.stream.read(&mut buf) {
-                Ok(0) => break,
-                Ok(size) => self.data.extend_from_slice(&buf[..size]),
+            match self.writer.transport.read(&mut buf) {
+                Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed by the server").into()),
+                Ok(size) => {
+                    self.decoder.push(&buf[..size]);
+                    self.last_recv = Instant::now();
+                }
                 Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
                 Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
-                Err(e) => return Err(e),
+                Err(e) => return Err(e.into()),
             }
         }
+
+        // Lines are never that long, the stream is not IRC. The complete
+        // lines of a burst are not counted, they are decoded later.
+        if self.decoder.partial() > MAX_LINE_LEN {
+            return Err(TppError::Parse(format!("no line ending in {} bytes", self.decoder.partial())));
+        }
         
         Ok(())
 
     }
 
-    /// Send a raw command using a format.
-    pub fn send_fmt(&mut self, fmt: fmt::Arguments) -> io::Result<()> {
-        self.stream.write_fmt(fmt)?;
-        self.stream.write_all(b"\r\n")
+    /// Duration since the last data was received.
+    pub fn idle(&self) -> Duration {
+        self.last_recv.elapsed()
     }
 
-    pub fn send_auth(&mut self, user: &str, token: &str) -> io::Result<()> {
+    /// Read a single reply from the internal raw data, read 
+    /// using [`Self::recv`].
+    pub fn decode_reply(&mut self) -> Option<IrcReplyRef<'_>> {
+        self.decoder.decode()
+    }
+
+    /// Split the client in its receiving and sending halves, to answer the
+    /// replies while they are borrowed from the decoder.
+    pub fn split(&mut self) -> (&mut IrcDecoder, &mut IrcWriter<T>) {
+        (&mut self.decoder, &mut self.writer)
+    }
+
+}
+
+impl<T> Deref for IrcClient<T> {
+    type Target = IrcWriter<T>;
+    fn deref(&self) -> &Self::Target {
+        &self.writer
+    }
+}
+
+impl<T> DerefMut for IrcClient<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.writer
+    }
+}
+
+impl<T: IrcTransport> IrcWriter<T> {
+
+    /// The underlying transport.
+    pub fn transport_mut(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    /// Send a raw command using a format, immediately. If the transport
+    /// is congested, the command is buffered and written by later calls to
+    /// [`Self::flush`].
+    pub fn send_fmt(&mut self, fmt: fmt::Arguments) -> TppResult<()> {
+        self.output.write_fmt(fmt)?;
+        self.output.extend_from_slice(b"\r\n");
+        self.flush()
+    }
+
+    /// Write the buffered bytes that the transport accepts without blocking,
+    /// the rest is kept for the next call.
+    pub fn flush(&mut self) -> TppResult<()> {
+
+        let mut written = 0;
+        let result = loop {
+            if written == self.output.len() {
+                break self.transport.flush();
+            }
+            match self.transport.write(&self.output[written..]) {
+                Ok(0) => break Err(io::ErrorKind::WriteZero.into()),
+                Ok(size) => written += size,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => break Err(e),
+            }
+        };
+
+        self.output.drain(..written);
+
+        match result {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            result => result?,
+        }
+
+        if self.output.len() > MAX_SEND_LEN {
+            return Err(TppError::Io(io::Error::other(format!("{} bytes could not be sent", self.output.len()))));
+        }
+
+        Ok(())
+
+    }
+
+    pub fn send_auth(&mut self, user: &str, token: &str) -> TppResult<()> {
         self.send_fmt(format_args!("PASS oauth:{token}"))?;
         self.send_fmt(format_args!("NICK {user}"))
     }
 
-    /// Read a single reply from the internal raw data, read 
-    /// using [`recv`].
-    pub fn decode_reply(&mut self) -> Option<IrcReply> {
+    /// Request the given IRCv3 capabilities, the server answers with a
+    /// [`IrcReplyCommand::CapAck`] or [`IrcReplyCommand::CapNak`] for all
+    /// of them at once, so capabilities that may be rejected should be
+    /// requested separately.
+    pub fn send_cap_req(&mut self, capabilities: &[&str]) -> TppResult<()> {
+        self.send_fmt(format_args!("CAP REQ :{}", capabilities.join(" ")))
+    }
+
+    /// Queue a chat message in the channel, it is sent immediately if the
+    /// rate limit allows it, or by a later [`Self::flush_queue`]. Line
+    /// breaks of the text are replaced by spaces, they would end the command
+    /// and send the rest of the text as another command.
+    pub fn send_privmsg(&mut self, limiter: &mut RateLimiter, channel: &str, moderator: bool, text: &str) -> TppResult<()> {
+        self.queue.push_back(QueuedMessage {
+            channel: channel.to_string(),
+            moderator,
+            text: text.replace(['\r', '\n'], " "),
+        });
+        self.flush_queue(limiter)
+    }
+
+    /// Send the queued chat messages allowed by the rate limit, the order
+    /// of the messages is kept in each channel.
+    pub fn flush_queue(&mut self, limiter: &mut RateLimiter) -> TppResult<()> {
+
+        let now = Instant::now();
+        let mut index = 0;
+
+        while let Some(message) = self.queue.get(index) {
+            if limiter.allows(now, &message.channel, message.moderator) {
+                let message = self.queue.remove(index).unwrap();
+                self.send_fmt(format_args!("PRIVMSG #{} :{}", message.channel, message.text))?;
+                limiter.record(now, &message.channel);
+            } else {
+                index += 1;
+            }
+        }
+
+        Ok(())
+
+    }
 
-        while let Some(cr_pos) = self.data.iter().position(|&b| b == b'\r') {
-            
-            let reply = IrcReply::from_str(std::str::from_utf8(&self.data[..cr_pos]).unwrap());
+    /// Drop all the queued chat messages, returning their number.
+    pub fn clear_queue(&mut self) -> usize {
+        let count = self.queue.len();
+        self.queue.clear();
+        count
+    }
+
+    /// Return true if chat messages of the channel are waiting for the rate
+    /// limit.
+    pub fn is_queued(&self, channel: &str) -> bool {
+        self.queue.iter().any(|message| message.channel == channel)
+    }
+
+}
+
+impl IrcDecoder {
+
+    /// Append raw data received from the server.
+    pub fn push(&mut self, data: &[u8]) {
+        self.data.drain(..self.start);
+        self.start = 0;
+        self.data.extend_from_slice(data);
+    }
+
+    /// Read a single reply from the raw data, borrowed until the next call.
+    /// Lines that are not valid UTF-8 are skipped and counted as errors.
+    pub fn decode(&mut self) -> Option<IrcReplyRef<'_>> {
 
-            let end_pos = match self.data[cr_pos + 1] {
-                b'\n' => cr_pos + 2,
+        while let Some(cr_pos) = self.data[self.start..].iter().position(|&b| b == b'\r') {
+
+            // The '\n' of the previous line wasn't received yet when it was
+            // decoded, if its '\r' was the last byte.
+            let line_start = match self.data[self.start] {
+                b'\n' => self.start + 1,
+                _ => self.start,
+            };
+            let cr_pos = self.start + cr_pos;
+
+            self.start = match self.data.get(cr_pos + 1) {
+                Some(b'\n') => cr_pos + 2,
                 _ => cr_pos + 1,
             };
 
-            self.data.drain(..end_pos);
+            let Ok(line) = std::str::from_utf8(&self.data[line_start..cr_pos]) else {
+                self.errors += 1;
+                continue;
+            };
 
-            if reply.is_some() {
-                return reply;
+            match IrcReplyRef::parse(line) {
+                _ if line.is_empty() => {}
+                Some(reply) => return Some(reply),
+                None => self.errors += 1,
             }
 
         }
@@ -77,21 +346,41 @@ impl IrcClient {
 
     }
 
+    /// Length of the data that is not yet decoded.
+    pub fn pending(&self) -> usize {
+        self.data.len() - self.start
+    }
+
+    /// Length of the data after the last line ending, the line being
+    /// received.
+    pub fn partial(&self) -> usize {
+        let data = &self.data[self.start..];
+        data.len() - data.iter().rposition(|&b| b == b'\r' || b == b'\n').map_or(0, |pos| pos + 1)
+    }
+
+    /// Number of lines skipped because they couldn't be decoded.
+    pub fn errors(&self) -> u64 {
+        self.errors
+    }
+
 }
 
 
-pub struct IrcReply {
-    pub raw: String,
-    pub command: IrcReplyCommand,
+/// A reply borrowed from the line it is parsed from.
+pub struct IrcReplyRef<'a> {
+    pub raw: &'a str,
+    pub command: IrcReplyCommand<'a>,
     metadata_range: Range<usize>,
     sender_range: Range<usize>,
     target_range: Range<usize>,
     text_start: usize,
+    /// Tags parsed from the metadata on first access.
+    tags: OnceCell<HashMap<&'a str, Cow<'a, str>>>,
 }
 
 #[derive(Debug)]
-pub enum IrcReplyCommand {
-    Raw(String),
+pub enum IrcReplyCommand<'a> {
+    Raw(&'a str),
     Welcome,
     YourHost,
     Created,
@@ -100,8 +389,31 @@ pub enum IrcReplyCommand {
     MotdText,
     MotdStop,
     PrivMsg,
+    Notice,
     Ping,
+    Pong,
     Join,
+    Part,
+    /// Capability reply other than ACK or NAK.
+    Cap,
+    CapAck,
+    CapNak,
+    /// The server is about to restart, the client should reconnect.
+    Reconnect,
+    /// State of the bot in a channel, sent on join and after each message.
+    UserState,
+    /// Chat modes of a channel, sent on join and when they change.
+    RoomState,
+    /// All messages of a user were cleared, by a timeout or a ban, or all
+    /// messages of the channel if no user is given.
+    ClearChat,
+    /// A single message was deleted.
+    ClearMsg,
+    /// A private message to the bot, the target is the bot login.
+    Whisper,
+    /// An event of the channel, like a subscription or a raid, given by the
+    /// `msg-id` tag, with an optional message of the user.
+    UserNotice,
     Name,
     EndOfNames,
 }
@@ -112,23 +424,26 @@ pub struct IrcSender<'a> {
     pub server: &'a str,
 }
 
-impl IrcReply {
+impl<'a> IrcReplyRef<'a> {
 
-    pub fn from_str<S: Into<String>>(line: S) -> Option<Self> {
+    /// Parse a line received from the server, none if it has no command.
+    pub fn parse(line: &'a str) -> Option<Self> {
 
-        let mut reply = IrcReply {
-            raw: line.into(),
+        let mut reply = IrcReplyRef {
+            raw: line,
             command: IrcReplyCommand::Welcome,
             metadata_range: 0..0,
             sender_range: 0..0,
             target_range: 0..0,
             text_start: 0,
+            tags: OnceCell::new(),
         };
 
         let mut start = 0;
         let mut offset = 0;
+        let mut has_command = false;
 
-        for (index, part) in reply.raw.splitn(5, ' ').enumerate() {
+        for (index, part) in line.splitn(5, ' ').enumerate() {
 
             let index = index - start;
 
@@ -140,6 +455,11 @@ impl IrcReply {
                 start += 1;
             } else if index == 0 {
 
+                if part.is_empty() {
+                    return None;
+                }
+                has_command = true;
+
                 reply.command = match part {
                     "001" => IrcReplyCommand::Welcome,
                     "002" => IrcReplyCommand::YourHost,
@@ -151,9 +471,20 @@ impl IrcReply {
                     "353" => IrcReplyCommand::Name,
                     "366" => IrcReplyCommand::EndOfNames,
                     "PRIVMSG" => IrcReplyCommand::PrivMsg,
+                    "NOTICE" => IrcReplyCommand::Notice,
                     "PING" => IrcReplyCommand::Ping,
+                    "PONG" => IrcReplyCommand::Pong,
                     "JOIN" => IrcReplyCommand::Join,
-                    _ => IrcReplyCommand::Raw(part.to_string()),
+                    "PART" => IrcReplyCommand::Part,
+                    "CAP" => IrcReplyCommand::Cap,
+                    "RECONNECT" => IrcReplyCommand::Reconnect,
+                    "USERSTATE" => IrcReplyCommand::UserState,
+                    "ROOMSTATE" => IrcReplyCommand::RoomState,
+                    "CLEARCHAT" => IrcReplyCommand::ClearChat,
+                    "CLEARMSG" => IrcReplyCommand::ClearMsg,
+                    "WHISPER" => IrcReplyCommand::Whisper,
+                    "USERNOTICE" => IrcReplyCommand::UserNotice,
+                    _ => IrcReplyCommand::Raw(part),
                 };
 
             } else {
@@ -165,17 +496,51 @@ impl IrcReply {
                     IrcReplyCommand::MotdStart |
                     IrcReplyCommand::MotdText |
                     IrcReplyCommand::MotdStop |
-                    IrcReplyCommand::PrivMsg => {
+                    IrcReplyCommand::PrivMsg |
+                    IrcReplyCommand::Notice |
+                    IrcReplyCommand::ClearChat |
+                    IrcReplyCommand::ClearMsg |
+                    IrcReplyCommand::Whisper |
+                    IrcReplyCommand::UserNotice |
+                    IrcReplyCommand::Pong => {
+                        if index == 1 {
+                            reply.target_range = offset..(offset + part.len());
+                        } else if index == 2 {
+                            if part.starts_with(':') {
+                                reply.text_start = offset + 1;
+                            }
+                            break;
+                        }
+                    }
+                    IrcReplyCommand::Cap => {
                         if index == 1 {
                             reply.target_range = offset..(offset + part.len());
                         } else if index == 2 {
+                            match part {
+                                "ACK" => reply.command = IrcReplyCommand::CapAck,
+                                "NAK" => reply.command = IrcReplyCommand::CapNak,
+                                _ => {}
+                            }
+                        } else if index == 3 {
                             if part.starts_with(':') {
                                 reply.text_start = offset + 1;
                             }
                             break;
                         }
                     }
-                    IrcReplyCommand::Join => {
+                    IrcReplyCommand::CapAck |
+                    IrcReplyCommand::CapNak => {
+                        if index == 3 && part.starts_with(':') {
+                            reply.text_start = offset + 1;
+                        }
+                        if index >= 3 {
+                            break;
+                        }
+                    }
+                    IrcReplyCommand::Join |
+                    IrcReplyCommand::Part |
+                    IrcReplyCommand::UserState |
+                    IrcReplyCommand::RoomState => {
                         if index == 1 {
                             reply.target_range = offset..(offset + part.len());
                         }
@@ -196,11 +561,11 @@ impl IrcReply {
 
         }
 
-        Some(reply)
+        has_command.then_some(reply)
 
     }
 
-    pub fn metadata(&self) -> Option<&str> {
+    pub fn metadata(&self) -> Option<&'a str> {
         if self.metadata_range.is_empty() {
             None
         } else {
@@ -208,7 +573,38 @@ impl IrcReply {
         }
     }
 
-    pub fn sender(&self) -> Option<IrcSender> {
+    /// Get all IRCv3 tags from the metadata, with their unescaped values.
+    /// The metadata is parsed on first access.
+    pub fn tags(&self) -> &HashMap<&'a str, Cow<'a, str>> {
+        self.tags.get_or_init(|| {
+            self.metadata().unwrap_or("")
+                .split(';')
+                .filter(|tag| !tag.is_empty())
+                .map(|tag| match tag.split_once('=') {
+                    Some((key, value)) => (key, unescape_tag_value(value)),
+                    None => (tag, Cow::Borrowed("")),
+                })
+                .collect()
+        })
+    }
+
+    /// Get the unescaped value of a single tag from the metadata, if present.
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        self.tags().get(key).map(Cow::as_ref)
+    }
+
+    /// Get the server time of this reply, from the IRCv3 `time` tag or
+    /// the Twitch-specific `tmi-sent-ts` tag.
+    pub fn server_time(&self) -> Option<DateTime<Utc>> {
+        if let Some(time) = self.tag("time") {
+            DateTime::parse_from_rfc3339(time).ok().map(|time| time.with_timezone(&Utc))
+        } else {
+            let millis = self.tag("tmi-sent-ts")?.parse::<i64>().ok()?;
+            DateTime::from_timestamp_millis(millis)
+        }
+    }
+
+    pub fn sender(&self) -> Option<IrcSender<'a>> {
 
         if self.sender_range.is_empty() {
             return None;
@@ -228,7 +624,7 @@ impl IrcReply {
 
     }
 
-    pub fn target(&self) -> Option<&str> {
+    pub fn target(&self) -> Option<&'a str> {
         if self.target_range.is_empty() {
             None
         } else {
@@ -236,7 +632,7 @@ impl IrcReply {
         }
     }
 
-    pub fn text(&self) -> Option<&str> {
+    pub fn text(&self) -> Option<&'a str> {
         if self.text_start == 0 {
             None
         } else {
@@ -244,11 +640,47 @@ impl IrcReply {
         }
     }
 
+    /// The capabilities listed in a CAP reply.
+    pub fn capabilities(&self) -> impl Iterator<Item = &'a str> {
+        self.text().unwrap_or("").split_whitespace()
+    }
+
 }
 
-impl fmt::Debug for IrcReply {
+/// Unescape an IRCv3 tag value, an invalid escape is replaced by the escaped
+/// character and a trailing backslash is dropped. The value is only copied
+/// if it contains escapes.
+fn unescape_tag_value(value: &str) -> Cow<'_, str> {
+
+    if !value.contains('\\') {
+        return Cow::Borrowed(value);
+    }
+
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some(':') => unescaped.push(';'),
+            Some('s') => unescaped.push(' '),
+            Some('r') => unescaped.push('\r'),
+            Some('n') => unescaped.push('\n'),
+            Some(c) => unescaped.push(c),
+            None => {}
+        }
+    }
+
+    Cow::Owned(unescaped)
+
+}
+
+impl fmt::Debug for IrcReplyRef<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let mut dbg = f.debug_struct("IrcReply");
+        let mut dbg = f.debug_struct("IrcReplyRef");
         dbg.field("command", &self.command);
         if let Some(metadata) = self.metadata() {
             dbg.field("metadata", &metadata);
@@ -280,4 +712,238 @@ impl fmt::Debug for IrcSender<'_> {
         dbg.finish()
     }
 
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::transport::MockTransport;
+
+    fn client(input: &str) -> IrcClient<MockTransport> {
+        IrcClient::new(MockTransport {
+            input: input.as_bytes().to_vec(),
+            ..MockTransport::default()
+        }).unwrap()
+    }
+
+    #[test]
+    fn decode_privmsg() {
+
+        let mut client = client("@first-msg=1;user-id=42 :foo!foo@foo.tmi.twitch.tv PRIVMSG #chan :hello world\r\n");
+        client.recv().unwrap();
+
+        let reply = client.decode_reply().unwrap();
+        assert!(matches!(reply.command, IrcReplyCommand::PrivMsg));
+        assert_eq!(reply.tag("user-id"), Some("42"));
+        assert_eq!(reply.sender().unwrap().nickname, Some("foo"));
+        assert_eq!(reply.target(), Some("#chan"));
+        assert_eq!(reply.text(), Some("hello world"));
+        assert!(client.decode_reply().is_none());
+
+    }
+
+    #[test]
+    fn decode_burst() {
+
+        // A burst of complete lines is longer than any line.
+        let line = ":foo!foo@foo.tmi.twitch.tv PRIVMSG #chan :hello world\r\n";
+        let count = 70 * 1024 / line.len() + 1;
+        let mut client = client(&line.repeat(count));
+        client.recv().unwrap();
+        let mut decoded = 0;
+        while client.decode_reply().is_some() {
+            decoded += 1;
+        }
+        assert_eq!(decoded, count);
+
+        // An endless line is still rejected.
+        client.transport_mut().input = vec![b'a'; MAX_LINE_LEN + 1];
+        assert!(matches!(client.recv(), Err(TppError::Parse(_))));
+
+    }
+
+    #[test]
+    fn decode_split_lines() {
+
+        let mut client = client(":tmi.twitch.tv 001 bot :Welcome, GLHF!\r\nPING :tmi.tw");
+        client.recv().unwrap();
+
+        assert!(matches!(client.decode_reply().unwrap().command, IrcReplyCommand::Welcome));
+        assert!(client.decode_reply().is_none());
+
+        client.transport_mut().input.extend_from_slice(b"itch.tv\r\n");
+        client.recv().unwrap();
+
+        let reply = client.decode_reply().unwrap();
+        assert!(matches!(reply.command, IrcReplyCommand::Ping));
+        assert_eq!(reply.text(), Some("tmi.twitch.tv"));
+
+    }
+
+    #[test]
+    fn decode_malformed() {
+
+        let mut client = client("PING :a\r");
+        client.recv().unwrap();
+
+        // A '\r' at the end of the data doesn't need its '\n'.
+        assert_eq!(client.decode_reply().unwrap().text(), Some("a"));
+        assert!(client.decode_reply().is_none());
+
+        client.transport_mut().input.extend_from_slice(b"\n:foo PRIVMSG #chan :\xff\xfe\r\n\r\nPING :b\r\n");
+        client.recv().unwrap();
+
+        assert_eq!(client.decode_reply().unwrap().text(), Some("b"));
+        assert!(client.decode_reply().is_none());
+        assert_eq!(client.decoder.errors(), 1);
+
+        // Lines without a command are skipped too.
+        client.transport_mut().input.extend_from_slice(b"@a=1 :foo\r\n:foo  PING :c\r\nPING :d\r\n");
+        client.recv().unwrap();
+
+        assert_eq!(client.decode_reply().unwrap().text(), Some("d"));
+        assert_eq!(client.decoder.errors(), 3);
+
+    }
+
+    #[test]
+    fn parse_tags() {
+
+        let reply = IrcReplyRef::parse(r"@badge-info=;display-name=Foo\sBar;msg=a\:b\\c\n;emote-only;trailing=x\ :foo PRIVMSG #chan :hi").unwrap();
+        let tags = reply.tags();
+
+        assert_eq!(tags.len(), 5);
+        assert_eq!(reply.tag("badge-info"), Some(""));
+        assert_eq!(reply.tag("display-name"), Some("Foo Bar"));
+        assert_eq!(reply.tag("msg"), Some("a;b\\c\n"));
+        assert_eq!(reply.tag("emote-only"), Some(""));
+        assert_eq!(reply.tag("trailing"), Some("x"));
+        assert_eq!(reply.tag("missing"), None);
+        // Only the values with escapes are copied.
+        assert!(matches!(tags["badge-info"], Cow::Borrowed(_)));
+        assert!(matches!(tags["msg"], Cow::Owned(_)));
+
+        assert!(IrcReplyRef::parse("PING :tmi.twitch.tv").unwrap().tags().is_empty());
+
+    }
+
+    #[test]
+    fn decode_cap() {
+
+        let mut client = client(":tmi.twitch.tv CAP * ACK :twitch.tv/tags twitch.tv/commands\r\n:tmi.twitch.tv CAP * NAK :server-time\r\n");
+        client.recv().unwrap();
+
+        let reply = client.decode_reply().unwrap();
+        assert!(matches!(reply.command, IrcReplyCommand::CapAck));
+        assert_eq!(reply.capabilities().collect::<Vec<_>>(), ["twitch.tv/tags", "twitch.tv/commands"]);
+
+        let reply = client.decode_reply().unwrap();
+        assert!(matches!(reply.command, IrcReplyCommand::CapNak));
+        assert_eq!(reply.capabilities().collect::<Vec<_>>(), ["server-time"]);
+
+    }
+
+    #[test]
+    fn decode_notice() {
+        let mut client = client(":tmi.twitch.tv NOTICE * :Login authentication failed\r\n");
+        client.recv().unwrap();
+        let reply = client.decode_reply().unwrap();
+        assert!(matches!(reply.command, IrcReplyCommand::Notice));
+        assert_eq!(reply.text(), Some("Login authentication failed"));
+    }
+
+    #[test]
+    fn decode_reconnect() {
+        let mut client = client(":tmi.twitch.tv RECONNECT\r\n");
+        client.recv().unwrap();
+        assert!(matches!(client.decode_reply().unwrap().command, IrcReplyCommand::Reconnect));
+    }
+
+    #[test]
+    fn decode_user_state() {
+        let mut client = client("@badges=moderator/1;mod=1 :tmi.twitch.tv USERSTATE #chan\r\n");
+        client.recv().unwrap();
+        let reply = client.decode_reply().unwrap();
+        assert!(matches!(reply.command, IrcReplyCommand::UserState));
+        assert_eq!(reply.target(), Some("#chan"));
+        assert_eq!(reply.tag("mod"), Some("1"));
+    }
+
+    #[test]
+    fn decode_clear_chat() {
+
+        let mut client = client("@ban-duration=600;room-id=1;target-user-id=2 :tmi.twitch.tv CLEARCHAT #chan :bot\r\n@room-id=1 :tmi.twitch.tv CLEARCHAT #chan\r\n");
+        client.recv().unwrap();
+
+        let reply = client.decode_reply().unwrap();
+        assert!(matches!(reply.command, IrcReplyCommand::ClearChat));
+        assert_eq!(reply.target(), Some("#chan"));
+        assert_eq!(reply.text(), Some("bot"));
+        assert_eq!(reply.tag("ban-duration"), Some("600"));
+
+        let reply = client.decode_reply().unwrap();
+        assert!(matches!(reply.command, IrcReplyCommand::ClearChat));
+        assert_eq!(reply.text(), None);
+
+    }
+
+    #[test]
+    fn send_commands() {
+
+        let mut client = client("");
+        client.send_auth("bot", "token").unwrap();
+        client.send_cap_req(&["twitch.tv/tags", "twitch.tv/commands"]).unwrap();
+        client.send_fmt(format_args!("JOIN #{}", "chan")).unwrap();
+
+        assert_eq!(client.transport_mut().output, b"PASS oauth:token\r\nNICK bot\r\nCAP REQ :twitch.tv/tags twitch.tv/commands\r\nJOIN #chan\r\n");
+
+    }
+
+    #[test]
+    fn send_congested() {
+
+        let mut client = client("");
+        client.transport_mut().writable = Some(10);
+        client.send_fmt(format_args!("PRIVMSG #chan :{}", "hello")).unwrap();
+        client.send_fmt(format_args!("PONG :{}", "tmi.twitch.tv")).unwrap();
+        assert_eq!(client.transport_mut().output, b"PRIVMSG #c");
+
+        // Lines are never cut, the rest is written once possible.
+        client.transport_mut().writable = None;
+        client.flush().unwrap();
+        assert_eq!(client.transport_mut().output, b"PRIVMSG #chan :hello\r\nPONG :tmi.twitch.tv\r\n");
+
+    }
+
+    #[test]
+    fn send_queue() {
+
+        let mut client = client("");
+        let mut limiter = RateLimiter::new(false);
+
+        client.send_privmsg(&mut limiter, "chan", false, "a").unwrap();
+        client.send_privmsg(&mut limiter, "chan", false, "b").unwrap();
+        client.send_privmsg(&mut limiter, "other", false, "c").unwrap();
+        // Commands are not delayed by the queued messages.
+        client.send_fmt(format_args!("PONG :{}", "tmi.twitch.tv")).unwrap();
+
+        assert!(client.is_queued("chan"));
+        assert!(!client.is_queued("other"));
+        assert_eq!(client.transport_mut().output, b"PRIVMSG #chan :a\r\nPRIVMSG #other :c\r\nPONG :tmi.twitch.tv\r\n");
+
+    }
+
+    #[test]
+    fn send_line_breaks() {
+
+        let mut client = client("");
+        let mut limiter = RateLimiter::new(false);
+
+        // The text can't send another command.
+        client.send_privmsg(&mut limiter, "chan", false, "a\r\nPART #chan\nb").unwrap();
+        assert_eq!(client.transport_mut().output, b"PRIVMSG #chan :a  PART #chan b\r\n");
+
+    }
+
+}
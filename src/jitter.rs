@@ -0,0 +1,80 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+use crate::config::Settings;
+
+
+/// Random variations of the sending cadence, so that the messages of the
+/// bot don't form a perfectly regular pattern.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct JitterConfig {
+    /// Ratio of the interval randomly added or removed.
+    pub ratio: f32,
+    /// Probability of skipping a send, as a human would miss some.
+    pub skip_rate: f32,
+}
+
+impl JitterConfig {
+
+    /// Read the configuration from the `send_jitter` and `send_skip_rate`
+    /// settings, ratios between 0 and 1, none by default.
+    pub fn from_settings(settings: &Settings) -> Result<Self, String> {
+
+        let ratio = |key: &str| match settings.parse::<f32>(key)? {
+            Some(ratio) if !(0.0..=1.0).contains(&ratio) => Err(format!("invalid {}: expected a ratio between 0 and 1", settings.origin(key))),
+            ratio => Ok(ratio.unwrap_or(0.0)),
+        };
+
+        Ok(Self {
+            ratio: ratio("send_jitter")?,
+            skip_rate: ratio("send_skip_rate")?,
+        })
+
+    }
+
+    /// The given interval with a random part added or removed.
+    pub fn interval(&self, interval: Duration, rng: &mut impl Rng) -> Duration {
+        if self.ratio == 0.0 {
+            return interval;
+        }
+        interval.mul_f32(1.0 + rng.gen_range(-self.ratio..=self.ratio))
+    }
+
+    /// Return true if the next send should be skipped.
+    pub fn skips(&self, rng: &mut impl Rng) -> bool {
+        self.skip_rate > 0.0 && rng.gen::<f32>() < self.skip_rate
+    }
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+
+    #[test]
+    fn jitter() {
+
+        let mut rng = StdRng::seed_from_u64(7);
+        let interval = Duration::from_secs(4);
+
+        let none = JitterConfig::default();
+        assert_eq!(none.interval(interval, &mut rng), interval);
+        assert!(!none.skips(&mut rng));
+
+        let config = JitterConfig { ratio: 0.15, skip_rate: 0.1 };
+        let intervals = (0..1000).map(|_| config.interval(interval, &mut rng).as_secs_f32()).collect::<Vec<_>>();
+        assert!(intervals.iter().all(|&secs| (3.4..=4.6).contains(&secs)));
+        assert!(intervals.iter().any(|&secs| secs != 4.0));
+
+        let skips = (0..1000).filter(|_| config.skips(&mut rng)).count();
+        assert!((50..150).contains(&skips), "{skips} skips");
+
+    }
+
+}
@@ -0,0 +1,322 @@
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::fs::File;
+
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+
+use crate::format::NumberFormat;
+use crate::variation::DUPLICATE_HORIZON;
+
+
+/// Hash preceding the first entry of a journal.
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+
+/// Kind of an action recorded in the journal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JournalKind {
+    /// A message sent in a channel.
+    Send,
+    /// A message that was decided but not sent.
+    Skip,
+    /// The bot mode switched automatically.
+    Auto,
+    /// A command of the operator.
+    Operator,
+    /// An action triggered by a rule.
+    Rule,
+    /// A session started, with its random seed.
+    Session,
+    /// The bot was timed out or banned by a moderator.
+    Moderation,
+}
+
+impl JournalKind {
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Send => "send",
+            Self::Skip => "skip",
+            Self::Auto => "auto",
+            Self::Operator => "operator",
+            Self::Rule => "rule",
+            Self::Session => "session",
+            Self::Moderation => "moderation",
+        }
+    }
+
+}
+
+
+/// An append-only journal of every automated action of the bot, where each
+/// entry is chained to the previous one by its hash, so that the journal
+/// can't be altered without breaking the chain. Each line of the file is:
+/// timestamp, kind, channel, detail, previous hash and hash.
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    last_hash: String,
+    /// Lines not yet written because the file failed, written before the
+    /// next entry so that the chain stays whole.
+    pending: Vec<String>,
+    /// True while the file fails, so that the error is only reported once.
+    failing: bool,
+}
+
+impl Journal {
+
+    /// Open the journal at the given path, continuing the existing chain.
+    pub fn open(path: PathBuf) -> io::Result<Self> {
+
+        let mut last_hash = GENESIS_HASH.to_string();
+
+        match File::open(&path) {
+            Ok(file) => {
+                for line in BufReader::new(file).lines() {
+                    if let Some(entry) = JournalEntry::parse(&line?) {
+                        last_hash = entry.hash;
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        Ok(Self { path, last_hash, pending: Vec::new(), failing: false })
+
+    }
+
+    /// Append an entry to the journal, the file is opened for each entry
+    /// because actions are rare and must be persisted immediately. If the
+    /// file fails, the entry is kept and written with the next one.
+    pub fn record(&mut self, time: DateTime<Utc>, kind: JournalKind, channel: &str, detail: &str) {
+
+        let detail = detail.replace(['\t', '\n', '\r'], " ");
+        let hash = entry_hash(&self.last_hash, time.timestamp_millis(), kind.as_str(), channel, &detail);
+
+        self.pending.push(format!("{}\t{}\t{channel}\t{detail}\t{}\t{hash}\n", time.timestamp_millis(), kind.as_str(), self.last_hash));
+        self.last_hash = hash;
+
+        match self.write_pending() {
+            Ok(()) => {
+                if self.failing {
+                    info!("{} written again", self.path.display());
+                    self.failing = false;
+                }
+            }
+            Err(e) => {
+                if !self.failing {
+                    warn!("\x07alert: failed to write {}: {e}, keeping entries in memory", self.path.display());
+                    self.failing = true;
+                }
+            }
+        }
+
+    }
+
+    /// Write the pending lines at once, a partial write is truncated so
+    /// that it's written again whole.
+    fn write_pending(&mut self) -> io::Result<()> {
+
+        let mut file = File::options().append(true).create(true).open(&self.path)?;
+        let len = file.metadata()?.len();
+
+        if let Err(e) = file.write_all(self.pending.concat().as_bytes()) {
+            let _ = file.set_len(len);
+            return Err(e);
+        }
+
+        self.pending.clear();
+        Ok(())
+
+    }
+
+}
+
+
+/// A single entry read from a journal file.
+#[derive(Debug)]
+struct JournalEntry {
+    timestamp: i64,
+    kind: String,
+    channel: String,
+    detail: String,
+    prev_hash: String,
+    hash: String,
+}
+
+impl JournalEntry {
+
+    fn parse(line: &str) -> Option<Self> {
+        let mut parts = line.split('\t');
+        Some(Self {
+            timestamp: parts.next()?.parse().ok()?,
+            kind: parts.next()?.to_string(),
+            channel: parts.next()?.to_string(),
+            detail: parts.next()?.to_string(),
+            prev_hash: parts.next()?.to_string(),
+            hash: parts.next()?.to_string(),
+        })
+    }
+
+    /// Return true if the entry hash matches its content.
+    fn is_valid(&self) -> bool {
+        self.hash == entry_hash(&self.prev_hash, self.timestamp, &self.kind, &self.channel, &self.detail)
+    }
+
+}
+
+/// Compute the hash of an entry, chained to the previous hash.
+fn entry_hash(prev_hash: &str, timestamp: i64, kind: &str, channel: &str, detail: &str) -> String {
+    let digest = Sha256::new()
+        .chain_update(prev_hash)
+        .chain_update(format!("\t{timestamp}\t{kind}\t{channel}\t{detail}"))
+        .finalize();
+    digest.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+
+/// Entry point of the `journal` subcommand, verifying the chain of the
+/// journal and exporting it as a single report.
+pub fn main(input: &Path, report: Option<&Path>, nf: &NumberFormat) -> io::Result<()> {
+
+    match report {
+        Some(output) => {
+            let mut output = BufWriter::new(File::create(output)?);
+            export(File::open(input)?, &mut output, nf)?;
+            output.flush()
+        }
+        None => export(File::open(input)?, &mut io::stdout().lock(), nf),
+    }
+
+}
+
+/// Verify the journal and write the report, starting with a summary of the
+/// chain integrity and of the rate of messages sent in each channel.
+pub fn export(input: File, output: &mut impl Write, nf: &NumberFormat) -> io::Result<()> {
+
+    let mut entries = Vec::new();
+    let mut broken = Vec::new();
+    let mut prev_hash = GENESIS_HASH.to_string();
+
+    for (index, line) in BufReader::new(input).lines().enumerate() {
+        let line = line?;
+        let Some(entry) = JournalEntry::parse(&line) else {
+            broken.push(format!("line {}: malformed entry", index + 1));
+            continue;
+        };
+        if entry.prev_hash != prev_hash {
+            broken.push(format!("line {}: chain broken, previous hash doesn't match", index + 1));
+        }
+        if !entry.is_valid() {
+            broken.push(format!("line {}: hash doesn't match the entry", index + 1));
+        }
+        prev_hash = entry.hash.clone();
+        entries.push(entry);
+    }
+
+    // Per channel: number of messages sent, shortest interval between two
+    // of them, and number of identical messages sent within the duplicate
+    // horizon.
+    let mut channels = HashMap::<&str, (usize, Option<i64>, usize)>::new();
+    let mut last_sent = HashMap::<&str, (i64, &str)>::new();
+
+    for entry in entries.iter().filter(|entry| entry.kind == JournalKind::Send.as_str()) {
+        let (count, min_interval, duplicates) = channels.entry(&entry.channel).or_default();
+        *count += 1;
+        if let Some(&(last_timestamp, last_detail)) = last_sent.get(entry.channel.as_str()) {
+            let interval = entry.timestamp - last_timestamp;
+            *min_interval = Some(min_interval.map_or(interval, |min| min.min(interval)));
+            if last_detail == message_of(&entry.detail) && interval < DUPLICATE_HORIZON.as_millis() as i64 {
+                *duplicates += 1;
+            }
+        }
+        last_sent.insert(&entry.channel, (entry.timestamp, message_of(&entry.detail)));
+    }
+
+    let time = |timestamp: i64| DateTime::from_timestamp_millis(timestamp)
+        .map(|time| time.format("%Y-%m-%d %H:%M:%S%.3f").to_string())
+        .unwrap_or_else(|| timestamp.to_string());
+
+    writeln!(output, "# tpp-bot journal report")?;
+    writeln!(output)?;
+    writeln!(output, "entries: {}", nf.int(entries.len() as i64))?;
+    if let (Some(first), Some(last)) = (entries.first(), entries.last()) {
+        writeln!(output, "period: {} to {} UTC", time(first.timestamp), time(last.timestamp))?;
+        writeln!(output, "last hash: {}", last.hash)?;
+    }
+
+    if broken.is_empty() {
+        writeln!(output, "integrity: valid")?;
+    } else {
+        writeln!(output, "integrity: INVALID")?;
+        for error in &broken {
+            writeln!(output, "  {error}")?;
+        }
+    }
+
+    let mut channels = channels.into_iter().collect::<Vec<_>>();
+    channels.sort_by_key(|&(channel, _)| channel);
+
+    for (channel, (count, min_interval, duplicates)) in channels {
+        let min_interval = min_interval.map(|ms| format!("{}s", nf.float(ms as f32 / 1000.0, 3))).unwrap_or_else(|| "-".to_string());
+        writeln!(output, "channel #{channel}: {} sent, shortest interval {min_interval}, {} duplicates",
+            nf.int(count as i64), nf.int(duplicates as i64))?;
+    }
+
+    writeln!(output)?;
+    writeln!(output, "# entries")?;
+    writeln!(output)?;
+
+    for entry in &entries {
+        writeln!(output, "{}\t{}\t{}\t{}", time(entry.timestamp), entry.kind, entry.channel, entry.detail)?;
+    }
+
+    Ok(())
+
+}
+
+/// The message part of a send detail, given by its `message=` field.
+fn message_of(detail: &str) -> &str {
+    detail.split(' ').find_map(|field| field.strip_prefix("message=")).unwrap_or(detail)
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn disk_full() {
+
+        let path = std::env::temp_dir().join(format!("tpp-bot-journal-{}.tsv", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let time = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+
+        // Writes to this device always fail with no space left.
+        let mut journal = Journal::open(path.clone()).unwrap();
+        journal.path = PathBuf::from("/dev/full");
+        journal.record(time, JournalKind::Send, "chan", "message=up");
+        assert!(journal.failing);
+        assert_eq!(journal.pending.len(), 1);
+
+        journal.path = path.clone();
+        journal.record(time, JournalKind::Operator, "chan", "pause");
+        assert!(!journal.failing);
+        assert!(journal.pending.is_empty());
+
+        let mut report = Vec::new();
+        export(File::open(&path).unwrap(), &mut report, &NumberFormat::default()).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        assert!(report.contains("entries: 2\n"), "{report}");
+        assert!(report.contains("integrity: valid\n"), "{report}");
+
+        std::fs::remove_file(&path).unwrap();
+
+    }
+
+}
@@ -0,0 +1,61 @@
+use std::time::{Duration, Instant};
+
+
+/// Estimate the end-to-end delay between the chat typing a command and
+/// the emulator acting on it, from a configured stream delay and the
+/// measured IRC latency.
+#[derive(Debug)]
+pub struct Latency {
+    /// Configured delay of the stream and the emulator.
+    stream_delay: Duration,
+    /// Interval between two pings.
+    ping_interval: Duration,
+    /// Time of the last ping sent, and true if still waiting its pong.
+    ping: Option<(Instant, bool)>,
+    /// Measured one-way IRC latency.
+    irc: Option<Duration>,
+}
+
+impl Latency {
+
+    pub fn new(stream_delay: Duration, ping_interval: Duration) -> Self {
+        Self {
+            stream_delay,
+            ping_interval,
+            ping: None,
+            irc: None,
+        }
+    }
+
+    /// Return true if a new ping should be sent.
+    pub fn should_ping(&self) -> bool {
+        match self.ping {
+            Some((time, _)) => time.elapsed() >= self.ping_interval,
+            None => true,
+        }
+    }
+
+    /// Notify that a ping has just been sent.
+    pub fn ping_sent(&mut self) {
+        self.ping = Some((Instant::now(), true));
+    }
+
+    /// Notify that the pong for the last ping has been received.
+    pub fn pong_received(&mut self) {
+        if let Some((time, waiting @ true)) = &mut self.ping {
+            *waiting = false;
+            let latency = time.elapsed() / 2;
+            self.irc = Some(match self.irc {
+                // Smooth the latency to absorb the network jitter.
+                Some(prev) => (prev * 3 + latency) / 4,
+                None => latency,
+            });
+        }
+    }
+
+    /// Total estimated delay.
+    pub fn total(&self) -> Duration {
+        self.stream_delay + self.irc.unwrap_or_default()
+    }
+
+}
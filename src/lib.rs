@@ -0,0 +1,90 @@
+//! Analysis of the chat of Twitch Plays channels, with a bot optionally
+//! playing along. The IRC client and parser, the samples of the chat and
+//! the sending decisions are usable on their own, the binary is only a
+//! command line wrapper around [`bot::session`].
+
+use std::io::Write;
+use std::fmt;
+
+pub mod analyze;
+pub mod archive;
+pub mod autobot;
+pub mod backtest;
+pub mod binlog;
+pub mod bot;
+pub mod burst;
+pub mod campaign;
+pub mod capture;
+pub mod channel;
+pub mod chaos;
+pub mod clock;
+pub mod config;
+pub mod console;
+pub mod control;
+pub mod curve;
+pub mod database;
+pub mod democracy;
+pub mod emergency;
+pub mod error;
+pub mod export;
+pub mod expr;
+pub mod format;
+pub mod funnel;
+pub mod history;
+pub mod http;
+pub mod hype;
+pub mod ignore;
+pub mod import;
+pub mod influx;
+pub mod irc;
+pub mod jitter;
+pub mod journal;
+pub mod latency;
+pub mod lock;
+pub mod logfile;
+pub mod logformat;
+pub mod logging;
+pub mod overlay;
+pub mod pause;
+pub mod policy;
+pub mod ratelimit;
+pub mod reconnect;
+pub mod replay;
+pub mod resolution;
+pub mod rng;
+pub mod roomstate;
+pub mod rules;
+pub mod sample;
+pub mod schedule;
+pub mod script;
+pub mod seal;
+pub mod seen;
+pub mod selftest;
+pub mod shadow;
+pub mod snapshot;
+pub mod socket;
+pub mod source;
+pub mod stats;
+pub mod status;
+pub mod touch;
+pub mod tppbot;
+pub mod transport;
+pub mod trigger;
+pub mod upload;
+pub mod usernotice;
+pub mod variation;
+pub mod vocabulary;
+pub mod warmstart;
+pub mod watchdog;
+pub mod weights;
+pub mod whisper;
+
+
+/// Print the interactive prompt line, it is overwritten by the next one and
+/// by the diagnostics.
+pub fn print_prompt(fmt: fmt::Arguments) {
+    if logging::prompt_enabled() {
+        print!("\r> {fmt}");
+        std::io::stdout().flush().unwrap();
+    }
+}
@@ -0,0 +1,76 @@
+use std::fs::{File, TryLockError};
+use std::io::{self, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+
+/// Advisory lock of a bot account in a channel, so that two instances
+/// started with the same configuration don't both send and double the
+/// message rate. The lock is held until dropped, or the process exits.
+#[derive(Debug)]
+pub struct SendLock {
+    _file: File,
+}
+
+impl SendLock {
+
+    /// Try to take the lock in the given directory, returning the process
+    /// id of the instance holding it if it is already taken.
+    pub fn acquire(dir: &Path, user: &str, channel: &str) -> io::Result<Result<Self, Option<u32>>> {
+
+        let mut file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(lock_path(dir, user, channel))?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let mut pid = String::new();
+                file.read_to_string(&mut pid)?;
+                return Ok(Err(pid.trim().parse().ok()));
+            }
+            Err(TryLockError::Error(e)) => return Err(e),
+        }
+
+        // The process id is only informative.
+        file.set_len(0)?;
+        file.rewind()?;
+        write!(file, "{}", process::id())?;
+
+        Ok(Ok(Self { _file: file }))
+
+    }
+
+}
+
+
+/// Path of the lock file of a bot account in a channel.
+fn lock_path(dir: &Path, user: &str, channel: &str) -> PathBuf {
+    dir.join(format!("tpp-bot.{}.{}.lock", user.to_lowercase(), channel.to_lowercase()))
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn exclusive() {
+
+        let dir = std::env::temp_dir();
+        let user = format!("test{}", process::id());
+
+        let lock = SendLock::acquire(&dir, &user, "chan").unwrap().unwrap();
+        assert_eq!(SendLock::acquire(&dir, &user, "chan").unwrap().unwrap_err(), Some(process::id()));
+        assert!(SendLock::acquire(&dir, &user, "other").unwrap().is_ok());
+
+        drop(lock);
+        assert!(SendLock::acquire(&dir, &user, "chan").unwrap().is_ok());
+
+    }
+
+}
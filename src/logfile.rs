@@ -0,0 +1,506 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::fs::{self, File};
+
+use chrono::{DateTime, Local, NaiveDate};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use tracing::{info, warn};
+
+use crate::config::{parse_seconds, Settings};
+
+
+/// Maximum number of records kept in memory while the file can't be written,
+/// about an hour of records at the default sample duration.
+const PENDING_CAPACITY: usize = 4096;
+/// Delay between two attempts to write the pending records.
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+
+/// When the records written to the log files are flushed to the system,
+/// and synced to the disk.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FlushPolicy {
+    /// Minimum interval between two flushes, the records are kept in a
+    /// buffer meanwhile. Zero flushes every record.
+    pub interval: Duration,
+    /// Sync the file to the disk after each flush, so that the records
+    /// survive a power loss.
+    pub sync: bool,
+}
+
+impl FlushPolicy {
+
+    /// Read the policy from the `log_flush_interval` (in seconds) and
+    /// `log_fsync` settings.
+    pub fn from_settings(settings: &Settings) -> Result<Self, String> {
+        Ok(Self {
+            interval: settings.parse_with("log_flush_interval", parse_seconds)?.unwrap_or_default(),
+            sync: settings.parse::<bool>("log_fsync")?.unwrap_or(false),
+        })
+    }
+
+}
+
+
+/// Rotation of the log files, so that they don't grow forever: the active
+/// file keeps its path and is rolled to a dated file like
+/// `tpp-2024-05-01.log` every day or once it reaches a size.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Rotation {
+    /// Roll the file when the local date changes.
+    pub daily: bool,
+    /// Roll the file before it exceeds this size, in bytes.
+    pub max_size: Option<u64>,
+    /// Compress the rolled files with gzip.
+    pub compress: bool,
+}
+
+impl Rotation {
+
+    /// Read the rotation from the `log_rotate_daily`, `log_rotate_size` (in
+    /// MB) and `log_rotate_compress` settings, none if the logs aren't
+    /// rotated.
+    pub fn from_settings(settings: &Settings) -> Result<Option<Self>, String> {
+
+        let daily = settings.parse::<bool>("log_rotate_daily")?.unwrap_or(false);
+        let max_size = settings.parse_with("log_rotate_size", |text| match text.parse::<f64>() {
+            Ok(size) if size > 0.0 => Ok((size * 1_000_000.0) as u64),
+            _ => Err("expected a positive size in MB"),
+        })?;
+        let compress = settings.parse::<bool>("log_rotate_compress")?.unwrap_or(false);
+
+        Ok((daily || max_size.is_some()).then_some(Self { daily, max_size, compress }))
+
+    }
+
+    /// Return true if a file of the given date and length must be rolled
+    /// before writing a record.
+    fn due(&self, date: NaiveDate, today: NaiveDate, len: u64, record_len: usize) -> bool {
+        (self.daily && date != today) || self.max_size.is_some_and(|max_size| len + record_len as u64 > max_size)
+    }
+
+}
+
+
+/// A buffered log file that survives write failures, like a full disk or a
+/// lost permission. Records that can't be written are kept in memory, the
+/// file is then reopened and written again periodically.
+#[derive(Debug)]
+pub struct LogFile {
+    path: PathBuf,
+    /// None if the file couldn't be reopened.
+    file: Option<BufWriter<File>>,
+    /// Length of the file with the records buffered.
+    len: u64,
+    /// Length of the file at the last flush, a failure truncates it back.
+    flushed_len: u64,
+    /// Header written at the start of each new file.
+    header: Vec<u8>,
+    rotation: Option<Rotation>,
+    flush: FlushPolicy,
+    /// Time of the next flush.
+    flush_time: Instant,
+    /// Local date of the records of the file, for its rotation.
+    date: NaiveDate,
+    /// Records not yet flushed, oldest first, the first ones are buffered.
+    pending: VecDeque<Vec<u8>>,
+    /// Number of pending records in the buffer.
+    buffered: usize,
+    /// Time of the next attempt, if failing.
+    retry_time: Option<Instant>,
+    /// Number of records dropped because the pending records were full.
+    dropped: u64,
+}
+
+impl LogFile {
+
+    /// Open the log file in append mode, creating it if needed, with the
+    /// header written at the start of each new file. Failing to open it at
+    /// startup is an error, as the path is likely wrong.
+    pub fn open(path: &Path, header: Vec<u8>) -> io::Result<Self> {
+
+        let mut file = File::options().append(true).create(true).open(path)?;
+        let metadata = file.metadata()?;
+        let mut len = metadata.len();
+        if len == 0 {
+            file.write_all(&header)?;
+            len = header.len() as u64;
+        }
+
+        // The records of an existing file are as old as its last write.
+        let date = match metadata.modified() {
+            Ok(modified) if metadata.len() > 0 => DateTime::<Local>::from(modified).date_naive(),
+            _ => Local::now().date_naive(),
+        };
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: Some(BufWriter::new(file)),
+            len,
+            flushed_len: len,
+            header,
+            rotation: None,
+            flush: FlushPolicy::default(),
+            flush_time: Instant::now(),
+            date,
+            pending: VecDeque::new(),
+            buffered: 0,
+            retry_time: None,
+            dropped: 0,
+        })
+
+    }
+
+    /// Rotate the file, by date or by size.
+    pub fn with_rotation(mut self, rotation: Option<Rotation>) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    /// Flush the records with the given policy.
+    pub fn with_flush(mut self, flush: FlushPolicy) -> Self {
+        self.flush = flush;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append a whole record to the file, flushed when the policy requires
+    /// it. If the file is failing, the record is kept until the next
+    /// attempt.
+    pub fn write_record(&mut self, now: Instant, record: Vec<u8>) {
+
+        // The buffered records are flushed before dropping any record.
+        if self.pending.len() >= PENDING_CAPACITY && self.buffered > 0 {
+            self.write_pending(now);
+        }
+        if self.pending.len() >= PENDING_CAPACITY {
+            self.pending.pop_front();
+            self.dropped += 1;
+        }
+        self.pending.push_back(record);
+
+        if self.retry_time.is_none_or(|retry_time| now >= retry_time) {
+            self.write_buffered(now, now >= self.flush_time);
+        }
+
+    }
+
+    /// Number of records not yet flushed.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Write and flush the pending records now, in order.
+    pub fn write_pending(&mut self, now: Instant) {
+        self.write_buffered(now, true);
+    }
+
+    /// Write the pending records to the buffer, and flush it if requested.
+    /// On failure, the records since the last flush are kept pending.
+    fn write_buffered(&mut self, now: Instant, flush: bool) {
+
+        let failing = self.retry_time.is_some();
+        let count = self.pending.len();
+        // Recovered records are flushed at once.
+        let flush = flush || failing;
+
+        let mut result = Ok(());
+        while result.is_ok() && self.buffered < self.pending.len() {
+            result = self.write();
+        }
+        if flush {
+            result = result.and_then(|()| self.flush());
+            self.flush_time = now + self.flush.interval;
+        }
+
+        if let Err(e) = result {
+            self.discard();
+            if !failing {
+                warn!("\x07alert: failed to write {}: {e}, keeping records in memory", self.path.display());
+            }
+            self.retry_time = Some(now + RETRY_INTERVAL);
+            return;
+        }
+
+        if failing {
+            info!("{} written again, {} records recovered", self.path.display(), count);
+            if self.dropped > 0 {
+                warn!("{} records of {} were lost", self.dropped, self.path.display());
+                self.dropped = 0;
+            }
+            self.retry_time = None;
+        }
+
+    }
+
+    /// Write the next pending record to the buffer.
+    fn write(&mut self) -> io::Result<()> {
+
+        // The file is reopened after a failure, in case it was removed or
+        // its permissions changed.
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => {
+                let file = File::options().append(true).create(true).open(&self.path)?;
+                self.len = file.metadata()?.len();
+                self.flushed_len = self.len;
+                self.file.insert(BufWriter::new(file))
+            }
+        };
+
+        // A file with only its header is never rolled.
+        let record_len = self.pending[self.buffered].len();
+        let today = Local::now().date_naive();
+        if self.len > self.header.len() as u64 && self.rotation.as_ref().is_some_and(|rotation| rotation.due(self.date, today, self.len, record_len)) {
+            self.flush()?;
+            self.file = None;
+            self.rotate()?;
+            return self.write();
+        }
+        self.date = today;
+
+        if self.len == 0 {
+            file.write_all(&self.header)?;
+            self.len = self.header.len() as u64;
+        }
+        file.write_all(&self.pending[self.buffered])?;
+        self.len += record_len as u64;
+        self.buffered += 1;
+
+        Ok(())
+
+    }
+
+    /// Flush the buffered records to the file, syncing them to the disk if
+    /// configured.
+    fn flush(&mut self) -> io::Result<()> {
+
+        if let Some(file) = &mut self.file {
+            file.flush()?;
+            if self.flush.sync {
+                file.get_ref().sync_data()?;
+            }
+        }
+
+        self.flushed_len = self.len;
+        self.pending.drain(..self.buffered);
+        self.buffered = 0;
+
+        Ok(())
+
+    }
+
+    /// Discard the records buffered since the last flush after a failure,
+    /// truncating the file so that it never has half a record. They stay
+    /// pending for the next attempt.
+    fn discard(&mut self) {
+        if let Some(file) = self.file.take() {
+            let (file, _) = file.into_parts();
+            let _ = file.set_len(self.flushed_len);
+        }
+        self.buffered = 0;
+    }
+
+    /// Roll the file to a dated file, compressed if configured.
+    fn rotate(&mut self) -> io::Result<()> {
+
+        let compress = self.rotation.as_ref().is_some_and(|rotation| rotation.compress);
+        let rolled = rolled_path(&self.path, self.date, compress);
+        fs::rename(&self.path, &rolled)?;
+        info!("{} rolled to {}", self.path.display(), rolled.display());
+
+        // The rolled file is kept as is if it can't be compressed.
+        if compress {
+            if let Err(e) = compress_file(&rolled) {
+                warn!("failed to compress {}: {e}", rolled.display());
+            }
+        }
+
+        Ok(())
+
+    }
+
+}
+
+
+/// The path a log is rolled to, like `tpp-2024-05-01.log`, then
+/// `tpp-2024-05-01.1.log` if the file was already rolled that day. The path
+/// is free even once compressed.
+fn rolled_path(path: &Path, date: NaiveDate, compress: bool) -> PathBuf {
+
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path.extension().map(|extension| format!(".{}", extension.to_string_lossy())).unwrap_or_default();
+
+    (0..)
+        .map(|index| match index {
+            0 => path.with_file_name(format!("{stem}-{date}{extension}")),
+            index => path.with_file_name(format!("{stem}-{date}.{index}{extension}")),
+        })
+        .find(|rolled| !(rolled.exists() || compress && gz_path(rolled).exists()))
+        .unwrap()
+
+}
+
+/// The files the given log was rolled to, with records of the given date
+/// or after, oldest first. A file rolled by size only is named after its
+/// last record, so it may also have older records.
+pub fn rolled_files(path: &Path, since: NaiveDate) -> io::Result<Vec<PathBuf>> {
+
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path.extension().map(|extension| format!(".{}", extension.to_string_lossy())).unwrap_or_default();
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let Some(rest) = name.strip_prefix(&format!("{stem}-")) else { continue };
+        let rest = rest.strip_suffix(".gz").unwrap_or(rest);
+        let Some(rest) = rest.strip_suffix(extension.as_str()) else { continue };
+        let (date, index) = match rest.split_once('.') {
+            Some((date, index)) => (date, index.parse::<u32>().ok()),
+            None => (rest, Some(0)),
+        };
+        if let (Ok(date), Some(index)) = (NaiveDate::parse_from_str(date, "%Y-%m-%d"), index) {
+            if date >= since {
+                files.push((date, index, entry.path()));
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files.into_iter().map(|(_, _, path)| path).collect())
+
+}
+
+/// Open a log file for reading, decompressing it if rolled with gzip.
+pub fn open_read(path: &Path) -> io::Result<Box<dyn BufRead>> {
+    let file = File::open(path)?;
+    if path.extension().is_some_and(|extension| extension == "gz") {
+        Ok(Box::new(BufReader::new(GzDecoder::new(file))))
+    } else {
+        Ok(Box::new(BufReader::new(file)))
+    }
+}
+
+fn gz_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".gz");
+    PathBuf::from(name)
+}
+
+/// Compress a file with gzip, replacing it with the `.gz` file.
+fn compress_file(path: &Path) -> io::Result<()> {
+    let gz = gz_path(path);
+    let mut encoder = GzEncoder::new(File::create(&gz)?, Compression::default());
+    io::copy(&mut File::open(path)?, &mut encoder)?;
+    encoder.finish()?.sync_all()?;
+    fs::remove_file(path)
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn disk_full() {
+
+        let path = std::env::temp_dir().join(format!("tpp-bot-log-{}.tsv", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        // Writes to this device always fail with no space left.
+        let mut log = LogFile::open(Path::new("/dev/full"), Vec::new()).unwrap();
+        let now = Instant::now();
+        log.write_record(now, b"a\n".to_vec());
+        log.write_record(now, b"b\n".to_vec());
+        assert_eq!(log.pending(), 2);
+
+        log.path = path.clone();
+        log.write_record(now + Duration::from_secs(1), b"c\n".to_vec());
+        assert_eq!(log.pending(), 3);
+        log.write_record(now + RETRY_INTERVAL, b"d\n".to_vec());
+        assert_eq!(log.pending(), 0);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "a\nb\nc\nd\n");
+
+        std::fs::remove_file(&path).unwrap();
+
+    }
+
+    #[test]
+    fn flush_policy() {
+
+        let path = std::env::temp_dir().join(format!("tpp-bot-flush-{}.tsv", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let flush = FlushPolicy { interval: Duration::from_secs(60), sync: true };
+        let mut log = LogFile::open(&path, b"h\n".to_vec()).unwrap().with_flush(flush);
+        let now = Instant::now();
+        log.write_record(now, b"a\n".to_vec());
+        log.write_record(now + Duration::from_secs(1), b"b\n".to_vec());
+        log.write_record(now + Duration::from_secs(2), b"c\n".to_vec());
+        assert_eq!((log.pending(), fs::read_to_string(&path).unwrap().as_str()), (2, "h\na\n"));
+
+        log.write_record(now + Duration::from_secs(60), b"d\n".to_vec());
+        assert_eq!((log.pending(), fs::read_to_string(&path).unwrap().as_str()), (0, "h\na\nb\nc\nd\n"));
+        log.write_record(now + Duration::from_secs(61), b"e\n".to_vec());
+        log.write_pending(now + Duration::from_secs(62));
+        assert_eq!((log.pending(), fs::read_to_string(&path).unwrap().as_str()), (0, "h\na\nb\nc\nd\ne\n"));
+
+        fs::remove_file(&path).unwrap();
+
+    }
+
+    #[test]
+    fn rotation() {
+
+        let dir = std::env::temp_dir().join(format!("tpp-bot-rotation-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+        let path = dir.join("tpp.log");
+
+        let rotation = Rotation { daily: true, max_size: Some(10), compress: false };
+        let mut log = LogFile::open(&path, b"h\n".to_vec()).unwrap().with_rotation(Some(rotation));
+        let now = Instant::now();
+        let today = Local::now().date_naive();
+        for record in ["a\n", "bbbb\n", "cccc\n"] {
+            log.write_record(now, record.as_bytes().to_vec());
+        }
+        assert_eq!(fs::read_to_string(dir.join(format!("tpp-{today}.log"))).unwrap(), "h\na\nbbbb\n");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "h\ncccc\n");
+
+        // The records of yesterday are rolled with their date.
+        let yesterday = today.pred_opt().unwrap();
+        log.date = yesterday;
+        log.write_record(now, b"d\n".to_vec());
+        assert_eq!(fs::read_to_string(dir.join(format!("tpp-{yesterday}.log"))).unwrap(), "h\ncccc\n");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "h\nd\n");
+
+        // A second roll of the day doesn't overwrite the first.
+        log.write_record(now, b"eeeeeee\n".to_vec());
+        assert_eq!(fs::read_to_string(dir.join(format!("tpp-{today}.1.log"))).unwrap(), "h\nd\n");
+
+        let rolled = dir.join(format!("tpp-{today}.log"));
+        compress_file(&rolled).unwrap();
+        assert!(!rolled.exists());
+        let mut content = String::new();
+        io::Read::read_to_string(&mut flate2::read::GzDecoder::new(File::open(gz_path(&rolled)).unwrap()), &mut content).unwrap();
+        assert_eq!(content, "h\na\nbbbb\n");
+        assert_eq!(rolled_path(&path, today, true), dir.join(format!("tpp-{today}.2.log")));
+
+        fs::remove_dir_all(&dir).unwrap();
+
+    }
+
+}
@@ -0,0 +1,374 @@
+use std::io::{self, BufWriter, Write};
+use std::sync::OnceLock;
+use std::path::Path;
+use std::fs::File;
+
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+
+use crate::config::Settings;
+use crate::source::MessageSource;
+use crate::vocabulary::Command;
+
+
+/// Version of the format of the log records, written in the header of the
+/// log, the logs without a header are older.
+pub const LOG_VERSION: u32 = 4;
+/// First version with the shares of all the buttons together, the older
+/// ones have the shares of the extended buttons after the moves.
+const GROUPED_SHARES_VERSION: u32 = 4;
+
+
+/// A value of a log record.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RecordValue<'a> {
+    Int(i64),
+    Float(f32),
+    Text(&'a str),
+}
+
+impl From<u16> for RecordValue<'_> {
+    fn from(value: u16) -> Self { Self::Int(value as i64) }
+}
+
+impl From<u32> for RecordValue<'_> {
+    fn from(value: u32) -> Self { Self::Int(value as i64) }
+}
+
+impl From<usize> for RecordValue<'_> {
+    fn from(value: usize) -> Self { Self::Int(value as i64) }
+}
+
+impl From<i64> for RecordValue<'_> {
+    fn from(value: i64) -> Self { Self::Int(value) }
+}
+
+impl From<f32> for RecordValue<'_> {
+    fn from(value: f32) -> Self { Self::Float(value) }
+}
+
+impl<'a> From<&'a str> for RecordValue<'a> {
+    fn from(value: &'a str) -> Self { Self::Text(value) }
+}
+
+
+/// The bot session writing the log records, so that the restarts and the
+/// reconnections can be told apart when analyzing the logs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogSession {
+    /// Start timestamp of the session with a random suffix, like
+    /// `1714564800-3fa2`.
+    pub id: String,
+    /// Number of the connection in the session, from 1.
+    pub connection: u32,
+}
+
+impl LogSession {
+
+    pub fn new(start: DateTime<Utc>) -> Self {
+        Self { id: format!("{}-{:04x}", start.timestamp(), rand::random::<u16>()), connection: 0 }
+    }
+
+    /// The session of the records not written by a live bot, like the
+    /// imported ones.
+    pub fn offline() -> Self {
+        Self { id: "-".to_string(), connection: 0 }
+    }
+
+}
+
+
+/// Format of the log records, given by the `record_format` setting.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RecordFormat {
+    /// Tab-separated values, the historical format.
+    #[default]
+    Tsv,
+    /// Comma-separated values, quoted when needed, for the spreadsheets.
+    Csv,
+    /// One JSON object per record, for the log pipelines.
+    Json,
+}
+
+impl RecordFormat {
+
+    pub fn parse(text: &str) -> Option<Self> {
+        match text {
+            "tsv" => Some(Self::Tsv),
+            "csv" => Some(Self::Csv),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+
+    pub fn from_settings(settings: &Settings) -> Result<Self, String> {
+        Ok(settings.parse_with("record_format", |text| Self::parse(text).ok_or("expected tsv, csv or json"))?
+            .unwrap_or_default())
+    }
+
+    /// The header written at the start of a new log: a line with the
+    /// version of the format, then the names of the columns, like
+    /// `pd.read_csv(path, sep="\t", skiprows=1)` expects. The JSON records
+    /// have no header, they name their values.
+    pub fn header(self) -> Option<String> {
+        let separator = match self {
+            Self::Tsv => "\t",
+            Self::Csv => ",",
+            Self::Json => return None,
+        };
+        Some(format!("#tpp-bot log v{LOG_VERSION}\n{}\n", log_columns().join(separator)))
+    }
+
+    /// Open a log file in append mode, writing the header if it is new.
+    pub fn open(self, path: &Path) -> io::Result<BufWriter<File>> {
+        let file = File::options().append(true).create(true).open(path)?;
+        let new = file.metadata()?.len() == 0;
+        let mut out = BufWriter::new(file);
+        if let Some(header) = self.header().filter(|_| new) {
+            out.write_all(header.as_bytes())?;
+        }
+        Ok(out)
+    }
+
+    /// Write a record with the values of the columns of [`log_columns`].
+    pub fn write_record(self, out: &mut impl Write, values: &[RecordValue]) -> io::Result<()> {
+
+        let mut line = String::new();
+
+        match self {
+            Self::Tsv | Self::Csv => {
+                for (index, value) in values.iter().enumerate() {
+                    if index > 0 {
+                        line.push(if self == Self::Tsv { '\t' } else { ',' });
+                    }
+                    match *value {
+                        RecordValue::Int(value) => line.push_str(&value.to_string()),
+                        RecordValue::Float(value) => line.push_str(&value.to_string()),
+                        RecordValue::Text(text) if self == Self::Csv && text.contains([',', '"', '\n', '\r']) => {
+                            line.push('"');
+                            line.push_str(&text.replace('"', "\"\""));
+                            line.push('"');
+                        }
+                        RecordValue::Text(text) => line.push_str(text),
+                    }
+                }
+            }
+            Self::Json => {
+                line.push('{');
+                for (index, (name, value)) in log_columns().iter().zip(values).enumerate() {
+                    if index > 0 {
+                        line.push(',');
+                    }
+                    line.push_str(&Value::from(name.as_str()).to_string());
+                    line.push(':');
+                    match *value {
+                        RecordValue::Int(value) => line.push_str(&value.to_string()),
+                        // JSON has no infinite numbers.
+                        RecordValue::Float(value) if !value.is_finite() => line.push_str("null"),
+                        RecordValue::Float(value) => line.push_str(&value.to_string()),
+                        RecordValue::Text(text) => line.push_str(&Value::from(text).to_string()),
+                    }
+                }
+                line.push('}');
+            }
+        }
+
+        line.push('\n');
+        out.write_all(line.as_bytes())
+
+    }
+
+}
+
+
+/// The names of the columns of the log records, in order: the rates of the
+/// global window and the share of each button of the vocabulary, then the
+/// counts since the last record and the session. The records of older logs
+/// are read in this order by a [`RecordReader`].
+pub fn log_columns() -> &'static [String] {
+
+    static COLUMNS: OnceLock<Vec<String>> = OnceLock::new();
+
+    COLUMNS.get_or_init(|| {
+
+        let mut columns = vec!["time".to_string(), "msg_sec".to_string(), "cmd_sec".to_string()];
+        columns.extend(Command::ALL.iter().map(|command| format!("share_{}", command.name())));
+        columns.extend(["new_voters", "returning_voters", "run", "bits", "redemptions"].map(String::from));
+        for source in MessageSource::ALL {
+            let name = source.name().replace('-', "_");
+            columns.push(format!("{name}_messages"));
+            columns.push(format!("{name}_commands"));
+        }
+        columns.extend([
+            "funnel_received", "funnel_candidates", "funnel_accepted", "funnel_counted", "funnel_weighted",
+            "hype_messages", "hype_exclamations", "hype_caps", "hype_emotes",
+            "subs", "resubs", "gifts", "raids", "raiders", "announcements", "raid_messages",
+            "moves", "matched_moves",
+            "session_id", "connection",
+        ].map(String::from));
+
+        columns
+
+    })
+
+}
+
+/// Return true if the line is part of the header of a log.
+pub fn is_log_header(line: &str) -> bool {
+    line.starts_with('#') || line.starts_with("time\t") || line.starts_with("time,")
+}
+
+/// Read the values of a log record in any format, in the order of
+/// [`log_columns`], none if the line is a header or isn't a record. The
+/// records of older logs have fewer columns, and the shares of their
+/// extended buttons are only moved in place by a [`RecordReader`].
+pub fn parse_record(line: &str) -> Option<Vec<String>> {
+
+    if is_log_header(line) {
+        return None;
+    }
+
+    if line.starts_with('{') {
+        let Ok(Value::Object(object)) = serde_json::from_str::<Value>(line) else { return None };
+        return Some(log_columns().iter()
+            .map_while(|name| match object.get(name)? {
+                Value::String(text) => Some(text.clone()),
+                Value::Null => Some("NaN".to_string()),
+                value => Some(value.to_string()),
+            })
+            .collect());
+    }
+
+    if line.contains('\t') {
+        return Some(line.split('\t').map(str::to_string).collect());
+    }
+
+    // Comma-separated values, with the quotes of the text values.
+    let mut values = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                values.last_mut().unwrap().push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => values.push(String::new()),
+            c => values.last_mut().unwrap().push(c),
+        }
+    }
+
+    Some(values)
+
+}
+
+/// Reader of the records of a whole log, following the version of its
+/// headers to read the records of older versions like the current ones.
+#[derive(Debug, Default)]
+pub struct RecordReader {
+    /// Version of the last header, none before the first one.
+    version: Option<u32>,
+}
+
+impl RecordReader {
+
+    /// Read the values of the next line of the log, like [`parse_record`].
+    /// The shares of the extended buttons of older records are moved with
+    /// the other shares, or left empty if these records have none.
+    pub fn parse(&mut self, line: &str) -> Option<Vec<String>> {
+
+        if let Some(version) = line.strip_prefix("#tpp-bot log v").and_then(|version| version.trim().parse().ok()) {
+            self.version = Some(version);
+        }
+
+        let mut values = parse_record(line)?;
+
+        // The JSON records name their values, and the logs without a header
+        // are older than all versions.
+        if !line.starts_with('{') && self.version.is_none_or(|version| version < GROUPED_SHARES_VERSION) && values.len() >= 3 + Command::BASE_COUNT {
+            let count = Command::ALL.len() - Command::BASE_COUNT;
+            let start = 3 + Command::BASE_COUNT + 5 + 2 * MessageSource::ALL.len() + 18;
+            let extended = if values.len() >= start + count {
+                values.drain(start..start + count).collect()
+            } else {
+                vec![String::new(); count]
+            };
+            values.splice(3 + Command::BASE_COUNT..3 + Command::BASE_COUNT, extended);
+        }
+
+        Some(values)
+
+    }
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn formats() {
+
+        let values = [RecordValue::Int(1700000000), RecordValue::Float(0.5), RecordValue::Text("run, \"one\"")];
+        let write = |format: RecordFormat| {
+            let mut out = Vec::new();
+            format.write_record(&mut out, &values).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        assert_eq!(write(RecordFormat::Tsv), "1700000000\t0.5\trun, \"one\"\n");
+        assert_eq!(write(RecordFormat::Csv), "1700000000,0.5,\"run, \"\"one\"\"\"\n");
+        assert_eq!(write(RecordFormat::Json), "{\"time\":1700000000,\"msg_sec\":0.5,\"cmd_sec\":\"run, \\\"one\\\"\"}\n");
+
+        let expected = ["1700000000", "0.5", "run, \"one\""];
+        for format in [RecordFormat::Tsv, RecordFormat::Csv, RecordFormat::Json] {
+            assert_eq!(parse_record(write(format).trim_end()).unwrap(), expected, "{format:?}");
+            let header = format.header().unwrap_or_default();
+            assert!(header.lines().all(|line| parse_record(line).is_none()), "{format:?}");
+        }
+
+        let header = RecordFormat::Tsv.header().unwrap();
+        let mut lines = header.lines();
+        assert_eq!(lines.next(), Some("#tpp-bot log v4"));
+        let columns = lines.next().unwrap().split('\t').collect::<Vec<_>>();
+        assert_eq!(&columns[..4], ["time", "msg_sec", "cmd_sec", "share_up"]);
+        assert_eq!(&columns[3 + Command::BASE_COUNT..3 + Command::ALL.len()], ["share_l", "share_r", "share_select", "share_touch"]);
+        assert_eq!(columns[3 + Command::ALL.len() + 2], "run");
+
+    }
+
+
+    #[test]
+    fn older_records() {
+
+        let count = Command::ALL.len() - Command::BASE_COUNT;
+        let old_start = 3 + Command::BASE_COUNT + 5 + 2 * MessageSource::ALL.len() + 18;
+        let shares_end = 3 + Command::ALL.len();
+
+        // The extended shares of a v3 record are moved with the others.
+        let mut old = (0..old_start).map(|index| index.to_string()).collect::<Vec<_>>();
+        old.extend(["l", "r", "select", "touch", "session", "1"].map(String::from));
+        let mut reader = RecordReader::default();
+        assert!(reader.parse("#tpp-bot log v3").is_none());
+        let values = reader.parse(&old.join("\t")).unwrap();
+        assert_eq!(values.len(), log_columns().len());
+        assert_eq!(&values[3 + Command::BASE_COUNT..shares_end], ["l", "r", "select", "touch"]);
+        assert_eq!(values[shares_end], (3 + Command::BASE_COUNT).to_string());
+        assert_eq!(&values[values.len() - 2..], ["session", "1"]);
+
+        // A record without a header and extended shares has them empty.
+        let values = RecordReader::default().parse(&old[..17].join("\t")).unwrap();
+        assert_eq!(values[3 + Command::BASE_COUNT..shares_end], vec![String::new(); count]);
+        assert_eq!(values[shares_end + 2], "16");
+
+        // The current records are read as written.
+        let mut reader = RecordReader::default();
+        assert!(reader.parse(&format!("#tpp-bot log v{LOG_VERSION}")).is_none());
+        assert_eq!(reader.parse(&old.join("\t")).unwrap(), old);
+
+    }
+
+}
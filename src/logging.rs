@@ -0,0 +1,131 @@
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::EnvFilter;
+
+use crate::config::Settings;
+
+
+/// Set when the interactive prompt is printed, it is disabled when the
+/// diagnostics are read by a machine.
+static PROMPT: AtomicBool = AtomicBool::new(true);
+
+
+/// Format of the diagnostics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    /// Human readable lines, between the prompt lines.
+    #[default]
+    Text,
+    /// One JSON object per line, without prompt, for service managers.
+    Json,
+}
+
+impl LogFormat {
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "text" => Some(Self::Text),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+
+}
+
+
+/// Configuration of the diagnostics.
+#[derive(Debug, Clone)]
+pub struct LogConfig {
+    /// Level filter, like `info` or `tpp_bot=debug`.
+    pub filter: String,
+    pub format: LogFormat,
+}
+
+impl LogConfig {
+
+    /// Read the configuration from the `log_level` and `log_format` settings.
+    pub fn from_settings(settings: &Settings) -> Result<Self, String> {
+
+        let filter = settings.get("log_level").unwrap_or("info");
+        EnvFilter::try_new(filter).map_err(|e| format!("invalid {}: {e}", settings.origin("log_level")))?;
+
+        Ok(Self {
+            filter: filter.to_string(),
+            format: settings.parse_with("log_format", |s| LogFormat::parse(s).ok_or("expected text or json"))?.unwrap_or_default(),
+        })
+
+    }
+
+}
+
+
+/// Install the subscriber printing the diagnostics to the standard output.
+pub fn init(config: &LogConfig) {
+
+    let filter = EnvFilter::new(&config.filter);
+
+    match config.format {
+        LogFormat::Text => {
+            tracing_subscriber::fmt()
+                .with_env_filter(filter)
+                .with_target(false)
+                .with_ansi(io::stdout().is_terminal())
+                .with_writer(PromptWriter)
+                .init();
+        }
+        LogFormat::Json => {
+            PROMPT.store(false, Ordering::Relaxed);
+            tracing_subscriber::fmt()
+                .json()
+                .with_env_filter(filter)
+                .with_current_span(true)
+                .with_writer(io::stdout)
+                .init();
+        }
+    }
+
+}
+
+/// Return true if the interactive prompt is printed.
+pub fn prompt_enabled() -> bool {
+    PROMPT.load(Ordering::Relaxed)
+}
+
+
+/// Writer of the diagnostics over the prompt line, which is printed again
+/// on the next iteration.
+struct PromptWriter;
+
+impl<'a> MakeWriter<'a> for PromptWriter {
+
+    type Writer = PromptLine;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        PromptLine { started: false }
+    }
+
+}
+
+/// A single diagnostic, starting by erasing the prompt line.
+struct PromptLine {
+    started: bool,
+}
+
+impl Write for PromptLine {
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut stdout = io::stdout().lock();
+        if !self.started {
+            self.started = true;
+            stdout.write_all(if stdout.is_terminal() { b"\r\x1b[2K" } else { b"\r" })?;
+        }
+        stdout.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+
+}
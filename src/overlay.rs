@@ -0,0 +1,285 @@
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::time::{Duration, Instant};
+use std::path::PathBuf;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::thread;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::json;
+use tracing::{debug, info};
+use tungstenite::{Message, WebSocket};
+
+use crate::config::{parse_seconds, Settings};
+use crate::status::ChannelStatus;
+
+
+/// Time given to a client to receive a message, a slower client is dropped
+/// rather than delaying the others.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+/// Number of commands shown in the vote breakdown of the overlay file.
+const FILE_VOTES: usize = 5;
+/// Width of the bar of a command with all the votes.
+const BAR_WIDTH: f32 = 20.0;
+
+
+/// Configuration of the WebSocket server of the overlays.
+#[derive(Debug, Clone)]
+pub struct OverlayConfig {
+    pub addr: SocketAddr,
+    /// Interval between two pushes of the live stats.
+    pub interval: Duration,
+}
+
+impl OverlayConfig {
+
+    /// Read the configuration from the `overlay_*` settings, none if no
+    /// address is configured.
+    pub fn from_settings(settings: &Settings) -> Result<Option<Self>, String> {
+
+        let Some(addr) = settings.parse::<SocketAddr>("overlay_addr")? else { return Ok(None) };
+
+        Ok(Some(Self {
+            addr,
+            interval: settings.parse_with("overlay_interval", parse_seconds)?.unwrap_or(Duration::from_millis(200)),
+        }))
+
+    }
+
+}
+
+
+/// A WebSocket server pushing the live stats of the channels to the
+/// overlays, like an OBS browser source showing the vote percentages. Each
+/// push is a JSON text message with the votes and the pending command of
+/// each channel, clients never have to send anything.
+#[derive(Debug)]
+pub struct OverlayServer {
+    config: OverlayConfig,
+    tx: Sender<String>,
+    /// Time of the next push.
+    next_time: Option<Instant>,
+}
+
+impl OverlayServer {
+
+    pub fn spawn(config: OverlayConfig) -> Result<Self, String> {
+
+        let listener = TcpListener::bind(config.addr).map_err(|e| format!("failed to listen on {}: {e}", config.addr))?;
+        let clients = Arc::new(Mutex::new(Vec::<WebSocket<TcpStream>>::new()));
+        let accept_clients = Arc::clone(&clients);
+
+        thread::Builder::new()
+            .name("overlay-accept".to_string())
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    let Ok(stream) = stream else { continue };
+                    let peer = stream.peer_addr().ok();
+                    // The handshake must not block the accepting forever.
+                    if stream.set_read_timeout(Some(WRITE_TIMEOUT)).and_then(|()| stream.set_write_timeout(Some(WRITE_TIMEOUT))).is_err() {
+                        continue;
+                    }
+                    match tungstenite::accept(stream) {
+                        Ok(socket) => {
+                            info!("overlay connected from {peer:?}");
+                            accept_clients.lock().unwrap().push(socket);
+                        }
+                        Err(e) => debug!("overlay handshake from {peer:?} failed: {e}"),
+                    }
+                }
+            })
+            .unwrap();
+
+        let (tx, rx) = mpsc::channel::<String>();
+
+        thread::Builder::new()
+            .name("overlay-push".to_string())
+            .spawn(move || {
+                for text in rx {
+                    clients.lock().unwrap().retain_mut(|socket| {
+                        socket.send(Message::text(text.clone()))
+                            .map_err(|e| debug!("overlay disconnected: {e}"))
+                            .is_ok()
+                    });
+                }
+            })
+            .unwrap();
+
+        Ok(Self { config, tx, next_time: None })
+
+    }
+
+    /// Push the live stats of the channels if the interval has passed.
+    pub fn push(&mut self, now: Instant, time: DateTime<Utc>, statuses: &[ChannelStatus]) {
+
+        if self.next_time.is_some_and(|next_time| now < next_time) {
+            return;
+        }
+        self.next_time = Some(now + self.config.interval);
+
+        let message = json!({
+            "time": time.to_rfc3339_opts(SecondsFormat::Millis, true),
+            "channels": statuses.iter().map(|status| {
+                let mut value = status.to_json(time);
+                value["votes"] = status.votes_json();
+                value["shares"] = status.shares_json();
+                value
+            }).collect::<Vec<_>>(),
+        });
+
+        // The push thread only stops with the process.
+        let _ = self.tx.send(message.to_string());
+
+    }
+
+}
+
+
+/// Configuration of the overlay file.
+#[derive(Debug, Clone)]
+pub struct OverlayFileConfig {
+    pub path: PathBuf,
+    /// Minimum interval between two writes of the file.
+    pub interval: Duration,
+    /// Write an HTML page instead of plain text, for a browser source.
+    pub html: bool,
+}
+
+impl OverlayFileConfig {
+
+    /// Read the configuration from the `overlay_file_*` settings, none if
+    /// no path is configured. The file is HTML if its extension is.
+    pub fn from_settings(settings: &Settings) -> Result<Option<Self>, String> {
+
+        let Some(path) = settings.get("overlay_file_path") else { return Ok(None) };
+        let path = PathBuf::from(path);
+
+        Ok(Some(Self {
+            html: path.extension().is_some_and(|extension| extension == "html" || extension == "htm"),
+            interval: settings.parse_with("overlay_file_interval", parse_seconds)?.unwrap_or(Duration::from_millis(500)),
+            path,
+        }))
+
+    }
+
+}
+
+
+/// A small file continuously rewritten with the pending command of each
+/// channel, its countdown and the vote breakdown, for an OBS text source.
+/// The file is replaced atomically, so that OBS never reads a partial write.
+#[derive(Debug)]
+pub struct OverlayFile {
+    config: OverlayFileConfig,
+    /// Time of the next write.
+    next_time: Option<Instant>,
+}
+
+impl OverlayFile {
+
+    pub fn new(config: OverlayFileConfig) -> Self {
+        Self { config, next_time: None }
+    }
+
+    /// Write the file if the interval since the last write has passed.
+    pub fn write(&mut self, now: Instant, statuses: &[ChannelStatus]) -> io::Result<()> {
+
+        if self.next_time.is_some_and(|next_time| now < next_time) {
+            return Ok(());
+        }
+        self.next_time = Some(now + self.config.interval);
+
+        let text = render_text(statuses);
+        let content = if self.config.html {
+            // The page reloads itself, as a browser source doesn't.
+            format!("<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><meta http-equiv=\"refresh\" content=\"{}\"></head>\n<body><pre>{}</pre></body></html>\n",
+                self.config.interval.as_secs().max(1), escape_html(&text))
+        } else {
+            text
+        };
+
+        let mut tmp_path = self.config.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(content.as_bytes())?;
+        fs::rename(tmp_path, &self.config.path)
+
+    }
+
+}
+
+
+/// Render the pending command of each channel and its vote breakdown as
+/// plain text lines, the channels are only named when several are joined.
+fn render_text(statuses: &[ChannelStatus]) -> String {
+
+    let mut text = String::new();
+
+    for status in statuses {
+
+        if statuses.len() > 1 {
+            text.push_str(&format!("#{} ", status.channel));
+        }
+
+        let action = if status.democracy { "vote" } else { "next" };
+        if status.paused.is_some() {
+            text.push_str(&format!("{action} {}, paused\n", status.command));
+        } else if !status.bot {
+            text.push_str(&format!("leading {}, bot off\n", status.command));
+        } else {
+            text.push_str(&format!("{action} {} in {:.1}s\n", status.command, status.next.as_secs_f32()));
+        }
+
+        let total = status.votes.iter().map(|&(_, count)| count).sum::<u32>();
+        let mut votes = status.votes.iter().filter(|&&(_, count)| count > 0).collect::<Vec<_>>();
+        votes.sort_by(|(_, a), (_, b)| b.cmp(a));
+
+        for (command, count) in votes.into_iter().take(FILE_VOTES) {
+            let share = *count as f32 / total as f32;
+            text.push_str(&format!("{command:10} {:>3}% {}\n", (share * 100.0).round(), "#".repeat((share * BAR_WIDTH).round() as usize)));
+        }
+
+    }
+
+    text
+
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn text_file() {
+
+        let status = ChannelStatus {
+            channel: "chan".to_string(),
+            democracy: false,
+            bot: true,
+            command: "a".to_string(),
+            next: Duration::from_millis(2340),
+            paused: None,
+            cmd_sec: 8.0,
+            ratio: 0.8,
+            margin: 0,
+            sent: 7,
+            votes: vec![("up".to_string(), 1), ("a".to_string(), 3), ("b".to_string(), 0)],
+        };
+
+        assert_eq!(render_text(std::slice::from_ref(&status)), "next a in 2.3s\na           75% ###############\nup          25% #####\n");
+
+        let paused = ChannelStatus { paused: Some(Duration::from_secs(60)), votes: Vec::new(), ..status };
+        assert_eq!(render_text(&[paused]), "next a, paused\n");
+
+    }
+
+}
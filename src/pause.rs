@@ -0,0 +1,199 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::fs;
+use std::io;
+
+use chrono::{DateTime, Duration, Local, NaiveTime, Utc};
+use tracing::{info, warn};
+
+
+/// The end of a pause, as given by the operator.
+#[derive(Debug, Clone, Copy)]
+pub enum PauseEnd {
+    /// Pause for the given duration.
+    For(Duration),
+    /// Pause until the next occurrence of the given local time.
+    Until(NaiveTime),
+}
+
+impl PauseEnd {
+
+    /// Parse a pause end, either a duration like `10m`, `1h30m` or `45s`,
+    /// or `until HH:MM` in local time.
+    pub fn parse(text: &str) -> Result<Self, String> {
+
+        if let Some(time) = text.strip_prefix("until ") {
+            return NaiveTime::parse_from_str(time.trim(), "%H:%M")
+                .map(Self::Until)
+                .map_err(|e| format!("invalid time '{}': {e}", time.trim()));
+        }
+
+        parse_duration(text).map(Self::For)
+
+    }
+
+    /// Resolve the end time of the pause if started now.
+    pub fn resolve(self, now: DateTime<Utc>) -> DateTime<Utc> {
+        match self {
+            Self::For(duration) => now + duration,
+            Self::Until(time) => {
+                let local_now = now.with_timezone(&Local);
+                let mut end = local_now.date_naive().and_time(time);
+                if end <= local_now.naive_local() {
+                    end += Duration::days(1);
+                }
+                end.and_local_timezone(Local).earliest()
+                    .map(|end| end.with_timezone(&Utc))
+                    // The time doesn't exist today because of a DST change.
+                    .unwrap_or(now + Duration::hours(1))
+            }
+        }
+    }
+
+}
+
+
+/// Parse a duration like `10m`, `1h30m` or `45s`.
+pub fn parse_duration(text: &str) -> Result<Duration, String> {
+
+    let mut total = Duration::zero();
+    let mut number = String::new();
+
+    for c in text.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let value = number.parse::<i64>().map_err(|_| format!("invalid duration '{text}'"))?;
+        number.clear();
+        total += match c {
+            'h' => Duration::hours(value),
+            'm' => Duration::minutes(value),
+            's' => Duration::seconds(value),
+            _ => return Err(format!("invalid duration unit '{c}' in '{text}'")),
+        };
+    }
+
+    if !number.is_empty() || total <= Duration::zero() {
+        return Err(format!("invalid duration '{text}', expected e.g. '10m' or '1h30m'"));
+    }
+
+    Ok(total)
+
+}
+
+
+/// Pause windows set by the operator, either global or per channel, during
+/// which the bot doesn't send any message even when the bot mode is on.
+/// Pauses are persisted to a file so that they survive a restart.
+#[derive(Debug)]
+pub struct Pauses {
+    path: Option<PathBuf>,
+    global: Option<DateTime<Utc>>,
+    channels: HashMap<String, DateTime<Utc>>,
+    /// True while the file fails, so that the error is only reported once.
+    failing: bool,
+}
+
+impl Pauses {
+
+    /// Open the pauses from the given file, if existing. Each line of the
+    /// file is a channel, or `*` for global, and the end timestamp.
+    pub fn open(path: Option<PathBuf>) -> io::Result<Self> {
+
+        let mut pauses = Self {
+            path,
+            global: None,
+            channels: HashMap::new(),
+            failing: false,
+        };
+
+        if let Some(path) = &pauses.path {
+            match fs::read_to_string(path) {
+                Ok(content) => {
+                    for line in content.lines() {
+                        let Some((scope, timestamp)) = line.split_once('\t') else { continue };
+                        let Some(end) = timestamp.parse::<i64>().ok()
+                            .and_then(|timestamp| DateTime::from_timestamp(timestamp, 0)) else { continue };
+                        match scope {
+                            "*" => pauses.global = Some(end),
+                            channel => { pauses.channels.insert(channel.to_string(), end); }
+                        }
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(pauses)
+
+    }
+
+    /// Pause the given channel, or all channels if none, until the given
+    /// time. This replaces any previous pause of the same scope.
+    pub fn pause(&mut self, channel: Option<&str>, end: DateTime<Utc>) {
+        match channel {
+            Some(channel) => { self.channels.insert(channel.to_string(), end); }
+            None => self.global = Some(end),
+        }
+        self.save();
+    }
+
+    /// Resume the given channel, or remove all pauses if none. Returns
+    /// true if a pause was removed.
+    pub fn resume(&mut self, channel: Option<&str>) -> bool {
+        let removed = match channel {
+            Some(channel) => self.channels.remove(channel).is_some(),
+            None => {
+                let removed = self.global.is_some() || !self.channels.is_empty();
+                self.global = None;
+                self.channels.clear();
+                removed
+            }
+        };
+        self.save();
+        removed
+    }
+
+    /// The remaining pause time of the given channel, considering the global
+    /// pause, or None if the channel is not paused.
+    pub fn remaining(&self, channel: &str, now: DateTime<Utc>) -> Option<Duration> {
+        let end = self.global.into_iter()
+            .chain(self.channels.get(channel).copied())
+            .max()?;
+        (end > now).then(|| end - now)
+    }
+
+    /// Save the pauses, which stay in memory if the file fails and are
+    /// saved again with the next change.
+    fn save(&mut self) {
+
+        let Some(path) = &self.path else { return };
+
+        let mut content = String::new();
+        if let Some(end) = self.global {
+            content.push_str(&format!("*\t{}\n", end.timestamp()));
+        }
+        for (channel, end) in &self.channels {
+            content.push_str(&format!("{channel}\t{}\n", end.timestamp()));
+        }
+
+        match fs::write(path, content) {
+            Ok(()) => {
+                if self.failing {
+                    info!("{} written again", path.display());
+                    self.failing = false;
+                }
+            }
+            Err(e) => {
+                if !self.failing {
+                    warn!("\x07alert: failed to write {}: {e}, keeping the pauses in memory", path.display());
+                    self.failing = true;
+                }
+            }
+        }
+
+    }
+
+}
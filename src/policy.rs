@@ -0,0 +1,166 @@
+use std::ops::Index;
+use std::time::Duration;
+
+use crate::config::{parse_seconds, Settings};
+use crate::vocabulary::Command;
+
+
+/// Weights of the votes for each command when choosing the most used one,
+/// favoring democracy over anarchy by default.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CommandWeights([f32; Command::ALL.len()]);
+
+impl Default for CommandWeights {
+    fn default() -> Self {
+        let mut weights = Self([1.0; Command::ALL.len()]);
+        weights.0[Command::Democracy.index()] = 2.0;
+        weights.0[Command::Anarchy.index()] = 0.25;
+        weights
+    }
+}
+
+impl Index<Command> for CommandWeights {
+    type Output = f32;
+    fn index(&self, command: Command) -> &f32 {
+        &self.0[command.index()]
+    }
+}
+
+impl CommandWeights {
+
+    /// Parse weights like `democracy=2, anarchy=0.25`, replacing the default
+    /// weights of the given commands only.
+    pub fn parse(text: &str) -> Result<Self, String> {
+
+        let mut weights = Self::default();
+
+        for item in text.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+            let (name, weight) = item.split_once('=')
+                .ok_or_else(|| format!("expected 'command=weight', got '{item}'"))?;
+            let command = parse_command(name.trim())?;
+            let weight = weight.trim().parse::<f32>().ok()
+                .filter(|weight| weight.is_finite() && *weight >= 0.0)
+                .ok_or_else(|| format!("invalid weight of '{}', expected a positive number", name.trim()))?;
+            weights.0[command.index()] = weight;
+        }
+
+        Ok(weights)
+
+    }
+
+}
+
+
+/// How the ties of the most used command are broken.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TieBreak {
+    /// The last of the ties in the order of [`Command::ALL`].
+    #[default]
+    Order,
+    /// A random order, drawn again for each sample.
+    Random,
+    /// The command voted the most recently.
+    Recent,
+}
+
+impl TieBreak {
+    pub fn parse(text: &str) -> Option<Self> {
+        match text {
+            "order" => Some(Self::Order),
+            "random" => Some(Self::Random),
+            "recent" => Some(Self::Recent),
+            _ => None,
+        }
+    }
+}
+
+
+/// Commands the bot may send, and how the most used one is chosen.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandPolicy {
+    /// Commands never sent, like `start` during battles, the bot waits for
+    /// the chat to agree on another command.
+    blocked: Vec<Command>,
+    pub weights: CommandWeights,
+    /// Minimum number of votes in the TPP window of a command sent by the
+    /// bot.
+    pub min_votes: u16,
+    pub tie_break: TieBreak,
+    /// Half-life of the votes when choosing the most used command, so that
+    /// the recent votes count more, the votes of the TPP window count
+    /// equally if none.
+    pub half_life: Option<Duration>,
+}
+
+impl Default for CommandPolicy {
+    fn default() -> Self {
+        Self {
+            blocked: Vec::new(),
+            weights: CommandWeights::default(),
+            min_votes: 1,
+            tie_break: TieBreak::default(),
+            half_life: None,
+        }
+    }
+}
+
+impl CommandPolicy {
+
+    /// Read the policy from the `blocked_commands` setting, a
+    /// comma-separated list of command names, and the `command_weights`,
+    /// `min_votes`, `tie_break` and `vote_half_life` ones.
+    pub fn from_settings(settings: &Settings) -> Result<Self, String> {
+
+        let blocked = settings.parse_with("blocked_commands", |text| text.split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(parse_command)
+            .collect::<Result<Vec<_>, _>>())?;
+
+        Ok(Self {
+            blocked: blocked.unwrap_or_default(),
+            weights: settings.parse_with("command_weights", CommandWeights::parse)?.unwrap_or_default(),
+            min_votes: settings.parse::<u16>("min_votes")?.unwrap_or(1).max(1),
+            tie_break: settings.parse_with("tie_break", |text| TieBreak::parse(text).ok_or("expected order, random or recent"))?
+                .unwrap_or_default(),
+            half_life: settings.parse_with("vote_half_life", |text| match parse_seconds(text)? {
+                half_life if half_life.is_zero() => Err("expected a positive duration".to_string()),
+                half_life => Ok(half_life),
+            })?,
+        })
+
+    }
+
+    pub fn is_blocked(&self, command: Command) -> bool {
+        self.blocked.contains(&command)
+    }
+
+}
+
+
+fn parse_command(name: &str) -> Result<Command, String> {
+    Command::from_name(name).ok_or_else(|| format!("unknown command '{name}'"))
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn weights() {
+
+        let weights = CommandWeights::default();
+        assert_eq!((weights[Command::Democracy], weights[Command::Anarchy], weights[Command::A]), (2.0, 0.25, 1.0));
+
+        let weights = CommandWeights::parse("anarchy=1, start=0.5").unwrap();
+        assert_eq!((weights[Command::Democracy], weights[Command::Anarchy], weights[Command::Start]), (2.0, 1.0, 0.5));
+
+        assert!(CommandWeights::parse("anarchy").is_err());
+        assert!(CommandWeights::parse("anarchie=1").is_err());
+        assert!(CommandWeights::parse("start=-1").is_err());
+
+    }
+
+}
@@ -0,0 +1,128 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+
+/// Window of the Twitch rate limits.
+pub const RATE_WINDOW: Duration = Duration::from_secs(30);
+/// Minimum spacing between two messages of a regular user in a channel.
+pub const CHANNEL_SPACING: Duration = Duration::from_secs(1);
+/// Margin added to the interval derived from a limit. If the limit is
+/// exceeded, the bot is ignored for 30 minutes by Twitch.
+const INTERVAL_MARGIN: Duration = Duration::from_millis(300);
+
+
+/// Tier of the Twitch rate limit that applies to a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateTier {
+    /// A regular user.
+    User,
+    /// The bot is moderator or broadcaster of the channel.
+    Moderator,
+    /// The account is a verified bot.
+    Verified,
+}
+
+impl RateTier {
+
+    /// Messages allowed in the rate window.
+    pub fn limit(self) -> usize {
+        match self {
+            Self::User => 20,
+            Self::Moderator => 100,
+            Self::Verified => 7500,
+        }
+    }
+
+    /// Minimum interval between two messages to stay under the limit when
+    /// sending continuously.
+    pub fn min_interval(self) -> Duration {
+        RATE_WINDOW / self.limit() as u32 + INTERVAL_MARGIN
+    }
+
+}
+
+
+/// The rate limits of the account, tracking the messages sent over the
+/// rate window across all channels, which is shared by all send paths.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    verified: bool,
+    /// Times of the messages sent in the rate window.
+    sent: VecDeque<Instant>,
+    /// Time of the last message sent in each channel.
+    last_sent: HashMap<String, Instant>,
+}
+
+impl RateLimiter {
+
+    /// Create the limiter of a regular account, or of a verified bot.
+    pub fn new(verified: bool) -> Self {
+        Self { verified, ..Self::default() }
+    }
+
+    /// The tier that applies to messages of a channel.
+    pub fn tier(&self, moderator: bool) -> RateTier {
+        if self.verified {
+            RateTier::Verified
+        } else if moderator {
+            RateTier::Moderator
+        } else {
+            RateTier::User
+        }
+    }
+
+    /// Number of messages that can still be sent in the rate window of the
+    /// given tier.
+    pub fn remaining(&mut self, now: Instant, tier: RateTier) -> usize {
+        while self.sent.front().is_some_and(|&time| now.saturating_duration_since(time) >= RATE_WINDOW) {
+            self.sent.pop_front();
+        }
+        tier.limit().saturating_sub(self.sent.len())
+    }
+
+    /// Return true if a message can be sent in the channel now, moderators
+    /// are not subject to the channel spacing.
+    pub fn allows(&mut self, now: Instant, channel: &str, moderator: bool) -> bool {
+        let spaced = moderator || self.last_sent.get(channel)
+            .is_none_or(|&time| now.saturating_duration_since(time) >= CHANNEL_SPACING);
+        spaced && self.remaining(now, self.tier(moderator)) > 0
+    }
+
+    /// Record a message sent in the channel at the given time.
+    pub fn record(&mut self, now: Instant, channel: &str) {
+        self.sent.push_back(now);
+        self.last_sent.insert(channel.to_string(), now);
+    }
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn limits() {
+
+        let mut limiter = RateLimiter::new(false);
+        let start = Instant::now();
+        for _ in 0..18 {
+            limiter.record(start, "a");
+        }
+        limiter.record(start + Duration::from_secs(10), "b");
+
+        let now = start + Duration::from_secs(10);
+        assert_eq!(limiter.remaining(now, RateTier::User), 1);
+        assert_eq!(limiter.remaining(now, RateTier::Moderator), 81);
+        // Regular users must wait between two messages of a channel.
+        assert!(limiter.allows(now, "a", false));
+        assert!(!limiter.allows(now, "b", false));
+        assert!(limiter.allows(now, "b", true));
+
+        assert_eq!(limiter.remaining(start + RATE_WINDOW, RateTier::User), 19);
+        assert_eq!(RateTier::User.min_interval(), Duration::from_millis(1800));
+
+    }
+
+}
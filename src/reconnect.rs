@@ -0,0 +1,153 @@
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use rand::Rng;
+
+use crate::config::{parse_seconds, Settings};
+
+
+/// A connection that lasted this long is considered stable, and resets the
+/// backoff.
+const STABLE_DURATION: Duration = Duration::from_secs(60);
+
+
+/// Policy of the reconnections after the connection is lost.
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy {
+    /// Delay before the first reconnection.
+    pub initial: Duration,
+    /// Maximum delay between two reconnections.
+    pub max: Duration,
+    /// Ratio of the delay randomly added or removed, so that many clients
+    /// don't reconnect all at once after an outage.
+    pub jitter: f32,
+    /// Maximum number of consecutive attempts, none to retry forever.
+    pub max_retries: Option<u32>,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(300),
+            jitter: 0.2,
+            max_retries: None,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+
+    /// Read the policy from the `reconnect_*` settings, delays are in seconds.
+    pub fn from_settings(settings: &Settings) -> Result<Self, String> {
+
+        let default = Self::default();
+        let seconds = |key: &str, default: Duration| settings.parse_with(key, parse_seconds)
+            .map(|delay| delay.unwrap_or(default));
+
+        let policy = Self {
+            initial: seconds("reconnect_initial", default.initial)?,
+            max: seconds("reconnect_max", default.max)?,
+            jitter: settings.parse("reconnect_jitter")?.unwrap_or(default.jitter),
+            max_retries: settings.parse("reconnect_max_retries")?,
+        };
+
+        if !(0.0..=1.0).contains(&policy.jitter) {
+            return Err(format!("invalid {}: expected a ratio between 0 and 1", settings.origin("reconnect_jitter")));
+        }
+
+        Ok(policy)
+
+    }
+
+    /// The delay before the given attempt, starting at 0, without jitter.
+    fn base_delay(&self, attempt: u32) -> Duration {
+        self.initial.saturating_mul(1 << attempt.min(16)).min(self.max)
+    }
+
+}
+
+
+/// State of the reconnections, with an exponential backoff on consecutive
+/// failed connections.
+#[derive(Debug)]
+pub struct Reconnect {
+    policy: ReconnectPolicy,
+    /// Number of consecutive attempts since the last stable connection.
+    attempts: u32,
+    /// Total number of reconnections.
+    total: u32,
+    /// Generator of the jitter.
+    rng: StdRng,
+}
+
+impl Reconnect {
+
+    pub fn new(policy: ReconnectPolicy, rng: StdRng) -> Self {
+        Self { policy, attempts: 0, total: 0, rng }
+    }
+
+    /// Record that the connection ended after the given duration, and return
+    /// the delay before reconnecting, or none if the maximum number of
+    /// attempts is reached.
+    pub fn next_delay(&mut self, connection_duration: Duration) -> Option<Duration> {
+
+        if connection_duration >= STABLE_DURATION {
+            self.attempts = 0;
+        }
+
+        if self.policy.max_retries.is_some_and(|max_retries| self.attempts >= max_retries) {
+            return None;
+        }
+
+        let delay = self.policy.base_delay(self.attempts);
+        let jitter = self.rng.gen_range(-self.policy.jitter..=self.policy.jitter);
+
+        self.attempts += 1;
+        self.total += 1;
+
+        Some(delay.mul_f32(1.0 + jitter))
+
+    }
+
+    /// Number of consecutive attempts since the last stable connection.
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// Total number of reconnections.
+    pub fn total(&self) -> u32 {
+        self.total
+    }
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use rand::SeedableRng;
+
+    use super::*;
+
+    #[test]
+    fn backoff() {
+
+        let mut reconnect = Reconnect::new(ReconnectPolicy {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(10),
+            jitter: 0.0,
+            max_retries: Some(5),
+        }, StdRng::seed_from_u64(0));
+
+        let delays = (0..5).map(|_| reconnect.next_delay(Duration::ZERO).unwrap().as_secs()).collect::<Vec<_>>();
+        assert_eq!(delays, [1, 2, 4, 8, 10]);
+        assert_eq!(reconnect.next_delay(Duration::ZERO), None);
+
+        // A stable connection resets the backoff.
+        assert_eq!(reconnect.next_delay(STABLE_DURATION), Some(Duration::from_secs(1)));
+        assert_eq!(reconnect.total(), 6);
+
+    }
+
+}
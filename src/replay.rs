@@ -0,0 +1,137 @@
+use std::io::{self, Read, Write};
+use std::time::Instant;
+use std::path::PathBuf;
+
+use chrono::NaiveDate;
+
+use crate::chaos::{answer_written, push_line};
+use crate::import::{self, ChatEvent, ImportFormat, TextZone};
+use crate::seal;
+use crate::transport::IrcTransport;
+
+
+/// Configuration of the replay of a recorded chat log.
+#[derive(Debug, Clone)]
+pub struct ReplayConfig {
+    pub format: ImportFormat,
+    pub path: PathBuf,
+    /// Date of the messages, for text logs that only contain the time.
+    pub date: Option<NaiveDate>,
+    /// Speed factor of the replay.
+    pub speed: f32,
+    /// Passphrase of the log if it is sealed and encrypted.
+    pub passphrase: Option<String>,
+}
+
+
+/// A fake IRC stream replaying the messages of a recorded chat log at their
+/// original pace, with their original server time. The stream ends with an
+/// error once all messages have been read.
+pub struct ReplayStream {
+    events: Vec<ChatEvent>,
+    /// Index of the next event to replay.
+    index: usize,
+    speed: f32,
+    channel: String,
+    /// Data waiting to be read.
+    pending: Vec<u8>,
+    /// Data written but not yet processed.
+    written: Vec<u8>,
+    start: Instant,
+    /// True once all data has been read, the end is reported on the next
+    /// read so that the last messages are processed first.
+    drained: bool,
+}
+
+impl ReplayStream {
+
+    pub fn open(config: &ReplayConfig, user: &str, channel: &str) -> io::Result<Self> {
+
+        let events = import::read_events(config.format, seal::open(&config.path, config.passphrase.as_deref())?, config.date, TextZone::Utc)?;
+
+        let mut stream = Self {
+            events,
+            index: 0,
+            speed: config.speed,
+            channel: channel.to_string(),
+            pending: Vec::new(),
+            written: Vec::new(),
+            start: Instant::now(),
+            drained: false,
+        };
+
+        push_line(&mut stream.pending, format_args!(":tmi.twitch.tv 001 {user} :Welcome, GLHF!"));
+        Ok(stream)
+
+    }
+
+    /// Push all messages due since the start of the replay.
+    fn replay(&mut self) {
+
+        let Some(first) = self.events.first() else { return };
+        let first_time = first.time;
+        let elapsed = self.start.elapsed().as_secs_f32() * self.speed;
+
+        while let Some(event) = self.events.get(self.index) {
+
+            let offset = (event.time - first_time).num_milliseconds() as f32 / 1000.0;
+            if offset > elapsed {
+                break;
+            }
+
+            self.index += 1;
+            push_line(&mut self.pending, format_args!("@first-msg={};tmi-sent-ts={};user-id={user} :{user}!{user}@{user}.tmi.twitch.tv PRIVMSG #{} :{}",
+                event.first_msg as u8,
+                event.time.timestamp_millis(),
+                self.channel,
+                event.text,
+                user = event.user));
+
+        }
+
+    }
+
+}
+
+
+impl IrcTransport for ReplayStream {
+    fn set_nonblocking(&mut self, _nonblocking: bool) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Read for ReplayStream {
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+
+        self.replay();
+
+        if self.pending.is_empty() {
+            if self.index >= self.events.len() && std::mem::replace(&mut self.drained, true) {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "end of the replay"));
+            }
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+
+        let len = buf.len().min(self.pending.len());
+        buf[..len].copy_from_slice(&self.pending[..len]);
+        self.pending.drain(..len);
+        Ok(len)
+
+    }
+
+}
+
+impl Write for ReplayStream {
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.written.extend_from_slice(buf);
+        answer_written(&mut self.written, &mut self.pending);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+}
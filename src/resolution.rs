@@ -0,0 +1,132 @@
+use std::time::{Duration, Instant};
+
+use crate::config::{parse_seconds, Settings};
+use crate::stats::SAMPLE_DURATION;
+
+
+/// Configuration of the adaptive sample resolution.
+#[derive(Debug, Clone)]
+pub struct ResolutionConfig {
+    /// Duration of the samples under load.
+    pub coarse: Duration,
+    /// Message rate across all channels over which the samples are
+    /// coarsened (msg/s).
+    pub high_rate: f32,
+    /// Message rate under which the default resolution is restored.
+    pub low_rate: f32,
+    /// Time the rate must stay over or under its threshold before
+    /// switching, so that short spikes are ignored.
+    pub sustain: Duration,
+}
+
+impl ResolutionConfig {
+
+    /// Read the configuration from the `sample_*` settings, none if no high
+    /// rate is configured.
+    pub fn from_settings(settings: &Settings) -> Result<Option<Self>, String> {
+
+        let Some(high_rate) = settings.parse::<f32>("sample_high_rate")? else { return Ok(None) };
+
+        let config = Self {
+            coarse: settings.parse_with("sample_coarse_duration", parse_seconds)?.unwrap_or(Duration::from_millis(250)),
+            high_rate,
+            low_rate: settings.parse("sample_low_rate")?.unwrap_or(high_rate / 2.0),
+            sustain: settings.parse_with("sample_sustain", parse_seconds)?.unwrap_or(Duration::from_secs(10)),
+        };
+
+        if config.coarse <= SAMPLE_DURATION {
+            return Err(format!("invalid {}: expected more than {}s", settings.origin("sample_coarse_duration"), SAMPLE_DURATION.as_secs_f32()));
+        }
+        if config.low_rate >= config.high_rate {
+            return Err(format!("invalid {}: expected less than {}", settings.origin("sample_low_rate"), settings.origin("sample_high_rate")));
+        }
+
+        Ok(Some(config))
+
+    }
+
+}
+
+
+/// Coarsen the samples when the chat is flooded for a while, to bound the
+/// work of the main loop, and restore them once the load drops.
+#[derive(Debug)]
+pub struct AdaptiveResolution {
+    config: ResolutionConfig,
+    coarse: bool,
+    /// Time since which the rate is beyond the threshold of the switch.
+    since: Option<Instant>,
+}
+
+impl AdaptiveResolution {
+
+    pub fn new(config: ResolutionConfig) -> Self {
+        Self { config, coarse: false, since: None }
+    }
+
+    /// Current duration of the samples.
+    pub fn sample_duration(&self) -> Duration {
+        if self.coarse { self.config.coarse } else { SAMPLE_DURATION }
+    }
+
+    /// Update with the current message rate, returning the new duration of
+    /// the samples if it changes.
+    pub fn update(&mut self, now: Instant, message_sec: f32) -> Option<Duration> {
+
+        let beyond = if self.coarse {
+            message_sec < self.config.low_rate
+        } else {
+            message_sec > self.config.high_rate
+        };
+
+        if !beyond {
+            self.since = None;
+            return None;
+        }
+
+        let since = *self.since.get_or_insert(now);
+        if now - since < self.config.sustain {
+            return None;
+        }
+
+        self.coarse = !self.coarse;
+        self.since = None;
+        Some(self.sample_duration())
+
+    }
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn hysteresis() {
+
+        let mut resolution = AdaptiveResolution::new(ResolutionConfig {
+            coarse: Duration::from_millis(250),
+            high_rate: 100.0,
+            low_rate: 50.0,
+            sustain: Duration::from_secs(10),
+        });
+
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        // A short spike is ignored.
+        assert_eq!(resolution.update(at(0), 200.0), None);
+        assert_eq!(resolution.update(at(5), 80.0), None);
+        assert_eq!(resolution.update(at(11), 200.0), None);
+
+        assert_eq!(resolution.update(at(21), 200.0), Some(Duration::from_millis(250)));
+        // Between both thresholds, the resolution is kept.
+        assert_eq!(resolution.update(at(40), 80.0), None);
+        assert_eq!(resolution.update(at(41), 20.0), None);
+        assert_eq!(resolution.update(at(51), 20.0), Some(SAMPLE_DURATION));
+
+    }
+
+}
@@ -0,0 +1,30 @@
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+
+/// Derive the random generator of a component from the seed of the session.
+/// Each component has its own generator, so that adding random choices to
+/// one component doesn't shift the choices of the others.
+pub fn derive(seed: u64, component: &str) -> StdRng {
+    // FNV-1a, which is stable across versions unlike the standard hasher.
+    let hash = component.bytes().fold(0xcbf29ce484222325u64, |hash, b| (hash ^ b as u64).wrapping_mul(0x100000001b3));
+    StdRng::seed_from_u64(seed ^ hash)
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use rand::Rng;
+
+    use super::*;
+
+    #[test]
+    fn reproducible() {
+        let draw = |seed, component| derive(seed, component).gen::<u64>();
+        assert_eq!(draw(42, "chaos"), draw(42, "chaos"));
+        assert_ne!(draw(42, "chaos"), draw(42, "reconnect"));
+        assert_ne!(draw(42, "chaos"), draw(43, "chaos"));
+    }
+
+}
@@ -0,0 +1,118 @@
+use std::time::Duration;
+
+use crate::irc::IrcReplyRef;
+
+
+/// Chat modes of a channel, as reported by the server in ROOMSTATE. The
+/// first one after joining carries all the modes, the next ones only carry
+/// the modes that changed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RoomState {
+    /// Minimum interval between two messages of a user, zero if disabled.
+    pub slow: Duration,
+    /// Time a user must have followed the channel to chat, if enabled.
+    pub followers_only: Option<Duration>,
+    pub emote_only: bool,
+    pub subs_only: bool,
+}
+
+impl RoomState {
+
+    /// Update the modes from a ROOMSTATE reply, returning true if any of
+    /// them changed.
+    pub fn update(&mut self, reply: &IrcReplyRef) -> bool {
+
+        let prev = self.clone();
+
+        if let Some(slow) = reply.tag("slow").and_then(|slow| slow.parse().ok()) {
+            self.slow = Duration::from_secs(slow);
+        }
+        if let Some(minutes) = reply.tag("followers-only").and_then(|minutes| minutes.parse::<i64>().ok()) {
+            self.followers_only = u64::try_from(minutes).ok().map(|minutes| Duration::from_secs(minutes * 60));
+        }
+        if let Some(emote_only) = reply.tag("emote-only") {
+            self.emote_only = emote_only == "1";
+        }
+        if let Some(subs_only) = reply.tag("subs-only") {
+            self.subs_only = subs_only == "1";
+        }
+
+        *self != prev
+
+    }
+
+    /// The mode preventing the bot from sending any command, if any. The
+    /// modes don't apply to moderators.
+    pub fn blocking(&self, moderator: bool) -> Option<&'static str> {
+        if moderator {
+            None
+        } else if self.emote_only {
+            Some("emote-only")
+        } else if self.subs_only {
+            Some("subscribers-only")
+        } else {
+            None
+        }
+    }
+
+    /// Minimum interval between two messages of the bot in the channel,
+    /// zero if the chat is not in slow mode.
+    pub fn min_interval(&self, moderator: bool) -> Duration {
+        if moderator { Duration::ZERO } else { self.slow }
+    }
+
+    /// Short description of the active modes.
+    pub fn describe(&self) -> String {
+
+        let mut modes = Vec::new();
+        if !self.slow.is_zero() {
+            modes.push(format!("slow {}s", self.slow.as_secs()));
+        }
+        if let Some(followers_only) = self.followers_only {
+            modes.push(format!("followers-only {}m", followers_only.as_secs() / 60));
+        }
+        if self.emote_only {
+            modes.push("emote-only".to_string());
+        }
+        if self.subs_only {
+            modes.push("subscribers-only".to_string());
+        }
+
+        if modes.is_empty() {
+            "no chat mode".to_string()
+        } else {
+            modes.join(", ")
+        }
+
+    }
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn partial_updates() {
+
+        let mut state = RoomState::default();
+        let reply = IrcReplyRef::parse("@emote-only=0;followers-only=-1;r9k=0;room-id=42;slow=0;subs-only=0 :tmi.twitch.tv ROOMSTATE #chan").unwrap();
+        assert!(!state.update(&reply));
+        assert_eq!(state.describe(), "no chat mode");
+
+        let reply = IrcReplyRef::parse("@room-id=42;slow=30 :tmi.twitch.tv ROOMSTATE #chan").unwrap();
+        assert!(state.update(&reply));
+        assert_eq!(state.min_interval(false), Duration::from_secs(30));
+        assert_eq!(state.min_interval(true), Duration::ZERO);
+
+        let reply = IrcReplyRef::parse("@emote-only=1;room-id=42 :tmi.twitch.tv ROOMSTATE #chan").unwrap();
+        assert!(state.update(&reply));
+        assert_eq!(state.blocking(false), Some("emote-only"));
+        assert_eq!(state.blocking(true), None);
+        assert_eq!(state.describe(), "slow 30s, emote-only");
+
+    }
+
+}
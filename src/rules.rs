@@ -0,0 +1,257 @@
+use std::time::{Duration, Instant};
+use std::path::Path;
+use std::fs;
+
+use crate::expr::Expr;
+use crate::pause::{self, PauseEnd};
+
+
+/// Metrics exposed to the rules.
+pub const METRICS: &[&str] = &[
+    "msg/s", "cmd/s", "cmd/msg", "latency", "bot", "paused", "sent",
+    "bits", "redemptions",
+];
+
+
+/// An action triggered by a rule.
+#[derive(Debug, Clone)]
+pub enum RuleAction {
+    /// Add a marker to the journal.
+    Marker(String),
+    /// Alert the operator in the console.
+    Notify(String),
+    /// Pause sending in the channel.
+    Pause(PauseEnd),
+    /// Whisper a message to a user.
+    Whisper { user: String, text: String },
+}
+
+impl RuleAction {
+
+    /// Name of the action, as written in rules.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Marker(_) => "marker",
+            Self::Notify(_) => "notify",
+            Self::Pause(_) => "pause",
+            Self::Whisper { .. } => "whisper",
+        }
+    }
+
+    fn parse(text: &str) -> Result<Self, String> {
+
+        let (name, arg) = text.split_once(' ')
+            .map(|(name, arg)| (name, arg.trim().trim_matches('"')))
+            .unwrap_or((text, ""));
+
+        match name {
+            "marker" => Ok(Self::Marker(arg.to_string())),
+            "notify" => Ok(Self::Notify(arg.to_string())),
+            "pause" if arg.is_empty() => Ok(Self::Pause(PauseEnd::For(chrono::Duration::minutes(5)))),
+            "pause" => Ok(Self::Pause(PauseEnd::parse(arg)?)),
+            "whisper" => {
+                let (user, text) = arg.split_once(' ')
+                    .ok_or_else(|| format!("expected 'whisper <user> <text>', got '{text}'"))?;
+                Ok(Self::Whisper { user: user.to_string(), text: text.trim().trim_matches('"').to_string() })
+            }
+            _ => Err(format!("unknown action '{name}'")),
+        }
+
+    }
+
+}
+
+
+/// A rule triggering actions when its condition holds for some duration.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    /// The rule as written in the file.
+    pub source: String,
+    condition: Expr,
+    duration: Duration,
+    actions: Vec<RuleAction>,
+    /// Time since the condition holds.
+    since: Option<Instant>,
+    /// True when the actions have been triggered since the condition holds.
+    fired: bool,
+}
+
+impl Rule {
+
+    /// Parse a rule like `if cmd/s > 20 for 30s then pause 10m and notify
+    /// "too fast"`, the duration being optional.
+    pub fn parse(line: &str) -> Result<Self, String> {
+
+        let rest = line.trim().strip_prefix("if ")
+            .ok_or_else(|| format!("expected 'if <condition> then <actions>', got '{line}'"))?;
+        let (condition, actions) = rest.split_once(" then ")
+            .ok_or_else(|| format!("missing 'then' in '{line}'"))?;
+
+        let (condition, duration) = match condition.rsplit_once(" for ") {
+            Some((condition, duration)) => (condition, pause::parse_duration(duration.trim())?.to_std().unwrap()),
+            None => (condition, Duration::ZERO),
+        };
+
+        let condition = Expr::parse(condition)?;
+        condition.check(&|name| METRICS.contains(&name))?;
+
+        let actions = actions.split(" and ")
+            .map(|action| RuleAction::parse(action.trim()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            source: line.trim().to_string(),
+            condition,
+            duration,
+            actions,
+            since: None,
+            fired: false,
+        })
+
+    }
+
+}
+
+
+/// The rules engine, evaluating all rules against the current metrics.
+#[derive(Debug, Clone, Default)]
+pub struct Rules {
+    rules: Vec<Rule>,
+}
+
+impl Rules {
+
+    /// Load the rules from a file, with one rule per line, empty lines and
+    /// lines starting with '#' are ignored.
+    pub fn load(path: &Path) -> Result<Self, String> {
+
+        let content = fs::read_to_string(path).map_err(|e| e.to_string())?;
+
+        let rules = content.lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+            .map(|(index, line)| Rule::parse(line).map_err(|e| format!("line {}: {e}", index + 1)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self { rules })
+
+    }
+
+    /// Evaluate all rules, returning the actions to trigger now with their
+    /// rule. The actions of a rule are triggered once when its condition has
+    /// held for its duration, and again only after the condition stopped.
+    pub fn update(&mut self, now: Instant, metric: &impl Fn(&str) -> Option<f32>) -> Vec<(&Rule, &RuleAction)> {
+
+        let mut triggered = Vec::new();
+
+        for rule in &mut self.rules {
+
+            if !rule.condition.test(metric) {
+                rule.since = None;
+                rule.fired = false;
+                continue;
+            }
+
+            let since = *rule.since.get_or_insert(now);
+            if !rule.fired && now - since >= rule.duration {
+                rule.fired = true;
+                triggered.push(&*rule);
+            }
+
+        }
+
+        triggered.into_iter()
+            .flat_map(|rule| rule.actions.iter().map(move |action| (rule, action)))
+            .collect()
+
+    }
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn parse() {
+
+        let rule = Rule::parse("  if cmd/s > 20 and not paused for 1m30s then pause 10m and notify \"too fast\"  ").unwrap();
+        assert_eq!(rule.source, "if cmd/s > 20 and not paused for 1m30s then pause 10m and notify \"too fast\"");
+        assert_eq!(rule.duration, Duration::from_secs(90));
+        assert_eq!(rule.actions.iter().map(RuleAction::name).collect::<Vec<_>>(), ["pause", "notify"]);
+        assert!(matches!(&rule.actions[0], RuleAction::Pause(PauseEnd::For(duration)) if *duration == chrono::Duration::minutes(10)));
+        assert!(matches!(&rule.actions[1], RuleAction::Notify(text) if text == "too fast"));
+
+        let rule = Rule::parse("if bits >= 100 then whisper owner \"bits!\" and marker hype").unwrap();
+        assert_eq!(rule.duration, Duration::ZERO);
+        assert!(matches!(&rule.actions[0], RuleAction::Whisper { user, text } if user == "owner" && text == "bits!"));
+        assert!(matches!(&rule.actions[1], RuleAction::Marker(text) if text == "hype"));
+
+        let rule = Rule::parse("if latency > 2 then pause").unwrap();
+        assert!(matches!(&rule.actions[0], RuleAction::Pause(PauseEnd::For(duration)) if *duration == chrono::Duration::minutes(5)));
+
+    }
+
+    #[test]
+    fn errors() {
+
+        let error = |line| Rule::parse(line).unwrap_err();
+        assert_eq!(error("cmd/s > 20 then notify"), "expected 'if <condition> then <actions>', got 'cmd/s > 20 then notify'");
+        assert_eq!(error("if cmd/s > 20 notify"), "missing 'then' in 'if cmd/s > 20 notify'");
+        assert_eq!(error("if viewers > 20 then notify"), "unknown metric 'viewers'");
+        assert_eq!(error("if cmd/s > then notify"), "unexpected end of expression");
+        assert_eq!(error("if cmd/s > 20 then ban"), "unknown action 'ban'");
+        assert_eq!(error("if cmd/s > 20 then whisper owner"), "expected 'whisper <user> <text>', got 'whisper owner'");
+        assert!(error("if cmd/s > 20 for 30 then notify").starts_with("invalid duration"));
+        assert!(error("if cmd/s > 20 then pause 10x").starts_with("invalid duration unit 'x'"));
+
+    }
+
+    #[test]
+    fn update() {
+
+        let mut rules = Rules {
+            rules: vec![
+                Rule::parse("if cmd/s > 20 for 10s then notify fast and marker fast").unwrap(),
+                Rule::parse("if sent == 0 then marker idle").unwrap(),
+            ],
+        };
+
+        let start = Instant::now();
+        let mut update = |secs: u64, cmd_sec: f32| {
+            let metric = |name: &str| match name {
+                "cmd/s" => Some(cmd_sec),
+                "sent" => Some(1.0),
+                _ => None,
+            };
+            rules.update(start + Duration::from_secs(secs), &metric)
+                .into_iter()
+                .map(|(rule, action)| format!("{} {}", rule.source, action.name()))
+                .collect::<Vec<_>>()
+        };
+
+        // The condition must hold for the whole duration, the second rule
+        // never holds.
+        assert!(update(0, 30.0).is_empty());
+        assert!(update(5, 30.0).is_empty());
+        assert!(update(6, 10.0).is_empty());
+        assert!(update(7, 30.0).is_empty());
+        assert!(update(16, 30.0).is_empty());
+
+        let fired = update(17, 30.0);
+        assert_eq!(fired, [
+            "if cmd/s > 20 for 10s then notify fast and marker fast notify",
+            "if cmd/s > 20 for 10s then notify fast and marker fast marker",
+        ]);
+
+        // Fired once until the condition stops.
+        assert!(update(60, 30.0).is_empty());
+        assert!(update(61, 0.0).is_empty());
+        assert!(update(62, 30.0).is_empty());
+        assert_eq!(update(72, 30.0).len(), 2);
+
+    }
+
+}
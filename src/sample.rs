@@ -0,0 +1,304 @@
+use std::collections::HashMap;
+use std::ops::{AddAssign, Index, IndexMut, SubAssign};
+use std::time::Duration;
+
+use crate::policy::CommandWeights;
+use crate::vocabulary::{Command, Modifier, Vocabulary};
+
+
+/// A count for each command, indexed by command.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CommandCounts([u32; Command::ALL.len()]);
+
+impl CommandCounts {
+
+    /// Each command with its count, in the order of [`Command::ALL`].
+    pub fn iter(&self) -> impl Iterator<Item = (Command, u32)> + '_ {
+        Command::ALL.into_iter().zip(self.0)
+    }
+
+}
+
+impl Index<Command> for CommandCounts {
+    type Output = u32;
+    fn index(&self, command: Command) -> &u32 {
+        &self.0[command.index()]
+    }
+}
+
+impl IndexMut<Command> for CommandCounts {
+    fn index_mut(&mut self, command: Command) -> &mut u32 {
+        &mut self.0[command.index()]
+    }
+}
+
+// The counts are wide enough for the weighted votes of the longest window,
+// so that subtracting a sample always removes exactly what it added.
+impl<'a> AddAssign<&'a Self> for CommandCounts {
+    fn add_assign(&mut self, rhs: &'a Self) {
+        for (count, rhs) in self.0.iter_mut().zip(rhs.0) {
+            *count += rhs;
+        }
+    }
+}
+
+impl<'a> SubAssign<&'a Self> for CommandCounts {
+    fn sub_assign(&mut self, rhs: &'a Self) {
+        for (count, rhs) in self.0.iter_mut().zip(rhs.0) {
+            *count -= rhs;
+        }
+    }
+}
+
+
+/// Counts of messages and TPP commands received during some time.
+#[derive(Debug, Default)]
+pub struct Sample {
+    pub message_count: u32,
+    pub tpp_command_count: u32,
+    /// Votes for each command.
+    pub counts: CommandCounts,
+    /// Bits cheered with messages.
+    pub bits: u32,
+    /// Channel points rewards redeemed with a message.
+    pub redemptions: u32,
+    /// Number of commands typed with each alias, in lowercase.
+    pub aliases: HashMap<String, u32>,
+}
+
+impl Sample {
+
+    /// Count a chat message in this sample, with its TPP command if any,
+    /// returning true if it is a TPP command.
+    pub fn push_message(&mut self, command: Option<Command>) -> bool {
+        self.push_weighted(command, 1)
+    }
+
+    /// Count a chat message with a vote of the given weight, returning true
+    /// if it is a TPP command. The message itself is counted once.
+    pub fn push_weighted(&mut self, command: Option<Command>, weight: u16) -> bool {
+
+        self.message_count += 1;
+
+        match command {
+            Some(command) => {
+                self.push_vote(command, weight);
+                true
+            }
+            None => false,
+        }
+
+    }
+
+    /// Build a sample from the rates of messages and commands over the given
+    /// duration, and the share of each command, aligned with
+    /// [`Command::ALL`].
+    pub fn from_rates(duration: Duration, message_sec: f32, cmd_sec: f32, shares: &[f32]) -> Self {
+
+        let secs = duration.as_secs_f32();
+        let mut sample = Self {
+            message_count: (message_sec * secs).round() as u32,
+            tpp_command_count: (cmd_sec * secs).round() as u32,
+            ..Self::default()
+        };
+
+        for (command, share) in Command::ALL.into_iter().zip(shares) {
+            sample.counts[command] = (share * sample.tpp_command_count as f32).round() as u32;
+        }
+
+        sample
+
+    }
+
+    /// Build a sample of the given counts of commands, all of them being
+    /// a message.
+    pub fn from_counts(counts: &[(Command, u32)]) -> Self {
+        let mut sample = Self::default();
+        for &(command, count) in counts {
+            sample.counts[command] += count;
+            sample.tpp_command_count += count;
+            sample.message_count += count;
+        }
+        sample
+    }
+
+    /// The given part of this sample split in even parts, the counts of all
+    /// the parts summing to the counts of this sample. Aliases aren't split.
+    pub fn part(&self, index: usize, count: usize) -> Self {
+
+        let split = |total: u32| ((index + 1) * total as usize / count - index * total as usize / count) as u32;
+
+        let mut part = Self {
+            message_count: split(self.message_count),
+            tpp_command_count: split(self.tpp_command_count),
+            bits: ((index + 1) * self.bits as usize / count - index * self.bits as usize / count) as u32,
+            redemptions: split(self.redemptions),
+            ..Self::default()
+        };
+
+        for command in Command::ALL {
+            part.counts[command] = split(self.counts[command]);
+        }
+
+        part
+
+    }
+
+    /// Count the alias a command was typed with.
+    pub fn push_alias(&mut self, alias: &str) {
+        *self.aliases.entry(alias.to_lowercase()).or_default() += 1;
+    }
+
+    /// Aliases the given command was typed with and their counts, the most
+    /// used first.
+    pub fn aliases<'a>(&'a self, command: Command, vocabulary: &Vocabulary) -> Vec<(&'a str, u32)> {
+        let mut aliases = self.aliases.iter()
+            .filter(|(alias, _)| vocabulary.parse(alias) == Some(command))
+            .map(|(alias, &count)| (alias.as_str(), count))
+            .collect::<Vec<_>>();
+        aliases.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        aliases
+    }
+
+    /// Modifier typed by most of the voters of the given command, if any.
+    pub fn dominant_modifier(&self, command: Command, vocabulary: &Vocabulary) -> Option<Modifier> {
+
+        let mut total = 0u32;
+        let mut modifiers = HashMap::<Modifier, u32>::new();
+        for (alias, &count) in &self.aliases {
+            let Some((alias_command, modifier)) = vocabulary.parse_modified(alias) else { continue };
+            if alias_command != command {
+                continue;
+            }
+            total += count;
+            if let Some(modifier) = modifier {
+                *modifiers.entry(modifier).or_default() += count;
+            }
+        }
+
+        // A strict majority is unique, whatever the hash order.
+        modifiers.into_iter()
+            .find(|&(_, count)| count * 2 > total)
+            .map(|(modifier, _)| modifier)
+
+    }
+
+    /// Combo starting with the given command typed by most of its voters,
+    /// if any, as it was typed the most.
+    pub fn dominant_combo(&self, command: Command, vocabulary: &Vocabulary) -> Option<&str> {
+
+        let mut total = 0u32;
+        let mut combos = HashMap::<Vec<(Command, Option<Modifier>)>, (u32, &str, u32)>::new();
+        for (alias, &count) in &self.aliases {
+            if vocabulary.parse(alias) != Some(command) {
+                continue;
+            }
+            total += count;
+            if let Some(combo) = vocabulary.parse_combo(alias) {
+                let (combo_count, text, text_count) = combos.entry(combo).or_insert((0, alias.as_str(), 0));
+                *combo_count += count;
+                // The most typed spelling, the first in alphabetical order
+                // of the ties, whatever the hash order.
+                if (count, std::cmp::Reverse(alias.as_str())) > (*text_count, std::cmp::Reverse(*text)) {
+                    *text = alias;
+                    *text_count = count;
+                }
+            }
+        }
+
+        combos.into_values()
+            .find(|&(count, _, _)| count * 2 > total)
+            .map(|(_, text, _)| text)
+
+    }
+
+    /// Count a vote without its message, for the other buttons of a combo.
+    pub fn push_vote(&mut self, command: Command, weight: u16) {
+        self.counts[command] += weight as u32;
+        self.tpp_command_count += weight as u32;
+    }
+
+    /// Number of votes for the given command.
+    pub fn count(&self, command: Command) -> u32 {
+        self.counts[command]
+    }
+
+    /// Average number of messages per second, for the given duration of
+    /// this sample.
+    pub fn message_sec(&self, duration: Duration) -> f32 {
+        self.message_count as f32 / duration.as_secs_f32()
+    }
+
+    /// Average number of TPP commands per second, for the given duration
+    /// of this sample.
+    pub fn tpp_command_sec(&self, duration: Duration) -> f32 {
+        self.tpp_command_count as f32 / duration.as_secs_f32()
+    }
+
+    /// Ratio of TPP commands over all messages, at most 1 as the weighted
+    /// votes count more than their message.
+    pub fn tpp_command_ratio(&self) -> f32 {
+        if self.message_count == 0 { 0.0 } else {
+            (self.tpp_command_count as f32 / self.message_count as f32).min(1.0)
+        }
+    }
+
+    /// Share of the given command among the TPP commands.
+    pub fn share(&self, command: Command) -> f32 {
+        if self.tpp_command_count == 0 { 0.0 } else {
+            self.count(command) as f32 / self.tpp_command_count as f32
+        }
+    }
+
+    /// Counts of each button command.
+    pub fn buttons(&self) -> Vec<(u32, Command)> {
+        Command::ALL.into_iter()
+            .filter(|command| command.is_button())
+            .map(|command| (self.count(command), command))
+            .collect()
+    }
+
+    /// The command with the most weighted votes, the last of the ties in
+    /// the order of [`Command::ALL`].
+    pub fn most_used(&self, weights: &CommandWeights) -> Command {
+        self.counts.iter()
+            .max_by_key(|&(command, count)| (count as f32 * weights[command]) as u32)
+            .unwrap()
+            .0
+    }
+
+}
+
+impl<'a> AddAssign<&'a Self> for Sample {
+
+    fn add_assign(&mut self, rhs: &'a Self) {
+        self.message_count += rhs.message_count;
+        self.tpp_command_count += rhs.tpp_command_count;
+        self.counts += &rhs.counts;
+        self.bits += rhs.bits;
+        self.redemptions += rhs.redemptions;
+        for (alias, count) in &rhs.aliases {
+            *self.aliases.entry(alias.clone()).or_default() += count;
+        }
+    }
+
+}
+impl<'a> SubAssign<&'a Self> for Sample {
+
+    fn sub_assign(&mut self, rhs: &'a Self) {
+        self.message_count -= rhs.message_count;
+        self.tpp_command_count -= rhs.tpp_command_count;
+        self.counts -= &rhs.counts;
+        self.bits -= rhs.bits;
+        self.redemptions -= rhs.redemptions;
+        for (alias, count) in &rhs.aliases {
+            if let Some(total) = self.aliases.get_mut(alias) {
+                *total -= count;
+                if *total == 0 {
+                    self.aliases.remove(alias);
+                }
+            }
+        }
+    }
+
+}
@@ -0,0 +1,148 @@
+use chrono::{DateTime, Local, NaiveDate, NaiveTime, Timelike, Utc};
+
+use crate::config::Settings;
+use crate::pause::PauseEnd;
+
+
+/// Hours and limits of the messages sent by the bot, for the bots running
+/// unattended.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Schedule {
+    /// Local start and end of the quiet hours, during which the channels
+    /// are paused, possibly across midnight.
+    quiet: Option<(NaiveTime, NaiveTime)>,
+    /// Maximum number of messages of a channel since the start of the bot.
+    session_limit: Option<u32>,
+    /// Maximum number of messages of a channel per local day.
+    daily_limit: Option<u32>,
+}
+
+impl Schedule {
+
+    /// Read the schedule from the `quiet_hours` setting, like `23:00-08:00`
+    /// in local time, and the `max_messages_session` and `max_messages_day`
+    /// ones.
+    pub fn from_settings(settings: &Settings) -> Result<Self, String> {
+        Ok(Self {
+            quiet: settings.parse_with("quiet_hours", parse_hours)?,
+            session_limit: settings.parse("max_messages_session")?,
+            daily_limit: settings.parse("max_messages_day")?,
+        })
+    }
+
+    /// The end of the pause of a channel that sent the given number of
+    /// messages today, with its reason, if it must be paused now.
+    pub fn pause(&self, now: DateTime<Utc>, daily_sent: u32) -> Option<(DateTime<Utc>, &'static str)> {
+
+        if let Some((start, end)) = self.quiet {
+            let time = now.with_timezone(&Local).time().with_nanosecond(0).unwrap();
+            let quiet = if start <= end {
+                start <= time && time < end
+            } else {
+                start <= time || time < end
+            };
+            if quiet {
+                return Some((PauseEnd::Until(end).resolve(now), "quiet hours"));
+            }
+        }
+
+        if self.daily_limit.is_some_and(|limit| daily_sent >= limit) {
+            return Some((PauseEnd::Until(NaiveTime::MIN).resolve(now), "daily limit"));
+        }
+
+        None
+
+    }
+
+    /// Return true if a channel that sent the given number of messages
+    /// since the start of the bot reached its limit.
+    pub fn is_session_over(&self, session_sent: u32) -> bool {
+        self.session_limit.is_some_and(|limit| session_sent >= limit)
+    }
+
+    pub fn daily_limit(&self) -> Option<u32> {
+        self.daily_limit
+    }
+
+}
+
+
+/// Number of messages sent by a channel in the current local day.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DailyCount {
+    date: Option<NaiveDate>,
+    count: u32,
+}
+
+impl DailyCount {
+
+    /// Count the messages sent at the given times during the current day,
+    /// like the messages of the history after a restart.
+    pub fn from_times(times: impl IntoIterator<Item = DateTime<Utc>>, now: DateTime<Utc>) -> Self {
+        let date = now.with_timezone(&Local).date_naive();
+        let count = times.into_iter().filter(|time| time.with_timezone(&Local).date_naive() == date).count();
+        Self { date: Some(date), count: count as u32 }
+    }
+
+    pub fn get(&self, now: DateTime<Utc>) -> u32 {
+        if self.date == Some(now.with_timezone(&Local).date_naive()) { self.count } else { 0 }
+    }
+
+    pub fn push(&mut self, now: DateTime<Utc>) {
+        let date = now.with_timezone(&Local).date_naive();
+        if self.date != Some(date) {
+            self.date = Some(date);
+            self.count = 0;
+        }
+        self.count += 1;
+    }
+
+}
+
+
+/// Parse hours like `23:00-08:00`.
+fn parse_hours(text: &str) -> Result<(NaiveTime, NaiveTime), String> {
+    let (start, end) = text.split_once('-').ok_or("expected 'HH:MM-HH:MM'")?;
+    let time = |time: &str| NaiveTime::parse_from_str(time.trim(), "%H:%M").map_err(|e| format!("invalid time '{}': {e}", time.trim()));
+    Ok((time(start)?, time(end)?))
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn quiet_hours() {
+
+        let at = |hour, minute| Local.with_ymd_and_hms(2024, 3, 12, hour, minute, 0).unwrap().with_timezone(&Utc);
+        let schedule = Schedule {
+            quiet: Some(parse_hours("23:00-08:00").unwrap()),
+            session_limit: Some(100),
+            daily_limit: Some(50),
+        };
+
+        assert_eq!(schedule.pause(at(12, 0), 0), None);
+        assert_eq!(schedule.pause(at(23, 30), 0), Some((at(8, 0) + chrono::Duration::days(1), "quiet hours")));
+        assert_eq!(schedule.pause(at(7, 59), 0), Some((at(8, 0), "quiet hours")));
+        assert_eq!(schedule.pause(at(12, 0), 50), Some((at(0, 0) + chrono::Duration::days(1), "daily limit")));
+        assert!(!schedule.is_session_over(99));
+        assert!(schedule.is_session_over(100));
+        assert!(parse_hours("23:00").is_err());
+
+        let mut daily = DailyCount::default();
+        daily.push(at(12, 0));
+        daily.push(at(13, 0));
+        assert_eq!(daily.get(at(14, 0)), 2);
+        assert_eq!(daily.get(at(14, 0) + chrono::Duration::days(1)), 0);
+
+        // Only the messages of the day count after a restart.
+        let daily = DailyCount::from_times([at(12, 0) - chrono::Duration::days(1), at(12, 0), at(13, 0)], at(14, 0));
+        assert_eq!(daily.get(at(14, 0)), 2);
+
+    }
+
+}
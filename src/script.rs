@@ -0,0 +1,190 @@
+use std::path::Path;
+use std::fs;
+
+use rhai::{Dynamic, Engine, Map, Scope, AST};
+use tracing::{info, warn};
+
+use crate::sample::Sample;
+use crate::vocabulary::{Command, Vocabulary};
+
+
+/// Name of the function called by the bot.
+const DECIDE: &str = "decide";
+/// Maximum number of operations of a single call, so that a looping script
+/// can't freeze the bot.
+const MAX_OPERATIONS: u64 = 100_000;
+
+
+/// A compiled decision script, given by the `script_path` setting.
+#[derive(Debug, Clone)]
+pub struct ScriptSource {
+    ast: AST,
+}
+
+impl ScriptSource {
+
+    /// Compile a script, it must define a `decide(chat)` function.
+    pub fn parse(text: &str) -> Result<Self, String> {
+
+        let ast = engine().compile(text).map_err(|e| e.to_string())?;
+        if !ast.iter_functions().any(|function| function.name == DECIDE && function.params.len() == 1) {
+            return Err(format!("missing function '{DECIDE}(chat)'"));
+        }
+
+        Ok(Self { ast })
+
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        Self::parse(&fs::read_to_string(path).map_err(|e| e.to_string())?)
+    }
+
+}
+
+
+/// Aggregates of the TPP window given to the script.
+#[derive(Debug, Clone, Copy)]
+pub struct ScriptInput<'a> {
+    pub channel: &'a str,
+    pub sample: &'a Sample,
+    /// The command with the most weighted votes.
+    pub most_used: Command,
+    pub cmd_sec: f32,
+    pub ratio: f32,
+}
+
+impl ScriptInput<'_> {
+
+    /// The object given to the script, like `#{channel: "chan", messages:
+    /// 120, commands: 80, cmd_sec: 2.6, ratio: 0.66, most_used: "a",
+    /// counts: #{up: 12, a: 40, ...}}`.
+    fn to_map(self) -> Map {
+
+        let counts = self.sample.counts.iter()
+            .map(|(command, count)| (command.name().into(), Dynamic::from_int(count as rhai::INT)))
+            .collect::<Map>();
+
+        let mut map = Map::new();
+        map.insert("channel".into(), self.channel.into());
+        map.insert("messages".into(), Dynamic::from_int(self.sample.message_count as rhai::INT));
+        map.insert("commands".into(), Dynamic::from_int(self.sample.tpp_command_count as rhai::INT));
+        map.insert("cmd_sec".into(), Dynamic::from_float(self.cmd_sec as rhai::FLOAT));
+        map.insert("ratio".into(), Dynamic::from_float(self.ratio as rhai::FLOAT));
+        map.insert("most_used".into(), self.most_used.name().into());
+        map.insert("counts".into(), counts.into());
+        map
+
+    }
+
+}
+
+
+/// A decision script of a channel, replacing the thresholds of the bot: at
+/// each decision its `decide` function receives the aggregates of the chat
+/// and returns the command to send, or `()` to send nothing. The returned
+/// command is a command name or an alias of the vocabulary.
+#[derive(Debug)]
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    /// True while the script fails, so that the error is only reported once.
+    failing: bool,
+}
+
+impl Script {
+
+    pub fn new(source: &ScriptSource) -> Self {
+        Self {
+            engine: engine(),
+            ast: source.ast.clone(),
+            failing: false,
+        }
+    }
+
+    /// Call the script with the given aggregates, an error of the script is
+    /// logged and decides nothing.
+    pub fn decide(&mut self, input: ScriptInput, vocabulary: &Vocabulary) -> Option<Command> {
+
+        let result = self.engine.call_fn::<Dynamic>(&mut Scope::new(), &self.ast, DECIDE, (input.to_map(),))
+            .map_err(|e| e.to_string())
+            .and_then(|value| parse_command(value, vocabulary));
+
+        match result {
+            Ok(command) => {
+                self.failing = false;
+                command
+            }
+            Err(e) => {
+                if !self.failing {
+                    warn!(channel = input.channel, "decision script failed: {e}");
+                    self.failing = true;
+                }
+                None
+            }
+        }
+
+    }
+
+}
+
+
+fn engine() -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+    // The standard output is the prompt.
+    engine.on_print(|text| info!("script: {text}"));
+    engine.on_debug(|text, _, _| info!("script: {text}"));
+    engine
+}
+
+/// Read the command returned by the script, if any.
+fn parse_command(value: Dynamic, vocabulary: &Vocabulary) -> Result<Option<Command>, String> {
+
+    if value.is_unit() {
+        return Ok(None);
+    }
+
+    let name = value.into_string().map_err(|type_name| format!("expected a command or (), got {type_name}"))?;
+    Command::from_name(&name)
+        .or_else(|| vocabulary.parse(&name))
+        .map(Some)
+        .ok_or_else(|| format!("unknown command '{name}'"))
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn decide() {
+
+        let source = ScriptSource::parse(r#"
+            fn decide(chat) {
+                if chat.channel == "quiet" { return (); }
+                if chat.counts.start > 0 { return "dance"; }
+                if chat.ratio >= 0.5 { chat.most_used } else { "n" }
+            }
+        "#).unwrap();
+        let mut script = Script::new(&source);
+
+        let vocabulary = Vocabulary::default();
+        let sample = Sample::from_counts(&[(Command::A, 3), (Command::B, 1)]);
+        let input = ScriptInput { channel: "chan", sample: &sample, most_used: Command::A, cmd_sec: 2.0, ratio: 0.8 };
+
+        assert_eq!(script.decide(input, &vocabulary), Some(Command::A));
+        assert_eq!(script.decide(ScriptInput { ratio: 0.2, ..input }, &vocabulary), Some(Command::Up));
+        assert_eq!(script.decide(ScriptInput { channel: "quiet", ..input }, &vocabulary), None);
+
+        let sample = Sample::from_counts(&[(Command::Start, 1)]);
+        assert_eq!(script.decide(ScriptInput { sample: &sample, ..input }, &vocabulary), None);
+        assert!(script.failing);
+
+        assert!(ScriptSource::parse("fn choose(chat) { () }").is_err());
+        assert!(ScriptSource::parse("fn decide(chat) {").is_err());
+
+    }
+
+}
@@ -0,0 +1,265 @@
+use std::io::{self, Cursor, Read, Write};
+use std::path::Path;
+use std::fs::{self, File};
+
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use sha2::{Digest, Sha256};
+
+use crate::config::Settings;
+
+
+/// Magic bytes at the start of a sealed file.
+const MAGIC: &[u8; 8] = b"TPPSEAL1";
+/// Magic bytes at the end of the footer of a sealed file.
+const FOOTER_MAGIC: &[u8; 8] = b"TPPSEND1";
+/// Length of the footer: line count, plain size, checksum and magic.
+const FOOTER_LEN: usize = 8 + 8 + 32 + 8;
+
+/// The body is compressed with zstd.
+const FLAG_COMPRESSED: u8 = 1;
+/// The body is encrypted with a key derived from a passphrase.
+const FLAG_ENCRYPTED: u8 = 2;
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+/// Iterations of the key derivation, slowing down passphrase guessing.
+#[cfg(not(test))]
+const KDF_ITERATIONS: u32 = 600_000;
+#[cfg(test)]
+const KDF_ITERATIONS: u32 = 1_000;
+
+
+/// Options of the sealing of a file.
+#[derive(Debug, Default)]
+pub struct SealOptions<'a> {
+    pub compress: bool,
+    /// Passphrase of the encryption, none to leave the content readable.
+    pub passphrase: Option<&'a str>,
+}
+
+/// Integrity information stored in the footer of a sealed file.
+#[derive(Debug, PartialEq)]
+pub struct Footer {
+    /// Number of lines, which are the events of line-based logs.
+    pub lines: u64,
+    /// Size of the plain content.
+    pub size: u64,
+    /// SHA-256 checksum of the plain content.
+    pub checksum: [u8; 32],
+}
+
+impl Footer {
+
+    fn of(data: &[u8]) -> Self {
+        Self {
+            lines: data.split(|&b| b == b'\n').filter(|line| !line.is_empty()).count() as u64,
+            size: data.len() as u64,
+            checksum: Sha256::digest(data).into(),
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(FOOTER_LEN);
+        bytes.extend_from_slice(&self.lines.to_le_bytes());
+        bytes.extend_from_slice(&self.size.to_le_bytes());
+        bytes.extend_from_slice(&self.checksum);
+        bytes.extend_from_slice(FOOTER_MAGIC);
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != FOOTER_LEN || &bytes[48..] != FOOTER_MAGIC {
+            return None;
+        }
+        Some(Self {
+            lines: u64::from_le_bytes(bytes[0..8].try_into().unwrap()),
+            size: u64::from_le_bytes(bytes[8..16].try_into().unwrap()),
+            checksum: bytes[16..48].try_into().unwrap(),
+        })
+    }
+
+}
+
+
+/// Seal the given content: optionally compress and encrypt it, and add an
+/// integrity footer. Chat logs contain usernames, so they should be
+/// encrypted before being shared.
+pub fn seal(data: &[u8], options: &SealOptions) -> io::Result<Vec<u8>> {
+
+    let footer = Footer::of(data).to_bytes();
+
+    let mut flags = 0;
+    let mut body = if options.compress {
+        flags |= FLAG_COMPRESSED;
+        zstd::encode_all(data, 0)?
+    } else {
+        data.to_vec()
+    };
+
+    let mut header = MAGIC.to_vec();
+
+    if let Some(passphrase) = options.passphrase {
+        flags |= FLAG_ENCRYPTED;
+        let salt = rand::random::<[u8; SALT_LEN]>();
+        let nonce = rand::random::<[u8; NONCE_LEN]>();
+        header.push(flags);
+        header.extend_from_slice(&salt);
+        header.extend_from_slice(&nonce);
+        // The header and footer are not secret but are authenticated.
+        let aad = [header.as_slice(), &footer].concat();
+        body = cipher(passphrase, &salt)
+            .encrypt(XNonce::from_slice(&nonce), Payload { msg: &body, aad: &aad })
+            .map_err(|_| io::Error::other("encryption failed"))?;
+    } else {
+        header.push(flags);
+    }
+
+    Ok([header, body, footer].concat())
+
+}
+
+/// Open a sealed content, verifying its integrity, and return the plain
+/// content with its footer.
+pub fn unseal(sealed: &[u8], passphrase: Option<&str>) -> io::Result<(Vec<u8>, Footer)> {
+
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+
+    if !is_sealed(sealed) || sealed.len() < MAGIC.len() + 1 + FOOTER_LEN {
+        return Err(invalid("not a sealed file"));
+    }
+
+    let flags = sealed[MAGIC.len()];
+    let header_len = MAGIC.len() + 1 + if flags & FLAG_ENCRYPTED != 0 { SALT_LEN + NONCE_LEN } else { 0 };
+    if sealed.len() < header_len + FOOTER_LEN {
+        return Err(invalid("truncated sealed file"));
+    }
+
+    let (header, rest) = sealed.split_at(header_len);
+    let (body, footer_bytes) = rest.split_at(rest.len() - FOOTER_LEN);
+    let footer = Footer::from_bytes(footer_bytes).ok_or_else(|| invalid("missing integrity footer, the file may be truncated"))?;
+
+    let mut body = body.to_vec();
+
+    if flags & FLAG_ENCRYPTED != 0 {
+        let passphrase = passphrase.ok_or_else(|| invalid("the file is encrypted, a passphrase is required"))?;
+        let salt = &header[MAGIC.len() + 1..][..SALT_LEN];
+        let nonce = &header[MAGIC.len() + 1 + SALT_LEN..];
+        let aad = [header, footer_bytes].concat();
+        body = cipher(passphrase, salt)
+            .decrypt(XNonce::from_slice(nonce), Payload { msg: &body, aad: &aad })
+            .map_err(|_| invalid("decryption failed, wrong passphrase or corrupted file"))?;
+    }
+
+    if flags & FLAG_COMPRESSED != 0 {
+        body = zstd::decode_all(body.as_slice())?;
+    }
+
+    if Footer::of(&body) != footer {
+        return Err(invalid("integrity check failed, the content doesn't match its footer"));
+    }
+
+    Ok((body, footer))
+
+}
+
+/// Return true if the given content is sealed.
+pub fn is_sealed(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// Open a file for reading, unsealing it if it is sealed.
+pub fn open(path: &Path, passphrase: Option<&str>) -> io::Result<Box<dyn Read>> {
+
+    let mut file = File::open(path)?;
+    let mut magic = [0; MAGIC.len()];
+    let len = file.read(&mut magic)?;
+
+    if is_sealed(&magic[..len]) {
+        let (data, _) = unseal(&fs::read(path)?, passphrase)?;
+        Ok(Box::new(Cursor::new(data)))
+    } else {
+        Ok(Box::new(File::open(path)?))
+    }
+
+}
+
+/// Derive the cipher of a passphrase with the given salt.
+fn cipher(passphrase: &str, salt: &[u8]) -> XChaCha20Poly1305 {
+    let mut key = [0; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, KDF_ITERATIONS, &mut key);
+    XChaCha20Poly1305::new(&key.into())
+}
+
+
+/// Entry point of the `seal` subcommand, the passphrase is read from the
+/// `seal_passphrase` setting when encrypting.
+pub fn main_seal(input: &Path, output: &Path, compress: bool, encrypt: bool, settings: &Settings) -> io::Result<()> {
+
+    let passphrase = if encrypt {
+        Some(settings.get("seal_passphrase").ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput,
+            format!("a passphrase is required to encrypt, set {}", settings.origin("seal_passphrase"))))?)
+    } else {
+        None
+    };
+
+    let data = fs::read(input)?;
+    let sealed = seal(&data, &SealOptions { compress, passphrase })?;
+    File::create(output)?.write_all(&sealed)?;
+
+    println!("sealed {} lines, {} bytes into {} bytes", Footer::of(&data).lines, data.len(), sealed.len());
+    Ok(())
+
+}
+
+/// Entry point of the `unseal` subcommand, the content is only verified if
+/// no output is given.
+pub fn main_unseal(input: &Path, output: Option<&Path>, settings: &Settings) -> io::Result<()> {
+
+    let (data, footer) = unseal(&fs::read(input)?, settings.get("seal_passphrase"))?;
+    if let Some(output) = output {
+        File::create(output)?.write_all(&data)?;
+    }
+
+    let checksum = footer.checksum.iter().map(|b| format!("{b:02x}")).collect::<String>();
+    println!("verified {} lines, {} bytes, sha256 {checksum}", footer.lines, footer.size);
+    Ok(())
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    const DATA: &[u8] = b"1700000000\tuser\thello\n1700000001\tother\tup\n";
+
+    #[test]
+    fn round_trip() {
+
+        let sealed = seal(DATA, &SealOptions { compress: true, passphrase: Some("secret") }).unwrap();
+        assert!(is_sealed(&sealed));
+
+        let (data, footer) = unseal(&sealed, Some("secret")).unwrap();
+        assert_eq!(data, DATA);
+        assert_eq!(footer.lines, 2);
+
+        assert!(unseal(&sealed, Some("wrong")).is_err());
+        assert!(unseal(&sealed, None).is_err());
+
+    }
+
+    #[test]
+    fn tampered() {
+
+        let mut sealed = seal(DATA, &SealOptions::default()).unwrap();
+        sealed[MAGIC.len() + 1] ^= 1;
+        assert!(unseal(&sealed, None).is_err());
+
+        let sealed = seal(DATA, &SealOptions::default()).unwrap();
+        assert!(unseal(&sealed[..sealed.len() - 1], None).is_err());
+
+    }
+
+}
@@ -0,0 +1,157 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::collections::HashSet;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use tracing::{info, warn};
+
+
+/// A persistent store of all users already seen in the channel, used
+/// to tell brand new chatters from returning ones. The store only exists
+/// with a file, a store kept in memory would count every chatter as new
+/// after a restart, so without it the `first-msg` tag of Twitch alone
+/// tells the new chatters.
+pub struct SeenUsers {
+    path: PathBuf,
+    users: HashSet<String>,
+    file: File,
+    /// Length of the complete lines of the file, a failed write is
+    /// truncated back to it.
+    len: u64,
+    /// Users not yet saved because the file failed, saved with the next
+    /// new user.
+    pending: String,
+    /// True while the file fails, so that the error is only reported once.
+    failing: bool,
+}
+
+impl SeenUsers {
+
+    /// Open the store backed by the given file, loading all users
+    /// already saved in it.
+    pub fn open(path: &Path) -> io::Result<Self> {
+
+        let file = File::options()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+
+        // A last line without its newline was cut by a crash, it is removed
+        // so that the next user isn't joined to it.
+        let file_len = file.metadata()?.len();
+        let mut users = HashSet::new();
+        let mut len = 0;
+        for line in BufReader::new(&file).split(b'\n') {
+            let line = line?;
+            if len + line.len() as u64 == file_len {
+                file.set_len(len)?;
+                break;
+            }
+            len += line.len() as u64 + 1;
+            if !line.is_empty() {
+                users.insert(String::from_utf8_lossy(&line).into_owned());
+            }
+        }
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            users,
+            file,
+            len,
+            pending: String::new(),
+            failing: false,
+        })
+
+    }
+
+    /// Insert a user in the store, returning true if this user was
+    /// never seen before. If the file fails, the user is still known and
+    /// is saved with the next new user.
+    pub fn insert(&mut self, user: &str) -> bool {
+
+        if self.users.contains(user) {
+            return false;
+        }
+
+        self.pending.push_str(user);
+        self.pending.push('\n');
+        // A failed write may have written part of the pending users, it is
+        // removed so that they are all written again whole.
+        let result = if self.failing { self.file.set_len(self.len) } else { Ok(()) };
+        match result.and_then(|()| self.file.write_all(self.pending.as_bytes())) {
+            Ok(()) => {
+                if self.failing {
+                    info!("{} written again", self.path.display());
+                    self.failing = false;
+                }
+                self.len += self.pending.len() as u64;
+                self.pending.clear();
+            }
+            Err(e) => {
+                if !self.failing {
+                    warn!("\x07alert: failed to write {}: {e}, keeping users in memory", self.path.display());
+                    self.failing = true;
+                }
+            }
+        }
+
+        self.users.insert(user.to_string());
+        true
+
+    }
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn load() {
+
+        let path = std::env::temp_dir().join(format!("tpp-bot-seen-{}.txt", std::process::id()));
+        fs::write(&path, "alice\n\nbob\n").unwrap();
+
+        let mut seen = SeenUsers::open(&path).unwrap();
+        assert!(!seen.insert("alice"));
+        assert!(!seen.insert("bob"));
+        assert!(seen.insert("carol"));
+        assert!(!seen.insert("carol"));
+
+        // A restarted bot knows all the users once.
+        let mut seen = SeenUsers::open(&path).unwrap();
+        assert!(!seen.insert("carol"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "alice\n\nbob\ncarol\n");
+
+        fs::remove_file(&path).unwrap();
+
+    }
+
+    #[test]
+    fn partial_write() {
+
+        let path = std::env::temp_dir().join(format!("tpp-bot-seen-partial-{}.txt", std::process::id()));
+
+        // The user cut by a crash is seen again as new.
+        fs::write(&path, "alice\nbo").unwrap();
+        let mut seen = SeenUsers::open(&path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "alice\n");
+        assert!(seen.insert("bob"));
+
+        // The part of a failed write is replaced by the whole users.
+        seen.file = File::options().append(true).open("/dev/full").unwrap();
+        assert!(seen.insert("carol"));
+        seen.file = File::options().append(true).open(&path).unwrap();
+        seen.file.write_all(b"car").unwrap();
+        assert!(seen.insert("dave"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "alice\nbob\ncarol\ndave\n");
+
+        fs::remove_file(&path).unwrap();
+
+    }
+
+}
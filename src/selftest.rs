@@ -0,0 +1,109 @@
+use std::io::{self, Write};
+
+use crate::curve::IntervalCurve;
+use crate::import::{self, ImportFormat, TextZone};
+use crate::logformat::RecordFormat;
+use crate::rng;
+use crate::shadow::Strategy;
+use crate::stats::Stats;
+use crate::vocabulary::Vocabulary;
+
+
+/// A recorded chat with the expected output of the pipeline.
+struct Golden {
+    name: &'static str,
+    log: &'static str,
+    expected: &'static str,
+}
+
+/// Golden replays shipped with the binary, from `tests/golden`.
+const GOLDENS: &[Golden] = &[
+    Golden {
+        name: "consensus",
+        log: include_str!("../tests/golden/consensus.irc"),
+        expected: include_str!("../tests/golden/consensus.expected"),
+    },
+    Golden {
+        name: "chatter",
+        log: include_str!("../tests/golden/chatter.irc"),
+        expected: include_str!("../tests/golden/chatter.expected"),
+    },
+];
+
+
+/// Run a chat log in the IRC format through the pipeline with the default
+/// vocabulary and the live strategy, and return the log records, each
+/// followed by the decision taken on the same stats.
+pub fn run(log: &str) -> io::Result<String> {
+
+    let events = import::read_events(ImportFormat::Irc, log.as_bytes(), None, TextZone::Utc)?;
+    let vocabulary = Vocabulary::default();
+    let strategy = Strategy::live(IntervalCurve::default());
+    // The live strategy doesn't draw any random choice.
+    let mut rng = rng::derive(0, "selftest");
+
+    let mut output = Vec::new();
+    import::simulate(&events, &vocabulary, Stats::new(), |stats, time| {
+        stats.write_log(&mut output, time, "-", RecordFormat::Tsv)?;
+        match strategy.decide(stats, &mut rng) {
+            Some((command, interval)) => writeln!(output, "decide\t{}\t{}\t{}", time.timestamp(), command.name(), interval.as_secs_f32()),
+            None => writeln!(output, "decide\t{}\t-", time.timestamp()),
+        }
+    })?;
+
+    Ok(String::from_utf8(output).unwrap())
+
+}
+
+/// Entry point of the `self-test` subcommand, returning true if all golden
+/// replays produce their expected output. The output of a golden replay is
+/// printed instead if its name is given, to update its expectations.
+pub fn main(print: Option<&str>) -> io::Result<bool> {
+
+    if let Some(name) = print {
+        let golden = GOLDENS.iter().find(|golden| golden.name == name)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no golden replay '{name}'")))?;
+        print!("{}", run(golden.log)?);
+        return Ok(true);
+    }
+
+    let mut passed = true;
+
+    for golden in GOLDENS {
+        let output = run(golden.log)?;
+        let mismatch = output.lines().zip(golden.expected.lines())
+            .enumerate()
+            .find(|(_, (actual, expected))| actual != expected);
+        match mismatch {
+            Some((index, (actual, expected))) => {
+                passed = false;
+                println!("{}: FAILED at line {}", golden.name, index + 1);
+                println!("  expected: {expected}");
+                println!("  actual:   {actual}");
+            }
+            None if output.lines().count() != golden.expected.lines().count() => {
+                passed = false;
+                println!("{}: FAILED, {} lines instead of {}", golden.name, output.lines().count(), golden.expected.lines().count());
+            }
+            None => println!("{}: ok", golden.name),
+        }
+    }
+
+    Ok(passed)
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn goldens() {
+        for golden in GOLDENS {
+            assert_eq!(run(golden.log).unwrap(), golden.expected, "golden replay '{}'", golden.name);
+        }
+    }
+
+}
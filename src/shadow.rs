@@ -0,0 +1,309 @@
+use std::time::{Duration, Instant};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::fs::{self, File};
+use std::io::{self, Write};
+
+use chrono::{DateTime, Utc};
+use rand::distributions::WeightedIndex;
+use rand::prelude::*;
+use rand::rngs::StdRng;
+use serde::Deserialize;
+
+use crate::curve::IntervalCurve;
+use crate::stats::Stats;
+use crate::vocabulary::Command;
+
+
+/// Name under which the decisions of the live strategy are logged.
+pub const LIVE_NAME: &str = "live";
+/// Minimum ratio of commands over messages of the live strategy.
+pub const LIVE_MIN_RATIO: f32 = 0.60;
+/// Minimum number of commands per second of the live strategy.
+pub const LIVE_MIN_CMD_SEC: f32 = 2.0;
+
+
+/// How a strategy chooses the command to send.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Choice {
+    /// The most used command, like the live bot.
+    #[default]
+    Majority,
+    /// A command drawn at random with the probability of its share of the
+    /// weighted votes.
+    WeightedRandom,
+}
+
+
+/// A strategy deciding when to send a command, and which one.
+#[derive(Debug, Clone)]
+pub struct Strategy {
+    pub name: String,
+    pub choice: Choice,
+    /// Minimum ratio of commands over messages.
+    pub min_ratio: f32,
+    /// Minimum number of commands per second.
+    pub min_cmd_sec: f32,
+    pub interval_curve: IntervalCurve,
+}
+
+impl Strategy {
+
+    /// The strategy of the live bot with the given interval curve.
+    pub fn live(interval_curve: IntervalCurve) -> Self {
+        Self {
+            name: LIVE_NAME.to_string(),
+            choice: Choice::Majority,
+            min_ratio: LIVE_MIN_RATIO,
+            min_cmd_sec: LIVE_MIN_CMD_SEC,
+            interval_curve,
+        }
+    }
+
+    /// Decide the command to send now from the stats, with the interval
+    /// before the next decision, the generator drawing the random choices.
+    pub fn decide(&self, stats: &Stats, rng: &mut impl Rng) -> Option<(Command, Duration)> {
+
+        let tpp = stats.tpp();
+        let cmd_sec = tpp.tpp_command_sec(stats.tpp_duration());
+
+        if !stats.is_full() || tpp.tpp_command_ratio() < self.min_ratio || cmd_sec < self.min_cmd_sec {
+            return None;
+        }
+
+        let command = match self.choice {
+            Choice::Majority => stats.most_used(),
+            Choice::WeightedRandom => {
+                let votes = tpp.counts.iter()
+                    .map(|(command, count)| (command, count as f32 * stats.weights()[command]))
+                    .collect::<Vec<_>>();
+                let index = WeightedIndex::new(votes.iter().map(|&(_, weight)| weight)).ok()?;
+                votes[index.sample(rng)].0
+            }
+        };
+
+        stats.is_eligible(command)
+            .then(|| (command, Duration::from_secs_f32(self.interval_curve.interval(cmd_sec))))
+
+    }
+
+}
+
+
+#[derive(Debug, Deserialize)]
+struct ShadowFile {
+    /// Path of the TSV log of all decisions.
+    log_path: PathBuf,
+    #[serde(default)]
+    strategy: Vec<StrategyEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StrategyEntry {
+    name: String,
+    #[serde(default)]
+    choice: Choice,
+    #[serde(default = "default_min_ratio")]
+    min_ratio: f32,
+    #[serde(default = "default_min_cmd_sec")]
+    min_cmd_sec: f32,
+    interval_curve: Option<PathBuf>,
+}
+
+fn default_min_ratio() -> f32 { LIVE_MIN_RATIO }
+fn default_min_cmd_sec() -> f32 { LIVE_MIN_CMD_SEC }
+
+
+/// Configuration of the shadow strategies.
+#[derive(Debug, Clone)]
+pub struct ShadowConfig {
+    pub log_path: PathBuf,
+    pub strategies: Vec<Strategy>,
+}
+
+impl ShadowConfig {
+
+    /// Load the shadow strategies from a TOML file, like:
+    ///
+    /// ```toml
+    /// log_path = "shadow.tsv"
+    ///
+    /// [[strategy]]
+    /// name = "eager"
+    /// choice = "weighted_random"
+    /// min_ratio = 0.5
+    /// min_cmd_sec = 1.0
+    /// interval_curve = "eager_curve.txt"
+    /// ```
+    pub fn load(path: &Path) -> Result<Self, String> {
+
+        let content = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let file = toml::from_str::<ShadowFile>(&content).map_err(|e| e.to_string())?;
+
+        let mut strategies = Vec::new();
+        for entry in file.strategy {
+
+            if entry.name == LIVE_NAME || strategies.iter().any(|s: &Strategy| s.name == entry.name) {
+                return Err(format!("duplicate strategy name '{}'", entry.name));
+            }
+
+            let interval_curve = match &entry.interval_curve {
+                Some(path) => IntervalCurve::load(path)
+                    .map_err(|e| format!("invalid interval curve of '{}': {e}", entry.name))?,
+                None => IntervalCurve::default(),
+            };
+
+            strategies.push(Strategy {
+                name: entry.name,
+                choice: entry.choice,
+                min_ratio: entry.min_ratio,
+                min_cmd_sec: entry.min_cmd_sec,
+                interval_curve,
+            });
+
+        }
+
+        Ok(Self { log_path: file.log_path, strategies })
+
+    }
+
+}
+
+
+/// State of a strategy run in shadow, or of the live strategy.
+#[derive(Debug)]
+struct ShadowState {
+    name: String,
+    /// The strategy, none for the live one that is only observed.
+    strategy: Option<Strategy>,
+    next_time: Instant,
+    decisions: u32,
+    hits: u32,
+}
+
+/// Run strategies in shadow of the live one: they decide on the same live
+/// stats but never send. A decision is a hit if the command is still the
+/// most used one once a full TPP window has passed, meaning the chat went
+/// on with it. All decisions and the hit rate of each strategy are logged.
+#[derive(Debug)]
+pub struct Shadows {
+    states: Vec<ShadowState>,
+    /// Decisions waiting to be evaluated: time, state index and command.
+    pending: VecDeque<(Instant, usize, Command)>,
+    log_file: File,
+    /// Generator of the random choices of the strategies.
+    rng: StdRng,
+}
+
+impl Shadows {
+
+    pub fn new(config: &ShadowConfig, rng: StdRng) -> io::Result<Self> {
+
+        let now = Instant::now();
+        let mut states = vec![ShadowState {
+            name: LIVE_NAME.to_string(),
+            strategy: None,
+            next_time: now,
+            decisions: 0,
+            hits: 0,
+        }];
+
+        states.extend(config.strategies.iter().map(|strategy| ShadowState {
+            name: strategy.name.clone(),
+            strategy: Some(strategy.clone()),
+            next_time: now,
+            decisions: 0,
+            hits: 0,
+        }));
+
+        Ok(Self {
+            states,
+            pending: VecDeque::new(),
+            log_file: File::options().append(true).create(true).open(&config.log_path)?,
+            rng,
+        })
+
+    }
+
+    /// Record a command actually sent by the live strategy.
+    pub fn record_live(&mut self, now: Instant, command: Command) {
+        self.pending.push_back((now, 0, command));
+    }
+
+    /// Make the decisions of the shadow strategies that are due, and
+    /// evaluate and log past decisions, the minimum interval is the one
+    /// required by the rate limit.
+    pub fn update(&mut self, now: Instant, time: DateTime<Utc>, stats: &Stats, min_interval: Duration) -> io::Result<()> {
+
+        for (index, state) in self.states.iter_mut().enumerate() {
+            let Some(strategy) = &state.strategy else { continue };
+            if now < state.next_time {
+                continue;
+            }
+            if let Some((command, interval)) = strategy.decide(stats, &mut self.rng) {
+                self.pending.push_back((now, index, command));
+                state.next_time = now + interval.max(min_interval);
+            }
+        }
+
+        while let Some(&(decision_time, index, command)) = self.pending.front() {
+
+            if now.saturating_duration_since(decision_time) < stats.tpp_duration() {
+                break;
+            }
+
+            self.pending.pop_front();
+
+            let state = &mut self.states[index];
+            let hit = stats.most_used() == command;
+            state.decisions += 1;
+            state.hits += hit as u32;
+
+            writeln!(self.log_file, "{}\t{}\t{command:?}\t{}\t{:.3}",
+                time.timestamp(),
+                state.name,
+                hit as u8,
+                state.hits as f32 / state.decisions as f32)?;
+
+        }
+
+        Ok(())
+
+    }
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn choices() {
+
+        let mut stats = Stats::new();
+        while !stats.is_full() {
+            for _ in 0..3 {
+                stats.push_message(Some(Command::A), None, 1);
+            }
+            stats.push_message(Some(Command::B), None, 1);
+            stats.flush();
+        }
+
+        let mut rng = StdRng::seed_from_u64(3);
+        let majority = Strategy::live(IntervalCurve::default());
+        let random = Strategy { choice: Choice::WeightedRandom, ..majority.clone() };
+
+        assert_eq!(majority.decide(&stats, &mut rng).map(|(command, _)| command), Some(Command::A));
+        let draws = (0..400).filter_map(|_| random.decide(&stats, &mut rng)).map(|(command, _)| command).collect::<Vec<_>>();
+        assert_eq!(draws.len(), 400);
+        assert!(draws.iter().all(|&command| command == Command::A || command == Command::B));
+        let b_share = draws.iter().filter(|&&command| command == Command::B).count() as f32 / 400.0;
+        assert!((0.15..0.35).contains(&b_share), "{b_share}");
+
+    }
+
+}
+
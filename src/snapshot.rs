@@ -0,0 +1,75 @@
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use std::fs::{self, File};
+use std::io::{self, Write};
+
+use serde_json::Value;
+
+use crate::config::{parse_seconds, Settings};
+
+
+/// Configuration of the snapshot file.
+#[derive(Debug, Clone)]
+pub struct SnapshotConfig {
+    pub path: PathBuf,
+    /// Minimum interval between two writes of the file.
+    pub interval: Duration,
+}
+
+impl SnapshotConfig {
+
+    /// Read the configuration from the `snapshot_*` settings, none if no
+    /// path is configured.
+    pub fn from_settings(settings: &Settings) -> Result<Option<Self>, String> {
+
+        let Some(path) = settings.get("snapshot_path") else { return Ok(None) };
+
+        Ok(Some(Self {
+            path: PathBuf::from(path),
+            interval: settings.parse_with("snapshot_interval", parse_seconds)?.unwrap_or(Duration::from_secs(5)),
+        }))
+
+    }
+
+}
+
+
+/// Periodically write the current state of the bot to a JSON file, for
+/// static overlays and scripts. The file is replaced atomically, so that
+/// its readers never see a partial write.
+#[derive(Debug)]
+pub struct SnapshotFile {
+    config: SnapshotConfig,
+    /// Time of the next write.
+    next_time: Option<Instant>,
+}
+
+impl SnapshotFile {
+
+    pub fn new(config: SnapshotConfig) -> Self {
+        Self { config, next_time: None }
+    }
+
+    /// Return true if the interval since the last write has passed.
+    pub fn is_due(&self, now: Instant) -> bool {
+        self.next_time.is_none_or(|next_time| now >= next_time)
+    }
+
+    /// Write the snapshot to a temporary file, then rename it over the file.
+    pub fn write(&mut self, now: Instant, snapshot: &Value) -> io::Result<()> {
+
+        self.next_time = Some(now + self.config.interval);
+
+        let mut tmp_path = self.config.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+
+        let mut file = File::create(&tmp_path)?;
+        serde_json::to_writer_pretty(&mut file, snapshot)?;
+        file.write_all(b"\n")?;
+        file.sync_all()?;
+
+        fs::rename(tmp_path, &self.config.path)
+
+    }
+
+}
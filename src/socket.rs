@@ -0,0 +1,230 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::sync::mpsc::{self, Sender};
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use std::thread;
+
+use tracing::{debug, warn};
+
+use crate::config::Settings;
+use crate::console::ConsoleLine;
+
+
+/// Time to wait for the bot to answer a command, it doesn't answer while
+/// reconnecting.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+
+/// Address of the control socket, given by the `control_socket` setting.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ControlAddress {
+    /// Path of a Unix domain socket, only accessible to its owner.
+    #[cfg(unix)]
+    Unix(PathBuf),
+    /// Local TCP address, for platforms or containers without Unix sockets.
+    Tcp(SocketAddr),
+}
+
+impl ControlAddress {
+
+    /// Read the address from the `control_socket` setting, none if not set.
+    /// A TCP address must be a loopback one, the protocol has no
+    /// authentication.
+    pub fn from_settings(settings: &Settings) -> Result<Option<Self>, String> {
+        settings.parse_with("control_socket", |value| match value.parse::<SocketAddr>() {
+            Ok(addr) if addr.ip().is_loopback() => Ok(Self::Tcp(addr)),
+            Ok(_) => Err("expected a loopback address or a socket path"),
+            #[cfg(unix)]
+            Err(_) => Ok(Self::Unix(PathBuf::from(value))),
+            #[cfg(not(unix))]
+            Err(_) => Err("expected a loopback address, Unix sockets are not supported"),
+        })
+    }
+
+}
+
+
+/// Listen on the control socket in a background thread, each line received
+/// on a connection is passed as a console line and answered by a single
+/// line. Commands of the socket are then the ones of the console.
+pub fn listen(address: &ControlAddress, tx: Sender<ConsoleLine>) -> io::Result<()> {
+
+    match address {
+        #[cfg(unix)]
+        ControlAddress::Unix(path) => {
+            let listener = bind_unix(path)?;
+            spawn_accept(move || listener.accept().map(|(stream, _)| stream), tx);
+        }
+        ControlAddress::Tcp(addr) => {
+            let listener = TcpListener::bind(addr)?;
+            spawn_accept(move || listener.accept().map(|(stream, _)| stream), tx);
+        }
+    }
+
+    Ok(())
+
+}
+
+/// Bind the Unix socket at the given path, only accessible to its owner.
+/// The socket is bound in a private directory and moved to its path once
+/// restricted, so that other users can never connect to it. Only a stale
+/// socket left by a previous instance is replaced, any other file is an
+/// error.
+#[cfg(unix)]
+fn bind_unix(path: &Path) -> io::Result<std::os::unix::net::UnixListener> {
+
+    use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::fs;
+
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if !metadata.file_type().is_socket() => {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} exists and is not a socket", path.display())));
+        }
+        Ok(_) if UnixStream::connect(path).is_ok() => {
+            return Err(io::Error::new(io::ErrorKind::AddrInUse, format!("{} is used by another instance", path.display())));
+        }
+        Ok(_) => fs::remove_file(path)?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+
+    let mut dir_name = path.file_name().unwrap_or_default().to_os_string();
+    dir_name.push(format!(".{}.tmp", std::process::id()));
+    let dir = path.with_file_name(dir_name);
+    fs::DirBuilder::new().mode(0o700).create(&dir)?;
+
+    let tmp_path = dir.join("socket");
+    let result = UnixListener::bind(&tmp_path).and_then(|listener| {
+        fs::set_permissions(&tmp_path, fs::Permissions::from_mode(0o600))?;
+        fs::rename(&tmp_path, path)?;
+        Ok(listener)
+    });
+
+    let _ = fs::remove_file(&tmp_path);
+    fs::remove_dir(&dir)?;
+    result
+
+}
+
+/// Accept the connections in a background thread, each connection being
+/// served by its own thread.
+fn spawn_accept<S, F>(mut accept: F, tx: Sender<ConsoleLine>)
+where
+    S: Read + Write + Send + 'static,
+    F: FnMut() -> io::Result<S> + Send + 'static,
+{
+    thread::Builder::new()
+        .name("control-socket".to_string())
+        .spawn(move || loop {
+            match accept() {
+                Ok(stream) => {
+                    let tx = tx.clone();
+                    thread::Builder::new()
+                        .name("control-conn".to_string())
+                        .spawn(move || {
+                            if let Err(e) = serve(stream, tx) {
+                                debug!("control connection closed: {e}");
+                            }
+                        })
+                        .unwrap();
+                }
+                Err(e) => {
+                    warn!("failed to accept a control connection: {e}");
+                    thread::sleep(Duration::from_secs(1));
+                }
+            }
+        })
+        .unwrap();
+}
+
+/// Serve a connection until it is closed, answering each line.
+fn serve<S: Read + Write>(stream: S, tx: Sender<ConsoleLine>) -> io::Result<()> {
+
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+
+    while reader.read_line(&mut line)? != 0 {
+
+        let text = line.trim();
+        if !text.is_empty() {
+            let (reply_tx, reply_rx) = mpsc::channel();
+            let reply = match tx.send(ConsoleLine { text: text.to_string(), reply: Some(reply_tx) }) {
+                Ok(()) => reply_rx.recv_timeout(REPLY_TIMEOUT).unwrap_or_else(|_| "error: the bot is not answering".to_string()),
+                Err(_) => "error: the bot has stopped".to_string(),
+            };
+            let stream = reader.get_mut();
+            writeln!(stream, "{}", reply.replace('\n', " "))?;
+            stream.flush()?;
+        }
+
+        line.clear();
+
+    }
+
+    Ok(())
+
+}
+
+
+#[cfg(all(test, unix))]
+mod tests {
+
+    use std::os::unix::net::UnixStream;
+
+    use super::*;
+
+    #[test]
+    fn line_protocol() {
+
+        let (tx, rx) = mpsc::channel::<ConsoleLine>();
+        let (client, server) = UnixStream::pair().unwrap();
+
+        thread::spawn(move || serve(server, tx));
+        thread::spawn(move || {
+            for line in rx {
+                line.reply.unwrap().send(format!("got {}", line.text)).unwrap();
+            }
+        });
+
+        let mut writer = client.try_clone().unwrap();
+        writer.write_all(b"status\n\n  pause 10m  \n").unwrap();
+
+        let mut lines = BufReader::new(client).lines();
+        assert_eq!(lines.next().unwrap().unwrap(), "got status");
+        assert_eq!(lines.next().unwrap().unwrap(), "got pause 10m");
+
+    }
+
+    #[test]
+    fn bind() {
+
+        use std::os::unix::fs::PermissionsExt;
+        use std::fs;
+
+        let path = std::env::temp_dir().join(format!("tpp-bot-control-{}.sock", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        // Any other file is kept.
+        fs::write(&path, "config").unwrap();
+        assert_eq!(bind_unix(&path).unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "config");
+        fs::remove_file(&path).unwrap();
+
+        let listener = bind_unix(&path).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        assert!(UnixStream::connect(&path).is_ok());
+
+        // A socket in use is kept, a stale one is replaced.
+        assert_eq!(bind_unix(&path).unwrap_err().kind(), io::ErrorKind::AddrInUse);
+        drop(listener);
+        let _listener = bind_unix(&path).unwrap();
+        assert!(UnixStream::connect(&path).is_ok());
+
+        fs::remove_file(&path).unwrap();
+
+    }
+
+}
@@ -0,0 +1,80 @@
+use crate::irc::IrcReplyRef;
+
+
+/// The likely source of a chat message, guessed from its tags, to tell the
+/// engaged players from the passive viewers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageSource {
+    /// Broadcaster, moderators, VIPs and Twitch staff.
+    Privileged,
+    /// Messages only made of emotes, mostly reactions.
+    EmoteOnly,
+    /// Official Twitch clients, web and mobile, that send a client nonce.
+    FirstParty,
+    /// Third-party clients and integrations, without client nonce.
+    ThirdParty,
+}
+
+impl MessageSource {
+
+    /// All the sources, in the order of the log columns.
+    pub const ALL: [MessageSource; 4] = [
+        MessageSource::Privileged, MessageSource::EmoteOnly,
+        MessageSource::FirstParty, MessageSource::ThirdParty,
+    ];
+
+    /// Guess the source of a message from its tags, the first matching
+    /// source is returned in the order of the variants.
+    pub fn classify(reply: &IrcReplyRef) -> Self {
+
+        let privileged_badge = reply.tag("badges").is_some_and(|badges| badges.split(',')
+            .any(|badge| ["broadcaster/", "moderator/", "vip/", "staff/"].iter().any(|prefix| badge.starts_with(prefix))));
+
+        if privileged_badge || reply.tag("user-type").is_some_and(|user_type| !user_type.is_empty()) {
+            Self::Privileged
+        } else if reply.tag("emote-only") == Some("1") {
+            Self::EmoteOnly
+        } else if reply.tag("client-nonce").is_some() {
+            Self::FirstParty
+        } else {
+            Self::ThirdParty
+        }
+
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Privileged => "privileged",
+            Self::EmoteOnly => "emote-only",
+            Self::FirstParty => "first-party",
+            Self::ThirdParty => "third-party",
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+
+}
+
+
+/// Messages and commands of each source.
+#[derive(Debug, Clone, Default)]
+pub struct SourceCounts {
+    counts: [(u32, u32); MessageSource::ALL.len()],
+}
+
+impl SourceCounts {
+
+    pub fn push(&mut self, source: MessageSource, is_command: bool) {
+        let (messages, commands) = &mut self.counts[source.index()];
+        *messages += 1;
+        *commands += is_command as u32;
+    }
+
+    /// Number of messages and commands of a source.
+    pub fn get(&self, source: MessageSource) -> (u32, u32) {
+        self.counts[source.index()]
+    }
+
+}
@@ -0,0 +1,714 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{self, Write};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+
+use crate::config::Settings;
+use crate::funnel::Funnel;
+use crate::hype::Hype;
+use crate::logformat::{log_columns, LogSession, RecordFormat, RecordValue};
+use crate::policy::{CommandPolicy, CommandWeights, TieBreak};
+use crate::sample::Sample;
+use crate::source::{MessageSource, SourceCounts};
+use crate::usernotice::{UserNoticeKind, UserNotices};
+use crate::vocabulary::Command;
+
+
+/// Default duration of a single sample.
+pub const SAMPLE_DURATION: Duration = Duration::from_millis(100);
+/// Number of samples to keep for computing global averages.
+pub const GLOBAL_SAMPLE_COUNT: usize = 100;
+/// Full duration of the global sample.
+pub const GLOBAL_SAMPLE_DURATION: Duration = Duration::from_millis(SAMPLE_DURATION.as_millis() as u64 * GLOBAL_SAMPLE_COUNT as u64);
+/// Duration of the samples used for computing tpp averages,
+/// used to choose which command to send.
+pub const TPP_DURATION: Duration = Duration::from_secs(2);
+/// Minimum duration of the samples for computing tpp averages, when
+/// the window is shortened to compensate the latency.
+pub const MIN_TPP_DURATION: Duration = Duration::from_millis(500);
+
+/// Interval between each log of the global sample.
+pub const LOG_INTERVAL: Duration = Duration::from_secs(1);
+
+
+/// How votes of a single user are deduplicated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VoteDedup {
+    /// Every command counts as a vote.
+    #[default]
+    Off,
+    /// At most one vote per user in each sample.
+    Sample,
+    /// At most one vote per user in the TPP window.
+    Window,
+}
+
+impl VoteDedup {
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "off" => Some(Self::Off),
+            "sample" => Some(Self::Sample),
+            "window" => Some(Self::Window),
+            _ => None,
+        }
+    }
+
+    /// Read the deduplication from the `vote_dedup` setting, off by default.
+    pub fn from_settings(settings: &Settings) -> Result<Self, String> {
+        Ok(settings.parse_with("vote_dedup", |s| Self::parse(s).ok_or("expected off, sample or window"))?.unwrap_or_default())
+    }
+
+}
+
+
+/// The sampling engine, counting chat messages in samples and maintaining
+/// the sliding windows over them. The engine doesn't track time by itself,
+/// the caller is responsible for flushing the active sample each time it
+/// has lasted for the sample duration, [`SAMPLE_DURATION`] by default,
+/// this allows running it over past chat logs as well as live.
+#[derive(Debug)]
+pub struct Stats {
+    /// All samples, the last one being the active one.
+    samples: VecDeque<Sample>,
+    /// Durations of the closed samples, aligned with the samples.
+    durations: VecDeque<Duration>,
+    /// Total duration of the closed samples.
+    elapsed: Duration,
+    /// Current duration of a sample.
+    sample_duration: Duration,
+    /// Used to average all samples.
+    global: Sample,
+    /// Used to average all samples and choose most used TPP command.
+    tpp: Sample,
+    /// Number of samples in the TPP window.
+    tpp_count: usize,
+    /// Delay compensated by shortening the TPP window.
+    delay: Duration,
+    /// How votes are deduplicated per user.
+    vote_dedup: VoteDedup,
+    /// How the most used command is chosen.
+    policy: CommandPolicy,
+    /// Random ranks of the commands, drawn again for each sample, to break
+    /// the ties randomly.
+    tie_ranks: [u32; Command::ALL.len()],
+    rng: StdRng,
+    /// Sequence number of the last counted vote of each command, to break
+    /// the ties by recency.
+    last_votes: [u64; Command::ALL.len()],
+    vote_sequence: u64,
+    /// Votes of each command decayed by their age, if they have a
+    /// half-life.
+    decayed: [f32; Command::ALL.len()],
+    /// Users that voted in each sample, aligned with the samples, only
+    /// tracked when votes are deduplicated.
+    voters: VecDeque<HashSet<String>>,
+    /// True if the vote of the last message was counted, and so the other
+    /// buttons of its combo.
+    vote_counted: bool,
+    /// Time elapsed since the last log.
+    log_elapsed: Duration,
+    /// All chatters since the last log, with true if they are new to
+    /// the channel and true if they have voted.
+    chatters: HashMap<String, (bool, bool)>,
+    /// Messages of each source since the last log.
+    sources: SourceCounts,
+    /// Messages at each stage of the vote pipeline since the last log.
+    funnel: Funnel,
+    /// Excitement of the chatter messages since the last log.
+    hype: Hype,
+    /// Subscriptions, raids and announcements since the last log.
+    notices: UserNotices,
+    /// Moves executed by the game since the last log, and how many of them
+    /// were the last command sent by the bot.
+    moves: (u32, u32),
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stats {
+
+    pub fn new() -> Self {
+        let mut samples = VecDeque::with_capacity(GLOBAL_SAMPLE_COUNT + 1);
+        samples.push_back(Sample::default());
+        Self {
+            samples,
+            durations: VecDeque::with_capacity(GLOBAL_SAMPLE_COUNT + 1),
+            elapsed: Duration::ZERO,
+            sample_duration: SAMPLE_DURATION,
+            global: Sample::default(),
+            tpp: Sample::default(),
+            tpp_count: tpp_count(SAMPLE_DURATION, Duration::ZERO),
+            delay: Duration::ZERO,
+            vote_dedup: VoteDedup::Off,
+            policy: CommandPolicy::default(),
+            tie_ranks: [0; Command::ALL.len()],
+            rng: StdRng::seed_from_u64(0),
+            last_votes: [0; Command::ALL.len()],
+            vote_sequence: 0,
+            decayed: [0.0; Command::ALL.len()],
+            voters: VecDeque::from([HashSet::new()]),
+            vote_counted: false,
+            log_elapsed: Duration::ZERO,
+            chatters: HashMap::new(),
+            sources: SourceCounts::default(),
+            funnel: Funnel::default(),
+            hype: Hype::default(),
+            notices: UserNotices::default(),
+            moves: (0, 0),
+        }
+    }
+
+    /// Deduplicate the votes of each user, only the first vote of a user
+    /// in a sample or in the TPP window is counted.
+    pub fn with_vote_dedup(mut self, vote_dedup: VoteDedup) -> Self {
+        self.vote_dedup = vote_dedup;
+        self
+    }
+
+    /// Choose the most used command with the given policy, the random
+    /// generator breaking the ties if random.
+    pub fn with_command_policy(mut self, policy: CommandPolicy, rng: StdRng) -> Self {
+        self.set_command_policy(policy);
+        self.rng = rng;
+        self
+    }
+
+    pub fn set_command_policy(&mut self, policy: CommandPolicy) {
+        self.policy = policy;
+    }
+
+    /// Return true when enough samples have been flushed to fill the
+    /// global window.
+    pub fn is_full(&self) -> bool {
+        self.elapsed >= GLOBAL_SAMPLE_DURATION
+    }
+
+    /// The global window, lasting [`GLOBAL_SAMPLE_DURATION`].
+    pub fn global(&self) -> &Sample {
+        &self.global
+    }
+
+    /// The TPP window, lasting [`Self::tpp_duration`].
+    pub fn tpp(&self) -> &Sample {
+        &self.tpp
+    }
+
+    /// The command with the most weighted votes in the TPP window, or the
+    /// most decayed votes if they have a half-life, the ties being broken by
+    /// the policy.
+    pub fn most_used(&self) -> Command {
+
+        let score = |command: Command| match self.policy.half_life {
+            Some(_) => self.decayed[command.index()] * self.policy.weights[command],
+            None => (self.tpp.count(command) as f32 * self.policy.weights[command]).trunc(),
+        };
+
+        let tie_rank = |command: Command| match self.policy.tie_break {
+            TieBreak::Order => command.index() as u64,
+            TieBreak::Random => self.tie_ranks[command.index()] as u64,
+            TieBreak::Recent => self.last_votes[command.index()],
+        };
+
+        Command::ALL.into_iter()
+            .max_by(|&a, &b| score(a).total_cmp(&score(b)).then(tie_rank(a).cmp(&tie_rank(b))))
+            .unwrap()
+
+    }
+
+    /// Weights of the votes when choosing the most used command.
+    pub fn weights(&self) -> &CommandWeights {
+        &self.policy.weights
+    }
+
+    /// Return true if the command has enough votes in the TPP window to be
+    /// sent.
+    pub fn is_eligible(&self, command: Command) -> bool {
+        self.tpp.count(command) >= self.policy.min_votes as u32
+    }
+
+    /// The sample closed by the last flush.
+    pub fn last_sample(&self) -> Option<&Sample> {
+        self.samples.len().checked_sub(2).and_then(|index| self.samples.get(index))
+    }
+
+    /// Full duration of the TPP window.
+    pub fn tpp_duration(&self) -> Duration {
+        self.sample_duration * self.tpp_count as u32
+    }
+
+    /// Current duration of a sample.
+    pub fn sample_duration(&self) -> Duration {
+        self.sample_duration
+    }
+
+    /// Change the duration of the samples, from the active one. This should
+    /// be called right after a flush. The global window keeps its duration
+    /// while the TPP window is rebuilt from the most recent samples.
+    pub fn set_sample_duration(&mut self, sample_duration: Duration) {
+        self.sample_duration = sample_duration;
+        self.update_tpp();
+    }
+
+    /// Shorten the TPP window by the given delay, so that the decision is
+    /// made on the most recent votes when the chat commands are acted on
+    /// with some delay.
+    pub fn compensate(&mut self, delay: Duration) {
+        self.delay = delay;
+        self.update_tpp();
+    }
+
+    /// Rebuild the TPP window if its number of samples changed.
+    fn update_tpp(&mut self) {
+
+        let tpp_count = tpp_count(self.sample_duration, self.delay);
+
+        if tpp_count != self.tpp_count {
+            self.tpp_count = tpp_count;
+            self.tpp = Sample::default();
+            // Skipping the active sample.
+            for sample in self.samples.iter().rev().skip(1).take(tpp_count) {
+                self.tpp += sample;
+            }
+        }
+
+    }
+
+    /// Count a chat message in the active sample, with its TPP command if
+    /// any, its sender and true if this sender is new to the channel, and
+    /// the weight of its vote. Returns true if the message is a TPP command,
+    /// even if its vote is deduplicated.
+    pub fn push_message(&mut self, command: Option<Command>, chatter: Option<(&str, bool)>, weight: u16) -> bool {
+
+        let sample = self.samples.back_mut().unwrap();
+
+        let duplicate = match (self.vote_dedup, chatter) {
+            (VoteDedup::Off, _) | (_, None) => false,
+            (VoteDedup::Sample, Some((user, _))) => self.voters.back().unwrap().contains(user),
+            (VoteDedup::Window, Some((user, _))) => self.voters.iter().rev()
+                .take(self.tpp_count + 1)
+                .any(|voters| voters.contains(user)),
+        };
+
+        // Only count the message, not the vote, of duplicates.
+        sample.push_weighted(if duplicate { None } else { command }, weight);
+        self.vote_counted = command.is_some() && !duplicate;
+        if let Some(command) = command.filter(|_| self.vote_counted) {
+            self.push_recent(command);
+        }
+        self.funnel.push(command, !duplicate, &self.policy.weights);
+        let is_tpp_command = command.is_some();
+
+        if let Some((user, new)) = chatter {
+            if is_tpp_command && !duplicate && self.vote_dedup != VoteDedup::Off {
+                self.voters.back_mut().unwrap().insert(user.to_string());
+            }
+            let (_, voted) = self.chatters.entry(user.to_string())
+                .or_insert((new, false));
+            *voted |= is_tpp_command;
+        }
+
+        is_tpp_command
+
+    }
+
+    /// Count the other buttons of the combo of the last message, unless its
+    /// vote is deduplicated.
+    pub fn push_combo(&mut self, commands: &[Command], weight: u16) {
+        if self.vote_counted {
+            for &command in commands {
+                self.samples.back_mut().unwrap().push_vote(command, weight);
+                self.push_recent(command);
+            }
+        }
+    }
+
+    fn push_recent(&mut self, command: Command) {
+        self.vote_sequence += 1;
+        self.last_votes[command.index()] = self.vote_sequence;
+    }
+
+    /// Count a message that isn't a command of the vocabulary but looks like
+    /// one, reported in the log only.
+    pub fn push_candidate(&mut self) {
+        self.funnel.candidates += 1;
+    }
+
+    /// Count the alias a command was typed with in the active sample, to
+    /// attribute the votes of each command to its aliases.
+    pub fn push_alias(&mut self, alias: &str) {
+        self.samples.back_mut().unwrap().push_alias(alias);
+    }
+
+    /// Count a message that isn't a command in the hype proxy, with its
+    /// number of emotes, reported in the log only.
+    pub fn push_hype(&mut self, text: &str, emotes: u32) {
+        self.hype.push(text, emotes);
+    }
+
+    /// Count a user notice, with the viewers of a raid, reported in the
+    /// log only.
+    pub fn push_notice(&mut self, kind: UserNoticeKind, viewers: u32) {
+        self.notices.push(kind, viewers);
+    }
+
+    /// Count a chatter message of a raid, it isn't counted in the samples
+    /// so that the spam of the raiders doesn't lower the command ratio.
+    pub fn push_raid_message(&mut self) {
+        self.notices.raid_messages += 1;
+    }
+
+    /// Count a move executed by the game, matching the last command sent
+    /// by the bot or not.
+    pub fn push_move(&mut self, matched: bool) {
+        self.moves.0 += 1;
+        self.moves.1 += matched as u32;
+    }
+
+    /// Count the likely source of a message, reported in the log only.
+    pub fn push_source(&mut self, source: MessageSource, is_command: bool) {
+        self.sources.push(source, is_command);
+    }
+
+    /// Count bits cheered and channel points redeemed with a message in the
+    /// active sample, the message itself being counted separately.
+    pub fn push_support(&mut self, bits: u32, redemption: bool) {
+        let sample = self.samples.back_mut().unwrap();
+        sample.bits += bits;
+        sample.redemptions += redemption as u32;
+    }
+
+    /// Fill the global window with the given sample, so that a restarted
+    /// bot has the recent rates right away instead of waiting for the window
+    /// to fill. The TPP window is left empty, the votes are only taken from
+    /// the live chat. This should be called before any message is counted.
+    pub fn warm_start(&mut self, global: &Sample) {
+
+        let count = (GLOBAL_SAMPLE_DURATION.as_millis() / self.sample_duration.as_millis().max(1)).max(1) as usize;
+        let warm_count = count.saturating_sub(self.tpp_count).max(1);
+        for index in 0..count {
+            if index < warm_count {
+                *self.samples.back_mut().unwrap() = global.part(index, warm_count);
+            }
+            self.flush();
+        }
+
+        self.log_elapsed = Duration::ZERO;
+
+    }
+
+    /// Flush the active sample into the windows and start a new one. This
+    /// returns true every [`LOG_INTERVAL`], when the log record should be
+    /// written.
+    pub fn flush(&mut self) -> bool {
+
+        let sample = self.samples.back().unwrap();
+        self.global += sample;
+        self.tpp += sample;
+
+        if let Some(half_life) = self.policy.half_life {
+            let decay = 0.5f32.powf(self.sample_duration.as_secs_f32() / half_life.as_secs_f32());
+            for (command, count) in sample.counts.iter() {
+                self.decayed[command.index()] = self.decayed[command.index()] * decay + count as f32;
+            }
+        }
+        self.durations.push_back(self.sample_duration);
+        self.elapsed += self.sample_duration;
+
+        // The global window keeps its duration whatever the sample duration.
+        while self.elapsed > GLOBAL_SAMPLE_DURATION {
+            self.global -= &self.samples.pop_front().unwrap();
+            self.elapsed -= self.durations.pop_front().unwrap();
+            self.voters.pop_front();
+        }
+
+        // Using gt '>' because of the the last sample being the active one.
+        if self.samples.len() > self.tpp_count {
+            self.tpp -= self.samples.get(self.samples.len() - 1 - self.tpp_count).unwrap();
+        }
+
+        // Create a new active sample.
+        self.samples.push_back(Sample::default());
+        self.voters.push_back(HashSet::new());
+
+        if self.policy.tie_break == TieBreak::Random {
+            let mut ranks = [0u32; Command::ALL.len()];
+            ranks.iter_mut().enumerate().for_each(|(index, rank)| *rank = index as u32);
+            ranks.shuffle(&mut self.rng);
+            self.tie_ranks = ranks;
+        }
+
+        self.log_elapsed += self.sample_duration;
+        if self.log_elapsed >= LOG_INTERVAL {
+            self.log_elapsed = Duration::ZERO;
+            true
+        } else {
+            false
+        }
+
+    }
+
+    /// Write the log record of the global window at the given time in the
+    /// given format. This also resets the chatters, sources, funnel, hype,
+    /// notices and moves counted since the last record. The record has no
+    /// session, this is for the offline logs.
+    pub fn write_log(&mut self, out: &mut impl Write, time: DateTime<Utc>, run_name: &str, format: RecordFormat) -> io::Result<()> {
+        let session = LogSession::offline();
+        let record = self.log_record(time, run_name, &session);
+        format.write_record(out, &record)
+    }
+
+    /// The values of the log record of the global window, in the order of
+    /// [`log_columns`]. This also resets the counts since the last record, like [`Self::write_log`].
+    pub fn log_record<'a>(&mut self, time: DateTime<Utc>, run_name: &'a str, session: &'a LogSession) -> Vec<RecordValue<'a>> {
+
+        let global = &self.global;
+        let mut record = Vec::<RecordValue>::with_capacity(log_columns().len());
+
+        let new_voters = self.chatters.values().filter(|&&(new, voted)| new && voted).count();
+        let returning_voters = self.chatters.values().filter(|&&(new, voted)| !new && voted).count();
+        self.chatters.clear();
+
+        record.extend([
+            RecordValue::from(time.timestamp()),
+            global.message_sec(GLOBAL_SAMPLE_DURATION).into(),
+            global.tpp_command_sec(GLOBAL_SAMPLE_DURATION).into(),
+        ]);
+
+        for command in Command::ALL {
+            record.push(global.share(command).into());
+        }
+
+        record.extend([
+            RecordValue::from(new_voters),
+            returning_voters.into(),
+            run_name.into(),
+            global.bits.into(),
+            global.redemptions.into(),
+        ]);
+
+        // Messages and commands of each source since the last record.
+        for source in MessageSource::ALL {
+            let (messages, commands) = self.sources.get(source);
+            record.extend([RecordValue::from(messages), commands.into()]);
+        }
+        self.sources = SourceCounts::default();
+
+        let funnel = std::mem::take(&mut self.funnel);
+        record.extend([
+            RecordValue::from(funnel.received),
+            funnel.candidates.into(),
+            funnel.accepted.into(),
+            funnel.counted.into(),
+            funnel.weighted.into(),
+        ]);
+
+        let hype = std::mem::take(&mut self.hype);
+        record.extend([
+            RecordValue::from(hype.messages),
+            hype.exclamations.into(),
+            hype.caps.into(),
+            hype.emotes.into(),
+        ]);
+
+        let notices = std::mem::take(&mut self.notices);
+        record.extend([
+            RecordValue::from(notices.subs),
+            notices.resubs.into(),
+            notices.gifts.into(),
+            notices.raids.into(),
+            notices.raiders.into(),
+            notices.announcements.into(),
+            notices.raid_messages.into(),
+        ]);
+
+        let (moves, matched_moves) = std::mem::take(&mut self.moves);
+        record.extend([RecordValue::from(moves), matched_moves.into()]);
+
+        record.extend([RecordValue::from(session.id.as_str()), session.connection.into()]);
+
+        record
+
+    }
+}
+
+
+/// Number of samples of the given duration in the TPP window, shortened by
+/// the given delay.
+fn tpp_count(sample_duration: Duration, delay: Duration) -> usize {
+    let sample_millis = sample_duration.as_millis().max(1);
+    let count = (TPP_DURATION.as_millis() / sample_millis).saturating_sub(delay.as_millis() / sample_millis);
+    count.max(MIN_TPP_DURATION.as_millis() / sample_millis).max(1) as usize
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::vocabulary::Vocabulary;
+    use crate::weights::MAX_WEIGHT;
+
+    #[test]
+    fn coarse_samples() {
+
+        let mut stats = Stats::new();
+        for _ in 0..50 {
+            stats.push_message(Some(Command::A), None, 1);
+            stats.flush();
+        }
+        assert!(!stats.is_full());
+
+        // The windows keep their duration with coarser samples.
+        stats.set_sample_duration(Duration::from_millis(250));
+        assert_eq!(stats.tpp_duration(), TPP_DURATION);
+        for _ in 0..20 {
+            stats.push_message(Some(Command::B), None, 1);
+            stats.flush();
+        }
+        assert!(stats.is_full());
+        assert_eq!(stats.global().tpp_command_count, 70);
+        assert_eq!(stats.tpp().count(Command::B), 8);
+
+        stats.flush();
+        assert_eq!(stats.global().tpp_command_count, 67);
+
+    }
+
+    #[test]
+    fn weighted_votes() {
+
+        // The weighted votes may outnumber the messages.
+        let mut stats = Stats::new();
+        stats.push_message(Some(Command::A), Some(("sub", false)), 3);
+        stats.push_message(Some(Command::B), Some(("viewer", false)), 1);
+        stats.push_message(None, None, 3);
+        stats.flush();
+        assert_eq!(stats.global().count(Command::A), 3);
+        assert_eq!((stats.global().message_count, stats.global().tpp_command_count), (3, 4));
+        assert_eq!(stats.global().tpp_command_ratio(), 1.0);
+
+        // The messages are counted once, whatever their weight.
+        let mut stats = Stats::new();
+        stats.push_message(Some(Command::B), None, 3);
+        stats.push_message(None, None, 3);
+        stats.flush();
+        assert_eq!((stats.global().message_count, stats.global().tpp_command_count), (2, 3));
+
+    }
+
+    #[test]
+    fn weighted_votes_at_cap() {
+
+        // A busy chat voting at the highest weight fills the global window
+        // over the range of a u16, the window must not drift when the
+        // samples leave it.
+        let mut stats = Stats::new();
+        for _ in 0..GLOBAL_SAMPLE_COUNT * 3 {
+            for _ in 0..100 {
+                stats.push_message(Some(Command::A), None, MAX_WEIGHT);
+            }
+            stats.flush();
+        }
+        let votes = 100 * MAX_WEIGHT as u32 * GLOBAL_SAMPLE_COUNT as u32;
+        assert!(votes > u16::MAX as u32);
+        assert_eq!(stats.global().count(Command::A), votes);
+        assert_eq!(stats.global().tpp_command_count, votes);
+        assert_eq!(stats.global().message_count, 100 * GLOBAL_SAMPLE_COUNT as u32);
+
+        for _ in 0..GLOBAL_SAMPLE_COUNT {
+            stats.push_message(Some(Command::B), None, 1);
+            stats.flush();
+        }
+        assert_eq!(stats.global().count(Command::A), 0);
+        assert_eq!(stats.global().tpp_command_count, GLOBAL_SAMPLE_COUNT as u32);
+        assert_eq!(stats.global().message_count, GLOBAL_SAMPLE_COUNT as u32);
+        assert_eq!(stats.most_used(), Command::B);
+
+    }
+
+    #[test]
+    fn alias_attribution() {
+
+        let vocabulary = Vocabulary::default();
+        let mut stats = Stats::new();
+        for alias in ["n", "HAUT", "haut", "a"] {
+            stats.push_message(vocabulary.parse(alias), None, 1);
+            stats.push_alias(alias);
+            stats.flush();
+        }
+
+        assert_eq!(stats.tpp().aliases(Command::Up, &vocabulary), [("haut", 2), ("n", 1)]);
+
+        // Aliases leave the window with their sample.
+        for _ in 0..TPP_DURATION.as_millis() / SAMPLE_DURATION.as_millis() {
+            stats.flush();
+        }
+        assert!(stats.tpp().aliases.is_empty());
+
+    }
+
+    #[test]
+    fn tie_breaks() {
+
+        let policy = |tie_break| {
+            let mut policy = CommandPolicy::default();
+            policy.tie_break = tie_break;
+            policy.min_votes = 2;
+            policy
+        };
+        let mut stats = Stats::new().with_command_policy(policy(TieBreak::Recent), StdRng::seed_from_u64(1));
+        for command in [Command::Start, Command::B, Command::Start, Command::B, Command::A] {
+            stats.push_message(Some(command), None, 1);
+        }
+        stats.flush();
+
+        assert_eq!(stats.most_used(), Command::B);
+        stats.set_command_policy(policy(TieBreak::Order));
+        assert_eq!(stats.most_used(), Command::Start);
+        assert!(stats.is_eligible(Command::Start));
+        assert!(!stats.is_eligible(Command::A));
+
+    }
+
+    #[test]
+    fn decayed_votes() {
+
+        let mut policy = CommandPolicy::default();
+        policy.half_life = Some(SAMPLE_DURATION);
+        let mut stats = Stats::new().with_command_policy(policy, StdRng::seed_from_u64(1));
+
+        // Many old votes for A, a few recent ones for B.
+        for command in [Command::A; 6].into_iter().chain([Command::B; 2]) {
+            stats.push_message(Some(command), None, 1);
+            stats.push_message(Some(command), None, 1);
+            stats.flush();
+        }
+        assert_eq!(stats.most_used(), Command::B);
+
+        stats.set_command_policy(CommandPolicy::default());
+        assert_eq!(stats.most_used(), Command::A);
+
+    }
+
+    #[test]
+    fn log_record() {
+        let time = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let session = LogSession { connection: 2, ..LogSession::new(time) };
+        let record = Stats::new().log_record(time, "-", &session);
+        assert_eq!(record.len(), log_columns().len());
+        assert_eq!(record[3 + Command::ALL.len() + 2], RecordValue::Text("-"));
+        let RecordValue::Text(id) = record[record.len() - 2] else { panic!("no session id") };
+        assert!(id.starts_with("1700000000-") && id.len() == 15, "{id}");
+        assert_eq!(record[record.len() - 1], RecordValue::Int(2));
+    }
+
+}
@@ -0,0 +1,205 @@
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::{json, Map, Value};
+
+use crate::config::{parse_seconds, Settings};
+
+
+/// Format of the periodic status.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StatusFormat {
+    /// The interactive prompt line, overwritten in place.
+    #[default]
+    Prompt,
+    /// One `key=value` line per channel, with stable keys.
+    KeyValue,
+    /// One JSON object per channel and line.
+    Json,
+}
+
+impl StatusFormat {
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "prompt" => Some(Self::Prompt),
+            "kv" => Some(Self::KeyValue),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+
+}
+
+
+/// Configuration of the periodic status.
+#[derive(Debug, Clone)]
+pub struct StatusConfig {
+    pub format: StatusFormat,
+    /// Interval between two status lines, the prompt is always updated.
+    pub interval: Duration,
+}
+
+impl StatusConfig {
+
+    /// Read the configuration from the `status_format` and `status_interval`
+    /// settings.
+    pub fn from_settings(settings: &Settings) -> Result<Self, String> {
+        Ok(Self {
+            format: settings.parse_with("status_format", |s| StatusFormat::parse(s).ok_or("expected prompt, kv or json"))?.unwrap_or_default(),
+            interval: settings.parse_with("status_interval", parse_seconds)?.unwrap_or(Duration::from_secs(1)),
+        })
+    }
+
+}
+
+
+/// Live state of a channel, as shown by the status.
+#[derive(Debug, Clone)]
+pub struct ChannelStatus {
+    pub channel: String,
+    /// True when piloting the democracy votes.
+    pub democracy: bool,
+    pub bot: bool,
+    /// The command that would be sent, or the leading vote.
+    pub command: String,
+    /// Time until the next message, or the end of the voting window.
+    pub next: Duration,
+    /// Remaining time of the pause, if paused.
+    pub paused: Option<Duration>,
+    pub cmd_sec: f32,
+    pub ratio: f32,
+    /// Margin of the leading vote, in democracy.
+    pub margin: u32,
+    /// Number of messages sent since the beginning.
+    pub sent: u32,
+    /// Votes of the TPP window, by command output.
+    pub votes: Vec<(String, u32)>,
+}
+
+impl ChannelStatus {
+
+    /// The votes as a JSON object of the count of each command.
+    pub fn votes_json(&self) -> Value {
+        self.votes.iter()
+            .map(|(command, count)| (command.clone(), Value::from(*count)))
+            .collect::<Map<_, _>>()
+            .into()
+    }
+
+    /// The votes as a JSON object of the share of each command.
+    pub fn shares_json(&self) -> Value {
+        let total = self.votes.iter().map(|&(_, count)| count as f32).sum::<f32>();
+        self.votes.iter()
+            .map(|(command, count)| (command.clone(), Value::from(if total > 0.0 { *count as f32 / total } else { 0.0 })))
+            .collect::<Map<_, _>>()
+            .into()
+    }
+
+    /// The status as a JSON object, without the votes.
+    pub fn to_json(&self, time: DateTime<Utc>) -> Value {
+        json!({
+            "time": time.to_rfc3339_opts(SecondsFormat::Millis, true),
+            "channel": self.channel,
+            "mode": if self.democracy { "democracy" } else { "anarchy" },
+            "bot": self.bot,
+            "command": self.command,
+            "next": self.next.as_secs_f32(),
+            "paused": self.paused.unwrap_or_default().as_secs(),
+            "cmd_sec": self.cmd_sec,
+            "ratio": self.ratio,
+            "margin": self.margin,
+            "sent": self.sent,
+        })
+    }
+
+    /// Render the status as a single line in the given machine format.
+    pub fn render(&self, format: StatusFormat, time: DateTime<Utc>) -> String {
+
+        match format {
+            StatusFormat::Json => self.to_json(time).to_string(),
+            StatusFormat::KeyValue | StatusFormat::Prompt => {
+                let time = time.to_rfc3339_opts(SecondsFormat::Millis, true);
+                let mode = if self.democracy { "democracy" } else { "anarchy" };
+                let paused = self.paused.unwrap_or_default().as_secs();
+                format!("time={time} channel={} mode={mode} bot={} command={} next={:.1} paused={paused} cmd_sec={:.1} ratio={:.2} margin={} sent={}",
+                    self.channel, self.bot, quote(&self.command), self.next.as_secs_f32(), self.cmd_sec, self.ratio, self.margin, self.sent)
+            }
+        }
+
+    }
+
+}
+
+
+/// Emitter of the status lines at the configured interval.
+#[derive(Debug)]
+pub struct StatusEmitter {
+    config: StatusConfig,
+    next_time: Option<Instant>,
+}
+
+impl StatusEmitter {
+
+    pub fn new(config: StatusConfig) -> Self {
+        Self { config, next_time: None }
+    }
+
+    /// Print the status lines of all channels if the interval has passed,
+    /// nothing is printed in the prompt format.
+    pub fn emit(&mut self, now: Instant, time: DateTime<Utc>, statuses: &[ChannelStatus]) {
+
+        if self.config.format == StatusFormat::Prompt || self.next_time.is_some_and(|next_time| now < next_time) {
+            return;
+        }
+
+        self.next_time = Some(now + self.config.interval);
+        for status in statuses {
+            println!("{}", status.render(self.config.format, time));
+        }
+
+    }
+
+}
+
+
+/// Quote a value if it can't be read back as a single token.
+fn quote(value: &str) -> String {
+    if value.is_empty() || value.contains(|c: char| c.is_whitespace() || c == '"' || c == '=') {
+        format!("{value:?}")
+    } else {
+        value.to_string()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn key_value() {
+
+        let status = ChannelStatus {
+            channel: "chan".to_string(),
+            democracy: false,
+            bot: true,
+            command: "a b".to_string(),
+            next: Duration::from_millis(1300),
+            paused: None,
+            cmd_sec: 8.46,
+            ratio: 0.812,
+            margin: 0,
+            sent: 7,
+            votes: vec![("a".to_string(), 3), ("b".to_string(), 1)],
+        };
+
+        let time = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        assert_eq!(status.render(StatusFormat::KeyValue, time),
+            r#"time=2023-11-14T22:13:20.000Z channel=chan mode=anarchy bot=true command="a b" next=1.3 paused=0 cmd_sec=8.5 ratio=0.81 margin=0 sent=7"#);
+        assert_eq!(status.shares_json(), json!({"a": 0.75, "b": 0.25}));
+
+    }
+
+}
@@ -0,0 +1,160 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::config::Settings;
+use crate::vocabulary::{Command, Vocabulary};
+
+
+/// Configuration of the touchscreen commands, like `120,80`.
+#[derive(Debug, Clone)]
+pub struct TouchConfig {
+    /// Size of the screen, the coordinates outside aren't commands.
+    pub width: u16,
+    pub height: u16,
+    /// Size of the square buckets the taps are clustered in.
+    pub bucket: u16,
+}
+
+impl TouchConfig {
+
+    /// Read the configuration from the `touch_screen` setting, like
+    /// `256x192`, and the `touch_bucket` one, none if no screen is set.
+    pub fn from_settings(settings: &Settings) -> Result<Option<Self>, String> {
+
+        let Some((width, height)) = settings.parse_with("touch_screen", |text| {
+            let (width, height) = text.split_once('x').ok_or("expected 'WIDTHxHEIGHT'")?;
+            Ok::<_, &str>((width.trim().parse::<u16>().map_err(|_| "invalid width")?, height.trim().parse::<u16>().map_err(|_| "invalid height")?))
+        })? else {
+            return Ok(None);
+        };
+
+        Ok(Some(Self {
+            width,
+            height,
+            bucket: settings.parse::<u16>("touch_bucket")?.unwrap_or(16).max(1),
+        }))
+
+    }
+
+    /// Parse a message as the coordinates of a tap, like `120,80`.
+    pub fn parse(&self, text: &str) -> Option<(u16, u16)> {
+        let (x, y) = text.trim().split_once(',')?;
+        let (x, y) = (x.trim().parse::<u16>().ok()?, y.trim().parse::<u16>().ok()?);
+        (x < self.width && y < self.height).then_some((x, y))
+    }
+
+}
+
+
+/// The taps of the TPP window, clustered in buckets when sending: the most
+/// popular cluster is the bucket with the most taps in it and its
+/// neighbours, so that close taps on both sides of a bucket edge agree.
+#[derive(Debug)]
+pub struct TouchTaps {
+    config: TouchConfig,
+    taps: VecDeque<(Instant, (u16, u16))>,
+}
+
+impl TouchTaps {
+
+    pub fn new(config: TouchConfig) -> Self {
+        Self { config, taps: VecDeque::new() }
+    }
+
+    pub fn config(&self) -> &TouchConfig {
+        &self.config
+    }
+
+    pub fn push(&mut self, now: Instant, point: (u16, u16)) {
+        self.taps.push_back((now, point));
+    }
+
+    /// Forget the taps older than the given window.
+    pub fn expire(&mut self, now: Instant, window: Duration) {
+        while self.taps.front().is_some_and(|&(time, _)| now.saturating_duration_since(time) > window) {
+            self.taps.pop_front();
+        }
+    }
+
+    /// Centroid of the most popular cluster of taps, with its number of
+    /// taps.
+    pub fn cluster(&self) -> Option<((u16, u16), usize)> {
+
+        let bucket_of = |(x, y): (u16, u16)| ((x / self.config.bucket) as i32, (y / self.config.bucket) as i32);
+        let is_near = |a: (i32, i32), b: (i32, i32)| (a.0 - b.0).abs() <= 1 && (a.1 - b.1).abs() <= 1;
+
+        let mut buckets = HashMap::<(i32, i32), usize>::new();
+        for &(_, point) in &self.taps {
+            *buckets.entry(bucket_of(point)).or_default() += 1;
+        }
+
+        // Ties are broken by the bucket with the most taps, then by the
+        // position, so that the choice doesn't depend on the hash order.
+        let (&center, _) = buckets.iter()
+            .max_by_key(|&(&bucket, &count)| {
+                let near = buckets.iter().filter(|&(&other, _)| is_near(bucket, other)).map(|(_, &count)| count).sum::<usize>();
+                (near, count, std::cmp::Reverse(bucket))
+            })?;
+
+        let taps = self.taps.iter()
+            .map(|&(_, point)| point)
+            .filter(|&point| is_near(bucket_of(point), center))
+            .collect::<Vec<_>>();
+
+        let count = taps.len();
+        let sum = taps.iter().fold((0u32, 0u32), |(sum_x, sum_y), &(x, y)| (sum_x + x as u32, sum_y + y as u32));
+        let centroid = ((sum.0 as f32 / count as f32).round() as u16, (sum.1 as f32 / count as f32).round() as u16);
+
+        Some((centroid, count))
+
+    }
+
+}
+
+
+/// The text sent for a command, the centroid of the most popular cluster
+/// for the touches, none if there is no tap to send.
+pub fn output<'a>(vocabulary: &'a Vocabulary, touches: Option<&TouchTaps>, command: Command) -> Option<Cow<'a, str>> {
+    match command {
+        Command::Touch => {
+            let ((x, y), _) = touches?.cluster()?;
+            Some(Cow::Owned(format!("{x},{y}")))
+        }
+        command => Some(Cow::Borrowed(vocabulary.output(command))),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn clusters() {
+
+        let config = TouchConfig { width: 256, height: 192, bucket: 16 };
+        assert_eq!(config.parse("120,80"), Some((120, 80)));
+        assert_eq!(config.parse(" 12 , 8 "), Some((12, 8)));
+        assert_eq!(config.parse("300,80"), None);
+        assert_eq!(config.parse("a,b"), None);
+        assert_eq!(config.parse("120"), None);
+
+        let now = Instant::now();
+        let mut touches = TouchTaps::new(config);
+        assert_eq!(touches.cluster(), None);
+
+        // Close taps across a bucket edge, and a larger single bucket far away.
+        for point in [(15, 100), (17, 102), (16, 98), (14, 101), (200, 20), (201, 21), (202, 22)] {
+            touches.push(now, point);
+        }
+        assert_eq!(touches.cluster(), Some(((16, 100), 4)));
+
+        touches.push(now + Duration::from_secs(10), (200, 20));
+        touches.expire(now + Duration::from_secs(15), Duration::from_secs(10));
+        assert_eq!(touches.cluster(), Some(((200, 20), 1)));
+
+    }
+
+}
@@ -0,0 +1,144 @@
+use crate::vocabulary::{Command, Vocabulary};
+
+
+/// First words of the announcements of a vote tally, like `Votes: a 12, up 5`.
+const TALLY_KEYWORDS: [&str; 3] = ["votes", "tally", "vote tally"];
+/// First words of the announcements of a move, like `Executed: up`.
+const MOVE_KEYWORDS: [&str; 4] = ["executed", "winner", "input", "move"];
+/// First words of the announcements of a new run, like `New run: Red`.
+const RUN_START_KEYWORDS: [&str; 3] = ["new run", "run start", "run started"];
+/// First words of the announcements of the end of a run, like `Run complete!`.
+const RUN_END_KEYWORDS: [&str; 3] = ["run end", "run ended", "run complete"];
+
+
+/// Input mode of the stream, switched by the votes of the chat.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputMode {
+    /// Every command is executed.
+    Anarchy,
+    /// The commands are voted in windows, only the winner is executed.
+    Democracy,
+}
+
+impl InputMode {
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "anarchy" => Some(Self::Anarchy),
+            "democracy" => Some(Self::Democracy),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Anarchy => "anarchy",
+            Self::Democracy => "democracy",
+        }
+    }
+
+}
+
+
+/// An announcement of the stream's own bot, identified by its nickname.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Announcement {
+    /// The stream switched to the given input mode.
+    Mode(InputMode),
+    /// Votes of the current democracy window, by command.
+    Tally(Vec<(Command, u16)>),
+    /// Command executed by the game.
+    Move(Command),
+    /// A new run started, with its name.
+    RunStart(String),
+    /// The current run ended.
+    RunEnd,
+}
+
+impl Announcement {
+
+    /// Parse an announcement from a message of the stream's bot, the
+    /// commands are read with the vocabulary of the chat. Tallies and moves
+    /// start with a keyword, otherwise a message naming a single mode
+    /// announces a switch to it, messages naming both, like the tallies of
+    /// the mode vote, don't. Runs start with their name after a colon.
+    pub fn parse(text: &str, vocabulary: &Vocabulary) -> Option<Self> {
+
+        // The name of a run keeps its case.
+        let original = text.trim();
+        let text = original.to_lowercase();
+
+        let head = text.split(':').next().unwrap_or("").trim().trim_end_matches(['!', '.']);
+        if RUN_START_KEYWORDS.contains(&head) {
+            let name = original.split_once(':')?.1.trim();
+            return (!name.is_empty()).then(|| Self::RunStart(name.to_string()));
+        }
+        if RUN_END_KEYWORDS.contains(&head) {
+            return Some(Self::RunEnd);
+        }
+
+        // The keyword is followed by a colon or by a space.
+        let (keyword, rest) = text.split_once(':')
+            .or_else(|| text.split_once(' '))
+            .map(|(keyword, rest)| (keyword.trim(), rest.trim()))
+            .unwrap_or((&text, ""));
+
+        if TALLY_KEYWORDS.contains(&keyword) {
+            let tally = rest.split(',')
+                .filter_map(|item| {
+                    let (command, count) = item.trim().rsplit_once(' ')?;
+                    Some((vocabulary.parse(command.trim().trim_end_matches(':'))?, count.trim_end_matches('%').parse().ok()?))
+                })
+                .collect::<Vec<_>>();
+            return (!tally.is_empty()).then_some(Self::Tally(tally));
+        }
+
+        if MOVE_KEYWORDS.contains(&keyword) {
+            return vocabulary.parse(rest).map(Self::Move);
+        }
+
+        let anarchy = text.contains("anarchy");
+        let democracy = text.contains("democracy");
+
+        match (anarchy, democracy) {
+            (true, false) => Some(Self::Mode(InputMode::Anarchy)),
+            (false, true) => Some(Self::Mode(InputMode::Democracy)),
+            _ => None,
+        }
+
+    }
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn announcements() {
+
+        let vocabulary = Vocabulary::default();
+        let parse = |text| Announcement::parse(text, &vocabulary);
+
+        assert_eq!(parse("Democracy mode activated! Votes last 20 seconds."), Some(Announcement::Mode(InputMode::Democracy)));
+        assert_eq!(parse("The chat switched back to ANARCHY"), Some(Announcement::Mode(InputMode::Anarchy)));
+        assert_eq!(parse("Mode vote: anarchy 55%, democracy 45%"), None);
+        assert_eq!(parse("Welcome to the stream!"), None);
+
+        assert_eq!(parse("Votes: a 12, n 5, dance 3"), Some(Announcement::Tally(vec![(Command::A, 12), (Command::Up, 5)])));
+        assert_eq!(parse("Executed: HAUT"), Some(Announcement::Move(Command::Up)));
+        assert_eq!(parse("winner b"), Some(Announcement::Move(Command::B)));
+        assert_eq!(parse("Executed: dance"), None);
+
+        assert_eq!(parse("New run: Pokémon Red"), Some(Announcement::RunStart("Pokémon Red".to_string())));
+        assert_eq!(parse("Run started: Anarchy Crystal"), Some(Announcement::RunStart("Anarchy Crystal".to_string())));
+        assert_eq!(parse("New run:"), None);
+        assert_eq!(parse("Run complete!"), Some(Announcement::RunEnd));
+        assert_eq!(parse("Run ended: 3d 4h 12m"), Some(Announcement::RunEnd));
+        assert_eq!(parse("Run in progress"), None);
+
+    }
+
+}
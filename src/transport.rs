@@ -0,0 +1,229 @@
+use std::net::{TcpStream, SocketAddr};
+use std::io::{self, Read, Write};
+use std::time::Duration;
+use std::sync::Arc;
+
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+use tungstenite::{Message, WebSocket};
+
+
+/// A transport used by the IRC client to exchange raw bytes with the
+/// server. When non-blocking, reads and writes that would block must
+/// return a [`io::ErrorKind::WouldBlock`] error.
+pub trait IrcTransport: Read + Write {
+
+    /// Switch the transport between blocking and non-blocking mode.
+    fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()>;
+
+}
+
+impl<T: IrcTransport + ?Sized> IrcTransport for Box<T> {
+    fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()> {
+        (**self).set_nonblocking(nonblocking)
+    }
+}
+
+/// Plain TCP transport.
+impl IrcTransport for TcpStream {
+    fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()> {
+        TcpStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+/// TLS transport.
+pub type TlsStream = StreamOwned<ClientConnection, TcpStream>;
+
+impl IrcTransport for TlsStream {
+    fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()> {
+        self.sock.set_nonblocking(nonblocking)
+    }
+}
+
+
+/// Open a blocking TCP stream, used while doing handshakes.
+pub fn connect_tcp(addr: &SocketAddr) -> io::Result<TcpStream> {
+    let stream = TcpStream::connect_timeout(addr, Duration::from_secs(2))?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    stream.set_write_timeout(Some(Duration::from_secs(5)))?;
+    Ok(stream)
+}
+
+/// Do the TLS handshake on the given blocking stream, the server name is
+/// used to verify the server certificate.
+pub fn connect_tls(mut stream: TcpStream, server_name: &str) -> io::Result<TlsStream> {
+
+    let roots = RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+
+    let config = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    let server_name = ServerName::try_from(server_name.to_string())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    let mut conn = ClientConnection::new(Arc::new(config), server_name)
+        .map_err(io::Error::other)?;
+
+    while conn.is_handshaking() {
+        conn.complete_io(&mut stream)?;
+    }
+
+    Ok(StreamOwned::new(conn, stream))
+
+}
+
+
+/// A WebSocket transport, over another blocking transport, where each IRC
+/// line is sent in its own text message.
+pub struct WsStream {
+    socket: WebSocket<Box<dyn IrcTransport>>,
+    /// Data received but not yet read.
+    read_buf: Vec<u8>,
+    /// Data written but not yet sent because the line is incomplete.
+    write_buf: Vec<u8>,
+}
+
+impl WsStream {
+
+    /// Do the WebSocket handshake with the given URL on a blocking stream.
+    pub fn connect(url: &str, stream: Box<dyn IrcTransport>) -> io::Result<Self> {
+
+        let (socket, _) = tungstenite::client(url, stream)
+            .map_err(|e| io::Error::new(io::ErrorKind::ConnectionRefused, e.to_string()))?;
+
+        Ok(Self {
+            socket,
+            read_buf: Vec::new(),
+            write_buf: Vec::new(),
+        })
+
+    }
+
+}
+
+/// Convert a WebSocket error into an IO error.
+fn ws_error(e: tungstenite::Error) -> io::Error {
+    match e {
+        tungstenite::Error::Io(e) => e,
+        e => io::Error::other(e),
+    }
+}
+
+impl IrcTransport for WsStream {
+    fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()> {
+        self.socket.get_mut().set_nonblocking(nonblocking)
+    }
+}
+
+impl Read for WsStream {
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+
+        while self.read_buf.is_empty() {
+            match self.socket.read() {
+                Ok(Message::Text(text)) => self.read_buf.extend_from_slice(text.as_bytes()),
+                Ok(Message::Binary(data)) => self.read_buf.extend_from_slice(&data),
+                Ok(Message::Close(_)) => return Ok(0),
+                Ok(_) => continue,
+                Err(tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed) => return Ok(0),
+                Err(e) => return Err(ws_error(e)),
+            }
+        }
+
+        let len = buf.len().min(self.read_buf.len());
+        buf[..len].copy_from_slice(&self.read_buf[..len]);
+        self.read_buf.drain(..len);
+        Ok(len)
+
+    }
+
+}
+
+impl Write for WsStream {
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+
+        self.write_buf.extend_from_slice(buf);
+
+        while let Some(pos) = self.write_buf.windows(2).position(|w| w == b"\r\n") {
+            let line = String::from_utf8_lossy(&self.write_buf[..pos]).into_owned();
+            self.write_buf.drain(..pos + 2);
+            match self.socket.send(Message::Text(line)) {
+                // The message is queued and will be flushed later.
+                Err(tungstenite::Error::Io(e)) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => return Err(ws_error(e)),
+                Ok(()) => {}
+            }
+        }
+
+        Ok(buf.len())
+
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.socket.flush() {
+            Err(tungstenite::Error::Io(e)) if e.kind() == io::ErrorKind::WouldBlock => Ok(()),
+            Err(e) => Err(ws_error(e)),
+            Ok(()) => Ok(()),
+        }
+    }
+
+}
+
+
+/// An in-memory transport, used to test the client without connection.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct MockTransport {
+    /// Data to be read by the client.
+    pub input: Vec<u8>,
+    /// Data written by the client.
+    pub output: Vec<u8>,
+    /// Number of bytes that can be written before blocking, unlimited if
+    /// none, to simulate a congested socket.
+    pub writable: Option<usize>,
+}
+
+#[cfg(test)]
+impl IrcTransport for MockTransport {
+    fn set_nonblocking(&mut self, _nonblocking: bool) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl Read for MockTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.input.is_empty() {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        let len = buf.len().min(self.input.len());
+        buf[..len].copy_from_slice(&self.input[..len]);
+        self.input.drain(..len);
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+impl Write for MockTransport {
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.writable.unwrap_or(usize::MAX));
+        if len == 0 && !buf.is_empty() {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        if let Some(writable) = &mut self.writable {
+            *writable -= len;
+        }
+        self.output.extend_from_slice(&buf[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+}
@@ -0,0 +1,197 @@
+use crate::config::Settings;
+use crate::curve::IntervalCurve;
+use crate::expr::Expr;
+use crate::shadow::{LIVE_MIN_CMD_SEC, LIVE_MIN_RATIO};
+
+
+/// Metrics exposed to the send condition and the interval formula, the
+/// `ratio` and `cmd_per_sec` names are aliases of `cmd/msg` and `cmd/s`.
+pub const METRICS: &[&str] = &[
+    "cmd/s", "cmd_per_sec", "cmd/msg", "ratio", "consensus",
+    "min_ratio", "min_cmd_sec",
+];
+
+
+/// Metrics of the TPP window of a channel, when deciding to send.
+#[derive(Debug, Clone, Copy)]
+pub struct TriggerMetrics {
+    pub cmd_sec: f32,
+    pub ratio: f32,
+    /// Share of the most used command in the commands.
+    pub consensus: f32,
+    /// Thresholds of the channel, may be changed by the owner.
+    pub min_ratio: f32,
+    pub min_cmd_sec: f32,
+}
+
+impl TriggerMetrics {
+
+    fn get(&self, name: &str) -> Option<f32> {
+        match name {
+            "cmd/s" | "cmd_per_sec" => Some(self.cmd_sec),
+            "cmd/msg" | "ratio" => Some(self.ratio),
+            "consensus" => Some(self.consensus),
+            "min_ratio" => Some(self.min_ratio),
+            "min_cmd_sec" => Some(self.min_cmd_sec),
+            _ => None,
+        }
+    }
+
+}
+
+
+/// Conditions under which the bot sends the most used command, and the
+/// interval between two messages.
+#[derive(Debug, Clone)]
+pub struct TriggerConfig {
+    /// Default thresholds of the channels.
+    pub min_ratio: f32,
+    pub min_cmd_sec: f32,
+    /// Condition replacing the thresholds, like `ratio >= 0.5 && cmd_per_sec
+    /// >= 1.0`.
+    condition: Option<Expr>,
+    /// Interval in seconds replacing the interval curve, like `6 - cmd/s / 2`.
+    interval: Option<Expr>,
+}
+
+impl Default for TriggerConfig {
+    fn default() -> Self {
+        Self {
+            min_ratio: LIVE_MIN_RATIO,
+            min_cmd_sec: LIVE_MIN_CMD_SEC,
+            condition: None,
+            interval: None,
+        }
+    }
+}
+
+impl TriggerConfig {
+
+    /// Read the configuration from the `min_ratio`, `min_cmd_sec`,
+    /// `send_condition` and `interval_formula` settings.
+    pub fn from_settings(settings: &Settings) -> Result<Self, String> {
+
+        let expr = |key: &str| settings.parse_with(key, |text| {
+            let expr = Expr::parse(text)?;
+            expr.check(&|name| METRICS.contains(&name))?;
+            Ok::<_, String>(expr)
+        });
+
+        if settings.get("interval_formula").is_some() && settings.get("interval_curve_path").is_some() {
+            return Err(format!("{} and {} are exclusive", settings.origin("interval_formula"), settings.origin("interval_curve_path")));
+        }
+
+        Ok(Self {
+            min_ratio: settings.parse::<f32>("min_ratio")?.unwrap_or(LIVE_MIN_RATIO),
+            min_cmd_sec: settings.parse::<f32>("min_cmd_sec")?.unwrap_or(LIVE_MIN_CMD_SEC),
+            condition: expr("send_condition")?,
+            interval: expr("interval_formula")?,
+        })
+
+    }
+
+    /// True if the metrics allow sending, by default when both thresholds
+    /// are reached.
+    pub fn is_met(&self, metrics: &TriggerMetrics) -> bool {
+        match &self.condition {
+            Some(condition) => condition.test(&|name| metrics.get(name)),
+            None => metrics.ratio >= metrics.min_ratio && metrics.cmd_sec >= metrics.min_cmd_sec,
+        }
+    }
+
+    /// Interval in seconds before the next message, from the formula if
+    /// any or else from the interval curve.
+    pub fn interval(&self, metrics: &TriggerMetrics, curve: &IntervalCurve) -> f32 {
+        match &self.interval {
+            Some(interval) => interval.eval(&|name| metrics.get(name)).max(0.0),
+            None => curve.interval(metrics.cmd_sec),
+        }
+    }
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn conditions() {
+
+        let metrics = TriggerMetrics { cmd_sec: 1.5, ratio: 0.55, consensus: 0.4, min_ratio: 0.6, min_cmd_sec: 2.0 };
+        let curve = IntervalCurve::default();
+
+        let config = TriggerConfig::default();
+        assert!(!config.is_met(&metrics));
+        assert_eq!(config.interval(&metrics, &curve), 6.5);
+
+        let config = TriggerConfig {
+            condition: Some(Expr::parse("ratio >= 0.5 && cmd_per_sec >= 1.0").unwrap()),
+            interval: Some(Expr::parse("4 - cmd/s * 2").unwrap()),
+            ..TriggerConfig::default()
+        };
+        assert!(config.is_met(&metrics));
+        assert_eq!(config.interval(&metrics, &curve), 1.0);
+        assert_eq!(config.interval(&TriggerMetrics { cmd_sec: 3.0, ..metrics }, &curve), 0.0);
+
+    }
+
+
+    fn condition(text: &str) -> TriggerConfig {
+        let condition = Expr::parse(text).unwrap();
+        condition.check(&|name| METRICS.contains(&name)).unwrap();
+        TriggerConfig { condition: Some(condition), ..TriggerConfig::default() }
+    }
+
+    #[test]
+    fn comparisons() {
+
+        let metrics = TriggerMetrics { cmd_sec: 2.0, ratio: 0.5, consensus: 0.4, min_ratio: 0.5, min_cmd_sec: 3.0 };
+
+        for (text, met) in [
+            ("cmd/s < 3", true), ("cmd/s < 2", false),
+            ("cmd/s <= 2", true), ("cmd/s <= 1.9", false),
+            ("cmd_per_sec > 1.9", true), ("cmd_per_sec > 2", false),
+            ("ratio >= 0.5", true), ("cmd/msg >= 0.6", false),
+            ("consensus == 0.4", true), ("consensus == 0.5", false),
+            ("ratio != 0.4", true), ("ratio != min_ratio", false),
+        ] {
+            assert_eq!(condition(text).is_met(&metrics), met, "{text}");
+        }
+
+    }
+
+    #[test]
+    fn combined_conditions() {
+
+        let metrics = TriggerMetrics { cmd_sec: 2.0, ratio: 0.5, consensus: 0.4, min_ratio: 0.6, min_cmd_sec: 1.0 };
+
+        assert!(condition("cmd/s >= min_cmd_sec and consensus > 0.3").is_met(&metrics));
+        assert!(!condition("cmd/s >= min_cmd_sec and ratio >= min_ratio").is_met(&metrics));
+        assert!(condition("ratio >= min_ratio or consensus >= 0.4").is_met(&metrics));
+        assert!(!condition("ratio >= min_ratio || consensus > 0.4").is_met(&metrics));
+        assert!(condition("not (ratio >= min_ratio) && cmd/s * ratio == 1").is_met(&metrics));
+        assert!(!condition("cmd/s > 1 and (ratio > 0.9 or consensus > 0.9)").is_met(&metrics));
+
+    }
+
+    #[test]
+    fn thresholds() {
+
+        let config = TriggerConfig::default();
+        let metrics = TriggerMetrics { cmd_sec: 2.0, ratio: 0.6, consensus: 0.4, min_ratio: 0.6, min_cmd_sec: 2.0 };
+
+        // The thresholds are reached when equal.
+        assert!(config.is_met(&metrics));
+        assert!(!config.is_met(&TriggerMetrics { cmd_sec: 1.9, ..metrics }));
+        assert!(!config.is_met(&TriggerMetrics { ratio: 0.59, ..metrics }));
+        assert!(!config.is_met(&TriggerMetrics { cmd_sec: 0.0, ratio: 0.0, ..metrics }));
+
+        // Only the known metrics are allowed.
+        let unknown = Expr::parse("viewers > 10").unwrap();
+        assert_eq!(unknown.check(&|name| METRICS.contains(&name)).unwrap_err(), "unknown metric 'viewers'");
+
+    }
+
+}
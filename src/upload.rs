@@ -0,0 +1,454 @@
+use std::sync::mpsc::{self, Sender};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::io::{self, BufRead, Write};
+use std::time::Duration;
+use std::{env, fs, process, thread};
+
+use chrono::{DateTime, Days, Local, NaiveDateTime, NaiveTime, Utc};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+
+use crate::analyze::{self, Selection};
+use crate::config::Settings;
+use crate::format::NumberFormat;
+use crate::logfile;
+use crate::logformat;
+
+
+/// Configuration of an S3-compatible destination.
+#[derive(Debug, Clone)]
+pub struct S3Config {
+    /// Base URL of the service, the bucket is given in the path.
+    pub endpoint: String,
+    pub bucket: String,
+    /// Prefix of the object keys, empty or ending with a slash.
+    pub prefix: String,
+    pub region: String,
+    pub access_key: String,
+    pub secret_key: String,
+}
+
+/// Where the files are uploaded.
+#[derive(Debug, Clone)]
+pub enum UploadDestination {
+    /// A local directory, like a mounted network share.
+    Directory(PathBuf),
+    /// An S3-compatible object storage.
+    S3(S3Config),
+    /// A directory of a SFTP server, uploaded with the `sftp` command and
+    /// its usual key authentication.
+    Sftp {
+        /// Host, with its user, as given to `sftp`.
+        host: String,
+        port: Option<u16>,
+        dir: String,
+    },
+}
+
+impl UploadDestination {
+
+    /// Upload a single file with the given name.
+    pub fn put(&self, name: &str, data: &[u8]) -> Result<(), String> {
+        match self {
+            Self::Directory(dir) => {
+                // Written aside then renamed, so that a partial file is never
+                // taken for a complete one.
+                let tmp_path = dir.join(format!(".{name}.tmp"));
+                fs::create_dir_all(dir)
+                    .and_then(|()| fs::write(&tmp_path, data))
+                    .and_then(|()| fs::rename(&tmp_path, dir.join(name)))
+                    .map_err(|e| e.to_string())
+            }
+            Self::S3(s3) => s3.put(name, data, Utc::now()),
+            Self::Sftp { host, port, dir } => {
+
+                let tmp_path = env::temp_dir().join(format!("tpp-bot-{}-{name}", process::id()));
+                fs::write(&tmp_path, data).map_err(|e| e.to_string())?;
+                let result = sftp_put(host, *port, &tmp_path, &format!("{dir}/{name}"));
+                let _ = fs::remove_file(&tmp_path);
+                result
+
+            }
+        }
+    }
+
+}
+
+
+/// Configuration of the scheduled upload of the logs and daily summaries.
+#[derive(Debug, Clone)]
+pub struct UploadConfig {
+    pub destination: UploadDestination,
+    /// Local times of the uploads, each day.
+    pub times: Vec<NaiveTime>,
+}
+
+impl UploadConfig {
+
+    /// Read the configuration from the `upload_*` settings, none if no
+    /// destination is configured. The destination is a directory path, a
+    /// `s3://bucket/prefix` or a `sftp://user@host:port/dir` URL.
+    pub fn from_settings(settings: &Settings) -> Result<Option<Self>, String> {
+
+        let Some(dest) = settings.get("upload_dest") else { return Ok(None) };
+        let invalid = |message: &str| format!("invalid {}: {message}", settings.origin("upload_dest"));
+
+        let destination = if let Some(rest) = dest.strip_prefix("s3://") {
+            let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+            if bucket.is_empty() {
+                return Err(invalid("missing bucket"));
+            }
+            let prefix = prefix.trim_end_matches('/');
+            UploadDestination::S3(S3Config {
+                endpoint: settings.require("upload_s3_endpoint")?.trim_end_matches('/').to_string(),
+                bucket: bucket.to_string(),
+                prefix: if prefix.is_empty() { String::new() } else { format!("{prefix}/") },
+                region: settings.get("upload_s3_region").unwrap_or("us-east-1").to_string(),
+                access_key: settings.require("upload_s3_access_key")?.to_string(),
+                secret_key: settings.require("upload_s3_secret_key")?.to_string(),
+            })
+        } else if let Some(rest) = dest.strip_prefix("sftp://") {
+            let (authority, dir) = rest.split_once('/').ok_or_else(|| invalid("missing directory"))?;
+            let (host, port) = match authority.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port.parse().map_err(|_| invalid("invalid port"))?)),
+                None => (authority, None),
+            };
+            UploadDestination::Sftp { host: host.to_string(), port, dir: format!("/{}", dir.trim_end_matches('/')) }
+        } else {
+            UploadDestination::Directory(PathBuf::from(dest))
+        };
+
+        let times = settings.get("upload_times").unwrap_or("00:00")
+            .split(',')
+            .map(|time| NaiveTime::parse_from_str(time.trim(), "%H:%M")
+                .map_err(|_| format!("invalid {}: expected times like '03:00,15:00'", settings.origin("upload_times"))))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Some(Self { destination, times }))
+
+    }
+
+    /// The first upload time strictly after the given time.
+    pub fn next_time(&self, after: NaiveDateTime) -> NaiveDateTime {
+        self.times_around(after).filter(|&time| time > after).min().unwrap()
+    }
+
+    /// The last upload time at or before the given time.
+    pub fn prev_time(&self, before: NaiveDateTime) -> NaiveDateTime {
+        self.times_around(before).filter(|&time| time <= before).max().unwrap()
+    }
+
+    /// The upload times of the day before, of the day and of the day after
+    /// the given time.
+    fn times_around(&self, time: NaiveDateTime) -> impl Iterator<Item = NaiveDateTime> + '_ {
+        [time.date() - Days::new(1), time.date(), time.date() + Days::new(1)].into_iter()
+            .flat_map(|date| self.times.iter().map(move |&time| date.and_time(time)))
+    }
+
+}
+
+
+/// Upload the logs and daily summaries at the configured times, to archive
+/// the data of an unattended bot. The records of the period are extracted
+/// from the logs and uploaded by a background thread, so that reading the
+/// logs or a slow destination doesn't block the bot.
+#[derive(Debug)]
+pub struct Uploader {
+    config: UploadConfig,
+    tx: Sender<UploadJob>,
+    /// Local time of the next upload.
+    next_time: NaiveDateTime,
+    /// Local time of the last upload, start of the period of the next
+    /// upload.
+    period_start: NaiveDateTime,
+}
+
+/// Upload of the records of a channel log over a period.
+#[derive(Debug)]
+struct UploadJob {
+    channel: String,
+    log_path: PathBuf,
+    /// Start and end timestamps, the end is excluded.
+    from: i64,
+    to: i64,
+    /// Label of the period in the names of the files.
+    label: String,
+}
+
+impl Uploader {
+
+    /// Spawn the upload thread, the first period starts at the upload time
+    /// preceding the given time, so that it doesn't depend on the start of
+    /// the connection.
+    pub fn spawn(config: UploadConfig, nf: NumberFormat, now: DateTime<Utc>) -> Self {
+
+        let (tx, rx) = mpsc::channel::<UploadJob>();
+        let destination = config.destination.clone();
+
+        thread::Builder::new()
+            .name("upload".to_string())
+            .spawn(move || {
+                for job in rx {
+                    let files = match job.files(&nf) {
+                        Ok(files) => files,
+                        Err(e) => {
+                            warn!(channel = %job.channel, "\x07alert: failed to read the log to upload: {e}");
+                            continue;
+                        }
+                    };
+                    for (name, data) in files {
+                        match destination.put(&name, &data) {
+                            Ok(()) => info!(channel = %job.channel, "uploaded {name}"),
+                            Err(e) => warn!(channel = %job.channel, "\x07alert: failed to upload {name}: {e}"),
+                        }
+                    }
+                }
+            })
+            .unwrap();
+
+        let local = now.with_timezone(&Local).naive_local();
+        Self {
+            next_time: config.next_time(local),
+            period_start: config.prev_time(local),
+            config,
+            tx,
+        }
+
+    }
+
+    /// Upload the given logs, by channel, if an upload is due. The next
+    /// upload is then scheduled.
+    pub fn update<'a>(&mut self, now: DateTime<Utc>, logs: impl IntoIterator<Item = (&'a str, &'a Path)>) {
+
+        let local = now.with_timezone(&Local).naive_local();
+        if local < self.next_time {
+            return;
+        }
+
+        let period_end = self.next_time;
+        let period_start = std::mem::replace(&mut self.period_start, period_end);
+        self.next_time = self.config.next_time(local);
+
+        let timestamp = |time: NaiveDateTime| time.and_local_timezone(Local).earliest()
+            .map(|time| time.timestamp())
+            .unwrap_or_else(|| time.and_utc().timestamp());
+
+        for (channel, log_path) in logs {
+            // The thread only stops with the process.
+            let _ = self.tx.send(UploadJob {
+                channel: channel.to_string(),
+                log_path: log_path.to_path_buf(),
+                from: timestamp(period_start),
+                to: timestamp(period_end),
+                label: period_end.format("%Y%m%d-%H%M").to_string(),
+            });
+        }
+
+    }
+
+}
+
+impl UploadJob {
+
+    /// The files to upload: the records of the period and their summary.
+    /// The records are read from the files the log was rolled to since the
+    /// start of the period, then from the log itself.
+    fn files(&self, nf: &NumberFormat) -> io::Result<Vec<(String, Vec<u8>)>> {
+
+        let since = DateTime::from_timestamp(self.from, 0).unwrap_or_default().with_timezone(&Local).date_naive();
+        let mut paths = logfile::rolled_files(&self.log_path, since)?;
+        paths.push(self.log_path.clone());
+
+        // The header of the log is kept in each period, once unless the
+        // format changed between the files.
+        let mut records = Vec::new();
+        let mut header = None;
+        for path in &paths {
+            for line in logfile::open_read(path)?.lines() {
+                let line = line?;
+                if logformat::is_log_header(&line) {
+                    if header.as_ref() == Some(&line) {
+                        continue;
+                    }
+                    header = Some(line.clone());
+                } else {
+                    let time = logformat::parse_record(&line).and_then(|columns| columns.first()?.parse::<i64>().ok());
+                    if !time.is_some_and(|time| time >= self.from && time < self.to) {
+                        continue;
+                    }
+                }
+                records.extend_from_slice(line.as_bytes());
+                records.push(b'\n');
+            }
+        }
+
+        let mut summary = Vec::new();
+        analyze::report_records(&Selection {
+            path: self.log_path.clone(),
+            run: None,
+            from: Some(self.from),
+            to: Some(self.to),
+        }, &records, &mut summary, nf)?;
+
+        Ok(vec![
+            (format!("{}-{}.log", self.channel, self.label), records),
+            (format!("{}-{}-summary.txt", self.channel, self.label), summary),
+        ])
+
+    }
+
+}
+
+
+impl S3Config {
+
+    /// Upload an object with a PUT request signed with AWS Signature V4,
+    /// the bucket is addressed in the path, as most compatible services
+    /// expect.
+    fn put(&self, name: &str, data: &[u8], now: DateTime<Utc>) -> Result<(), String> {
+
+        let host = self.endpoint.split_once("://").map(|(_, rest)| rest).unwrap_or(&self.endpoint);
+        let path = format!("/{}/{}{name}", self.bucket, self.prefix);
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let payload_hash = hex(&Sha256::digest(data));
+
+        let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+        let canonical_request = format!("PUT\n{path}\n\nhost:{host}\nx-amz-content-sha256:{payload_hash}\nx-amz-date:{amz_date}\n\n{signed_headers}\n{payload_hash}");
+        let scope = format!("{date}/{}/s3/aws4_request", self.region);
+        let string_to_sign = format!("AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}", hex(&Sha256::digest(canonical_request)));
+        let signature = hex(&hmac(&signing_key(&self.secret_key, &date, &self.region, "s3"), string_to_sign.as_bytes()));
+
+        let authorization = format!("AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}", self.access_key);
+
+        ureq::AgentBuilder::new()
+            .timeout(Duration::from_secs(60))
+            .build()
+            .put(&format!("{}{path}", self.endpoint))
+            .set("x-amz-content-sha256", &payload_hash)
+            .set("x-amz-date", &amz_date)
+            .set("Authorization", &authorization)
+            .send_bytes(data)
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+
+    }
+
+}
+
+
+/// Upload a local file with the `sftp` command in batch mode.
+fn sftp_put(host: &str, port: Option<u16>, local: &Path, remote: &str) -> Result<(), String> {
+
+    let mut command = Command::new("sftp");
+    command.args(["-q", "-b", "-"]);
+    if let Some(port) = port {
+        command.args(["-P", &port.to_string()]);
+    }
+
+    let mut child = command.arg(host)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("failed to run sftp: {e}"))?;
+
+    // Paths are quoted, the names of the files never contain quotes.
+    writeln!(child.stdin.take().unwrap(), "put \"{}\" \"{remote}\"", local.display())
+        .map_err(|e| e.to_string())?;
+
+    let output = child.wait_with_output().map_err(|e| e.to_string())?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+    }
+
+}
+
+
+/// Derive the key signing the requests of a day.
+fn signing_key(secret_key: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    let key = hmac(format!("AWS4{secret_key}").as_bytes(), date.as_bytes());
+    let key = hmac(&key, region.as_bytes());
+    let key = hmac(&key, service.as_bytes());
+    hmac(&key, b"aws4_request")
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::time::Instant;
+    use crate::logfile::{LogFile, Rotation};
+
+    #[test]
+    fn schedule() {
+
+        let config = UploadConfig {
+            destination: UploadDestination::Directory(PathBuf::new()),
+            times: vec![NaiveTime::from_hms_opt(3, 0, 0).unwrap(), NaiveTime::from_hms_opt(15, 0, 0).unwrap()],
+        };
+
+        let time = |text: &str| NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M").unwrap();
+        assert_eq!(config.next_time(time("2024-01-01 02:00")), time("2024-01-01 03:00"));
+        assert_eq!(config.next_time(time("2024-01-01 03:00")), time("2024-01-01 15:00"));
+        assert_eq!(config.next_time(time("2024-01-01 16:00")), time("2024-01-02 03:00"));
+        assert_eq!(config.prev_time(time("2024-01-01 02:00")), time("2023-12-31 15:00"));
+        assert_eq!(config.prev_time(time("2024-01-01 15:00")), time("2024-01-01 15:00"));
+
+    }
+
+    #[test]
+    fn rotated_upload() {
+
+        let dir = std::env::temp_dir().join(format!("tpp-bot-upload-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+        let path = dir.join("tpp.log");
+
+        // Each record is rolled to its own file, compressed.
+        let rotation = Rotation { daily: false, max_size: Some(20), compress: true };
+        let mut log = LogFile::open(&path, b"time\tvalue\n".to_vec()).unwrap().with_rotation(Some(rotation));
+        let from = Utc::now().timestamp() - 3600;
+        for time in [from - 10, from, from + 10, from + 20] {
+            log.write_record(Instant::now(), format!("{time}\ta\n").into_bytes());
+        }
+        assert_eq!(logfile::rolled_files(&path, Local::now().date_naive()).unwrap().len(), 3);
+
+        let job = UploadJob {
+            channel: "chan".to_string(),
+            log_path: path,
+            from,
+            to: from + 20,
+            label: "label".to_string(),
+        };
+        let files = job.files(&NumberFormat::default()).unwrap();
+        assert_eq!(files[0].0, "chan-label.log");
+        assert_eq!(String::from_utf8_lossy(&files[0].1), format!("time\tvalue\n{from}\ta\n{}\ta\n", from + 10));
+
+        fs::remove_dir_all(&dir).unwrap();
+
+    }
+
+    #[test]
+    fn signing() {
+        // Example of the AWS documentation.
+        let key = signing_key("wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY", "20120215", "us-east-1", "iam");
+        assert_eq!(hex(&key), "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d");
+    }
+
+}
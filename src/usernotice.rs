@@ -0,0 +1,92 @@
+/// Kind of a USERNOTICE, given by its `msg-id` tag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserNoticeKind {
+    Sub,
+    Resub,
+    /// A subscription gifted to a user, gifts to the community are then
+    /// notified one by one.
+    SubGift,
+    /// Another channel raided this one, with its viewers.
+    Raid,
+    Announcement,
+    Other,
+}
+
+impl UserNoticeKind {
+
+    pub fn parse(msg_id: &str) -> Self {
+        match msg_id {
+            "sub" => Self::Sub,
+            "resub" => Self::Resub,
+            "subgift" | "anonsubgift" => Self::SubGift,
+            "raid" => Self::Raid,
+            "announcement" => Self::Announcement,
+            _ => Self::Other,
+        }
+    }
+
+}
+
+
+/// Counts of the user notices since the last log record.
+#[derive(Debug, Clone, Default)]
+pub struct UserNotices {
+    pub subs: u32,
+    pub resubs: u32,
+    pub gifts: u32,
+    pub raids: u32,
+    /// Viewers brought by the raids.
+    pub raiders: u32,
+    pub announcements: u32,
+    /// Chatter messages received after a raid, not counted in the samples.
+    pub raid_messages: u32,
+}
+
+impl UserNotices {
+
+    /// Count a notice, with the viewers of a raid.
+    pub fn push(&mut self, kind: UserNoticeKind, viewers: u32) {
+        match kind {
+            UserNoticeKind::Sub => self.subs += 1,
+            UserNoticeKind::Resub => self.resubs += 1,
+            UserNoticeKind::SubGift => self.gifts += 1,
+            UserNoticeKind::Raid => {
+                self.raids += 1;
+                self.raiders += viewers;
+            }
+            UserNoticeKind::Announcement => self.announcements += 1,
+            UserNoticeKind::Other => {}
+        }
+    }
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use crate::irc::{IrcReplyCommand, IrcReplyRef};
+
+    use super::*;
+
+    #[test]
+    fn raid() {
+
+        let reply = IrcReplyRef::parse("@msg-id=raid;msg-param-viewerCount=250;login=raider :tmi.twitch.tv USERNOTICE #chan").unwrap();
+        assert!(matches!(reply.command, IrcReplyCommand::UserNotice));
+        assert_eq!(reply.target(), Some("#chan"));
+        assert_eq!(reply.text(), None);
+
+        let mut notices = UserNotices::default();
+        notices.push(UserNoticeKind::parse(reply.tag("msg-id").unwrap()), 250);
+        notices.push(UserNoticeKind::parse("resub"), 0);
+        notices.push(UserNoticeKind::parse("submysterygift"), 0);
+
+        assert_eq!((notices.raids, notices.raiders, notices.resubs, notices.gifts), (1, 250, 1, 0));
+
+        let reply = IrcReplyRef::parse("@msg-id=resub;login=user :tmi.twitch.tv USERNOTICE #chan :still here").unwrap();
+        assert_eq!(reply.text(), Some("still here"));
+
+    }
+
+}
@@ -0,0 +1,305 @@
+use std::time::{Duration, Instant};
+use std::collections::{HashMap, VecDeque};
+
+use crate::config::Settings;
+use crate::vocabulary::{Command, Vocabulary};
+
+
+/// Twitch drops a message identical to the previous message sent in the
+/// same channel within this duration.
+pub const DUPLICATE_HORIZON: Duration = Duration::from_secs(30);
+/// Character appended by the invisible strategy, the tag space that Twitch
+/// clients commonly use for this purpose.
+const INVISIBLE: &str = " \u{E0000}";
+/// Maximum number of case patterns of a command, its first letters only are
+/// varied.
+const MAX_PATTERNS: usize = 16;
+
+
+/// A way of writing a command differently.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    /// The command as is, uppercase and capitalized.
+    Case,
+    /// The patterns of lowercase and uppercase letters of the command.
+    Patterns,
+    /// The other aliases of the command in the vocabulary.
+    Aliases,
+    /// Each previous variant followed by an invisible character.
+    Invisible,
+}
+
+impl Strategy {
+    pub fn parse(text: &str) -> Option<Self> {
+        match text {
+            "case" => Some(Self::Case),
+            "patterns" => Some(Self::Patterns),
+            "aliases" => Some(Self::Aliases),
+            "invisible" => Some(Self::Invisible),
+            _ => None,
+        }
+    }
+}
+
+
+/// Strategies of the variation engine, and how many messages it remembers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VariationConfig {
+    /// Strategies in order of preference, their variants are tried in turn.
+    strategies: Vec<Strategy>,
+    /// Number of last messages of a channel that can't be sent again within
+    /// the duplicate horizon, Twitch only checks the last one.
+    memory: usize,
+}
+
+impl Default for VariationConfig {
+    fn default() -> Self {
+        Self { strategies: vec![Strategy::Case], memory: 1 }
+    }
+}
+
+impl VariationConfig {
+
+    /// Read the configuration from the `variation` setting, a
+    /// comma-separated list of strategies like `case, aliases, invisible`,
+    /// and the `variation_memory` one.
+    pub fn from_settings(settings: &Settings) -> Result<Self, String> {
+
+        let strategies = settings.parse_with("variation", |text| text.split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| Strategy::parse(name).ok_or_else(|| format!("unknown strategy '{name}', expected case, patterns, aliases or invisible")))
+            .collect::<Result<Vec<_>, _>>())?;
+
+        Ok(Self {
+            strategies: strategies.filter(|strategies| !strategies.is_empty()).unwrap_or_else(|| vec![Strategy::Case]),
+            memory: settings.parse::<usize>("variation_memory")?.unwrap_or(1).max(1),
+        })
+
+    }
+
+}
+
+
+/// The variation engine, choosing how to write each message sent by the
+/// bot so that it's never dropped by the Twitch duplicate-message rule.
+/// The state of the rule, which is the last messages sent and their time,
+/// is tracked per channel.
+#[derive(Debug, Default)]
+pub struct Variation {
+    config: VariationConfig,
+    /// Aliases of each command output, for the aliases strategy.
+    aliases: HashMap<String, Vec<String>>,
+    last: HashMap<String, VecDeque<(Instant, String)>>,
+}
+
+impl Variation {
+
+    pub fn new(config: VariationConfig, vocabulary: &Vocabulary) -> Self {
+
+        let aliases = Command::ALL.into_iter()
+            .map(|command| {
+                let output = vocabulary.output(command);
+                let aliases = vocabulary.aliases(command).into_iter()
+                    .filter(|alias| *alias != output)
+                    .map(str::to_string)
+                    .collect();
+                (output.to_string(), aliases)
+            })
+            .collect();
+
+        Self { config, aliases, last: HashMap::new() }
+
+    }
+
+    /// Return true if the given message would be dropped as a duplicate if
+    /// sent in the channel at the given time.
+    pub fn is_duplicate(&self, channel: &str, message: &str, now: Instant) -> bool {
+        self.last.get(channel).is_some_and(|last| last.iter()
+            .any(|(time, last)| last == message && now.saturating_duration_since(*time) < DUPLICATE_HORIZON))
+    }
+
+    /// Choose the variant of the command to send in the channel at the given
+    /// time, the returned message is guaranteed not to be a duplicate. None
+    /// is returned if no variant is available, in which case nothing should
+    /// be sent.
+    pub fn vary(&self, channel: &str, command: &str, now: Instant) -> Option<String> {
+        self.variants(command).into_iter()
+            .find(|variant| !self.is_duplicate(channel, variant, now))
+    }
+
+    /// Record a message as sent in the channel at the given time.
+    pub fn record(&mut self, channel: &str, message: String, now: Instant) {
+        let last = self.last.entry(channel.to_string()).or_default();
+        last.push_back((now, message));
+        while last.len() > self.config.memory {
+            last.pop_front();
+        }
+    }
+
+    /// All distinct variants of a command given by the strategies, the
+    /// original one first.
+    fn variants(&self, command: &str) -> Vec<String> {
+
+        let mut variants = vec![command.to_string()];
+        for strategy in &self.config.strategies {
+            let new_variants = match strategy {
+                Strategy::Case => case_variants(command),
+                Strategy::Patterns => case_patterns(command),
+                Strategy::Aliases => self.aliases.get(command).cloned().unwrap_or_default(),
+                Strategy::Invisible => variants.iter().map(|variant| format!("{variant}{INVISIBLE}")).collect(),
+            };
+            for variant in new_variants {
+                if !variants.contains(&variant) {
+                    variants.push(variant);
+                }
+            }
+        }
+
+        variants
+
+    }
+
+}
+
+
+/// All distinct case variants of a command, the original one first.
+fn case_variants(command: &str) -> Vec<String> {
+
+    let mut capitalized = String::with_capacity(command.len());
+    let mut chars = command.chars();
+    if let Some(first) = chars.next() {
+        capitalized.extend(first.to_uppercase());
+        capitalized.push_str(&chars.as_str().to_lowercase());
+    }
+
+    let mut variants = Vec::with_capacity(3);
+    for variant in [command.to_string(), command.to_uppercase(), capitalized] {
+        if !variants.contains(&variant) {
+            variants.push(variant);
+        }
+    }
+
+    variants
+
+}
+
+/// The patterns of lowercase and uppercase of the first letters of a
+/// command, starting with the lowercase one.
+fn case_patterns(command: &str) -> Vec<String> {
+
+    let letters = command.chars().filter(|c| c.is_alphabetic()).count().min(MAX_PATTERNS.ilog2() as usize);
+
+    (0..1usize << letters)
+        .map(|pattern| {
+            let mut letter = 0;
+            command.chars()
+                .flat_map(|c| {
+                    let upper = c.is_alphabetic() && letter < letters && pattern & (1 << letter) != 0;
+                    letter += c.is_alphabetic() as usize;
+                    if upper { c.to_uppercase().collect::<Vec<_>>() } else { c.to_lowercase().collect() }
+                })
+                .collect()
+        })
+        .collect()
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    /// Send the given commands at a fixed interval and check that no message
+    /// is ever a duplicate of the previous one within the horizon.
+    fn check_sequence(commands: &[&str], interval: Duration) {
+
+        let mut variation = Variation::default();
+        let start = Instant::now();
+        let mut last: Option<(Instant, String)> = None;
+
+        for (index, command) in commands.iter().enumerate() {
+
+            let now = start + interval * index as u32;
+            let Some(message) = variation.vary("chan", command, now) else { continue };
+
+            assert!(message.eq_ignore_ascii_case(command));
+            if let Some((time, last)) = &last {
+                assert!(*last != message || now - *time >= DUPLICATE_HORIZON, "duplicate '{message}' at {index}");
+            }
+
+            variation.record("chan", message.clone(), now);
+            last = Some((now, message));
+
+        }
+
+    }
+
+    #[test]
+    fn repeated_single_letter() {
+        check_sequence(&["n"; 100], Duration::from_millis(1800));
+    }
+
+    #[test]
+    fn repeated_word() {
+        check_sequence(&["democratie"; 100], Duration::from_millis(1800));
+    }
+
+    #[test]
+    fn mixed_commands() {
+        let commands = ["n", "a", "n", "n", "s", "s", "a", "a", "a", "start", "start", "n"];
+        check_sequence(&commands.repeat(10), Duration::from_millis(1800));
+    }
+
+    #[test]
+    fn no_variant() {
+
+        let mut variation = Variation::default();
+        let now = Instant::now();
+
+        assert_eq!(variation.vary("chan", "1", now).as_deref(), Some("1"));
+        variation.record("chan", "1".to_string(), now);
+        assert_eq!(variation.vary("chan", "1", now + Duration::from_secs(2)), None);
+        assert_eq!(variation.vary("chan", "1", now + DUPLICATE_HORIZON).as_deref(), Some("1"));
+
+    }
+
+    #[test]
+    fn strategies() {
+
+        let config = VariationConfig { strategies: vec![Strategy::Patterns, Strategy::Aliases, Strategy::Invisible], memory: 8 };
+        let mut variation = Variation::new(config, &Vocabulary::default());
+        let now = Instant::now();
+
+        assert_eq!(case_patterns("ab"), ["ab", "Ab", "aB", "AB"]);
+        assert_eq!(case_patterns("democratie").len(), MAX_PATTERNS);
+
+        // "n" has two patterns, the aliases "haut" and "u" of up, and the
+        // invisible variants of all of them.
+        let mut sent = Vec::new();
+        for index in 0..10 {
+            let Some(message) = variation.vary("chan", "n", now + Duration::from_secs(index)) else { break };
+            assert!(!sent.contains(&message), "duplicate '{message}'");
+            variation.record("chan", message.clone(), now + Duration::from_secs(index));
+            sent.push(message);
+        }
+        assert_eq!(sent.len(), 8);
+        assert!(sent.iter().any(|message| message == "haut"));
+        assert!(sent.iter().any(|message| message.ends_with(INVISIBLE)));
+
+    }
+
+    #[test]
+    fn independent_channels() {
+
+        let mut variation = Variation::default();
+        let now = Instant::now();
+
+        variation.record("chan", "a".to_string(), now);
+        assert!(variation.is_duplicate("chan", "a", now));
+        assert!(!variation.is_duplicate("other", "a", now));
+
+    }
+
+}
@@ -0,0 +1,437 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::fs;
+use std::fmt;
+
+use serde::Deserialize;
+
+use crate::config::Settings;
+
+
+/// A canonical TPP command, whatever the alias typed in the chat.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Command {
+    Up,
+    Left,
+    Down,
+    Right,
+    A,
+    B,
+    X,
+    Y,
+    Democracy,
+    Anarchy,
+    Start,
+    /// Shoulder buttons and select of the later consoles.
+    L,
+    R,
+    Select,
+    /// A tap on the touchscreen, its coordinates are kept apart.
+    Touch,
+}
+
+impl Command {
+
+    /// All the commands.
+    pub const ALL: [Command; 15] = [
+        Command::Up, Command::Left, Command::Down, Command::Right,
+        Command::A, Command::B, Command::X, Command::Y,
+        Command::Democracy, Command::Anarchy, Command::Start,
+        Command::L, Command::R, Command::Select, Command::Touch,
+    ];
+
+    /// Number of commands counted by the first logs, their records have no
+    /// share of the commands added later.
+    pub const BASE_COUNT: usize = 11;
+
+    /// Index of the command in [`Command::ALL`].
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    /// The name of the command, as in the vocabulary file.
+    pub fn name(self) -> &'static str {
+        match self {
+            Command::Up => "up",
+            Command::Left => "left",
+            Command::Down => "down",
+            Command::Right => "right",
+            Command::A => "a",
+            Command::B => "b",
+            Command::X => "x",
+            Command::Y => "y",
+            Command::Democracy => "democracy",
+            Command::Anarchy => "anarchy",
+            Command::Start => "start",
+            Command::L => "l",
+            Command::R => "r",
+            Command::Select => "select",
+            Command::Touch => "touch",
+        }
+    }
+
+    /// Return true for commands that press a button, as opposed to the
+    /// commands voting for the mode and the touches.
+    pub fn is_button(self) -> bool {
+        !matches!(self, Command::Democracy | Command::Anarchy | Command::Touch)
+    }
+
+    /// The command of the given name, see [`Command::name`].
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|command| command.name() == name)
+    }
+
+}
+
+
+/// A modifier typed after a button command, depending on the channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Modifier {
+    /// Press the button several times, like `a2`.
+    Repeat(u8),
+    /// Hold the button until the next command, like `up-`.
+    Hold,
+    /// Hold the button for some milliseconds, like `b500ms`.
+    HoldFor(u16),
+}
+
+impl fmt::Display for Modifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Repeat(count) => write!(f, "{count}"),
+            Self::Hold => f.write_str("-"),
+            Self::HoldFor(millis) => write!(f, "{millis}ms"),
+        }
+    }
+}
+
+
+/// Maximum number of buttons of a combo.
+const MAX_COMBO: usize = 4;
+
+
+/// Syntaxes of the modifiers and combos accepted by a channel, none by
+/// default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ModifierSyntax {
+    pub repeat: bool,
+    pub hold: bool,
+    pub duration: bool,
+    /// Chained buttons, like `up2left` or `a+b`.
+    pub combo: bool,
+}
+
+impl ModifierSyntax {
+
+    /// Parse a comma-separated list of `repeat`, `hold`, `duration` and
+    /// `combo`.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut syntax = Self::default();
+        for name in text.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            match name {
+                "repeat" => syntax.repeat = true,
+                "hold" => syntax.hold = true,
+                "duration" => syntax.duration = true,
+                "combo" => syntax.combo = true,
+                _ => return Err(format!("unknown modifier '{name}', expected repeat, hold, duration or combo")),
+            }
+        }
+        Ok(syntax)
+    }
+
+    /// Split a text in the command and its modifier, if it ends with one of
+    /// the accepted modifiers.
+    fn split(self, text: &str) -> Option<(&str, Modifier)> {
+
+        if let Some(rest) = text.strip_suffix("ms").filter(|_| self.duration) {
+            let command = rest.trim_end_matches(|c: char| c.is_ascii_digit());
+            let millis = rest[command.len()..].parse().ok().filter(|&millis| millis > 0)?;
+            return Some((command, Modifier::HoldFor(millis)));
+        }
+
+        if let Some(command) = text.strip_suffix('-').filter(|_| self.hold) {
+            return Some((command, Modifier::Hold));
+        }
+
+        let digit = text.chars().last()?.to_digit(10).filter(|_| self.repeat)?;
+        (2..=9).contains(&digit).then(|| (&text[..text.len() - 1], Modifier::Repeat(digit as u8)))
+
+    }
+
+    /// The accepted modifier at the start of a text, with its length.
+    fn prefix(self, text: &str) -> Option<(Modifier, usize)> {
+
+        let digits = text.len() - text.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        if self.duration && digits > 0 && text[digits..].starts_with("ms") {
+            let millis = text[..digits].parse().ok().filter(|&millis| millis > 0)?;
+            return Some((Modifier::HoldFor(millis), digits + 2));
+        }
+
+        if self.hold && text.starts_with('-') {
+            return Some((Modifier::Hold, 1));
+        }
+
+        match text.bytes().next()? {
+            digit @ b'2'..=b'9' if self.repeat => Some((Modifier::Repeat(digit - b'0'), 1)),
+            _ => None,
+        }
+
+    }
+
+}
+
+
+/// An entry of the vocabulary file.
+#[derive(Debug, Deserialize)]
+struct VocabularyEntry {
+    /// All the texts typed in the chat for this command.
+    aliases: Vec<String>,
+    /// The text sent by the bot for this command.
+    output: String,
+}
+
+/// The vocabulary of a channel, mapping the texts typed in the chat to
+/// commands, and each command to the text sent by the bot.
+#[derive(Debug, Clone)]
+pub struct Vocabulary {
+    aliases: HashMap<String, Command>,
+    outputs: HashMap<Command, String>,
+    modifiers: ModifierSyntax,
+}
+
+impl Default for Vocabulary {
+
+    /// The vocabulary of the French TPP channel, without the shoulder
+    /// buttons as `l` and `r` are directions there.
+    fn default() -> Self {
+
+        let entries = [
+            (Command::Up, &["u", "n", "haut"][..], "n"),
+            (Command::Left, &["l", "w", "gauche"], "w"),
+            (Command::Down, &["d", "s", "bas"], "s"),
+            (Command::Right, &["r", "e", "droite"], "e"),
+            (Command::A, &["a"], "a"),
+            (Command::B, &["b"], "b"),
+            (Command::X, &["x"], "x"),
+            (Command::Y, &["y"], "y"),
+            (Command::Democracy, &["démocratie", "democratie"], "democratie"),
+            (Command::Anarchy, &["anarchie"], "anarchie"),
+            (Command::Start, &["start"], "start"),
+            (Command::Select, &["select"], "select"),
+        ];
+
+        let mut vocabulary = Self {
+            aliases: HashMap::new(),
+            outputs: HashMap::new(),
+            modifiers: ModifierSyntax::default(),
+        };
+
+        for (command, aliases, output) in entries {
+            vocabulary.aliases.extend(aliases.iter().map(|alias| (alias.to_string(), command)));
+            vocabulary.outputs.insert(command, output.to_string());
+        }
+
+        vocabulary
+
+    }
+
+}
+
+impl Vocabulary {
+
+    /// Load the vocabulary from a TOML file, with a table for each command
+    /// giving its `aliases` and `output`, like:
+    ///
+    /// ```toml
+    /// [up]
+    /// aliases = ["u", "n", "up"]
+    /// output = "up"
+    /// ```
+    ///
+    /// Commands missing from the file are never counted.
+    pub fn load(path: &Path) -> Result<Self, String> {
+
+        let content = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let entries = toml::from_str::<HashMap<Command, VocabularyEntry>>(&content)
+            .map_err(|e| e.to_string())?;
+
+        let mut vocabulary = Self {
+            aliases: HashMap::new(),
+            outputs: HashMap::new(),
+            modifiers: ModifierSyntax::default(),
+        };
+
+        for (command, entry) in entries {
+            for alias in entry.aliases {
+                if let Some(prev) = vocabulary.aliases.insert(alias.to_lowercase(), command) {
+                    return Err(format!("alias '{alias}' used for both {prev:?} and {command:?}"));
+                }
+            }
+            vocabulary.outputs.insert(command, entry.output);
+        }
+
+        Ok(vocabulary)
+
+    }
+
+    /// Read the vocabulary from the file of the `vocabulary_path` setting,
+    /// the default one if none, with the modifiers of the `input_modifiers`
+    /// setting.
+    pub fn from_settings(settings: &Settings) -> Result<Self, String> {
+        let vocabulary = settings.parse_with("vocabulary_path", |path| Self::load(Path::new(path)))?.unwrap_or_default();
+        let modifiers = settings.parse_with("input_modifiers", ModifierSyntax::parse)?.unwrap_or_default();
+        Ok(vocabulary.with_modifiers(modifiers))
+    }
+
+    /// Accept the given modifiers after the button commands.
+    pub fn with_modifiers(mut self, modifiers: ModifierSyntax) -> Self {
+        self.modifiers = modifiers;
+        self
+    }
+
+    /// Parse a chat message as a command, aliases are case-insensitive. A
+    /// modified button counts as the button, and a combo as its first
+    /// button.
+    pub fn parse(&self, text: &str) -> Option<Command> {
+        self.parse_modified(text)
+            .map(|(command, _)| command)
+            .or_else(|| Some(self.parse_combo(text)?[0].0))
+    }
+
+    /// Parse a chat message as a command with its modifier, if any.
+    pub fn parse_modified(&self, text: &str) -> Option<(Command, Option<Modifier>)> {
+
+        let text = text.to_lowercase();
+        if let Some(&command) = self.aliases.get(&text) {
+            return Some((command, None));
+        }
+
+        let (alias, modifier) = self.modifiers.split(&text)?;
+        let command = self.aliases.get(alias).copied().filter(|command| command.is_button())?;
+        Some((command, Some(modifier)))
+
+    }
+
+    /// Parse a chat message as a combo of buttons, like `up2left` or `a+b`,
+    /// if the channel accepts them. Without a `+` between them, each button
+    /// but the last must have a modifier, so that words aren't read as
+    /// combos.
+    pub fn parse_combo(&self, text: &str) -> Option<Vec<(Command, Option<Modifier>)>> {
+
+        if !self.modifiers.combo {
+            return None;
+        }
+
+        let text = text.to_lowercase();
+        let mut components = Vec::new();
+
+        for part in text.split('+') {
+            let mut rest = part;
+            loop {
+                let (command, modifier, len) = self.component(rest)?;
+                components.push((command, modifier));
+                rest = &rest[len..];
+                if rest.is_empty() {
+                    break;
+                }
+                modifier?;
+            }
+        }
+
+        (2..=MAX_COMBO).contains(&components.len()).then_some(components)
+
+    }
+
+    /// The button at the start of a text, the longest alias, with its
+    /// modifier if any and the length of both.
+    fn component(&self, text: &str) -> Option<(Command, Option<Modifier>, usize)> {
+
+        let (alias, &command) = self.aliases.iter()
+            .filter(|(alias, command)| command.is_button() && text.starts_with(alias.as_str()))
+            .max_by_key(|(alias, _)| alias.len())?;
+
+        match self.modifiers.prefix(&text[alias.len()..]) {
+            Some((modifier, len)) => Some((command, Some(modifier), alias.len() + len)),
+            None => Some((command, None, alias.len())),
+        }
+
+    }
+
+    /// Aliases of the given command, in alphabetical order.
+    pub fn aliases(&self, command: Command) -> Vec<&str> {
+        let mut aliases = self.aliases.iter()
+            .filter(|(_, &alias_command)| alias_command == command)
+            .map(|(alias, _)| alias.as_str())
+            .collect::<Vec<_>>();
+        aliases.sort_unstable();
+        aliases
+    }
+
+    /// The text sent by the bot for the given command.
+    pub fn output(&self, command: Command) -> &str {
+        self.outputs.get(&command).map(String::as_str).unwrap_or("")
+    }
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::sample::Sample;
+
+    #[test]
+    fn modifiers() {
+
+        let vocabulary = Vocabulary::default();
+        assert_eq!(vocabulary.parse("a2"), None);
+
+        let vocabulary = vocabulary.with_modifiers(ModifierSyntax::parse("repeat, hold, duration").unwrap());
+        assert_eq!(vocabulary.parse_modified("A"), Some((Command::A, None)));
+        assert_eq!(vocabulary.parse_modified("a2"), Some((Command::A, Some(Modifier::Repeat(2)))));
+        assert_eq!(vocabulary.parse_modified("haut-"), Some((Command::Up, Some(Modifier::Hold))));
+        assert_eq!(vocabulary.parse_modified("b500ms"), Some((Command::B, Some(Modifier::HoldFor(500)))));
+        assert_eq!(vocabulary.parse("a1"), None);
+        assert_eq!(vocabulary.parse("anarchie2"), None);
+        assert_eq!(vocabulary.parse("bms"), None);
+
+        assert_eq!(Modifier::HoldFor(500).to_string(), "500ms");
+        assert!(ModifierSyntax::parse("turbo").is_err());
+
+        let mut sample = Sample::default();
+        for alias in ["a2", "A2", "a", "b-", "b", "b"] {
+            sample.push_alias(alias);
+        }
+        assert_eq!(sample.dominant_modifier(Command::A, &vocabulary), Some(Modifier::Repeat(2)));
+        assert_eq!(sample.dominant_modifier(Command::B, &vocabulary), None);
+
+    }
+
+    #[test]
+    fn combos() {
+
+        let vocabulary = Vocabulary::default();
+        assert_eq!(vocabulary.parse_combo("a+b"), None);
+
+        let vocabulary = vocabulary.with_modifiers(ModifierSyntax::parse("repeat, combo").unwrap());
+        assert_eq!(vocabulary.parse_combo("haut2gauche"), Some(vec![(Command::Up, Some(Modifier::Repeat(2))), (Command::Left, None)]));
+        assert_eq!(vocabulary.parse_combo("A+B"), Some(vec![(Command::A, None), (Command::B, None)]));
+        assert_eq!(vocabulary.parse("a+b"), Some(Command::A));
+        // Words and single buttons aren't combos.
+        assert_eq!(vocabulary.parse_combo("sand"), None);
+        assert_eq!(vocabulary.parse_combo("a2"), None);
+        assert_eq!(vocabulary.parse_combo("a+"), None);
+        assert_eq!(vocabulary.parse_combo("a+b+a+b+a"), None);
+
+        let mut sample = Sample::default();
+        for alias in ["a+b", "a+b", "A+B", "a2b", "a"] {
+            sample.push_alias(alias);
+        }
+        assert_eq!(sample.dominant_combo(Command::A, &vocabulary), Some("a+b"));
+        assert_eq!(sample.dominant_combo(Command::B, &vocabulary), None);
+
+    }
+
+}
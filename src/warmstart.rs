@@ -0,0 +1,84 @@
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::fs::File;
+
+use chrono::{DateTime, TimeDelta, Utc};
+
+use crate::logformat;
+use crate::sample::Sample;
+use crate::stats::GLOBAL_SAMPLE_DURATION;
+use crate::vocabulary::Command;
+
+
+/// Maximum age of the last log record to warm start from, older rates don't
+/// tell anything about the current chat.
+pub const WARM_START_MAX_AGE: TimeDelta = TimeDelta::seconds(60);
+/// Length of the tail of the log read to find the last record, longer than
+/// any record.
+const TAIL_LEN: u64 = 4096;
+
+
+/// Read the global window of the last record of a log, with its age, if it
+/// was written recently enough to warm start from it.
+pub fn read_last_record(path: &Path, now: DateTime<Utc>) -> io::Result<Option<(TimeDelta, Sample)>> {
+
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    let len = file.metadata()?.len();
+    file.seek(SeekFrom::Start(len.saturating_sub(TAIL_LEN)))?;
+    let mut tail = String::new();
+    file.take(TAIL_LEN).read_to_string(&mut tail)?;
+
+    let Some((time, sample)) = tail.lines().rev().find_map(parse_record) else {
+        return Ok(None);
+    };
+
+    let age = now - time;
+    Ok((age <= WARM_START_MAX_AGE).then_some((age, sample)))
+
+}
+
+/// Parse the time and the global window of a log record: the rates of
+/// messages and commands, followed by the share of each command.
+fn parse_record(line: &str) -> Option<(DateTime<Utc>, Sample)> {
+
+    let columns = logformat::parse_record(line)?;
+    let time = DateTime::from_timestamp(columns.first()?.parse().ok()?, 0)?;
+    let values = columns.get(1..3 + Command::BASE_COUNT)?
+        .iter()
+        .map(|value| value.parse::<f32>().ok())
+        .collect::<Option<Vec<_>>>()?;
+
+    Some((time, Sample::from_rates(GLOBAL_SAMPLE_DURATION, values[0], values[1], &values[2..])))
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::policy::CommandWeights;
+
+    #[test]
+    fn last_record() {
+
+        let (time, sample) = parse_record("1700000001\t6\t5\t0.6\t0.1\t0\t0\t0.3\t0\t0\t0\t0\t0\t0\t10\t0\t-").unwrap();
+        assert_eq!(time.timestamp(), 1700000001);
+        assert_eq!((sample.message_count, sample.tpp_command_count, sample.count(Command::Up), sample.count(Command::Left), sample.count(Command::A)), (60, 50, 30, 5, 15));
+        assert_eq!(sample.most_used(&CommandWeights::default()), Command::Up);
+
+        // Parts of the sample sum to the sample.
+        let parts = (0..100).map(|index| sample.part(index, 100)).collect::<Vec<_>>();
+        assert_eq!(parts.iter().map(|part| part.count(Command::Up)).sum::<u32>(), 30);
+        assert_eq!(parts.iter().map(|part| part.message_count).sum::<u32>(), 60);
+
+        assert!(parse_record("garbage").is_none());
+
+    }
+
+}
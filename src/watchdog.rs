@@ -0,0 +1,101 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::thread;
+use std::fmt::Write;
+
+use tracing::warn;
+
+
+/// Progress of the current iteration of the main loop.
+#[derive(Debug)]
+struct Progress {
+    /// Start time of the iteration.
+    start: Instant,
+    /// Stages entered in this iteration, with their start time.
+    stages: Vec<(&'static str, Instant)>,
+    /// True once the current stall has been reported.
+    reported: bool,
+}
+
+#[derive(Debug)]
+struct Shared {
+    progress: Mutex<Progress>,
+    stalled: AtomicBool,
+}
+
+/// A watchdog thread detecting when the main loop doesn't complete an
+/// iteration within a timeout, for example when blocked on the disk or on
+/// the network. The stages of the stuck iteration are printed like a stack
+/// trace, the innermost being the stage where the loop is stuck.
+#[derive(Debug, Clone)]
+pub struct Watchdog {
+    shared: Arc<Shared>,
+}
+
+impl Watchdog {
+
+    pub fn spawn(timeout: Duration) -> Self {
+
+        let shared = Arc::new(Shared {
+            progress: Mutex::new(Progress {
+                start: Instant::now(),
+                stages: Vec::new(),
+                reported: false,
+            }),
+            stalled: AtomicBool::new(false),
+        });
+
+        let thread_shared = Arc::clone(&shared);
+        thread::Builder::new()
+            .name("watchdog".to_string())
+            .spawn(move || loop {
+
+                thread::sleep(timeout / 4);
+
+                let mut progress = thread_shared.progress.lock().unwrap();
+                let elapsed = progress.start.elapsed();
+                if elapsed < timeout || progress.reported {
+                    continue;
+                }
+
+                progress.reported = true;
+                thread_shared.stalled.store(true, Ordering::Relaxed);
+
+                let mut diagnostic = format!("watchdog: main loop stalled for {:.1}s", elapsed.as_secs_f32());
+                let now = Instant::now();
+                let mut end = now;
+                for &(stage, start) in progress.stages.iter().rev() {
+                    let at = if end == now { "at" } else { "after" };
+                    write!(diagnostic, "\n    {at} {stage} ({:.1}s)", (end - start).as_secs_f32()).unwrap();
+                    end = start;
+                }
+
+                warn!("{diagnostic}");
+
+            })
+            .unwrap();
+
+        Self { shared }
+
+    }
+
+    /// Notify the start of a new iteration of the main loop.
+    pub fn iteration(&self) {
+        let mut progress = self.shared.progress.lock().unwrap();
+        progress.start = Instant::now();
+        progress.stages.clear();
+        progress.reported = false;
+    }
+
+    /// Notify that the main loop enters a new stage of its iteration.
+    pub fn stage(&self, stage: &'static str) {
+        self.shared.progress.lock().unwrap().stages.push((stage, Instant::now()));
+    }
+
+    /// Return true if a stall has been detected since the last call.
+    pub fn take_stall(&self) -> bool {
+        self.shared.stalled.swap(false, Ordering::Relaxed)
+    }
+
+}
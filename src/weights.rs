@@ -0,0 +1,152 @@
+use std::collections::HashSet;
+
+use crate::config::Settings;
+use crate::irc::IrcReplyRef;
+
+
+/// Pseudo badge of the known bots of the channel.
+const BOT_BADGE: &str = "bot";
+/// Maximum weight of a vote, so that a few voters can't outweigh the whole
+/// chat.
+pub const MAX_WEIGHT: u16 = 10;
+
+
+/// A weight given to the votes of the users with some badge.
+#[derive(Debug, Clone, PartialEq)]
+struct BadgeWeight {
+    badge: String,
+    /// Minimum number of months given by the badge info, like the months
+    /// of subscription.
+    min_months: u32,
+    weight: u16,
+}
+
+
+/// Weights of the votes by role, read from the `badges` and `badge-info`
+/// tags of the messages. A message counts as many messages as its weight,
+/// the highest weight of its badges, and 1 without any weighted badge.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VoteWeights {
+    badges: Vec<BadgeWeight>,
+    /// Nicknames of the known bots, lowercase.
+    bots: HashSet<String>,
+    /// Weight of the known bots, 0 unless configured.
+    bot_weight: u16,
+}
+
+impl VoteWeights {
+
+    /// Read the weights from the `vote_weights` and `known_bots` settings,
+    /// none if neither is set.
+    pub fn from_settings(settings: &Settings) -> Result<Option<Self>, String> {
+
+        let weights = settings.parse_with("vote_weights", Self::parse)?;
+        let bots = settings.get("known_bots").map(|bots| bots.split(',')
+            .map(|bot| bot.trim().to_lowercase())
+            .filter(|bot| !bot.is_empty())
+            .collect::<HashSet<_>>());
+
+        Ok(match (weights, bots) {
+            (None, None) => None,
+            (weights, bots) => {
+                let mut weights = weights.unwrap_or_default();
+                weights.bots = bots.unwrap_or_default();
+                Some(weights)
+            }
+        })
+
+    }
+
+    /// Parse weights like `subscriber=2, subscriber/12=3, moderator=3,
+    /// bot=0`, where `/12` requires at least 12 months of the badge and
+    /// `bot` is the weight of the known bots.
+    pub fn parse(text: &str) -> Result<Self, String> {
+
+        let mut weights = Self::default();
+
+        for item in text.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+
+            let (badge, weight) = item.split_once('=')
+                .ok_or_else(|| format!("expected 'badge=weight', got '{item}'"))?;
+            let weight = weight.trim().parse::<u16>()
+                .map_err(|e| format!("invalid weight of '{}': {e}", badge.trim()))?;
+            if weight > MAX_WEIGHT {
+                return Err(format!("weight of '{}' must be at most {MAX_WEIGHT}, got {weight}", badge.trim()));
+            }
+
+            let (badge, min_months) = match badge.trim().split_once('/') {
+                Some((badge, months)) => (badge, months.parse::<u32>().map_err(|e| format!("invalid months of '{badge}': {e}"))?),
+                None => (badge.trim(), 0),
+            };
+
+            if badge == BOT_BADGE {
+                weights.bot_weight = weight;
+            } else {
+                weights.badges.push(BadgeWeight { badge: badge.to_string(), min_months, weight });
+            }
+
+        }
+
+        Ok(weights)
+
+    }
+
+    /// Weight of the vote of a message.
+    pub fn weight(&self, reply: &IrcReplyRef) -> u16 {
+        let nickname = reply.sender().and_then(|sender| sender.nickname);
+        self.weight_of(reply.tag("badges"), reply.tag("badge-info"), nickname)
+    }
+
+    fn weight_of(&self, badges: Option<&str>, badge_info: Option<&str>, nickname: Option<&str>) -> u16 {
+
+        if nickname.is_some_and(|nickname| self.bots.contains(&nickname.to_lowercase())) {
+            return self.bot_weight;
+        }
+
+        // The badge info gives the number of months, where the badge only
+        // gives a tier.
+        let months = |badge: &str| badge_info.unwrap_or_default().split(',')
+            .filter_map(|item| item.split_once('/'))
+            .find(|(name, _)| *name == badge)
+            .and_then(|(_, months)| months.parse::<u32>().ok());
+
+        let names = badges.unwrap_or_default().split(',')
+            .filter_map(|badge| badge.split('/').next())
+            .collect::<Vec<_>>();
+
+        self.badges.iter()
+            .filter(|weight| names.contains(&weight.badge.as_str()))
+            .filter(|weight| weight.min_months == 0 || months(&weight.badge).is_some_and(|months| months >= weight.min_months))
+            .map(|weight| weight.weight)
+            .max()
+            .unwrap_or(1)
+
+    }
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn badge_weights() {
+
+        let mut weights = VoteWeights::parse("subscriber=2, subscriber/12=4, moderator=3").unwrap();
+        weights.bots.insert("nightbot".to_string());
+
+        assert_eq!(weights.weight_of(None, None, Some("viewer")), 1);
+        assert_eq!(weights.weight_of(Some("subscriber/3000"), Some("subscriber/5"), Some("sub")), 2);
+        assert_eq!(weights.weight_of(Some("subscriber/3012,premium/1"), Some("subscriber/14"), Some("sub")), 4);
+        assert_eq!(weights.weight_of(Some("moderator/1,subscriber/0"), Some("subscriber/1"), Some("mod")), 3);
+        assert_eq!(weights.weight_of(Some("moderator/1"), None, Some("NightBot")), 0);
+
+        assert!(VoteWeights::parse("subscriber").is_err());
+        assert!(VoteWeights::parse("subscriber=x").is_err());
+        assert!(VoteWeights::parse("subscriber=11").is_err());
+
+    }
+
+}
@@ -0,0 +1,157 @@
+use std::sync::mpsc::{self, Sender};
+use std::collections::HashMap;
+use std::time::Duration;
+use std::thread;
+
+use serde_json::{json, Value};
+use tracing::{info, warn};
+
+use crate::config::Settings;
+
+
+/// Base URL of the Helix API of Twitch.
+const HELIX_URL: &str = "https://api.twitch.tv/helix";
+
+
+/// Configuration of the whispers, sent with the Helix API because Twitch
+/// ignores the `/w` chat command over IRC. The token of the bot needs the
+/// `user:manage:whispers` scope.
+#[derive(Debug, Clone)]
+pub struct WhisperConfig {
+    /// Client id of the application the token was issued to.
+    pub client_id: String,
+}
+
+impl WhisperConfig {
+
+    /// Read the configuration from the `whisper_client_id` setting, none if
+    /// not set, the bot then doesn't whisper.
+    pub fn from_settings(settings: &Settings) -> Result<Option<Self>, String> {
+        Ok(settings.get("whisper_client_id").map(|client_id| Self { client_id: client_id.to_string() }))
+    }
+
+}
+
+
+/// Send whispers from the account of the bot. Requests are sent by a
+/// background thread, so that a slow API doesn't block the bot.
+#[derive(Debug)]
+pub struct Whisperer {
+    tx: Sender<(String, String)>,
+}
+
+impl Whisperer {
+
+    /// Spawn the thread whispering from the given user with its token.
+    pub fn spawn(config: WhisperConfig, user: &str, token: &str) -> Self {
+
+        let (tx, rx) = mpsc::channel::<(String, String)>();
+        let mut helix = Helix {
+            agent: ureq::AgentBuilder::new()
+                .timeout(Duration::from_secs(10))
+                .build(),
+            client_id: config.client_id,
+            authorization: format!("Bearer {token}"),
+            user_ids: HashMap::new(),
+        };
+        let user = user.to_lowercase();
+
+        thread::Builder::new()
+            .name("whisper".to_string())
+            .spawn(move || {
+                for (to, text) in rx {
+                    match helix.whisper(&user, &to, &text) {
+                        Ok(()) => info!("whispered to {to}: {text}"),
+                        Err(e) => warn!("\x07alert: failed to whisper to {to}: {e}"),
+                    }
+                }
+            })
+            .unwrap();
+
+        Self { tx }
+
+    }
+
+    /// Whisper a message to a user, given by login.
+    pub fn send(&self, user: &str, text: &str) {
+        // The thread only stops with the process.
+        let _ = self.tx.send((user.to_lowercase(), text.to_string()));
+    }
+
+}
+
+
+/// Client of the Helix API.
+struct Helix {
+    agent: ureq::Agent,
+    client_id: String,
+    authorization: String,
+    /// Ids of the users already requested, by login.
+    user_ids: HashMap<String, String>,
+}
+
+impl Helix {
+
+    fn whisper(&mut self, from: &str, to: &str, text: &str) -> Result<(), String> {
+
+        let from_id = self.user_id(from)?;
+        let to_id = self.user_id(to)?;
+
+        self.agent.post(&format!("{HELIX_URL}/whispers"))
+            .query("from_user_id", &from_id)
+            .query("to_user_id", &to_id)
+            .set("Client-Id", &self.client_id)
+            .set("Authorization", &self.authorization)
+            .set("Content-Type", "application/json")
+            .send_string(&json!({ "message": text }).to_string())
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+
+    }
+
+    /// The id of a user, requested once.
+    fn user_id(&mut self, login: &str) -> Result<String, String> {
+
+        if let Some(id) = self.user_ids.get(login) {
+            return Ok(id.clone());
+        }
+
+        let body = self.agent.get(&format!("{HELIX_URL}/users"))
+            .query("login", login)
+            .set("Client-Id", &self.client_id)
+            .set("Authorization", &self.authorization)
+            .call()
+            .map_err(|e| e.to_string())?
+            .into_string()
+            .map_err(|e| e.to_string())?;
+
+        let id = parse_user_id(&body).ok_or_else(|| format!("unknown user {login}"))?;
+        self.user_ids.insert(login.to_string(), id.clone());
+        Ok(id)
+
+    }
+
+}
+
+/// Parse the id of the first user of a response of the users endpoint.
+fn parse_user_id(body: &str) -> Option<String> {
+    let value = serde_json::from_str::<Value>(body).ok()?;
+    Some(value["data"].get(0)?["id"].as_str()?.to_string())
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn user_id() {
+        // Example of the Twitch documentation, shortened.
+        let body = r#"{"data":[{"id":"141981764","login":"twitchdev","display_name":"TwitchDev","type":""}]}"#;
+        assert_eq!(parse_user_id(body).as_deref(), Some("141981764"));
+        assert_eq!(parse_user_id(r#"{"data":[]}"#), None);
+        assert_eq!(parse_user_id("<html>"), None);
+    }
+
+}
@@ -0,0 +1,37 @@
+use tpp_bot::irc::{IrcReplyCommand, IrcReplyRef};
+use tpp_bot::stats::Stats;
+use tpp_bot::vocabulary::{Command, Vocabulary};
+
+
+/// Parse raw chat lines and aggregate their votes, as the bot does.
+#[test]
+fn parse_and_aggregate() {
+
+    let lines = [
+        "@user-id=1 :foo!foo@foo.tmi.twitch.tv PRIVMSG #chan :n",
+        "@user-id=2 :bar!bar@bar.tmi.twitch.tv PRIVMSG #chan :N",
+        "@user-id=3 :baz!baz@baz.tmi.twitch.tv PRIVMSG #chan :a",
+        "@user-id=4 :qux!qux@qux.tmi.twitch.tv PRIVMSG #chan :hello there",
+        ":tmi.twitch.tv PONG tmi.twitch.tv :tpp-bot",
+    ];
+
+    let vocabulary = Vocabulary::default();
+    let mut stats = Stats::new();
+
+    for line in lines {
+        let reply = IrcReplyRef::parse(line).unwrap();
+        if let IrcReplyCommand::PrivMsg = reply.command {
+            assert_eq!(reply.target(), Some("#chan"));
+            let command = vocabulary.parse(reply.text().unwrap());
+            stats.push_message(command, reply.tag("user-id").map(|user| (user, false)), 1);
+        }
+    }
+
+    stats.flush();
+
+    let tpp = stats.tpp();
+    assert_eq!(tpp.message_count, 4);
+    assert_eq!(tpp.tpp_command_count, 3);
+    assert_eq!(stats.most_used(), Command::Up);
+
+}